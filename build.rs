@@ -0,0 +1,44 @@
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    // `sqlx::migrate!()` embeds the migrations, so rebuild when they change
+    println!("cargo:rerun-if-changed=migrations");
+
+    // tonic-build shells out to protoc, which PROTOC points at
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path().expect("vendored protoc is available"));
+    tonic_build::compile_protos("proto/orders.proto").expect("could not compile proto/orders.proto");
+
+    build_info();
+}
+
+/// Bakes the commit and the build time in for `GET /version`. Images built
+/// without `.git` pass `GIT_COMMIT` instead; `SOURCE_DATE_EPOCH` pins the
+/// timestamp for reproducible builds.
+fn build_info() {
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // a new commit or checkout moves HEAD or the branch it points at
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        if let Some(branch) = head.strip_prefix("ref: ") {
+            let branch = Path::new(".git").join(branch.trim());
+            if branch.exists() {
+                println!("cargo:rerun-if-changed={}", branch.display());
+            }
+        }
+    }
+
+    let commit = std::env::var("GIT_COMMIT").ok().filter(|commit| !commit.is_empty()).or_else(|| {
+        let output = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    });
+    println!("cargo:rustc-env=BUILD_COMMIT={}", commit.as_deref().unwrap_or("unknown"));
+
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", built_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+}
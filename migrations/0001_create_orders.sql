@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS orders (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255),
+    coffee_name VARCHAR(255),
+    size VARCHAR(50),
+    total INT
+);
@@ -0,0 +1,3 @@
+-- bumped on every update, exposed to clients as the order's ETag
+ALTER TABLE orders
+    ADD COLUMN version INT NOT NULL DEFAULT 1;
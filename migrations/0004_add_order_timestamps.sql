@@ -0,0 +1,4 @@
+-- existing rows get the migration time, there is no better value to backfill
+ALTER TABLE orders
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
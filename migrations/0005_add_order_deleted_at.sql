@@ -0,0 +1,3 @@
+-- soft delete: set instead of removing the row, so finance can still reconcile
+ALTER TABLE orders
+    ADD COLUMN deleted_at TIMESTAMPTZ;
@@ -0,0 +1,12 @@
+-- audit trail; no foreign key so history outlives a hard delete
+CREATE TABLE order_events (
+    id BIGSERIAL PRIMARY KEY,
+    order_id INT NOT NULL,
+    action TEXT NOT NULL
+        CHECK (action IN ('created', 'updated', 'status_changed', 'deleted', 'restored')),
+    actor TEXT NOT NULL,
+    changes JSONB NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX order_events_order_id_idx ON order_events (order_id, id DESC);
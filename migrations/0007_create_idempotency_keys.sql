@@ -0,0 +1,14 @@
+-- the primary key is what makes concurrent retries safe: the second insert
+-- waits on the first transaction instead of racing it
+CREATE TABLE idempotency_keys (
+    client TEXT NOT NULL,
+    key TEXT NOT NULL,
+    request_hash TEXT NOT NULL,
+    status_code INT,
+    -- kept verbatim so a replay is byte-for-byte the original response
+    response_body TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (client, key)
+);
+
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
@@ -0,0 +1,29 @@
+-- one row per person; names match case-insensitively so "Sam" and "sam" are the same customer
+CREATE TABLE customers (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    phone VARCHAR(50),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX customers_name_key ON customers (LOWER(name));
+
+-- orders.name stays as the customer's name so existing clients keep reading it
+ALTER TABLE orders
+    ADD COLUMN customer_id INT REFERENCES customers (id);
+
+CREATE INDEX orders_customer_id_idx ON orders (customer_id);
+
+-- backfill: the first spelling seen for each name becomes the customer
+INSERT INTO customers (name)
+SELECT DISTINCT ON (LOWER(TRIM(name))) TRIM(name)
+FROM orders
+WHERE TRIM(name) <> ''
+ORDER BY LOWER(TRIM(name)), id;
+
+UPDATE orders
+SET customer_id = customers.id
+FROM customers
+WHERE LOWER(customers.name) = LOWER(TRIM(orders.name));
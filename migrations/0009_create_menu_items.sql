@@ -0,0 +1,31 @@
+-- prices in the smallest currency unit; an order's total comes from here
+CREATE TABLE menu_items (
+    id SERIAL PRIMARY KEY,
+    coffee_name VARCHAR(255) NOT NULL,
+    size VARCHAR(50) NOT NULL CHECK (size IN ('small', 'medium', 'large')),
+    price INT NOT NULL CHECK (price > 0),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX menu_items_coffee_name_size_key ON menu_items (LOWER(coffee_name), size);
+
+INSERT INTO menu_items (coffee_name, size, price) VALUES
+    ('espresso', 'small', 250),
+    ('espresso', 'medium', 300),
+    ('espresso', 'large', 350),
+    ('americano', 'small', 300),
+    ('americano', 'medium', 350),
+    ('americano', 'large', 400),
+    ('cappuccino', 'small', 350),
+    ('cappuccino', 'medium', 400),
+    ('cappuccino', 'large', 450),
+    ('flat white', 'small', 350),
+    ('flat white', 'medium', 400),
+    ('flat white', 'large', 450),
+    ('latte', 'small', 400),
+    ('latte', 'medium', 450),
+    ('latte', 'large', 550),
+    ('mocha', 'small', 450),
+    ('mocha', 'medium', 500),
+    ('mocha', 'large', 600);
@@ -0,0 +1,19 @@
+-- one row per line of an order; orders.total is the sum of quantity * unit_price
+-- unless an admin overrode it, and orders.coffee_name/size mirror the first line
+CREATE TABLE order_items (
+    id SERIAL PRIMARY KEY,
+    order_id INT NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+    coffee_name VARCHAR(255) NOT NULL,
+    size VARCHAR(50) NOT NULL,
+    quantity INT NOT NULL CHECK (quantity > 0),
+    unit_price INT NOT NULL CHECK (unit_price >= 0)
+);
+
+CREATE INDEX order_items_order_id_idx ON order_items (order_id);
+
+-- backfill: every existing order becomes a single line
+INSERT INTO order_items (order_id, coffee_name, size, quantity, unit_price)
+SELECT id, coffee_name, size, 1, total
+FROM orders
+WHERE coffee_name IS NOT NULL AND size IS NOT NULL AND total IS NOT NULL
+ORDER BY id;
@@ -0,0 +1,8 @@
+-- cups across all of an order's lines, so "3x cappuccino" is one order
+ALTER TABLE orders
+    ADD COLUMN quantity INT NOT NULL DEFAULT 1 CHECK (quantity >= 1);
+
+UPDATE orders
+SET quantity = lines.quantity
+FROM (SELECT order_id, SUM(quantity)::INT AS quantity FROM order_items GROUP BY order_id) AS lines
+WHERE lines.order_id = orders.id;
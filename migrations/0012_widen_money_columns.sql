@@ -0,0 +1,13 @@
+-- amounts are integer cents; BIGINT so sums of many orders cannot overflow a row
+UPDATE orders SET total = 0 WHERE total IS NULL;
+
+ALTER TABLE orders
+    ALTER COLUMN total TYPE BIGINT,
+    ALTER COLUMN total SET NOT NULL,
+    ADD CONSTRAINT orders_total_check CHECK (total >= 0);
+
+ALTER TABLE order_items
+    ALTER COLUMN unit_price TYPE BIGINT;
+
+ALTER TABLE menu_items
+    ALTER COLUMN price TYPE BIGINT;
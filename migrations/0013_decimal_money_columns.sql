@@ -0,0 +1,9 @@
+-- exact decimal amounts in currency units instead of integer cents
+ALTER TABLE orders
+    ALTER COLUMN total TYPE NUMERIC(10, 2) USING total / 100.0;
+
+ALTER TABLE order_items
+    ALTER COLUMN unit_price TYPE NUMERIC(10, 2) USING unit_price / 100.0;
+
+ALTER TABLE menu_items
+    ALTER COLUMN price TYPE NUMERIC(10, 2) USING price / 100.0;
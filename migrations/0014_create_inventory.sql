@@ -0,0 +1,8 @@
+-- cups left per coffee, across sizes; coffees with no row are not tracked
+CREATE TABLE inventory (
+    coffee_name VARCHAR(255) PRIMARY KEY,
+    stock INT NOT NULL CHECK (stock >= 0),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX inventory_coffee_name_key ON inventory (LOWER(coffee_name));
@@ -0,0 +1,3 @@
+-- why the customer cancelled, when they said; null for orders never cancelled
+ALTER TABLE orders
+    ADD COLUMN cancellation_reason TEXT;
@@ -0,0 +1,3 @@
+-- free text from the barista, e.g. "extra hot, oat milk, no lid"
+ALTER TABLE orders
+    ADD COLUMN notes TEXT;
@@ -0,0 +1,6 @@
+-- labels such as "loyalty" or "staff", stored normalized (trimmed, lowercase, unique)
+ALTER TABLE orders
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
+
+-- serves the `tags @>` containment filter
+CREATE INDEX orders_tags_idx ON orders USING GIN (tags);
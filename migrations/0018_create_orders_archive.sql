@@ -0,0 +1,14 @@
+-- completed and cancelled orders moved out by the retention job. the row is
+-- kept as json, its items included, so later changes to orders never need
+-- a matching change here
+CREATE TABLE orders_archive (
+    id INT PRIMARY KEY,
+    status TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL,
+    data JSONB NOT NULL,
+    archived_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- matches the retention job's filter, so a batch never scans the open orders
+CREATE INDEX orders_finished_updated_at_idx ON orders (updated_at)
+    WHERE status IN ('completed', 'cancelled');
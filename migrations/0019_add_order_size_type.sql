@@ -0,0 +1,45 @@
+-- size was free text, so older rows carry spellings such as 'Large', 'LG' or 'grande'
+CREATE TYPE order_size AS ENUM ('small', 'medium', 'large');
+
+CREATE TEMPORARY TABLE size_spellings (spelling TEXT PRIMARY KEY, size TEXT NOT NULL);
+
+INSERT INTO size_spellings (spelling, size) VALUES
+    ('small', 'small'),
+    ('s', 'small'),
+    ('sm', 'small'),
+    ('short', 'small'),
+    ('medium', 'medium'),
+    ('m', 'medium'),
+    ('md', 'medium'),
+    ('med', 'medium'),
+    ('regular', 'medium'),
+    ('tall', 'medium'),
+    ('large', 'large'),
+    ('l', 'large'),
+    ('lg', 'large'),
+    ('grande', 'large'),
+    ('venti', 'large');
+
+UPDATE orders SET size = s.size FROM size_spellings s WHERE LOWER(TRIM(orders.size)) = s.spelling;
+UPDATE order_items SET size = s.size FROM size_spellings s WHERE LOWER(TRIM(order_items.size)) = s.spelling;
+
+-- refuse to guess: an unmapped spelling stops the migration so it can be added above
+DO $$
+DECLARE
+    unknown TEXT;
+BEGIN
+    SELECT string_agg(DISTINCT size, ', ') INTO unknown
+    FROM (SELECT size FROM orders UNION ALL SELECT size FROM order_items) AS sizes
+    WHERE size NOT IN (SELECT size FROM size_spellings);
+    IF unknown IS NOT NULL THEN
+        RAISE EXCEPTION 'cannot map sizes to order_size: %', unknown;
+    END IF;
+END $$;
+
+DROP TABLE size_spellings;
+
+ALTER TABLE menu_items DROP CONSTRAINT menu_items_size_check;
+
+ALTER TABLE orders ALTER COLUMN size TYPE order_size USING size::order_size;
+ALTER TABLE order_items ALTER COLUMN size TYPE order_size USING size::order_size;
+ALTER TABLE menu_items ALTER COLUMN size TYPE order_size USING size::order_size;
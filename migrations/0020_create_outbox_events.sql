@@ -0,0 +1,20 @@
+-- webhook events written in the same transaction as the order change they
+-- describe, so a restart between the commit and the delivery loses nothing.
+-- no foreign key: an event outlives a hard delete of its order
+CREATE TABLE outbox_events (
+    id BIGSERIAL PRIMARY KEY,
+    order_id INT NOT NULL,
+    event_type TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    -- targets that already took the event, skipped when it is retried
+    delivered_to TEXT[] NOT NULL DEFAULT '{}',
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ
+);
+
+-- what the dispatcher polls, kept small by leaving delivered and failed rows out
+CREATE INDEX outbox_events_due_idx ON outbox_events (next_attempt_at) WHERE status = 'pending';
@@ -0,0 +1,19 @@
+-- one row per attempt at landing a day's orders in the export bucket; a
+-- succeeded row is what later runs skip the day for unless forced
+CREATE TABLE exports (
+    id BIGSERIAL PRIMARY KEY,
+    export_date DATE NOT NULL,
+    format TEXT NOT NULL CHECK (format IN ('csv', 'ndjson')),
+    object_key TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'running' CHECK (status IN ('running', 'succeeded', 'failed')),
+    forced BOOLEAN NOT NULL DEFAULT false,
+    row_count BIGINT,
+    byte_count BIGINT,
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    finished_at TIMESTAMPTZ
+);
+
+-- the scheduler and a manual trigger racing for the same day start one run
+CREATE UNIQUE INDEX exports_one_running_idx ON exports (export_date, format) WHERE status = 'running';
+CREATE INDEX exports_date_idx ON exports (export_date, format);
@@ -0,0 +1,255 @@
+//! API key and JWT authentication, scopes and roles.
+use std::str::FromStr;
+use std::sync::Arc;
+use axum::{
+  async_trait,
+  extract::{FromRequestParts, Request, State},
+  http::{request::Parts, header::AUTHORIZATION, HeaderMap, HeaderName, Method},
+  middleware::Next,
+};
+use serde::Deserialize;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{DecodingKey, Validation};
+
+use crate::config::{ApiKey, Config};
+use crate::errors::ApiError;
+
+pub(crate) static X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+pub(crate) const SCOPE_READ: &str = "orders:read";
+pub(crate) const SCOPE_WRITE: &str = "orders:write";
+pub(crate) const SCOPE_DELETE: &str = "orders:delete";
+pub(crate) const ALL_SCOPES: [&str; 3] = [SCOPE_READ, SCOPE_WRITE, SCOPE_DELETE];
+
+pub(crate) struct Authenticator {
+    pub(crate) api_keys: Vec<ApiKey>,
+    pub(crate) jwt: Option<JwtVerifier>,
+    pub(crate) disabled: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct JwtVerifier {
+    pub(crate) keys: JwtKeys,
+    pub(crate) validation: Validation,
+}
+
+#[derive(Clone)]
+pub(crate) enum JwtKeys {
+    Single(DecodingKey),
+    /// Keys from a JWKS document, matched on the token's `kid`.
+    Jwks(Vec<(Option<String>, DecodingKey)>),
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Claims {
+    sub: String,
+    /// Space separated, as issued by most OAuth servers.
+    #[serde(default)]
+    scope: Option<String>,
+    /// Array form used by some issuers instead of `scope`.
+    #[serde(default)]
+    scp: Option<Vec<String>>,
+    /// Missing means barista.
+    #[serde(default)]
+    role: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Role {
+    Admin,
+    Barista,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "admin" => Ok(Role::Admin),
+            "barista" => Ok(Role::Barista),
+            other => Err(format!("invalid role '{other}', expected admin or barista")),
+        }
+    }
+}
+
+/// Who made the request and what they may do. API keys and disabled auth
+/// are granted every scope.
+#[derive(Clone)]
+pub(crate) struct AuthContext {
+    pub(crate) subject: String,
+    scopes: Vec<String>,
+    role: Role,
+}
+
+impl AuthContext {
+    fn with_all_scopes(subject: String, role: Role) -> Self {
+        AuthContext { subject, scopes: ALL_SCOPES.iter().map(|s| s.to_string()).collect(), role }
+    }
+
+    pub(crate) fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
+
+    pub(crate) fn require_admin(&self) -> Result<(), ApiError> {
+        if self.role != Role::Admin {
+            return Err(ApiError::Forbidden("admin role required".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthContext {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthContext>()
+            .cloned()
+            .ok_or_else(|| ApiError::Unauthorized("not authenticated".to_owned()))
+    }
+}
+
+/// Extractor for destructive endpoints, rejects anyone below admin with 403.
+pub(crate) struct RequireAdmin(pub(crate) AuthContext);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for RequireAdmin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = AuthContext::from_request_parts(parts, state).await?;
+        auth.require_admin()?;
+        Ok(RequireAdmin(auth))
+    }
+}
+
+// compares every byte so the time taken does not reveal how much of a key matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub(crate) enum Credential<'a> {
+    ApiKey(&'a str),
+    Bearer(&'a str),
+}
+
+pub(crate) fn presented_credential(headers: &HeaderMap) -> Option<Credential<'_>> {
+    if let Some(key) = headers.get(&X_API_KEY) {
+        return key.to_str().ok().map(Credential::ApiKey);
+    }
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| Credential::Bearer(token.trim()))
+}
+
+impl Authenticator {
+    pub(crate) fn new(config: &Config) -> Self {
+        Authenticator {
+            api_keys: config.api_keys.clone(),
+            jwt: config.jwt.clone(),
+            disabled: config.auth_disabled,
+        }
+    }
+
+    fn check_api_key(&self, presented: &str) -> Option<AuthContext> {
+        // check every key rather than stopping at the first match
+        let mut matched = None;
+        for key in &self.api_keys {
+            if constant_time_eq(key.key.as_bytes(), presented.as_bytes()) {
+                matched = Some(key);
+            }
+        }
+        matched.map(|key| AuthContext::with_all_scopes(key.client.clone(), key.role))
+    }
+
+    fn check_jwt(&self, jwt: &JwtVerifier, token: &str) -> Result<AuthContext, ApiError> {
+        let invalid = || ApiError::Unauthorized("invalid token".to_owned());
+
+        let key = match &jwt.keys {
+            JwtKeys::Single(key) => key,
+            JwtKeys::Jwks(keys) => {
+                let kid = jsonwebtoken::decode_header(token).map_err(|_| invalid())?.kid;
+                match keys.iter().find(|(key_id, _)| kid.is_some() && *key_id == kid) {
+                    Some((_, key)) => key,
+                    // a lone key without a kid is used for every token
+                    None if keys.len() == 1 && keys[0].0.is_none() => &keys[0].1,
+                    None => return Err(invalid()),
+                }
+            }
+        };
+
+        let claims = jsonwebtoken::decode::<Claims>(token, key, &jwt.validation)
+            .map_err(|err| match err.kind() {
+                ErrorKind::ExpiredSignature => ApiError::Unauthorized("token expired".to_owned()),
+                _ => invalid(),
+            })?
+            .claims;
+
+        let mut scopes: Vec<String> = claims
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect();
+        scopes.extend(claims.scp.unwrap_or_default());
+
+        let role = match claims.role.as_deref() {
+            Some(role) => role.parse().map_err(|_| invalid())?,
+            None => Role::Barista,
+        };
+
+        Ok(AuthContext { subject: claims.sub, scopes, role })
+    }
+
+    /// Checks the credentials in `headers`, which gRPC metadata fills too.
+    pub(crate) fn authenticate(&self, headers: &HeaderMap) -> Result<AuthContext, ApiError> {
+        if self.disabled {
+            return Ok(AuthContext::with_all_scopes("anonymous".to_owned(), Role::Admin));
+        }
+
+        let credential = presented_credential(headers)
+            .ok_or_else(|| ApiError::Unauthorized("missing credentials".to_owned()))?;
+
+        match credential {
+            Credential::ApiKey(key) => self.check_api_key(key),
+            // a bearer with JWT shape goes to the verifier, anything else is tried as an api key
+            Credential::Bearer(token) => match &self.jwt {
+                Some(jwt) if token.split('.').count() == 3 => return self.check_jwt(jwt, token),
+                _ => self.check_api_key(token),
+            },
+        }
+        .ok_or_else(|| ApiError::Unauthorized("invalid credentials".to_owned()))
+    }
+}
+
+pub(crate) fn required_scope(method: &Method) -> &'static str {
+    match *method {
+        Method::GET | Method::HEAD => SCOPE_READ,
+        Method::DELETE => SCOPE_DELETE,
+        _ => SCOPE_WRITE,
+    }
+}
+
+pub(crate) async fn authenticate(
+    State(auth): State<Arc<Authenticator>>,
+    mut request: Request,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    let context = auth.authenticate(request.headers())?;
+
+    let scope = required_scope(request.method());
+    if !context.has_scope(scope) {
+        return Err(ApiError::Forbidden(format!("missing scope '{scope}'")));
+    }
+
+    tracing::Span::current().record("client", context.subject.as_str());
+    request.extensions_mut().insert(context);
+    Ok(next.run(request).await)
+}
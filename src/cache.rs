@@ -0,0 +1,111 @@
+//! A short-lived in-process cache of `GET /orders` pages, for clients such
+//! as the wall display that poll the same list every second.
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use axum::http::HeaderName;
+
+use crate::handlers::reports::TimeRange;
+use crate::models::Size;
+use crate::repository::OrderPage;
+
+pub(crate) static X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// Everything that decides which page `list` returns, normalized so that
+/// equivalent queries share an entry.
+#[derive(Clone, Hash, PartialEq, Eq)]
+pub(crate) struct ListKey {
+    pub(crate) name: Option<String>,
+    pub(crate) coffee_name: Option<String>,
+    pub(crate) size: Option<Size>,
+    pub(crate) status: Option<String>,
+    pub(crate) q: Option<String>,
+    pub(crate) search_notes: bool,
+    pub(crate) include_deleted: bool,
+    pub(crate) created: TimeRange,
+    pub(crate) tags: Vec<String>,
+    pub(crate) limit: i64,
+    pub(crate) offset: i64,
+    pub(crate) after_id: Option<i32>,
+    pub(crate) order_by: String,
+    pub(crate) include_items: bool,
+    /// A page read from the replica may lag, so it never answers `X-Read-From: primary`.
+    pub(crate) from_primary: bool,
+}
+
+/// Pages by `ListKey`, each kept for `ttl` or until the next order write,
+/// whichever comes first. Cheap to clone; clones share the entries.
+#[derive(Clone)]
+pub(crate) struct OrderListCache {
+    /// `None` caches nothing.
+    ttl: Option<Duration>,
+    max_entries: usize,
+    inner: Arc<RwLock<Entries>>,
+}
+
+#[derive(Default)]
+struct Entries {
+    pages: HashMap<ListKey, (Instant, OrderPage)>,
+    /// Bumped by every invalidation, so a page read before a write is not
+    /// stored after it.
+    generation: u64,
+}
+
+impl OrderListCache {
+    pub(crate) fn new(ttl: Option<Duration>, max_entries: usize) -> OrderListCache {
+        OrderListCache { ttl, max_entries, inner: Arc::default() }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.ttl.is_some() && self.max_entries > 0
+    }
+
+    /// Where a read that misses starts; pass it back to `insert`.
+    pub(crate) fn generation(&self) -> u64 {
+        self.inner.read().unwrap().generation
+    }
+
+    pub(crate) fn get(&self, key: &ListKey) -> Option<OrderPage> {
+        let ttl = self.ttl?;
+        let entries = self.inner.read().unwrap();
+        entries
+            .pages
+            .get(key)
+            .filter(|(stored, _)| stored.elapsed() < ttl)
+            .map(|(_, page)| page.clone())
+    }
+
+    /// Stores `page` unless an invalidation happened since `generation`.
+    /// When full, expired pages go first and then the oldest one, so a scan
+    /// of random filters only ever holds `max_entries` pages.
+    pub(crate) fn insert(&self, generation: u64, key: ListKey, page: OrderPage) {
+        let Some(ttl) = self.ttl.filter(|_| self.max_entries > 0) else {
+            return;
+        };
+        let mut entries = self.inner.write().unwrap();
+        if entries.generation != generation {
+            return;
+        }
+        if entries.pages.len() >= self.max_entries && !entries.pages.contains_key(&key) {
+            entries.pages.retain(|_, (stored, _)| stored.elapsed() < ttl);
+            if entries.pages.len() >= self.max_entries {
+                let oldest = entries.pages.iter().min_by_key(|(_, (stored, _))| *stored).map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    entries.pages.remove(&oldest);
+                }
+            }
+        }
+        entries.pages.insert(key, (Instant::now(), page));
+    }
+
+    /// Drops every page. Called on each order write, see `OrderFeed::publish`,
+    /// and after writes that bypass it.
+    pub(crate) fn invalidate(&self) {
+        if !self.enabled() {
+            return;
+        }
+        let mut entries = self.inner.write().unwrap();
+        entries.generation += 1;
+        entries.pages.clear();
+    }
+}
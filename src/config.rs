@@ -0,0 +1,482 @@
+//! Settings read from the environment at startup.
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use axum::http::HeaderValue;
+use rust_decimal::Decimal;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use tracing_subscriber::EnvFilter;
+
+use crate::auth::{JwtKeys, JwtVerifier, Role};
+use crate::exports::{export_from_env, ExportSettings};
+use crate::handlers::menu::PriceTolerance;
+use crate::pagination::{PageLimits, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+use crate::receipts::{mail_from_env, MailSettings};
+use crate::retention::RetentionSettings;
+use crate::slack::{slack_from_env, SlackSettings};
+use crate::tls::{tls_from_env, TlsCertificate};
+use crate::worker::WorkerSettings;
+
+/// Used when `RUST_LOG` is unset.
+pub const DEFAULT_LOG_FILTER: &str = "rust_orders=info,tower_http=info";
+
+pub struct Config {
+    pub environment: String,
+    pub host: String,
+    pub port: u16,
+    /// Every address the HTTP router listens on; empty means `host`:`port`.
+    pub bind_addrs: Vec<SocketAddr>,
+    /// The gRPC listener, on the same host.
+    pub grpc_port: u16,
+    /// `None` serves plain HTTP.
+    pub(crate) tls: Option<TlsCertificate>,
+    /// Also serves HTTP on this socket, next to the TCP listener.
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the socket file, e.g. 0o660 for the proxy's group.
+    pub unix_socket_mode: u32,
+    pub(crate) database_url: String,
+    /// A read replica for the handlers that only read; `None` reads from the primary.
+    pub(crate) database_read_url: Option<String>,
+    pub(crate) db_max_connections: u32,
+    pub(crate) db_min_connections: u32,
+    pub(crate) db_acquire_timeout: Duration,
+    /// `None` keeps idle connections open until they reach `db_max_lifetime`.
+    pub(crate) db_idle_timeout: Option<Duration>,
+    /// `None` never retires a connection for its age.
+    pub(crate) db_max_lifetime: Option<Duration>,
+    /// Zero fails on the first refused connection.
+    pub(crate) db_connect_retries: u32,
+    pub(crate) db_connect_backoff: Duration,
+    pub shutdown_timeout: Duration,
+    pub(crate) request_timeout: Duration,
+    pub(crate) health_timeout: Duration,
+    pub(crate) compression_min_bytes: u16,
+    pub idempotency_ttl: Duration,
+    pub(crate) import_max_rows: usize,
+    /// Largest request body most routes read, in bytes.
+    pub(crate) body_limit: usize,
+    /// The same for the batch and import routes.
+    pub(crate) bulk_body_limit: usize,
+    /// `None` turns the `GET /orders` cache off.
+    pub(crate) list_cache_ttl: Option<Duration>,
+    pub(crate) list_cache_max_entries: usize,
+    pub(crate) price_tolerance: PriceTolerance,
+    pub(crate) page_limits: PageLimits,
+    pub run_migrations: bool,
+    pub(crate) docs_enabled: bool,
+    /// Serves the GraphQL playground at `GET /graphql`.
+    pub(crate) graphql_playground: bool,
+    /// Mounts `/dev/*`, which can wipe the database.
+    pub(crate) dev_routes: bool,
+    pub(crate) cors_origins: CorsOrigins,
+    /// `RUST_LOG`, which `main` reads itself before the config exists.
+    pub(crate) log_filter: String,
+    /// Refuses writes until turned off again with a reload.
+    pub(crate) maintenance_mode: bool,
+    pub(crate) write_rate_limit: RateLimit,
+    pub(crate) read_rate_limit: RateLimit,
+    pub(crate) api_keys: Vec<ApiKey>,
+    pub(crate) jwt: Option<JwtVerifier>,
+    pub(crate) auth_disabled: bool,
+    pub(crate) webhook_urls: Vec<reqwest::Url>,
+    pub(crate) webhook_secret: Option<String>,
+    pub(crate) webhook_timeout: Duration,
+    /// `None` sends no receipt emails.
+    pub(crate) mail: Option<MailSettings>,
+    /// `None` posts nothing to Slack.
+    pub(crate) slack: Option<SlackSettings>,
+    /// `None` keeps the export job from starting and refuses manual runs.
+    pub(crate) export: Option<ExportSettings>,
+    /// `None` keeps the order worker from starting.
+    pub(crate) worker_interval: Option<Duration>,
+    pub(crate) worker: WorkerSettings,
+    /// `None` keeps the retention job from starting.
+    pub(crate) retention: Option<RetentionSettings>,
+}
+
+#[derive(Clone)]
+pub(crate) struct ApiKey {
+    pub(crate) client: String,
+    pub(crate) key: String,
+    pub(crate) role: Role,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct RateLimit {
+    pub(crate) per_second: f64,
+    pub(crate) burst: f64,
+}
+
+#[derive(Clone, PartialEq)]
+pub(crate) enum CorsOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+impl Config {
+    /// Reads every setting from the environment, reporting all missing or
+    /// invalid variables at once rather than stopping at the first.
+    pub fn from_env() -> Result<Config, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let database_url = env::var("DATABASE_URL").unwrap_or_default();
+        if database_url.is_empty() {
+            errors.push("DATABASE_URL is empty or not provided".to_owned());
+        }
+
+        let environment = env::var("ENVIRONMENT").unwrap_or_else(|_| "development".to_owned());
+        let cors_origins = cors_origins_from_env(&environment, &mut errors);
+        let api_keys = api_keys_from_env(&mut errors);
+        let jwt = jwt_from_env(&mut errors);
+        let auth_disabled: bool = env_or("AUTH_DISABLED", false, &mut errors);
+        if auth_disabled && environment != "development" {
+            errors.push("AUTH_DISABLED is only allowed in development".to_owned());
+        }
+        if !auth_disabled && api_keys.is_empty() && jwt.is_none() {
+            errors.push("configure API_KEYS or a JWT key, or set AUTH_DISABLED=true in development".to_owned());
+        }
+
+        let config = Config {
+            environment: environment.clone(),
+            host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_owned()),
+            port: env_or("PORT", 3000, &mut errors),
+            bind_addrs: bind_addrs_from_env(&mut errors),
+            grpc_port: env_or("GRPC_PORT", 50051, &mut errors),
+            tls: tls_from_env(&mut errors),
+            unix_socket: env::var("LISTEN_UNIX_SOCKET").ok().filter(|v| !v.is_empty()).map(PathBuf::from),
+            unix_socket_mode: unix_socket_mode_from_env(&mut errors),
+            database_url,
+            database_read_url: env::var("DATABASE_READ_URL").ok().filter(|v| !v.is_empty()),
+            db_max_connections: env_or("DB_MAX_CONNECTIONS", 16, &mut errors),
+            db_min_connections: env_or("DB_MIN_CONNECTIONS", 0, &mut errors),
+            db_acquire_timeout: Duration::from_secs(env_or("DB_ACQUIRE_TIMEOUT_SECS", 30, &mut errors)),
+            // zero turns the idle timeout or the lifetime off
+            db_idle_timeout: Some(Duration::from_secs(env_or("DB_IDLE_TIMEOUT_SECS", 600, &mut errors)))
+                .filter(|timeout| !timeout.is_zero()),
+            db_max_lifetime: Some(Duration::from_secs(env_or("DB_MAX_LIFETIME_SECS", 1800, &mut errors)))
+                .filter(|lifetime| !lifetime.is_zero()),
+            db_connect_retries: env_or("DB_CONNECT_RETRIES", 10, &mut errors),
+            db_connect_backoff: Duration::from_millis(env_or("DB_CONNECT_BACKOFF_MS", 500, &mut errors)),
+            shutdown_timeout: Duration::from_secs(env_or("SHUTDOWN_TIMEOUT_SECS", 20, &mut errors)),
+            request_timeout: Duration::from_secs(env_or("REQUEST_TIMEOUT_SECS", 10, &mut errors)),
+            health_timeout: Duration::from_secs(env_or("HEALTH_TIMEOUT_SECS", 3, &mut errors)),
+            compression_min_bytes: env_or("COMPRESSION_MIN_BYTES", 1024, &mut errors),
+            idempotency_ttl: Duration::from_secs(env_or("IDEMPOTENCY_TTL_HOURS", 24, &mut errors) * 3600),
+            import_max_rows: env_or("IMPORT_MAX_ROWS", 10_000, &mut errors),
+            body_limit: env_or("MAX_BODY_BYTES", 64 * 1024, &mut errors),
+            bulk_body_limit: env_or("MAX_BULK_BODY_BYTES", 10 * 1024 * 1024, &mut errors),
+            // zero turns the cache off
+            list_cache_ttl: Some(Duration::from_millis(env_or("ORDER_LIST_CACHE_TTL_MS", 2000, &mut errors)))
+                .filter(|ttl| !ttl.is_zero()),
+            list_cache_max_entries: env_or("ORDER_LIST_CACHE_MAX_ENTRIES", 256, &mut errors),
+            price_tolerance: PriceTolerance(env_or("PRICE_TOLERANCE_PERCENT", Decimal::ZERO, &mut errors)),
+            page_limits: PageLimits {
+                default: env_or("PAGE_SIZE_DEFAULT", DEFAULT_PAGE_SIZE, &mut errors),
+                max: env_or("PAGE_SIZE_MAX", MAX_PAGE_SIZE, &mut errors),
+                clamp: env_or("PAGE_SIZE_CLAMP", false, &mut errors),
+            },
+            run_migrations: env_or("RUN_MIGRATIONS", true, &mut errors),
+            docs_enabled: env_or("DOCS_ENABLED", environment == "development", &mut errors),
+            graphql_playground: env_or("GRAPHQL_PLAYGROUND", environment == "development", &mut errors),
+            dev_routes: env_or("ENABLE_DEV_ROUTES", environment == "development", &mut errors),
+            cors_origins,
+            log_filter: env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_owned()),
+            maintenance_mode: env_or("MAINTENANCE_MODE", false, &mut errors),
+            api_keys,
+            jwt,
+            auth_disabled,
+            webhook_urls: webhook_urls_from_env(&mut errors),
+            webhook_secret: env::var("WEBHOOK_SECRET").ok().filter(|v| !v.is_empty()),
+            webhook_timeout: Duration::from_secs(env_or("WEBHOOK_TIMEOUT_SECS", 5, &mut errors)),
+            mail: mail_from_env(&mut errors),
+            slack: slack_from_env(&mut errors),
+            export: export_from_env(&mut errors),
+            // zero turns the worker, or either of its steps, off
+            worker_interval: Some(Duration::from_secs(env_or("WORKER_INTERVAL_SECS", 30, &mut errors)))
+                .filter(|interval| !interval.is_zero()),
+            worker: WorkerSettings {
+                accept_after: Some(Duration::from_secs(env_or("AUTO_ACCEPT_AFTER_SECS", 60, &mut errors)))
+                    .filter(|age| !age.is_zero()),
+                stale_after: Some(Duration::from_secs(env_or("STALE_PENDING_AFTER_SECS", 900, &mut errors)))
+                    .filter(|age| !age.is_zero()),
+            },
+            // unset, or zero, keeps every order
+            retention: Some(env_or("ORDER_RETENTION_DAYS", 0, &mut errors))
+                .filter(|days| *days != 0)
+                .map(|after_days| RetentionSettings {
+                    after_days,
+                    archive: env_or("ORDER_RETENTION_ARCHIVE", true, &mut errors),
+                }),
+            write_rate_limit: RateLimit {
+                per_second: env_or("RATE_LIMIT_WRITE_PER_SEC", 5.0, &mut errors),
+                burst: env_or("RATE_LIMIT_WRITE_BURST", 20.0, &mut errors),
+            },
+            read_rate_limit: RateLimit {
+                per_second: env_or("RATE_LIMIT_READ_PER_SEC", 100.0, &mut errors),
+                burst: env_or("RATE_LIMIT_READ_BURST", 200.0, &mut errors),
+            },
+        };
+
+        if config.dev_routes && config.environment == "production" {
+            errors.push("ENABLE_DEV_ROUTES is not allowed in production".to_owned());
+        }
+
+        if config.retention.is_some_and(|retention| retention.after_days < 0) {
+            errors.push("ORDER_RETENTION_DAYS must not be negative".to_owned());
+        }
+
+        if EnvFilter::try_new(&config.log_filter).is_err() {
+            errors.push(format!("RUST_LOG has an invalid filter '{}'", config.log_filter));
+        }
+
+        if config.unix_socket.is_some() && cfg!(not(unix)) {
+            errors.push("LISTEN_UNIX_SOCKET needs a unix platform".to_owned());
+        }
+
+        if config.grpc_port == config.port {
+            errors.push("GRPC_PORT must differ from PORT".to_owned());
+        }
+
+        for (name, limit) in [("WRITE", config.write_rate_limit), ("READ", config.read_rate_limit)] {
+            if !(limit.per_second > 0.0 && limit.per_second.is_finite()) {
+                errors.push(format!("RATE_LIMIT_{name}_PER_SEC must be a positive number"));
+            }
+            if !(limit.burst >= 1.0 && limit.burst.is_finite()) {
+                errors.push(format!("RATE_LIMIT_{name}_BURST must be at least 1"));
+            }
+        }
+
+        if config.request_timeout.is_zero() {
+            errors.push("REQUEST_TIMEOUT_SECS must be at least 1".to_owned());
+        }
+
+        if config.idempotency_ttl.is_zero() {
+            errors.push("IDEMPOTENCY_TTL_HOURS must be at least 1".to_owned());
+        }
+
+        if config.page_limits.max < 1 {
+            errors.push("PAGE_SIZE_MAX must be at least 1".to_owned());
+        }
+
+        if !(1..=config.page_limits.max).contains(&config.page_limits.default) {
+            errors.push("PAGE_SIZE_DEFAULT must be between 1 and PAGE_SIZE_MAX".to_owned());
+        }
+
+        if config.body_limit == 0 {
+            errors.push("MAX_BODY_BYTES must be at least 1".to_owned());
+        }
+
+        if config.bulk_body_limit < config.body_limit {
+            errors.push("MAX_BULK_BODY_BYTES must not be less than MAX_BODY_BYTES".to_owned());
+        }
+
+        if config.webhook_timeout.is_zero() {
+            errors.push("WEBHOOK_TIMEOUT_SECS must be at least 1".to_owned());
+        }
+
+        if config.health_timeout.is_zero() {
+            errors.push("HEALTH_TIMEOUT_SECS must be at least 1".to_owned());
+        }
+
+        if !(Decimal::ZERO..=Decimal::ONE_HUNDRED).contains(&config.price_tolerance.0) {
+            errors.push("PRICE_TOLERANCE_PERCENT must be between 0 and 100".to_owned());
+        }
+
+        if config.db_max_connections == 0 {
+            errors.push("DB_MAX_CONNECTIONS must be at least 1".to_owned());
+        }
+
+        if config.db_min_connections > config.db_max_connections {
+            errors.push("DB_MIN_CONNECTIONS must not exceed DB_MAX_CONNECTIONS".to_owned());
+        }
+
+        if config.db_acquire_timeout.is_zero() {
+            errors.push("DB_ACQUIRE_TIMEOUT_SECS must be at least 1".to_owned());
+        }
+
+        if config.db_connect_backoff.is_zero() {
+            errors.push("DB_CONNECT_BACKOFF_MS must be at least 1".to_owned());
+        }
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Parses an optional environment variable, falling back to `default` when it
+/// is unset and recording an error when it is set but invalid.
+pub(crate) fn env_or<T: FromStr>(name: &str, default: T, errors: &mut Vec<String>) -> T {
+    match env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            errors.push(format!("{name} has an invalid value '{value}'"));
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+// `ip:port` entries like `0.0.0.0:3000,[::]:3000`, which replace HOST and PORT
+pub(crate) fn bind_addrs_from_env(errors: &mut Vec<String>) -> Vec<SocketAddr> {
+    let raw = env::var("BIND_ADDRS").unwrap_or_default();
+    let mut addrs = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match entry.parse::<SocketAddr>() {
+            Ok(addr) if addrs.contains(&addr) => errors.push(format!("BIND_ADDRS lists '{entry}' twice")),
+            Ok(addr) => addrs.push(addr),
+            Err(_) => errors.push(format!("BIND_ADDRS has an invalid address '{entry}', expected ip:port such as [::]:3000")),
+        }
+    }
+    addrs
+}
+
+// octal like chmod, so 660 and 0660 are the same
+pub(crate) fn unix_socket_mode_from_env(errors: &mut Vec<String>) -> u32 {
+    let Ok(raw) = env::var("LISTEN_UNIX_SOCKET_MODE") else {
+        return 0o660;
+    };
+    match u32::from_str_radix(raw.trim().trim_start_matches("0o"), 8) {
+        Ok(mode) if mode <= 0o777 => mode,
+        _ => {
+            errors.push(format!("LISTEN_UNIX_SOCKET_MODE has an invalid value '{raw}', expected octal such as 660"));
+            0o660
+        }
+    }
+}
+
+// development falls back to any origin, everywhere else a wildcard must be opted into
+pub(crate) fn cors_origins_from_env(environment: &str, errors: &mut Vec<String>) -> CorsOrigins {
+    let allow_wildcard: bool = env_or("CORS_ALLOW_WILDCARD", false, errors);
+    let raw = match env::var("CORS_ALLOWED_ORIGINS") {
+        Ok(raw) => raw,
+        Err(_) if environment == "development" => return CorsOrigins::Any,
+        Err(_) => return CorsOrigins::List(Vec::new()),
+    };
+
+    let mut origins = Vec::new();
+    for origin in raw.split(',').map(str::trim).filter(|origin| !origin.is_empty()) {
+        if origin == "*" {
+            if environment != "development" && !allow_wildcard {
+                errors.push("CORS_ALLOWED_ORIGINS may only be '*' when CORS_ALLOW_WILDCARD is true".to_owned());
+            }
+            return CorsOrigins::Any;
+        }
+        match HeaderValue::from_str(origin) {
+            Ok(value) => origins.push(value),
+            Err(_) => errors.push(format!("CORS_ALLOWED_ORIGINS has an invalid origin '{origin}'")),
+        }
+    }
+    CorsOrigins::List(origins)
+}
+
+// entries are `client:key` or `client:key:role`; a bare key is named after its position.
+// keys default to the barista role, admins must be listed explicitly
+pub(crate) fn api_keys_from_env(errors: &mut Vec<String>) -> Vec<ApiKey> {
+    let raw = env::var("API_KEYS").unwrap_or_default();
+    let mut keys = Vec::new();
+    for (index, entry) in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()).enumerate() {
+        let mut parts = entry.splitn(3, ':').map(str::trim);
+        let (client, key, role) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(key), None, _) => (format!("key-{}", index + 1), key.to_owned(), None),
+            (Some(client), Some(key), role) => (client.to_owned(), key.to_owned(), role),
+            (None, _, _) => continue,
+        };
+        if client.is_empty() || key.is_empty() {
+            errors.push(format!("API_KEYS entry {} is missing a client name or key", index + 1));
+            continue;
+        }
+        let role = match role.map(Role::from_str).transpose() {
+            Ok(role) => role.unwrap_or(Role::Barista),
+            Err(err) => {
+                errors.push(format!("API_KEYS entry {}: {err}", index + 1));
+                continue;
+            }
+        };
+        keys.push(ApiKey { client, key, role });
+    }
+    keys
+}
+
+pub(crate) fn webhook_urls_from_env(errors: &mut Vec<String>) -> Vec<reqwest::Url> {
+    let raw = env::var("WEBHOOK_URLS").unwrap_or_default();
+    let mut urls = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        match reqwest::Url::parse(entry) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => urls.push(url),
+            _ => errors.push(format!("WEBHOOK_URLS has an invalid url '{entry}'")),
+        }
+    }
+    urls
+}
+
+// exactly one of JWT_SECRET (HS256), JWT_PUBLIC_KEY_PATH or JWT_JWKS_PATH (RS256) picks the key source
+pub(crate) fn jwt_from_env(errors: &mut Vec<String>) -> Option<JwtVerifier> {
+    let secret = env::var("JWT_SECRET").ok().filter(|v| !v.is_empty());
+    let pem_path = env::var("JWT_PUBLIC_KEY_PATH").ok().filter(|v| !v.is_empty());
+    let jwks_path = env::var("JWT_JWKS_PATH").ok().filter(|v| !v.is_empty());
+
+    let (algorithm, keys) = match (secret, pem_path, jwks_path) {
+        (None, None, None) => return None,
+        (Some(secret), None, None) => (
+            Algorithm::HS256,
+            JwtKeys::Single(DecodingKey::from_secret(secret.as_bytes())),
+        ),
+        (None, Some(path), None) => {
+            let key = std::fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|pem| DecodingKey::from_rsa_pem(&pem).map_err(|err| err.to_string()));
+            match key {
+                Ok(key) => (Algorithm::RS256, JwtKeys::Single(key)),
+                Err(err) => {
+                    errors.push(format!("JWT_PUBLIC_KEY_PATH '{path}' could not be loaded: {err}"));
+                    return None;
+                }
+            }
+        }
+        (None, None, Some(path)) => {
+            let keys = std::fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|json| serde_json::from_slice::<JwkSet>(&json).map_err(|err| err.to_string()))
+                .and_then(|set| {
+                    set.keys
+                        .iter()
+                        .map(|jwk| Ok((jwk.common.key_id.clone(), DecodingKey::from_jwk(jwk)?)))
+                        .collect::<Result<Vec<_>, jsonwebtoken::errors::Error>>()
+                        .map_err(|err| err.to_string())
+                });
+            match keys {
+                Ok(keys) if !keys.is_empty() => (Algorithm::RS256, JwtKeys::Jwks(keys)),
+                Ok(_) => {
+                    errors.push(format!("JWT_JWKS_PATH '{path}' contains no keys"));
+                    return None;
+                }
+                Err(err) => {
+                    errors.push(format!("JWT_JWKS_PATH '{path}' could not be loaded: {err}"));
+                    return None;
+                }
+            }
+        }
+        _ => {
+            errors.push("set only one of JWT_SECRET, JWT_PUBLIC_KEY_PATH and JWT_JWKS_PATH".to_owned());
+            return None;
+        }
+    };
+
+    let mut validation = Validation::new(algorithm);
+    validation.leeway = env_or("JWT_LEEWAY_SECS", 30, errors);
+    match env::var("JWT_ISSUER") {
+        Ok(issuer) if !issuer.is_empty() => validation.set_issuer(&[issuer]),
+        _ => {}
+    }
+    match env::var("JWT_AUDIENCE") {
+        Ok(audience) if !audience.is_empty() => validation.set_audience(&[audience]),
+        _ => validation.validate_aud = false,
+    }
+
+    Some(JwtVerifier { keys, validation })
+}
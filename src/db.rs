@@ -0,0 +1,150 @@
+//! Connection pool and schema migrations.
+use std::collections::HashSet;
+use std::str::FromStr;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::{postgres::{PgConnectOptions, PgPoolOptions}, ConnectOptions, Connection, PgPool, Postgres, Transaction};
+
+use crate::errors::ApiError;
+use crate::Config;
+
+/// Opens the pool, retrying up to `DB_CONNECT_RETRIES` times so the API can
+/// start before Postgres accepts connections. The wait between attempts
+/// starts at `DB_CONNECT_BACKOFF_MS` and doubles up to eight times that.
+pub async fn connect_pool(config: &Config) -> Result<PgPool, sqlx::Error> {
+    connect(&config.database_url, config).await
+}
+
+/// Opens the `DATABASE_READ_URL` pool, sized and retried like the primary,
+/// or `None` when there is no replica and reads use the primary.
+pub async fn connect_read_pool(config: &Config) -> Result<Option<PgPool>, sqlx::Error> {
+    let Some(url) = &config.database_read_url else {
+        return Ok(None);
+    };
+    tracing::info!("reads go to DATABASE_READ_URL");
+    connect(url, config).await.map(Some)
+}
+
+async fn connect(url: &str, config: &Config) -> Result<PgPool, sqlx::Error> {
+    // dropping a query future does not stop the statement server side, so
+    // postgres enforces the same budget as the request timeout
+    let options = PgConnectOptions::from_str(url)?
+        .options([("statement_timeout", format!("{}ms", config.request_timeout.as_millis()))]);
+
+    tracing::info!(
+        max_connections = config.db_max_connections,
+        min_connections = config.db_min_connections,
+        acquire_timeout_secs = config.db_acquire_timeout.as_secs(),
+        idle_timeout_secs = config.db_idle_timeout.map(|timeout| timeout.as_secs()),
+        max_lifetime_secs = config.db_max_lifetime.map(|lifetime| lifetime.as_secs()),
+        "database pool configured"
+    );
+
+    // the pool itself keeps retrying until the acquire timeout, so attempts
+    // are single connections that fail fast and the pool opens after one succeeds
+    let mut backoff = config.db_connect_backoff;
+    let mut attempt = 0;
+    loop {
+        match options.connect().await {
+            Ok(conn) => {
+                conn.close().await.ok();
+                break;
+            }
+            // a bad DATABASE_URL will not fix itself
+            Err(err @ sqlx::Error::Configuration(_)) => return Err(err),
+            Err(err) if attempt == config.db_connect_retries => return Err(err),
+            Err(err) => {
+                attempt += 1;
+                tracing::warn!(
+                    error = %err,
+                    "database connection failed, retry {attempt} of {} in {}ms",
+                    config.db_connect_retries,
+                    backoff.as_millis()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(config.db_connect_backoff * 8);
+            }
+        }
+    }
+
+    PgPoolOptions::new()
+        .max_connections(config.db_max_connections)
+        .min_connections(config.db_min_connections)
+        .acquire_timeout(config.db_acquire_timeout)
+        .idle_timeout(config.db_idle_timeout)
+        .max_lifetime(config.db_max_lifetime)
+        .connect_with(options)
+        .await
+}
+
+tokio::task_local! {
+    // set per request by `read_from`, so the repository needs no extra argument
+    pub(crate) static READ_FROM_PRIMARY: bool;
+}
+
+/// The pool a read-only query runs on: the replica when there is one,
+/// unless the request asked for the primary to read its own writes.
+pub(crate) fn read_pool<'a>(primary: &'a PgPool, replica: Option<&'a PgPool>) -> &'a PgPool {
+    match replica {
+        Some(replica) if !reading_from_primary() => replica,
+        _ => primary,
+    }
+}
+
+pub(crate) fn reading_from_primary() -> bool {
+    READ_FROM_PRIMARY.try_with(|primary| *primary).unwrap_or(false)
+}
+
+/// `State<ReadPool>` in a handler that only reads; see `read_pool`.
+pub(crate) struct ReadPool(pub(crate) PgPool);
+
+/// Commits `tx` when the write path that ran on it succeeded and rolls it
+/// back when it failed. Rolling back here rather than on drop means a
+/// rollback that fails too is reported, wrapped around the original error.
+pub(crate) async fn finish<T>(tx: Transaction<'_, Postgres>, result: Result<T, ApiError>) -> Result<T, ApiError> {
+    match result {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(error) => match tx.rollback().await {
+            Ok(()) => Err(error),
+            Err(rollback) => Err(ApiError::RollbackFailed { error: Box::new(error), rollback }),
+        },
+    }
+}
+
+pub(crate) static MIGRATOR: Migrator = sqlx::migrate!();
+
+/// Applies pending migrations from `migrations/` and logs each one applied.
+pub async fn run_migrations(db: &PgPool) -> Result<(), MigrateError> {
+    let mut conn = db.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    let applied: HashSet<i64> = conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+
+    // migrations may legitimately outlast the per-request statement_timeout;
+    // RESET restores the pool's connect-time value before the connection is reused
+    sqlx::query("SET statement_timeout = 0").execute(&mut *conn).await?;
+    let result = MIGRATOR.run(&mut *conn).await;
+    sqlx::query("RESET statement_timeout").execute(&mut *conn).await?;
+    drop(conn);
+    result?;
+
+    let mut pending = MIGRATOR
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .peekable();
+
+    if pending.peek().is_none() {
+        tracing::info!("database schema is up to date");
+    }
+    for migration in pending {
+        tracing::info!("applied migration {} {}", migration.version, migration.description);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! The OpenAPI document, Swagger UI and the banner.
+use axum::Json;
+use axum::response::Html;
+use utoipa::{
+  openapi::{schema::{AllOfBuilder, ArrayBuilder, KnownFormat, ObjectBuilder, Ref, Schema, SchemaFormat, SchemaType}, security, RefOr}, Modify, OpenApi,
+};
+
+use crate::errors::{FieldError, FieldErrorCode};
+use crate::handlers::{audit, csv, customers, exports, inventory, menu, orders, outbox, probes, reports, stats};
+use crate::{feed, metrics};
+use crate::handlers::audit::OrderEvent;
+use crate::handlers::csv::{ImportReport, ImportRowError};
+use crate::handlers::customers::{CreateCustomerReq, Customer, UpdateCustomerReq};
+use crate::handlers::exports::{ExportRun, StartedExports};
+use crate::handlers::inventory::{InventoryItem, UpdateInventoryReq};
+use crate::handlers::menu::MenuItem;
+use crate::handlers::orders::{
+    CancelOrderReq, CreateOrdersReq, CreateOrdersRow, DeleteOrdersReq, DeleteOrdersRow, OrderCount,
+    OrderItemReq, SkippedOrder, TagCount, UpdateOrderStatusReq, UpdateOrdersReq, UpdateStatusesReq,
+    UpdateStatusesRow,
+};
+use crate::handlers::outbox::OutboxEvent;
+use crate::handlers::probes::{BuildInfo, HealthResponse, PoolStats};
+use crate::handlers::reports::RevenueRow;
+use crate::handlers::stats::{OrderStats, StatsBucket};
+use crate::models::{
+    CreatedOrdersResponse, CustomerListResponse, CustomerResponse, DeleteOrdersResponse,
+    ExportRunsResponse, ImportResponse, InventoryListResponse, InventoryResponse, MenuResponse, MessageResponse, Money,
+    OrderCountResponse, OrderDetail, OrderEventsResponse, OrderItem, OrderListResponse,
+    OrderResponse, OrderStatsResponse, Orders, OutboxEventResponse, OutboxEventsResponse, PageMeta,
+    RevenueReportResponse, Size, StartedExportsResponse, TagCountsResponse, UpdateStatusesResponse, VersionResponse,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "rust-orders", description = "Coffee order service"),
+    // relative to the spec's own URL, so "try it out" follows any proxy prefix
+    servers((url = "..")),
+    paths(
+        banner, probes::health, probes::livez, probes::readyz, probes::version, metrics::metrics,
+        orders::get_orders, orders::get_order_count, orders::get_order_tags, orders::add_order, orders::delete_orders,
+        orders::add_orders_batch, csv::export_orders_csv, csv::import_orders_csv,
+        feed::stream_orders, feed::order_socket, stats::get_order_stats,
+        reports::revenue_report,
+        orders::get_order, orders::update_order, orders::patch_order, orders::delete_order,
+        orders::update_order_status, orders::update_order_statuses, orders::cancel_order, orders::restore_order,
+        audit::get_order_events,
+        menu::get_menu, inventory::get_inventory, inventory::update_inventory,
+        customers::get_customers, customers::add_customer, customers::get_customer,
+        customers::update_customer, customers::delete_customer, customers::get_customer_orders,
+        outbox::get_outbox_events, outbox::retry_outbox_event,
+        exports::run_exports, exports::get_exports,
+    ),
+    components(schemas(
+        Orders, OrderDetail, OrderItem, OrderItemReq, CreateOrdersReq, CreateOrdersRow, UpdateOrdersReq,
+        UpdateOrderStatusReq, UpdateStatusesReq, UpdateStatusesRow, SkippedOrder, CancelOrderReq,
+        DeleteOrdersReq, DeleteOrdersRow, OrderCount, TagCount, OrderEvent, FieldError, FieldErrorCode, PageMeta,
+        Customer, CreateCustomerReq, UpdateCustomerReq, MenuItem, InventoryItem, UpdateInventoryReq,
+        Money, Size, ImportReport, ImportRowError, OrderStats, StatsBucket, RevenueRow, HealthResponse, PoolStats, BuildInfo,
+        OrderResponse, OrderListResponse, OrderCountResponse, TagCountsResponse, CreatedOrdersResponse,
+        DeleteOrdersResponse, UpdateStatusesResponse, OrderEventsResponse, ImportResponse, OrderStatsResponse,
+        RevenueReportResponse,
+        CustomerResponse, CustomerListResponse, MenuResponse, InventoryResponse, InventoryListResponse,
+        OutboxEvent, OutboxEventResponse, OutboxEventsResponse,
+        ExportRun, StartedExports, StartedExportsResponse, ExportRunsResponse,
+        VersionResponse, MessageResponse,
+    )),
+    modifiers(&EnvelopeSchemas, &SecuritySchemes),
+    tags(
+        (name = "orders", description = "Order CRUD, import/export and audit history"),
+        (name = "customers", description = "Customers and the orders linked to them"),
+        (name = "reports", description = "Aggregated sales reports"),
+        (name = "admin", description = "Webhook events waiting in or stuck in the outbox, and order exports"),
+        (name = "probes", description = "Health, liveness, readiness and the running build, never authenticated"),
+    ),
+)]
+pub(crate) struct ApiDoc;
+
+/// `ErrorResponse` and `CursorResponse` flatten a generic `Response<T>`, which
+/// utoipa cannot resolve per alias, so their schemas are composed here from
+/// the concrete `Response` aliases instead of derived.
+pub(crate) struct EnvelopeSchemas;
+
+impl Modify for EnvelopeSchemas {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let extend = |base: &str, field: &str, field_schema: ObjectBuilder| {
+            AllOfBuilder::new()
+                .item(Ref::from_schema_name(base))
+                .item(ObjectBuilder::new().property(field, field_schema.nullable(true)))
+                .into()
+        };
+
+        // `data` is always null on message-only responses; typing it keeps the
+        // schema readable by tools that refuse a schema without a type
+        if let Some(RefOr::T(Schema::Object(message))) = components.schemas.get_mut("MessageResponse") {
+            message.properties.insert(
+                "data".to_owned(),
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::Object)
+                    .nullable(true)
+                    .description(Some("Always null."))
+                    .into(),
+            );
+        }
+
+        // status is always false and request_id matches the X-Request-Id header
+        let request_id = || {
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .example(Some(serde_json::json!("4f9c1d2e-8a43-4b8e-9d5f-2f0c6a7b1e3d")))
+        };
+        for (name, base) in [("ErrorBody", "MessageResponse"), ("ImportErrorBody", "ImportResponse")] {
+            components.schemas.insert(name.to_owned(), extend(base, "request_id", request_id()));
+        }
+        // validation failures list every bad field under `errors`, with data null
+        components.schemas.insert(
+            "ValidationErrorBody".to_owned(),
+            AllOfBuilder::new()
+                .item(Ref::from_schema_name("MessageResponse"))
+                .item(
+                    ObjectBuilder::new()
+                        .property("errors", ArrayBuilder::new().items(Ref::from_schema_name("FieldError")))
+                        .required("errors")
+                        .property("request_id", request_id().nullable(true)),
+                )
+                .into(),
+        );
+        components.schemas.insert(
+            "OrderCursorResponse".to_owned(),
+            extend(
+                "OrderListResponse",
+                "next_cursor",
+                ObjectBuilder::new().schema_type(SchemaType::Integer).format(Some(SchemaFormat::KnownFormat(KnownFormat::Int32))),
+            ),
+        );
+    }
+}
+
+/// Both credential kinds `authenticate` accepts; either one satisfies an
+/// endpoint's `security` requirement.
+pub(crate) struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            security::SecurityScheme::ApiKey(security::ApiKey::Header(security::ApiKeyValue::new("x-api-key"))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            security::SecurityScheme::Http(
+                security::HttpBuilder::new().scheme(security::HttpAuthScheme::Bearer).bearer_format("JWT").build(),
+            ),
+        );
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "probes",
+    responses((status = 200, description = "Banner", content_type = "text/plain", body = String)),
+)]
+pub(crate) async fn banner() -> &'static str {
+    "MAY THE FORCE BE WITH YOU"
+}
+
+/// Swagger UI from the CDN, pointed at the spec with a relative URL so it keeps
+/// working when a reverse proxy mounts the service under a path prefix.
+pub(crate) const SWAGGER_UI_HTML: &str = r##"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>rust-orders API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "api-docs/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+pub(crate) async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI_HTML)
+}
+
+/// The spec is generated from the handler annotations, so it cannot drift
+/// from the routes registered in `build_router`.
+pub(crate) async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
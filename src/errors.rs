@@ -0,0 +1,361 @@
+//! `ApiError` and the JSON error bodies it renders.
+use std::time::Duration;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{
+  async_trait,
+  body::Bytes,
+  extract::{rejection::JsonRejection, FromRequest, Request},
+  http::{header::RETRY_AFTER, StatusCode, Uri},
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use utoipa::ToSchema;
+
+use crate::middleware::current_request_id;
+use crate::models::Response;
+use crate::negotiate::is_msgpack;
+
+/// `Json` extractor whose rejections use the `Response` envelope instead of
+/// axum's plain-text bodies. A body sent as `application/msgpack` is decoded
+/// into the same type instead.
+pub(crate) struct JsonBody<T>(pub(crate) T);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if is_msgpack(req.headers()) {
+            let body = Bytes::from_request(req, state).await.map_err(|rejection| {
+                if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                    ApiError::body_too_large()
+                } else {
+                    ApiError::BadRequest(rejection.body_text())
+                }
+            })?;
+            return rmp_serde::from_slice(&body)
+                .map(JsonBody)
+                .map_err(|err| ApiError::BadRequest(format!("invalid MessagePack body: {err}")));
+        }
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        Ok(JsonBody(value))
+    }
+}
+
+/// `JsonBody` for a body the client may leave out: an empty or blank body
+/// is `None`, anything else is decoded and rejected as `JsonBody` would.
+pub(crate) struct OptionalJsonBody<T>(pub(crate) Option<T>);
+
+#[async_trait]
+impl<S, T> FromRequest<S> for OptionalJsonBody<T>
+where
+    T: DeserializeOwned,
+    Json<T>: FromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let (parts, body) = req.into_parts();
+        let body = Bytes::from_request(Request::from_parts(parts.clone(), body), state)
+            .await
+            .map_err(|rejection| {
+                if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+                    ApiError::body_too_large()
+                } else {
+                    ApiError::BadRequest(rejection.body_text())
+                }
+            })?;
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(OptionalJsonBody(None));
+        }
+        let JsonBody(value) = JsonBody::from_request(Request::from_parts(parts, body.into()), state).await?;
+        Ok(OptionalJsonBody(Some(value)))
+    }
+}
+
+
+/// Every way a handler can fail. The variant decides the status code and the
+/// envelope, so handlers can use `?` and never build error responses.
+pub(crate) enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    MethodNotAllowed(String),
+    /// None of the media types the `Accept` header allows, which are these.
+    NotAcceptable(Vec<&'static str>),
+    Validation(Vec<FieldError>),
+    Unprocessable(String),
+    /// The request clashes with the resource's current state, e.g. a status
+    /// it cannot move from or stock that has run out.
+    Conflict(String),
+    /// The write would duplicate a record that a unique rule protects.
+    AlreadyExists(String),
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    UnsupportedMediaType(String),
+    /// Carries how long the client should wait before retrying.
+    TooManyRequests(Duration),
+    /// The handler or its statement ran past the request timeout.
+    Timeout,
+    /// No pooled connection came free within the acquire timeout.
+    DatabaseBusy,
+    /// `MAINTENANCE_MODE` is on, which pauses writes.
+    Maintenance,
+    JsonRejection(JsonRejection),
+    Database(sqlx::Error),
+    /// A write path failed with `error` and rolling its transaction back
+    /// failed as well; the client sees `error`.
+    RollbackFailed { error: Box<ApiError>, rollback: sqlx::Error },
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, message) = match self {
+            ApiError::Validation(errors) => {
+                let error_response: Response<()> = Response {
+                    status: false,
+                    message: "validation failed".to_owned(),
+                    data: None,
+                    meta: None,
+                };
+                let body = ErrorResponse { errors: Some(errors), ..ErrorResponse::new(error_response) };
+                return (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response();
+            }
+            ApiError::NotAcceptable(supported) => {
+                let error_response = Response {
+                    status: false,
+                    message: format!("cannot respond in any accepted type, supported: {}", supported.join(", ")),
+                    data: Some(supported),
+                    meta: None,
+                };
+                return (StatusCode::NOT_ACCEPTABLE, Json(ErrorResponse::new(error_response))).into_response();
+            }
+            ApiError::TooManyRequests(retry_after) => {
+                return retry_later(StatusCode::TOO_MANY_REQUESTS, "too many requests", retry_after);
+            }
+            ApiError::DatabaseBusy => {
+                return retry_later(StatusCode::SERVICE_UNAVAILABLE, "database is busy", DATABASE_BUSY_RETRY_AFTER);
+            }
+            ApiError::Maintenance => {
+                return retry_later(StatusCode::SERVICE_UNAVAILABLE, "in maintenance, writes are paused", MAINTENANCE_RETRY_AFTER);
+            }
+            ApiError::RollbackFailed { error, rollback } => {
+                tracing::error!(error = %rollback, "transaction rollback failed");
+                return error.into_response();
+            }
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
+            ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, message),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiError::MethodNotAllowed(message) => (StatusCode::METHOD_NOT_ALLOWED, message),
+            ApiError::Unprocessable(message) => (StatusCode::UNPROCESSABLE_ENTITY, message),
+            ApiError::Conflict(message) | ApiError::AlreadyExists(message) => (StatusCode::CONFLICT, message),
+            ApiError::PreconditionFailed(message) => (StatusCode::PRECONDITION_FAILED, message),
+            ApiError::PayloadTooLarge(message) => (StatusCode::PAYLOAD_TOO_LARGE, message),
+            ApiError::UnsupportedMediaType(message) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, message),
+            ApiError::Timeout => (StatusCode::SERVICE_UNAVAILABLE, "request timed out".to_owned()),
+            ApiError::JsonRejection(rejection) => (rejection.status(), rejection.body_text()),
+            // database details are logged but never sent to the client
+            ApiError::Database(err) => {
+                tracing::error!(error = %err, "database error");
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_owned())
+            }
+        };
+
+        let error_response: Response<()> = Response {
+            status: false,
+            message,
+            data: None,
+            meta: None,
+        };
+        (status, Json(ErrorResponse::new(error_response))).into_response()
+    }
+}
+
+/// Router fallback, so unknown paths get the JSON envelope rather than an
+/// empty 404.
+pub(crate) async fn route_not_found(uri: Uri) -> ApiError {
+    ApiError::NotFound(format!("route not found: {}", uri.path()))
+}
+
+fn retry_later(status: StatusCode, message: &str, retry_after: Duration) -> axum::response::Response {
+    let error_response: Response<()> = Response {
+        status: false,
+        message: message.to_owned(),
+        data: None,
+        meta: None,
+    };
+    // Retry-After is whole seconds, so round up rather than invite an early retry
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    (
+        status,
+        [(RETRY_AFTER, seconds.max(1).to_string())],
+        Json(ErrorResponse::new(error_response)),
+    ).into_response()
+}
+
+// a busy pool usually frees a connection within the next request or two
+const DATABASE_BUSY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+// maintenance lasts as long as an operator needs, so clients back off for a while
+const MAINTENANCE_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+// sqlstates raised for constraint violations
+pub(crate) const UNIQUE_VIOLATION: &str = "23505";
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+
+// sqlstate raised when postgres cancels a statement, here via statement_timeout
+pub(crate) const QUERY_CANCELED: &str = "57014";
+
+/// Sorts database failures into what the client can act on: a missing row
+/// is a 404, a unique or foreign-key violation a 409 or 422 naming the
+/// constraint, and an exhausted pool a 503 worth retrying. The rest stays a 500.
+impl From<sqlx::Error> for ApiError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::RowNotFound => return ApiError::NotFound("record not found".to_owned()),
+            sqlx::Error::PoolTimedOut => {
+                metrics::counter!("db_pool_acquire_timeouts_total").increment(1);
+                tracing::warn!("timed out waiting for a database connection");
+                return ApiError::DatabaseBusy;
+            }
+            sqlx::Error::Database(db_err) => {
+                let constraint = db_err.constraint().unwrap_or("unknown");
+                match db_err.code().as_deref() {
+                    Some(QUERY_CANCELED) => {
+                        tracing::warn!(error = %err, "statement timed out");
+                        return ApiError::Timeout;
+                    }
+                    Some(UNIQUE_VIOLATION) => {
+                        return ApiError::AlreadyExists(format!("conflicts with an existing record (constraint {constraint})"));
+                    }
+                    Some(FOREIGN_KEY_VIOLATION) => {
+                        return ApiError::Unprocessable(format!("refers to a missing record (constraint {constraint})"));
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        ApiError::Database(err)
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return ApiError::body_too_large();
+        }
+        if let Some(error) = body_field_error(&rejection) {
+            return ApiError::Validation(vec![error]);
+        }
+        ApiError::JsonRejection(rejection)
+    }
+}
+
+/// The field of a well-formed JSON body that did not fit the request type,
+/// such as an unknown size, when serde could tell which one it was. Serde
+/// stops at the first such field, so it is the only one reported.
+fn body_field_error(rejection: &JsonRejection) -> Option<FieldError> {
+    let JsonRejection::JsonDataError(err) = rejection else {
+        return None;
+    };
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    let err = loop {
+        let err = source?;
+        if let Some(err) = err.downcast_ref::<serde_path_to_error::Error<serde_json::Error>>() {
+            break err;
+        }
+        source = err.source();
+    };
+    let field = err.path().to_string();
+    if field == "." {
+        return None;
+    }
+
+    let inner = err.inner();
+    let message = inner.to_string();
+    let position = format!(" at line {} column {}", inner.line(), inner.column());
+    let message = message.strip_suffix(&position).unwrap_or(&message);
+    let code = if message.starts_with("missing field") { FieldErrorCode::Required } else { FieldErrorCode::Invalid };
+    Some(FieldError::new(&field, code, message))
+}
+
+impl ApiError {
+    /// The 413 for a body over the route's `DefaultBodyLimit`.
+    pub(crate) fn body_too_large() -> ApiError {
+        ApiError::PayloadTooLarge("request body is too large".to_owned())
+    }
+}
+
+
+#[derive(Serialize)]
+pub(crate) struct ErrorResponse<T> {
+    #[serde(flatten)]
+    response: Response<T>,
+    /// Every field that failed validation, on 422s from `ApiError::Validation` only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+impl<T> ErrorResponse<T> {
+    pub(crate) fn new(response: Response<T>) -> Self {
+        ErrorResponse { response, errors: None, request_id: current_request_id() }
+    }
+}
+
+/// One field that failed validation. `field` is a path into the body such
+/// as `items[1].size`, or `[2].total` in a batch.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct FieldError {
+    #[schema(example = "total")]
+    pub(crate) field: String,
+    pub(crate) code: FieldErrorCode,
+    #[schema(example = "must be at most 1000.00")]
+    pub(crate) message: String,
+}
+
+impl FieldError {
+    pub(crate) fn new(field: &str, code: FieldErrorCode, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_owned(),
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The same error on a field nested under `prefix`, e.g. `items[0].`.
+    pub(crate) fn prefixed(self, prefix: &str) -> Self {
+        FieldError { field: format!("{prefix}{}", self.field), ..self }
+    }
+}
+
+/// Why a field was refused, stable for clients to branch on; the message
+/// may change wording.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FieldErrorCode {
+    /// Missing, or blank once trimmed.
+    Required,
+    /// Not a value the field takes, such as an unknown size or status.
+    Invalid,
+    TooLong,
+    /// A number, or a count of entries, outside the allowed bounds.
+    OutOfRange,
+    /// Sent together with a field it excludes.
+    Conflict,
+    /// Names a customer or menu item that does not exist.
+    NotFound,
+    /// Not accepted on this endpoint.
+    NotAllowed,
+}
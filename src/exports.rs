@@ -0,0 +1,353 @@
+//! The nightly export, which lands a file of each day's orders in an
+//! S3-compatible bucket. Every run is recorded in `exports`, and a day
+//! with a succeeded run is skipped unless the export is forced.
+use std::env;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{NaiveDate, NaiveTime, TimeDelta, Utc};
+use futures::TryStreamExt;
+use sqlx::PgPool;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::env_or;
+use crate::handlers::csv::{csv_line, order_csv_fields, CSV_COLUMNS};
+use crate::handlers::exports::ExportRun;
+use crate::models::Orders;
+use crate::s3::{s3_from_env, S3Client, S3Settings};
+use crate::Config;
+
+/// How much of a file is held before it goes up as one part. S3 wants
+/// every part but the last to be at least 5 MiB.
+pub(crate) const EXPORT_PART_SIZE: usize = 8 * 1024 * 1024;
+/// Runs still marked running after this long died with their process.
+const EXPORT_STALE_AFTER_HOURS: i32 = 6;
+/// The error recorded for a run that shutdown cut short.
+const EXPORT_INTERRUPTED: &str = "interrupted by shutdown";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            _ => Err(format!("unknown export format '{s}', expected csv or ndjson")),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ExportSettings {
+    s3: S3Settings,
+    /// Put before `2024-05-21.csv` in every object key.
+    prefix: String,
+    format: ExportFormat,
+    /// When, in UTC, the previous day is exported.
+    run_at: NaiveTime,
+}
+
+/// `EXPORT_S3_BUCKET` turns exports on. `EXPORT_S3_PREFIX` (default
+/// `orders/`), `EXPORT_FORMAT` (csv or ndjson) and `EXPORT_RUN_AT`
+/// (HH:MM in UTC, default 01:00) shape them.
+pub(crate) fn export_from_env(errors: &mut Vec<String>) -> Option<ExportSettings> {
+    let s3 = s3_from_env(errors)?;
+    let prefix = env::var("EXPORT_S3_PREFIX").unwrap_or_else(|_| "orders/".to_owned());
+    let format = env_or("EXPORT_FORMAT", ExportFormat::Csv, errors);
+    let run_at = env::var("EXPORT_RUN_AT").unwrap_or_else(|_| "01:00".to_owned());
+    let Ok(run_at) = NaiveTime::parse_from_str(&run_at, "%H:%M") else {
+        errors.push(format!("EXPORT_RUN_AT has an invalid value '{run_at}', expected HH:MM in UTC"));
+        return None;
+    };
+    Some(ExportSettings { s3, prefix, format, run_at })
+}
+
+/// Starts and records export runs; cheap to clone.
+#[derive(Clone)]
+pub(crate) struct Exporter {
+    s3: S3Client,
+    prefix: String,
+    format: ExportFormat,
+    run_at: NaiveTime,
+    /// Cuts every upload short, aborting it and failing its run.
+    shutdown: CancellationToken,
+    /// Manual runs still uploading, awaited at shutdown.
+    tasks: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl Exporter {
+    pub(crate) fn new(settings: &ExportSettings, shutdown: CancellationToken) -> Exporter {
+        Exporter {
+            s3: S3Client::new(settings.s3.clone()),
+            prefix: settings.prefix.clone(),
+            format: settings.format,
+            run_at: settings.run_at,
+            shutdown,
+            tasks: Arc::default(),
+        }
+    }
+
+    /// Waits for every manual run, each of which records how it ended,
+    /// including any started while the earlier ones were being awaited.
+    pub(crate) async fn finish_runs(&self) {
+        loop {
+            let mut tasks = std::mem::take(&mut *self.tasks.lock().expect("export tasks lock poisoned"));
+            if tasks.is_empty() {
+                break;
+            }
+            while let Some(done) = tasks.join_next().await {
+                if let Err(err) = done {
+                    tracing::error!(error = %err, "manual export panicked");
+                }
+            }
+        }
+    }
+
+    /// Runs `runs` one after another on a tracked task, so a long range
+    /// holds one connection rather than one per day.
+    pub(crate) fn spawn_runs(&self, db: PgPool, runs: Vec<ExportRun>) {
+        let exporter = self.clone();
+        let mut tasks = self.tasks.lock().expect("export tasks lock poisoned");
+        // runs that are done need not be held until shutdown
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(async move {
+            for run in &runs {
+                exporter.run(&db, run).await;
+            }
+        });
+    }
+
+    pub(crate) fn object_key(&self, date: NaiveDate) -> String {
+        format!("{}{}.{}", self.prefix, date.format("%Y-%m-%d"), self.format.as_str())
+    }
+
+    /// Records a running export of `date`, or returns `None` when the day
+    /// was already exported and this is not `forced`, or another run of it
+    /// is under way. Runs left behind by a crash are failed first.
+    pub(crate) async fn claim(&self, db: &PgPool, date: NaiveDate, forced: bool) -> Result<Option<ExportRun>, sqlx::Error> {
+        sqlx::query!(
+            "
+            UPDATE exports SET status = 'failed', error = 'interrupted', finished_at = now()
+            WHERE status = 'running' AND started_at < now() - make_interval(hours => $1)
+            ",
+            EXPORT_STALE_AFTER_HOURS
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query_as!(
+            ExportRun,
+            "
+            INSERT INTO exports (export_date, format, object_key, forced)
+            SELECT $1, $2, $3, $4
+            WHERE $4 OR NOT EXISTS (
+                SELECT 1 FROM exports WHERE export_date = $1 AND format = $2 AND status = 'succeeded'
+            )
+            ON CONFLICT DO NOTHING
+            RETURNING id, export_date, format, object_key, status, forced, row_count, byte_count, error,
+                started_at, finished_at
+            ",
+            date,
+            self.format.as_str(),
+            self.object_key(date),
+            forced
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Uploads the orders created on the run's day, in UTC, and records
+    /// how it went. Failures end up in the row and the log, not the caller;
+    /// so does shutdown, which fails the run rather than leave it running.
+    pub(crate) async fn run(&self, db: &PgPool, run: &ExportRun) {
+        match self.upload(db, run).await {
+            Ok((rows, bytes)) => {
+                tracing::info!(export_id = run.id, date = %run.export_date, key = run.object_key, rows, bytes, "orders exported");
+                record(db, run, "succeeded", Some((rows, bytes)), None).await;
+            }
+            Err(err) => {
+                tracing::error!(export_id = run.id, date = %run.export_date, error = err, "order export failed");
+                record(db, run, "failed", None, Some(err)).await;
+            }
+        }
+    }
+
+    /// Streams rows into parts of `EXPORT_PART_SIZE`, so no more than one
+    /// part is ever held. A file that fits in one part is a single put.
+    async fn upload(&self, db: &PgPool, run: &ExportRun) -> Result<(i64, i64), String> {
+        let key = &run.object_key;
+        let content_type = self.format.content_type();
+        let mut upload_id = None;
+        let written = self.unless_shut_down(self.stream_parts(db, run, &mut upload_id)).await;
+        match (written, upload_id) {
+            (Ok((rows, bytes, last)), None) => {
+                self.unless_shut_down(self.s3.put_object(key, content_type, last)).await?;
+                Ok((rows, bytes))
+            }
+            (Ok((rows, bytes, last)), Some((upload_id, mut etags))) => {
+                let finished = async {
+                    if !last.is_empty() {
+                        etags.push(self.s3.upload_part(key, &upload_id, etags.len() + 1, last).await?);
+                    }
+                    self.s3.complete_multipart_upload(key, &upload_id, &etags).await
+                };
+                match self.unless_shut_down(finished).await {
+                    Ok(()) => Ok((rows, bytes)),
+                    Err(err) => Err(self.abort(key, &upload_id, err).await),
+                }
+            }
+            (Err(err), None) => Err(err),
+            (Err(err), Some((upload_id, _))) => Err(self.abort(key, &upload_id, err).await),
+        }
+    }
+
+    /// Writes the file, uploading every full part as it goes; returns the
+    /// row and byte counts and what is left over for the last part.
+    async fn stream_parts(
+        &self,
+        db: &PgPool,
+        run: &ExportRun,
+        upload: &mut Option<(String, Vec<String>)>,
+    ) -> Result<(i64, i64, Vec<u8>), String> {
+        let from = run.export_date.and_time(NaiveTime::MIN).and_utc();
+        let to = from + TimeDelta::days(1);
+        let mut part = Vec::new();
+        let mut rows = 0i64;
+        let mut bytes = 0i64;
+        if self.format == ExportFormat::Csv {
+            part = csv_line(CSV_COLUMNS).map_err(|err| err.to_string())?;
+        }
+
+        let mut tx = db.begin().await.map_err(|err| err.to_string())?;
+        // a day of orders legitimately takes longer than the per-request statement budget
+        sqlx::query("SET LOCAL statement_timeout = 0").execute(&mut *tx).await.map_err(|err| err.to_string())?;
+        let mut orders = sqlx::query_as::<_, Orders>(
+            "SELECT * FROM orders WHERE deleted_at IS NULL AND created_at >= $1 AND created_at < $2 ORDER BY id",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch(&mut *tx);
+        while let Some(order) = orders.try_next().await.map_err(|err| err.to_string())? {
+            rows += 1;
+            match self.format {
+                ExportFormat::Csv => part.extend(csv_line(order_csv_fields(order)).map_err(|err| err.to_string())?),
+                ExportFormat::Ndjson => {
+                    serde_json::to_writer(&mut part, &order).map_err(|err| err.to_string())?;
+                    part.push(b'\n');
+                }
+            }
+            if part.len() >= EXPORT_PART_SIZE {
+                let (upload_id, etags) = match upload {
+                    Some(upload) => upload,
+                    None => upload.insert((self.s3.create_multipart_upload(&run.object_key, self.format.content_type()).await?, Vec::new())),
+                };
+                bytes += part.len() as i64;
+                let etag = self.s3.upload_part(&run.object_key, upload_id, etags.len() + 1, std::mem::take(&mut part)).await?;
+                etags.push(etag);
+            }
+        }
+        bytes += part.len() as i64;
+        Ok((rows, bytes, part))
+    }
+
+    /// `step`, or the interrupted error once shutdown starts, dropping it.
+    async fn unless_shut_down<T>(&self, step: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+        tokio::select! {
+            biased;
+            _ = self.shutdown.cancelled() => Err(EXPORT_INTERRUPTED.to_owned()),
+            done = step => done,
+        }
+    }
+
+    async fn abort(&self, key: &str, upload_id: &str, error: String) -> String {
+        if let Err(abort) = self.s3.abort_multipart_upload(key, upload_id).await {
+            tracing::warn!(key, error = abort, "could not abort multipart upload, its parts stay until the bucket's lifecycle removes them");
+        }
+        error
+    }
+
+    /// How long until the next `run_at`, from `now`.
+    fn until_next_run(&self, now: chrono::DateTime<Utc>) -> Duration {
+        let today = now.date_naive().and_time(self.run_at).and_utc();
+        let next = if today > now { today } else { today + TimeDelta::days(1) };
+        (next - now).to_std().unwrap_or_default()
+    }
+}
+
+/// Fills in how `run` ended, or logs when even that fails.
+async fn record(db: &PgPool, run: &ExportRun, status: &str, counts: Option<(i64, i64)>, error: Option<String>) {
+    let recorded = sqlx::query!(
+        "
+        UPDATE exports SET status = $2, row_count = $3, byte_count = $4, error = $5, finished_at = now()
+        WHERE id = $1
+        ",
+        run.id,
+        status,
+        counts.map(|(rows, _)| rows),
+        counts.map(|(_, bytes)| bytes),
+        error
+    )
+    .execute(db)
+    .await;
+    if let Err(err) = recorded {
+        tracing::error!(export_id = run.id, error = %err, "could not record export outcome");
+    }
+}
+
+/// Starts the export job, which exports the previous UTC day at
+/// `EXPORT_RUN_AT` every day until `shutdown` is cancelled. Does nothing
+/// when `EXPORT_S3_BUCKET` is unset.
+pub fn spawn_export_job(db: PgPool, config: &Config, shutdown: CancellationToken) -> JoinHandle<()> {
+    let exporter = config.export.as_ref().map(|settings| Exporter::new(settings, shutdown.clone()));
+    tokio::spawn(async move {
+        let Some(exporter) = exporter else {
+            tracing::info!("EXPORT_S3_BUCKET is unset, export job disabled");
+            return;
+        };
+        tracing::info!(bucket = exporter.s3.bucket(), run_at = %exporter.run_at, "order export job enabled");
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep(exporter.until_next_run(Utc::now())) => {}
+            }
+            let yesterday = Utc::now().date_naive() - TimeDelta::days(1);
+            let run = match exporter.claim(&db, yesterday, false).await {
+                Ok(Some(run)) => run,
+                Ok(None) => {
+                    tracing::info!(date = %yesterday, "orders already exported, skipping");
+                    continue;
+                }
+                Err(err) => {
+                    tracing::warn!(error = %err, "could not start order export");
+                    continue;
+                }
+            };
+            exporter.run(&db, &run).await;
+        }
+        tracing::info!("export job stopped");
+    })
+}
@@ -0,0 +1,266 @@
+//! Live order events over SSE and WebSocket.
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use axum::Json;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::response::{sse::{Event, KeepAlive, Sse}, IntoResponse};
+use axum::{extract::State, http::{HeaderName, HeaderValue}};
+use serde::Deserialize;
+use chrono::Utc;
+use uuid::Uuid;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::AppState;
+use crate::auth::{AuthContext, SCOPE_WRITE};
+use crate::cache::OrderListCache;
+use crate::handlers::orders::{UpdateOrdersReq, write_order_update};
+use crate::middleware::{CURRENT_REQUEST_ID, current_request_id};
+use crate::models::Orders;
+use crate::outbox::Outbox;
+
+pub(crate) const LIVE_FEED_CAPACITY: usize = 256;
+pub(crate) const SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+pub(crate) static X_ACCEL_BUFFERING: HeaderName = HeaderName::from_static("x-accel-buffering");
+
+#[derive(Clone, Copy)]
+pub(crate) enum OrderEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl OrderEventKind {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            OrderEventKind::Created => "order.created",
+            OrderEventKind::Updated => "order.updated",
+            OrderEventKind::Deleted => "order.deleted",
+        }
+    }
+}
+
+/// `{id, event, occurred_at, data}`, the shape of an event on the live
+/// stream and in a webhook alike.
+pub(crate) fn order_event_body(id: &str, kind: OrderEventKind, order: &Orders) -> serde_json::Value {
+    serde_json::json!({
+        "id": id,
+        "event": kind.as_str(),
+        "occurred_at": Utc::now(),
+        "data": order,
+    })
+}
+
+/// An order change rendered once and shared by every live subscriber.
+pub(crate) struct OrderNotice {
+    pub(crate) event: &'static str,
+    pub(crate) body: String,
+}
+
+/// Fans successful order mutations out to live subscribers. Webhooks go
+/// through `outbox` instead, written before the commit rather than after.
+/// CSV imports are bulk loads and do not publish per-row events.
+#[derive(Clone)]
+pub(crate) struct OrderFeed {
+    live: broadcast::Sender<Arc<OrderNotice>>,
+    outbox: Outbox,
+    list_cache: OrderListCache,
+}
+
+impl OrderFeed {
+    pub(crate) fn new(outbox: Outbox, list_cache: OrderListCache) -> OrderFeed {
+        let (live, _) = broadcast::channel(LIVE_FEED_CAPACITY);
+        OrderFeed { live, outbox, list_cache }
+    }
+
+    /// For the write paths, to queue webhooks in their own transaction.
+    pub(crate) fn outbox(&self) -> Outbox {
+        self.outbox
+    }
+
+    /// Every order write through the API ends up here, so cached lists do
+    /// not outlive a change. The CSV import and the retention job, which
+    /// write without publishing, invalidate the cache themselves.
+    pub(crate) fn publish(&self, kind: OrderEventKind, order: &Orders) {
+        self.list_cache.invalidate();
+        let id = Uuid::new_v4().to_string();
+        let notice = Arc::new(OrderNotice {
+            event: kind.as_str(),
+            body: order_event_body(&id, kind, order).to_string(),
+        });
+        // fails only when nobody is subscribed, which is fine
+        let _ = self.live.send(notice);
+    }
+}
+
+/// Live order activity from the moment of connecting; there is no replay.
+/// The stream ends when shutdown starts so it does not hold up draining.
+#[utoipa::path(
+    get,
+    path = "/orders/stream",
+    tag = "orders",
+    responses(
+        (status = 200, description = "Server-sent events named order.created, order.updated and order.deleted, \
+            each carrying `{id, event, occurred_at, data}`; `lagged` reports events a slow client missed", content_type = "text/event-stream", body = String),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn stream_orders(State(state): State<AppState>) -> impl IntoResponse {
+    let mut rx = state.feed.live.subscribe();
+    let shutting_down = state.shutting_down.clone();
+
+    let events = async_stream::stream! {
+        loop {
+            let received = tokio::select! {
+                _ = shutting_down.cancelled() => break,
+                received = rx.recv() => received,
+            };
+            match received {
+                Ok(notice) => yield Ok::<_, Infallible>(Event::default().event(notice.event).data(&notice.body)),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "order stream subscriber fell behind");
+                    yield Ok(Event::default().event("lagged").data(serde_json::json!({ "missed": missed }).to_string()));
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    };
+
+    (
+        // nginx buffers proxied responses by default, which would hold events back
+        [(X_ACCEL_BUFFERING.clone(), HeaderValue::from_static("no"))],
+        Sse::new(events).keep_alive(KeepAlive::new().interval(SSE_KEEP_ALIVE).text("keep-alive")),
+    )
+}
+
+pub(crate) const WS_PING_INTERVAL: Duration = Duration::from_secs(20);
+pub(crate) const WS_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Commands a socket client may send, tagged by `op`.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub(crate) enum SocketCommand {
+    SetStatus { id: i32, status: String },
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "orders",
+    responses(
+        (status = 101, description = "WebSocket carrying the same events as /orders/stream; clients may send \
+            `{\"op\":\"set_status\",\"id\":5,\"status\":\"ready\"}` and get an `ack` or `error` frame back"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn order_socket(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    auth: AuthContext,
+) -> impl IntoResponse {
+    // the socket outlives the request task, so carry the id over for its errors and logs
+    let request_id = current_request_id().unwrap_or_default();
+    ws.max_message_size(WS_MAX_MESSAGE_BYTES)
+        .on_upgrade(move |socket| CURRENT_REQUEST_ID.scope(request_id, run_order_socket(socket, state, auth)))
+}
+
+/// Pushes feed events and answers commands on one task. A client that lets
+/// two pings go by without any frame in between is considered dead.
+pub(crate) async fn run_order_socket(mut socket: WebSocket, state: AppState, auth: AuthContext) {
+    let mut rx = state.feed.live.subscribe();
+    let mut ping = tokio::time::interval(WS_PING_INTERVAL);
+    ping.tick().await;
+    let mut last_seen = Instant::now();
+    tracing::info!(client = auth.subject, "order socket connected");
+
+    loop {
+        let outgoing = tokio::select! {
+            _ = state.shutting_down.cancelled() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            _ = ping.tick() => {
+                if last_seen.elapsed() > WS_PING_INTERVAL * 2 {
+                    tracing::info!(client = auth.subject, "order socket stopped answering pings");
+                    break;
+                }
+                Message::Ping(Vec::new())
+            }
+            received = rx.recv() => match received {
+                Ok(notice) => Message::Text(notice.body.clone()),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "order socket subscriber fell behind");
+                    Message::Text(serde_json::json!({ "event": "lagged", "data": { "missed": missed } }).to_string())
+                }
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => {
+                last_seen = Instant::now();
+                match incoming {
+                    Some(Ok(Message::Text(text))) => Message::Text(socket_command(&state, &auth, &text).await.to_string()),
+                    Some(Ok(Message::Binary(_))) => Message::Text(socket_error("binary frames are not supported").to_string()),
+                    Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(err)) => {
+                        tracing::debug!(error = %err, "order socket read failed");
+                        break;
+                    }
+                }
+            }
+        };
+        if socket.send(outgoing).await.is_err() {
+            break;
+        }
+    }
+    tracing::info!(client = auth.subject, "order socket closed");
+}
+
+/// Runs one command through the REST update path, so validation, status
+/// rules, auditing and fan-out are identical. Failures come back as an
+/// `error` frame holding the REST error body instead of closing the socket.
+pub(crate) async fn socket_command(state: &AppState, auth: &AuthContext, text: &str) -> serde_json::Value {
+    let command = match serde_json::from_str::<SocketCommand>(text) {
+        Ok(command) => command,
+        Err(err) => return socket_error(&format!("invalid command: {err}")),
+    };
+
+    match command {
+        SocketCommand::SetStatus { id, status } => {
+            if !auth.has_scope(SCOPE_WRITE) {
+                return socket_error(&format!("missing scope {SCOPE_WRITE}"));
+            }
+            let update = UpdateOrdersReq {
+                name: None,
+                coffee_name: None,
+                size: None,
+                quantity: None,
+                total: None,
+                total_override: None,
+                status: Some(status),
+                notes: None,
+                tags: None,
+            };
+            match write_order_update(state.orders.as_ref(), &state.feed, id, auth, None, update).await {
+                Ok((_, _, Json(response))) => serde_json::json!({ "event": "ack", "op": "set_status", "data": response.data }),
+                Err(err) => {
+                    let response = err.into_response();
+                    let status = response.status().as_u16();
+                    let body = axum::body::to_bytes(response.into_body(), WS_MAX_MESSAGE_BYTES).await.unwrap_or_default();
+                    let error: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                    serde_json::json!({ "event": "error", "op": "set_status", "status": status, "error": error })
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn socket_error(message: &str) -> serde_json::Value {
+    serde_json::json!({ "event": "error", "message": message })
+}
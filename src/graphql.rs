@@ -0,0 +1,476 @@
+//! The orders API as a GraphQL schema at `/graphql`. Resolvers reuse the
+//! REST validation and repository; customers and items are batched per
+//! request with dataloaders so a page of orders costs one query for each.
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use async_graphql::{
+  ComplexObject, Context, EmptySubscription, ErrorExtensions, InputObject, InputValueError, InputValueResult, MaybeUndefined, Object,
+  Scalar, ScalarType, Schema, SimpleObject, Value,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+  extract::State,
+  http::{HeaderMap, StatusCode},
+  response::Html,
+  routing::post,
+  Router,
+};
+use sqlx::PgPool;
+
+use crate::auth::{AuthContext, Authenticator, SCOPE_DELETE, SCOPE_READ, SCOPE_WRITE};
+use crate::config::Config;
+use crate::errors::{ApiError, FieldError, FieldErrorCode};
+use crate::feed::OrderEventKind;
+use crate::handlers::customers::{customers_by_id, Customer};
+use crate::handlers::orders::{
+  create_order, normalize_tags, order_by_clause, order_items, write_order_update, CreateOrdersReq, OrderFilter,
+  OrderItemReq, UpdateOrdersReq,
+};
+use crate::middleware::timeout;
+use crate::models::{parse_size, Money, OrderItem, Orders, Size};
+use crate::repository::{Created, OrderPage, PageRequest};
+use crate::AppState;
+
+pub(crate) type OrdersSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+#[derive(Clone)]
+struct GraphqlState {
+    schema: OrdersSchema,
+    auth: Arc<Authenticator>,
+    db: PgPool,
+}
+
+/// Where the schema is served; the maintenance layer leaves it alone, as
+/// mutations check maintenance mode themselves.
+pub(crate) const GRAPHQL_PATH: &str = "/graphql";
+
+/// `POST /graphql`, plus the playground on `GET` when it is enabled.
+/// Credentials are checked here rather than by the route middleware, which
+/// would ask every POST for the write scope; resolvers check scopes instead.
+pub(crate) fn graphql_router(state: AppState, config: &Config) -> Router<AppState> {
+    let db = state.db.clone();
+    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription).data(state).finish();
+    let mut route = post(graphql);
+    if config.graphql_playground {
+        route = route.get(graphql_playground);
+    }
+    Router::new()
+        .route(GRAPHQL_PATH, route)
+        .route_layer(axum::middleware::from_fn_with_state(config.request_timeout, timeout))
+        .with_state(GraphqlState { schema, auth: Arc::new(Authenticator::new(config)), db })
+}
+
+async fn graphql(
+    State(graphql): State<GraphqlState>,
+    headers: HeaderMap,
+    request: GraphQLRequest,
+) -> Result<GraphQLResponse, ApiError> {
+    let auth = graphql.auth.authenticate(&headers)?;
+    tracing::Span::current().record("client", auth.subject.as_str());
+
+    // loaders live for one request, so nothing is cached between callers
+    let request = request
+        .into_inner()
+        .data(auth)
+        .data(DataLoader::new(CustomerLoader(graphql.db.clone()), tokio::spawn))
+        .data(DataLoader::new(ItemLoader(graphql.db), tokio::spawn));
+    Ok(graphql.schema.execute(request).await.into())
+}
+
+async fn graphql_playground() -> Html<String> {
+    Html(playground_source(GraphQLPlaygroundConfig::new(GRAPHQL_PATH)))
+}
+
+/// The caller, once their credentials allow `scope`. Writes also wait out
+/// maintenance mode, as over gRPC.
+fn authorize<'a>(ctx: &'a Context<'_>, scope: &str) -> Result<&'a AuthContext, ApiError> {
+    let auth = ctx
+        .data_opt::<AuthContext>()
+        .ok_or_else(|| ApiError::Unauthorized("not authenticated".to_owned()))?;
+    if !auth.has_scope(scope) {
+        return Err(ApiError::Forbidden(format!("missing scope '{scope}'")));
+    }
+    if scope != SCOPE_READ && ctx.data_unchecked::<AppState>().reloader.settings().borrow().maintenance {
+        return Err(ApiError::Maintenance);
+    }
+    Ok(auth)
+}
+
+/// Errors carry the REST status as `extensions.status` and its name as
+/// `extensions.code`, e.g. 404 and NOT_FOUND.
+impl From<ApiError> for async_graphql::Error {
+    fn from(error: ApiError) -> Self {
+        let (status, message, fields) = match error {
+            ApiError::Validation(errors) => {
+                let fields = async_graphql::to_value(&errors).ok();
+                (StatusCode::UNPROCESSABLE_ENTITY, "validation failed".to_owned(), fields)
+            }
+            ApiError::RollbackFailed { error, rollback } => {
+                tracing::error!(error = %rollback, "transaction rollback failed");
+                return async_graphql::Error::from(*error);
+            }
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, message, None),
+            ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, message, None),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, message, None),
+            ApiError::NotFound(message) => (StatusCode::NOT_FOUND, message, None),
+            ApiError::Unprocessable(message) => (StatusCode::UNPROCESSABLE_ENTITY, message, None),
+            ApiError::Conflict(message) | ApiError::AlreadyExists(message) => (StatusCode::CONFLICT, message, None),
+            ApiError::PreconditionFailed(message) => (StatusCode::PRECONDITION_FAILED, message, None),
+            ApiError::TooManyRequests(_) => (StatusCode::TOO_MANY_REQUESTS, "too many requests".to_owned(), None),
+            ApiError::Timeout => (StatusCode::SERVICE_UNAVAILABLE, "request timed out".to_owned(), None),
+            ApiError::DatabaseBusy => (StatusCode::SERVICE_UNAVAILABLE, "database is busy".to_owned(), None),
+            ApiError::Maintenance => (StatusCode::SERVICE_UNAVAILABLE, "in maintenance, writes are paused".to_owned(), None),
+            // database details are logged but never sent to the client
+            ApiError::Database(err) => {
+                tracing::error!(error = %err, "database error");
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_owned(), None)
+            }
+            // HTTP-only failures that no resolver produces
+            ApiError::MethodNotAllowed(_)
+            | ApiError::NotAcceptable(_)
+            | ApiError::PayloadTooLarge(_)
+            | ApiError::UnsupportedMediaType(_)
+            | ApiError::JsonRejection(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal server error".to_owned(), None),
+        };
+
+        let code = status.canonical_reason().unwrap_or("ERROR").to_uppercase().replace(' ', "_");
+        async_graphql::Error::new(message).extend_with(|_, extensions| {
+            extensions.set("code", code);
+            extensions.set("status", status.as_u16());
+            if let Some(fields) = fields {
+                extensions.set("fields", fields);
+            }
+        })
+    }
+}
+
+/// A decimal amount as a string, e.g. "4.50".
+#[Scalar(name = "Money")]
+impl ScalarType for Money {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(amount) => amount.parse().map_err(InputValueError::custom),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+/// A cup size as lowercase text, e.g. "medium". Inputs keep taking a plain
+/// string so that an unknown size is a validation error naming the field.
+#[Scalar(name = "Size")]
+impl ScalarType for Size {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(size) => size.parse().map_err(InputValueError::custom),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.as_str().to_owned())
+    }
+}
+
+/// What the REST API would answer with a bare 500.
+fn internal_error() -> async_graphql::Error {
+    async_graphql::Error::new("internal server error").extend_with(|_, extensions| {
+        extensions.set("code", "INTERNAL_SERVER_ERROR");
+        extensions.set("status", 500);
+    })
+}
+
+/// A database failure from a dataloader, which only hands out clones.
+fn load_failed(err: Arc<sqlx::Error>) -> async_graphql::Error {
+    tracing::error!(error = %err, "database error");
+    internal_error()
+}
+
+struct CustomerLoader(PgPool);
+
+impl Loader<i32> for CustomerLoader {
+    type Value = Customer;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, ids: &[i32]) -> Result<HashMap<i32, Customer>, Self::Error> {
+        let customers = customers_by_id(&self.0, ids).await?;
+        Ok(customers.into_iter().map(|customer| (customer.id, customer)).collect())
+    }
+}
+
+struct ItemLoader(PgPool);
+
+impl Loader<i32> for ItemLoader {
+    type Value = Vec<OrderItem>;
+    type Error = Arc<sqlx::Error>;
+
+    async fn load(&self, order_ids: &[i32]) -> Result<HashMap<i32, Vec<OrderItem>>, Self::Error> {
+        let mut conn = self.0.acquire().await?;
+        Ok(order_items(&mut conn, order_ids).await?)
+    }
+}
+
+#[ComplexObject]
+impl Orders {
+    async fn customer(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Customer>> {
+        let Some(id) = self.customer_id else {
+            return Ok(None);
+        };
+        ctx.data_unchecked::<DataLoader<CustomerLoader>>().load_one(id).await.map_err(load_failed)
+    }
+
+    async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<OrderItem>> {
+        let Some(id) = self.id else {
+            return Ok(Vec::new());
+        };
+        let items = ctx.data_unchecked::<DataLoader<ItemLoader>>().load_one(id).await.map_err(load_failed)?;
+        Ok(items.unwrap_or_default())
+    }
+}
+
+/// One page of `orders`.
+#[derive(SimpleObject)]
+struct OrderList {
+    orders: Vec<Orders>,
+    /// Orders matching the filter across all pages.
+    total: i64,
+}
+
+/// The `GET /orders` filters; every one given must match.
+#[derive(InputObject, Default)]
+struct OrderFilterInput {
+    /// Exact match, case-insensitive.
+    name: Option<String>,
+    /// Exact match, case-insensitive.
+    coffee_name: Option<String>,
+    size: Option<String>,
+    status: Option<String>,
+    /// Substring search over name and coffee_name.
+    q: Option<String>,
+    /// Search notes with `q` as well.
+    search_notes: Option<bool>,
+    /// Orders must carry every tag given.
+    tags: Option<Vec<String>>,
+    /// Admin only, surfaces soft-deleted orders.
+    include_deleted: Option<bool>,
+    /// Earliest `createdAt`, an RFC 3339 timestamp or a date meaning its start.
+    created_from: Option<String>,
+    /// Latest `createdAt`, an RFC 3339 timestamp or a date meaning its end.
+    created_to: Option<String>,
+}
+
+impl From<OrderFilterInput> for OrderFilter {
+    fn from(input: OrderFilterInput) -> Self {
+        let mut filter = OrderFilter::default();
+        filter.name = input.name;
+        filter.coffee_name = input.coffee_name;
+        filter.size = input.size;
+        filter.status = input.status;
+        filter.q = input.q;
+        filter.search_notes = input.search_notes;
+        filter.include_deleted = input.include_deleted;
+        filter.created_from = input.created_from;
+        filter.created_to = input.created_to;
+        filter.tags = normalize_tags(&input.tags.unwrap_or_default());
+        filter
+    }
+}
+
+/// As the body of `POST /orders`.
+#[derive(InputObject)]
+struct CreateOrderInput {
+    /// Customer name, matched case-insensitively and created when new.
+    name: Option<String>,
+    /// An existing customer, instead of `name`.
+    customer_id: Option<i32>,
+    /// Single-item shorthand for `items`.
+    coffee_name: Option<String>,
+    size: Option<String>,
+    quantity: Option<i32>,
+    items: Option<Vec<OrderItemInput>>,
+    /// Rejected when it does not match the menu.
+    total: Option<Money>,
+    /// Admin only. Charged instead of the menu prices.
+    total_override: Option<Money>,
+    status: Option<String>,
+    notes: Option<String>,
+    tags: Option<Vec<String>>,
+    /// Sent a receipt once the order is placed.
+    email: Option<String>,
+}
+
+#[derive(InputObject)]
+struct OrderItemInput {
+    coffee_name: String,
+    size: String,
+    quantity: Option<i32>,
+}
+
+/// Sizes arrive as text and are parsed here, every bad one reported with
+/// the field it was given in, as the REST validation does.
+impl TryFrom<CreateOrderInput> for CreateOrdersReq {
+    type Error = ApiError;
+
+    fn try_from(input: CreateOrderInput) -> Result<Self, Self::Error> {
+        let mut errors = Vec::new();
+        let mut size = |field: String, size: &str| match size.parse::<Size>() {
+            Ok(size) => Some(size),
+            Err(message) => {
+                errors.push(FieldError { field, code: FieldErrorCode::Invalid, message });
+                None
+            }
+        };
+        let order_size = input.size.as_deref().and_then(|value| size("size".to_owned(), value));
+        let items: Option<Vec<OrderItemReq>> = input.items.map(|items| {
+            items
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, item)| {
+                    let item_size = size(format!("items[{index}].size"), &item.size)?;
+                    Some(OrderItemReq { coffee_name: item.coffee_name, size: item_size, quantity: item.quantity })
+                })
+                .collect()
+        });
+        if !errors.is_empty() {
+            return Err(ApiError::Validation(errors));
+        }
+
+        Ok(CreateOrdersReq {
+            name: input.name,
+            customer_id: input.customer_id,
+            coffee_name: input.coffee_name,
+            size: order_size,
+            quantity: input.quantity,
+            items,
+            total: input.total,
+            total_override: input.total_override,
+            status: input.status,
+            notes: input.notes,
+            tags: input.tags.unwrap_or_default(),
+            email: input.email,
+        })
+    }
+}
+
+/// As the body of `PATCH /orders/:id`; fields left out are unchanged.
+#[derive(InputObject)]
+struct UpdateOrderInput {
+    name: Option<String>,
+    coffee_name: Option<String>,
+    size: Option<String>,
+    quantity: Option<i32>,
+    total: Option<Money>,
+    /// Admin only.
+    total_override: Option<Money>,
+    status: Option<String>,
+    /// `null` or an empty string clears the notes.
+    notes: MaybeUndefined<String>,
+    /// Replaces the tags; an empty list removes them all.
+    tags: Option<Vec<String>>,
+}
+
+impl TryFrom<UpdateOrderInput> for UpdateOrdersReq {
+    type Error = ApiError;
+
+    fn try_from(input: UpdateOrderInput) -> Result<Self, Self::Error> {
+        Ok(UpdateOrdersReq {
+            name: input.name,
+            coffee_name: input.coffee_name,
+            size: input.size.as_deref().map(parse_size).transpose()?,
+            quantity: input.quantity,
+            total: input.total,
+            total_override: input.total_override,
+            status: input.status,
+            notes: input.notes.into(),
+            tags: input.tags,
+        })
+    }
+}
+
+pub(crate) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A page of orders, newest last unless `sort` says otherwise. `limit`
+    /// and `offset` follow the same rules as on `GET /orders`.
+    async fn orders(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<OrderFilterInput>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort: Option<String>,
+        dir: Option<String>,
+    ) -> async_graphql::Result<OrderList> {
+        let auth = authorize(ctx, SCOPE_READ)?;
+        let state = ctx.data_unchecked::<AppState>();
+
+        let mut filter = OrderFilter::from(filter.unwrap_or_default());
+        filter.validate(auth)?;
+        let (limit, offset) = state.page_limits.resolve(limit, offset)?;
+        let order_by = order_by_clause(sort.as_deref(), dir.as_deref()).map_err(ApiError::BadRequest)?;
+
+        // items resolve through the dataloader, not the page query
+        let page = PageRequest { limit, offset, after_id: None, order_by, include_items: false };
+        let OrderPage { orders, total, .. } = state.orders.list(&filter, &page).await?;
+        Ok(OrderList { orders: orders.into_iter().map(|detail| detail.order).collect(), total })
+    }
+
+    /// The order, or null when there is none with this id.
+    async fn order(&self, ctx: &Context<'_>, id: i32) -> async_graphql::Result<Option<Orders>> {
+        authorize(ctx, SCOPE_READ)?;
+        let state = ctx.data_unchecked::<AppState>();
+        let order = state.orders.get(id, false).await?;
+        Ok(order.map(|detail| detail.order))
+    }
+}
+
+pub(crate) struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    async fn create_order(&self, ctx: &Context<'_>, input: CreateOrderInput) -> async_graphql::Result<Orders> {
+        let auth = authorize(ctx, SCOPE_WRITE)?;
+        let state = ctx.data_unchecked::<AppState>();
+
+        // without an idempotency key there is nothing to replay
+        let Created::Fresh(data) = create_order(state, auth, input.try_into()?, None).await? else {
+            return Err(internal_error());
+        };
+        let detail = data.data.ok_or_else(internal_error)?;
+        Ok(detail.order)
+    }
+
+    async fn update_order(&self, ctx: &Context<'_>, id: i32, input: UpdateOrderInput) -> async_graphql::Result<Orders> {
+        let auth = authorize(ctx, SCOPE_WRITE)?;
+        let state = ctx.data_unchecked::<AppState>();
+
+        let (_, _, axum::Json(data)) =
+            write_order_update(state.orders.as_ref(), &state.feed, id, auth, None, input.try_into()?).await?;
+        data.data.ok_or_else(internal_error)
+    }
+
+    /// Admin only. Soft-deletes unless `hard` is true, and returns the order.
+    async fn delete_order(
+        &self,
+        ctx: &Context<'_>,
+        id: i32,
+        #[graphql(default = false)] hard: bool,
+    ) -> async_graphql::Result<Orders> {
+        let auth = authorize(ctx, SCOPE_DELETE)?;
+        auth.require_admin()?;
+        let state = ctx.data_unchecked::<AppState>();
+
+        let Some(order) = state.orders.delete(id, hard, &auth.subject).await? else {
+            return Err(ApiError::NotFound("order not found".to_owned()).into());
+        };
+        tracing::info!(client = auth.subject, id, hard, "order deleted");
+        state.feed.publish(OrderEventKind::Deleted, &order);
+        Ok(order)
+    }
+}
@@ -0,0 +1,254 @@
+//! The orders API over gRPC, served from `proto/orders.proto` on its own
+//! port. Every RPC goes through the same validation and repository as its
+//! REST endpoint; only the envelope differs.
+use std::future::Future;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Status};
+
+use crate::auth::{AuthContext, Authenticator, SCOPE_DELETE, SCOPE_READ, SCOPE_WRITE};
+use crate::config::Config;
+use crate::errors::ApiError;
+use crate::feed::OrderEventKind;
+use crate::handlers::orders::{
+  create_order, normalize_tags, order_by_clause, write_order_update, CreateOrdersReq, OrderFilter,
+  UpdateOrdersReq,
+};
+use crate::models::{parse_size, Money, Orders};
+use crate::repository::{Created, OrderPage, PageRequest};
+use crate::AppState;
+
+use self::proto::orders_service_server::{OrdersService, OrdersServiceServer};
+
+/// Code generated from `proto/orders.proto`, client included.
+#[allow(clippy::all)]
+pub mod proto {
+    tonic::include_proto!("orders.v1");
+}
+
+struct OrdersGrpc {
+    state: AppState,
+    auth: Arc<Authenticator>,
+}
+
+impl OrdersGrpc {
+    /// Authenticates the request's metadata and checks `scope`, as the HTTP
+    /// middleware does for the matching method. Writes also wait out
+    /// maintenance mode.
+    fn authorize<T>(&self, request: &Request<T>, scope: &str) -> Result<AuthContext, ApiError> {
+        let auth = self.auth.authenticate(&request.metadata().clone().into_headers())?;
+        if !auth.has_scope(scope) {
+            return Err(ApiError::Forbidden(format!("missing scope '{scope}'")));
+        }
+        if scope != SCOPE_READ && self.state.reloader.settings().borrow().maintenance {
+            return Err(ApiError::Maintenance);
+        }
+        Ok(auth)
+    }
+}
+
+#[tonic::async_trait]
+impl OrdersService for OrdersGrpc {
+    async fn list_orders(
+        &self,
+        request: Request<proto::ListOrdersRequest>,
+    ) -> Result<tonic::Response<proto::ListOrdersResponse>, Status> {
+        let auth = self.authorize(&request, SCOPE_READ)?;
+        let req = request.into_inner();
+
+        let mut filter = OrderFilter::default();
+        filter.name = req.name;
+        filter.coffee_name = req.coffee_name;
+        filter.size = req.size;
+        filter.status = req.status;
+        filter.q = req.q;
+        filter.tags = normalize_tags(&req.tags);
+        filter.validate(&auth)?;
+
+        let (limit, offset) = self.state.page_limits.resolve(req.limit, req.offset)?;
+        let order_by = order_by_clause(req.sort.as_deref(), req.dir.as_deref()).map_err(ApiError::BadRequest)?;
+
+        let page = PageRequest { limit, offset, after_id: None, order_by, include_items: false };
+        let OrderPage { orders, total, .. } = self.state.orders.list(&filter, &page).await?;
+        Ok(tonic::Response::new(proto::ListOrdersResponse {
+            orders: orders.into_iter().map(|detail| detail.order.into()).collect(),
+            total,
+        }))
+    }
+
+    async fn get_order(&self, request: Request<proto::GetOrderRequest>) -> Result<tonic::Response<proto::Order>, Status> {
+        self.authorize(&request, SCOPE_READ)?;
+        let id = order_id(request.into_inner().id)?;
+
+        let order = self
+            .state
+            .orders
+            .get(id, false)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("order not found".to_owned()))?;
+        Ok(tonic::Response::new(order.order.into()))
+    }
+
+    async fn create_order(
+        &self,
+        request: Request<proto::CreateOrderRequest>,
+    ) -> Result<tonic::Response<proto::Order>, Status> {
+        let auth = self.authorize(&request, SCOPE_WRITE)?;
+        let req = request.into_inner();
+
+        let order = CreateOrdersReq {
+            name: req.name,
+            customer_id: req.customer_id,
+            coffee_name: present(req.coffee_name),
+            size: present(req.size).as_deref().map(parse_size).transpose()?,
+            quantity: req.quantity,
+            items: None,
+            total: req.total.as_deref().map(money).transpose()?,
+            total_override: None,
+            status: req.status,
+            notes: req.notes,
+            tags: req.tags,
+            email: req.email,
+        };
+        // without an idempotency key there is nothing to replay
+        let Created::Fresh(data) = create_order(&self.state, &auth, order, None).await? else {
+            return Err(Status::internal("internal server error"));
+        };
+        let detail = data.data.ok_or_else(|| Status::internal("internal server error"))?;
+        Ok(tonic::Response::new(detail.order.into()))
+    }
+
+    async fn update_order(
+        &self,
+        request: Request<proto::UpdateOrderRequest>,
+    ) -> Result<tonic::Response<proto::Order>, Status> {
+        let auth = self.authorize(&request, SCOPE_WRITE)?;
+        let req = request.into_inner();
+        let id = order_id(req.id)?;
+
+        let order = UpdateOrdersReq {
+            name: req.name,
+            coffee_name: req.coffee_name,
+            size: req.size.as_deref().map(parse_size).transpose()?,
+            quantity: req.quantity,
+            total: req.total.as_deref().map(money).transpose()?,
+            total_override: None,
+            status: req.status,
+            notes: req.notes.map(Some),
+            tags: req.set_tags.then_some(req.tags),
+        };
+        let (_, _, axum::Json(data)) =
+            write_order_update(self.state.orders.as_ref(), &self.state.feed, id, &auth, None, order).await?;
+        let updated = data.data.ok_or_else(|| Status::internal("internal server error"))?;
+        Ok(tonic::Response::new(updated.into()))
+    }
+
+    async fn delete_order(
+        &self,
+        request: Request<proto::DeleteOrderRequest>,
+    ) -> Result<tonic::Response<proto::DeleteOrderResponse>, Status> {
+        let auth = self.authorize(&request, SCOPE_DELETE)?;
+        auth.require_admin()?;
+        let req = request.into_inner();
+        let id = order_id(req.id)?;
+
+        let Some(order) = self.state.orders.delete(id, req.hard, &auth.subject).await? else {
+            return Err(ApiError::NotFound("order not found".to_owned()).into());
+        };
+        tracing::info!(client = auth.subject, id, hard = req.hard, "order deleted");
+        self.state.feed.publish(OrderEventKind::Deleted, &order);
+        Ok(tonic::Response::new(proto::DeleteOrderResponse { id }))
+    }
+}
+
+/// Proto3 strings cannot be absent, so empty means not given.
+fn present(value: String) -> Option<String> {
+    Some(value).filter(|value| !value.is_empty())
+}
+
+fn money(value: &str) -> Result<Money, ApiError> {
+    value.parse().map_err(|err: String| ApiError::BadRequest(format!("total: {err}")))
+}
+
+fn order_id(id: i32) -> Result<i32, ApiError> {
+    if id <= 0 {
+        return Err(ApiError::BadRequest("order id must be a positive integer".to_owned()));
+    }
+    Ok(id)
+}
+
+impl From<Orders> for proto::Order {
+    fn from(order: Orders) -> Self {
+        proto::Order {
+            id: order.id.unwrap_or_default(),
+            name: order.name.unwrap_or_default(),
+            customer_id: order.customer_id,
+            coffee_name: order.coffee_name.unwrap_or_default(),
+            size: order.size.map(|size| size.to_string()).unwrap_or_default(),
+            quantity: order.quantity.unwrap_or_default(),
+            total: order.total.to_string(),
+            status: order.status.unwrap_or_default(),
+            notes: order.notes,
+            tags: order.tags,
+            cancellation_reason: order.cancellation_reason,
+            version: order.version.unwrap_or_default(),
+            created_at: order.created_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+            updated_at: order.updated_at.map(|at| at.to_rfc3339()).unwrap_or_default(),
+        }
+    }
+}
+
+/// The gRPC code closest to the status the REST endpoint would answer with.
+impl From<ApiError> for Status {
+    fn from(error: ApiError) -> Self {
+        match error {
+            ApiError::NotFound(message) => Status::not_found(message),
+            ApiError::BadRequest(message) | ApiError::Unprocessable(message) => Status::invalid_argument(message),
+            ApiError::Validation(errors) => {
+                let errors: Vec<String> = errors.iter().map(|error| format!("{}: {}", error.field, error.message)).collect();
+                Status::invalid_argument(format!("validation failed: {}", errors.join("; ")))
+            }
+            ApiError::Conflict(message) => Status::failed_precondition(message),
+            ApiError::AlreadyExists(message) => Status::already_exists(message),
+            ApiError::PreconditionFailed(message) => Status::failed_precondition(message),
+            ApiError::Unauthorized(message) => Status::unauthenticated(message),
+            ApiError::Forbidden(message) => Status::permission_denied(message),
+            ApiError::TooManyRequests(_) => Status::resource_exhausted("too many requests"),
+            ApiError::Timeout => Status::deadline_exceeded("request timed out"),
+            ApiError::DatabaseBusy => Status::unavailable("database is busy"),
+            ApiError::Maintenance => Status::unavailable("in maintenance, writes are paused"),
+            ApiError::RollbackFailed { error, rollback } => {
+                tracing::error!(error = %rollback, "transaction rollback failed");
+                Status::from(*error)
+            }
+            // database details are logged but never sent to the client
+            ApiError::Database(err) => {
+                tracing::error!(error = %err, "database error");
+                Status::internal("internal server error")
+            }
+            // HTTP-only failures that no RPC produces
+            ApiError::MethodNotAllowed(message)
+            | ApiError::PayloadTooLarge(message)
+            | ApiError::UnsupportedMediaType(message) => Status::internal(message),
+            ApiError::NotAcceptable(_) | ApiError::JsonRejection(_) => Status::internal("internal server error"),
+        }
+    }
+}
+
+/// Serves `OrdersService` on `listener` until `shutdown` resolves, then
+/// lets in-flight calls finish.
+pub async fn serve_grpc(
+    listener: TcpListener,
+    state: AppState,
+    config: &Config,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), tonic::transport::Error> {
+    let service = OrdersGrpc { state, auth: Arc::new(Authenticator::new(config)) };
+    let incoming = TcpIncoming::from_listener(listener, true, None).expect("listener has a local address");
+    tonic::transport::Server::builder()
+        .timeout(config.request_timeout)
+        .add_service(OrdersServiceServer::new(service))
+        .serve_with_incoming_shutdown(incoming, shutdown)
+        .await
+}
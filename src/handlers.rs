@@ -1,2 +1,14 @@
-
+//! Route handlers, one module per resource.
+pub(crate) mod audit;
+pub(crate) mod csv;
+pub(crate) mod customers;
+pub(crate) mod dev;
+pub(crate) mod exports;
+pub(crate) mod inventory;
+pub(crate) mod menu;
+pub(crate) mod orders;
+pub(crate) mod outbox;
+pub(crate) mod probes;
+pub(crate) mod reports;
+pub(crate) mod stats;
 
@@ -0,0 +1,141 @@
+//! Per-order audit history.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::PgConnection;
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+use crate::db::ReadPool;
+use crate::errors::ApiError;
+use crate::handlers::orders::OrderId;
+use crate::models::{Orders, PageMeta, Response};
+use crate::pagination::{PageParams, Pagination};
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct OrderEvent {
+    id: i64,
+    order_id: i32,
+    /// created, updated, status_changed, deleted or restored.
+    action: String,
+    actor: String,
+    /// Snapshot for created, `{field: {from, to}}` for updates.
+    #[schema(value_type = Object)]
+    changes: serde_json::Value,
+    created_at: DateTime<Utc>,
+}
+
+/// Writes an audit row on the caller's transaction, so it commits or rolls
+/// back together with the mutation it describes.
+pub(crate) async fn record_order_event(
+    conn: &mut PgConnection,
+    order_id: i32,
+    action: &str,
+    actor: &str,
+    changes: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO order_events (order_id, action, actor, changes) VALUES ($1, $2, $3, $4)",
+        order_id,
+        action,
+        actor,
+        changes
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub(crate) fn order_snapshot(order: &Orders) -> serde_json::Value {
+    serde_json::to_value(order).unwrap_or_default()
+}
+
+// bookkeeping columns change on every write and would only add noise
+pub(crate) const UNAUDITED_FIELDS: [&str; 3] = ["version", "updated_at", "created_at"];
+
+/// `{field: {from, to}}` for every field that differs between the two rows.
+pub(crate) fn order_diff(before: &Orders, after: &Orders) -> serde_json::Value {
+    let (serde_json::Value::Object(before), serde_json::Value::Object(after)) =
+        (order_snapshot(before), order_snapshot(after))
+    else {
+        return serde_json::Value::Null;
+    };
+
+    let changes = after
+        .into_iter()
+        .filter(|(field, _)| !UNAUDITED_FIELDS.contains(&field.as_str()))
+        .filter_map(|(field, to)| {
+            let from = before.get(&field).cloned().unwrap_or_default();
+            (from != to).then(|| (field, serde_json::json!({ "from": from, "to": to })))
+        })
+        .collect();
+    serde_json::Value::Object(changes)
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/{id}/events",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+        PageParams,
+    ),
+    responses(
+        (status = 200, description = "Audit events, newest first", body = OrderEventsResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_order_events(
+    OrderId(id): OrderId,
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    Pagination { limit, offset, .. }: Pagination,
+) -> Result<impl IntoResponse, ApiError> {
+
+    let events = sqlx::query_as!(
+        OrderEvent,
+        "
+        SELECT * FROM order_events
+        WHERE order_id = $1
+        ORDER BY id DESC
+        LIMIT $2 OFFSET $3
+        ",
+        id,
+        limit,
+        offset
+    )
+    .fetch_all(&pg_pool)
+    .await?;
+
+    // history outlives hard deletes, so only a first page with no events means unknown
+    if events.is_empty() && offset == 0 {
+        let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM orders WHERE id = $1)", id)
+            .fetch_one(&pg_pool)
+            .await?
+            .unwrap_or(false);
+        if !exists {
+            return Err(ApiError::NotFound("order not found".to_owned()));
+        }
+    }
+    let total = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM order_events WHERE order_id = $1"#, id)
+        .fetch_one(&pg_pool)
+        .await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} events (limit {limit}, offset {offset})", events.len()),
+        data: Some(events),
+        meta: Some(PageMeta::offset(limit, offset, total)),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
@@ -0,0 +1,427 @@
+//! CSV export and import.
+use axum::Json;
+use axum::body::Body;
+use axum::response::IntoResponse;
+use axum::RequestExt;
+use axum::{
+  extract::{multipart::MultipartError, FromRequest, Multipart, Query, Request, State},
+  http::{header::{CONTENT_DISPOSITION, CONTENT_TYPE}, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
+use http_body_util::LengthLimitError;
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use tokio_util::io::StreamReader;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::auth::{AuthContext, RequireAdmin};
+use crate::db::{finish, ReadPool};
+use crate::errors::{ApiError, ErrorResponse, FieldError, FieldErrorCode};
+use crate::handlers::menu::{Menu, PriceTolerance};
+use crate::handlers::orders::{
+    CreateOrdersReq, MAX_BATCH_SIZE, NewOrders, OrderFilter, PricedOrder, TagFilter, insert_orders,
+    push_order_filters, validate_new_order,
+};
+use crate::models::{OrderStatus, Orders, Response};
+
+pub(crate) const CSV_COLUMNS: [&str; 10] = [
+    "id", "name", "coffee_name", "size", "quantity", "total", "status", "created_at", "updated_at", "notes",
+];
+
+/// One CSV line, quoted and escaped as needed.
+pub(crate) fn csv_line<I, T>(fields: I) -> Result<Vec<u8>, csv::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    writer.into_inner().map_err(|err| err.into_error().into())
+}
+
+pub(crate) fn order_csv_fields(order: Orders) -> [String; 10] {
+    let text = |value: Option<String>| value.unwrap_or_default();
+    let number = |value: Option<i32>| value.map(|v| v.to_string()).unwrap_or_default();
+    let time = |value: Option<DateTime<Utc>>| value.map(|v| v.to_rfc3339()).unwrap_or_default();
+    [
+        number(order.id),
+        text(order.name),
+        text(order.coffee_name),
+        order.size.map(|size| size.to_string()).unwrap_or_default(),
+        number(order.quantity),
+        order.total.to_string(),
+        text(order.status),
+        time(order.created_at),
+        time(order.updated_at),
+        text(order.notes),
+    ]
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/export.csv",
+    tag = "orders",
+    params(
+        OrderFilter,
+        TagFilter,
+    ),
+    responses(
+        (status = 200, description = "Matching orders as CSV, streamed", content_type = "text/csv", body = String),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn export_orders_csv(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    auth: AuthContext,
+    mut filter: OrderFilter,
+) -> Result<impl IntoResponse, ApiError> {
+    filter.validate(&auth)?;
+
+    // the status line is already sent when a row fails, so all we can do is log and cut the body short
+    let body = order_csv_stream(pg_pool, filter)
+        .inspect_err(|err| tracing::error!(error = %err, "csv export failed"));
+
+    Ok((
+        StatusCode::OK,
+        [
+            (CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"orders.csv\""),
+        ],
+        Body::from_stream(body),
+    ))
+}
+
+/// Header line and then one line per order, as rows arrive from postgres.
+pub(crate) fn order_csv_stream(
+    pg_pool: PgPool,
+    filter: OrderFilter,
+) -> impl Stream<Item = Result<Vec<u8>, CsvExportError>> {
+    async_stream::try_stream! {
+        yield csv_line(CSV_COLUMNS)?;
+
+        let mut tx = pg_pool.begin().await?;
+        // an export legitimately runs longer than the per-request statement budget
+        sqlx::query("SET LOCAL statement_timeout = 0").execute(&mut *tx).await?;
+
+        let mut q = QueryBuilder::<Postgres>::new("SELECT * FROM orders WHERE TRUE");
+        push_order_filters(&mut q, &filter);
+        q.push(" ORDER BY id");
+
+        let mut rows = q.build_query_as::<Orders>().fetch(&mut *tx);
+        while let Some(order) = rows.try_next().await? {
+            yield csv_line(order_csv_fields(order))?;
+        }
+    }
+}
+
+/// Errors that can end an export stream midway.
+#[derive(Debug)]
+pub(crate) enum CsvExportError {
+    Database(sqlx::Error),
+    Csv(csv::Error),
+}
+
+impl std::fmt::Display for CsvExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvExportError::Database(err) => write!(f, "database error: {err}"),
+            CsvExportError::Csv(err) => write!(f, "csv error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for CsvExportError {}
+
+impl From<sqlx::Error> for CsvExportError {
+    fn from(err: sqlx::Error) -> Self {
+        CsvExportError::Database(err)
+    }
+}
+
+impl From<csv::Error> for CsvExportError {
+    fn from(err: csv::Error) -> Self {
+        CsvExportError::Csv(err)
+    }
+}
+
+// totals come from the menu, a `total_override` column is honoured when present
+pub(crate) const IMPORT_REQUIRED_COLUMNS: [&str; 3] = ["name", "coffee_name", "size"];
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ImportParams {
+    /// `strict` aborts on the first bad row, the default skips bad rows.
+    mode: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ImportRowError {
+    line: u64,
+    errors: Vec<FieldError>,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+pub(crate) struct ImportReport {
+    inserted: usize,
+    skipped: usize,
+    errors: Vec<ImportRowError>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/orders/import",
+    tag = "orders",
+    params(
+        ImportParams,
+    ),
+    request_body(content = String, description = "CSV with a header row, sent as text/csv or as the `file` part of a multipart form", content_type = "text/csv"),
+    responses(
+        (status = 200, description = "Import finished", body = ImportResponse),
+        (status = 409, description = "Out of stock, nothing was imported", body = ErrorBody),
+        (status = 413, description = "More rows than IMPORT_MAX_ROWS, or a body over MAX_BULK_BODY_BYTES", body = ErrorBody),
+        (status = 415, description = "Body is neither text/csv nor multipart/form-data", body = ErrorBody),
+        (status = 422, description = "Strict mode rejected the file", body = ImportErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn import_orders_csv(
+    State(state): State<AppState>,
+    RequireAdmin(auth): RequireAdmin,
+    Query(params): Query<ImportParams>,
+    request: Request,
+) -> Result<axum::response::Response, ApiError> {
+    let strict = match params.mode.as_deref() {
+        None | Some("skip") => false,
+        Some("strict") => true,
+        Some(other) => {
+            return Err(ApiError::BadRequest(format!(
+                "invalid mode '{other}', expected strict or skip"
+            )))
+        }
+    };
+
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    if content_type.starts_with("text/csv") {
+        let body = request.into_limited_body().into_data_stream().map_err(std::io::Error::other);
+        return import_csv(StreamReader::new(body), &state, &auth.subject, strict).await;
+    }
+
+    if content_type.starts_with("multipart/form-data") {
+        let mut multipart = Multipart::from_request(request, &state)
+            .await
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+
+        // first part named `file` or carrying a filename is the upload
+        while let Some(field) = multipart
+            .next_field()
+            .await
+            .map_err(|err| match err.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => ApiError::body_too_large(),
+                _ => ApiError::BadRequest(err.body_text()),
+            })?
+        {
+            if field.name() == Some("file") || field.file_name().is_some() {
+                let body = field.map_err(std::io::Error::other);
+                return import_csv(StreamReader::new(body), &state, &auth.subject, strict).await;
+            }
+        }
+        return Err(ApiError::BadRequest("multipart body has no file part".to_owned()));
+    }
+
+    Err(ApiError::UnsupportedMediaType(
+        "expected a text/csv or multipart/form-data body".to_owned(),
+    ))
+}
+
+pub(crate) fn parse_import_row(
+    record: &StringRecord,
+    headers: &StringRecord,
+    menu: &Menu,
+    tolerance: PriceTolerance,
+) -> Result<(CreateOrdersReq, PricedOrder, OrderStatus), Vec<FieldError>> {
+    // name the offending column when csv can tell us which one it was
+    let order: CreateOrdersReq = record.deserialize(Some(headers)).map_err(|err| match err.kind() {
+        csv_async::ErrorKind::Deserialize { err, .. }
+            if !matches!(err.kind(), csv_async::DeserializeErrorKind::UnexpectedEndOfRow) =>
+        {
+            let field = err
+                .field()
+                .and_then(|index| headers.get(index as usize))
+                .unwrap_or("row");
+            vec![FieldError::new(field, FieldErrorCode::Invalid, err.kind().to_string())]
+        }
+        _ => vec![FieldError::new("row", FieldErrorCode::Invalid, err.to_string())],
+    })?;
+
+    let mut errors = validate_new_order(&order);
+    // ids can't be checked row by row mid-stream, so imports link customers by name
+    if order.customer_id.is_some() {
+        errors.retain(|error| error.field != "customer_id");
+        errors.push(FieldError::new("customer_id", FieldErrorCode::NotAllowed, "imports link customers by name"));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    // validate_new_order has refused unknown statuses
+    let status = order.status.as_deref().and_then(|status| status.parse().ok()).unwrap_or(OrderStatus::Pending);
+
+    let priced = menu.price_order(&order, tolerance)?;
+    Ok((order, priced, status))
+}
+
+/// Whether reading the upload failed because it ran past the route's body
+/// limit, in a plain body or inside a multipart field.
+fn upload_too_large(err: &csv_async::Error) -> bool {
+    let csv_async::ErrorKind::Io(err) = err.kind() else {
+        return false;
+    };
+    let Some(inner) = err.get_ref() else {
+        return false;
+    };
+    if let Some(multipart) = inner.downcast_ref::<MultipartError>() {
+        return multipart.status() == StatusCode::PAYLOAD_TOO_LARGE;
+    }
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(inner);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Reads the upload record by record, flushing valid rows to the database in
+/// chunks so memory stays bounded regardless of file size. Everything runs in
+/// one transaction, so a failure midway inserts nothing.
+pub(crate) async fn import_csv<R>(
+    reader: R,
+    state: &AppState,
+    actor: &str,
+    strict: bool,
+) -> Result<axum::response::Response, ApiError>
+where
+    R: tokio::io::AsyncRead + Unpin + Send,
+{
+    let invalid_csv = |err: csv_async::Error| {
+        if upload_too_large(&err) {
+            return ApiError::body_too_large();
+        }
+        let line = err.position().map(|pos| pos.line()).unwrap_or_default();
+        ApiError::BadRequest(format!("invalid csv at line {line}: {err}"))
+    };
+
+    let mut csv = AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .create_reader(reader);
+    let headers = csv.headers().await.map_err(invalid_csv)?.clone();
+
+    let missing: Vec<&str> = IMPORT_REQUIRED_COLUMNS
+        .into_iter()
+        .filter(|column| !headers.iter().any(|header| header == *column))
+        .collect();
+    if !missing.is_empty() {
+        return Err(ApiError::BadRequest(format!("csv is missing columns: {}", missing.join(", "))));
+    }
+
+    let mut report = ImportReport::default();
+    let mut tx = state.db.begin().await?;
+    // Some(line) when a strict import stopped at a bad row
+    let result = async {
+        let menu = Menu::load(&mut tx).await?;
+        let mut pending = NewOrders::default();
+        let mut record = StringRecord::new();
+        let mut rows = 0;
+
+        while csv.read_record(&mut record).await.map_err(invalid_csv)? {
+            rows += 1;
+            if rows > state.import_max_rows {
+                return Err(ApiError::PayloadTooLarge(format!(
+                    "import may contain at most {} rows",
+                    state.import_max_rows
+                )));
+            }
+
+            let line = record.position().map(|pos| pos.line()).unwrap_or_default();
+            match parse_import_row(&record, &headers, &menu, state.price_tolerance) {
+                Ok((order, priced, status)) => {
+                    pending.push(order, priced, status);
+                    if pending.len() >= MAX_BATCH_SIZE {
+                        report.inserted += insert_orders(&mut tx, &pending, actor).await?.len();
+                        pending = NewOrders::default();
+                    }
+                }
+                Err(errors) => {
+                    report.errors.push(ImportRowError { line, errors });
+                    if strict {
+                        return Ok(Some(line));
+                    }
+                    report.skipped += 1;
+                }
+            }
+        }
+
+        if rows == 0 {
+            return Err(ApiError::BadRequest("csv contains no rows".to_owned()));
+        }
+
+        if !pending.is_empty() {
+            report.inserted += insert_orders(&mut tx, &pending, actor).await?.len();
+        }
+        Ok(None)
+    }
+    .await;
+    let aborted = match result {
+        // a strict import keeps none of the chunks it already flushed
+        Ok(Some(line)) => {
+            tx.rollback().await?;
+            Some(line)
+        }
+        result => finish(tx, result).await?,
+    };
+    if let Some(line) = aborted {
+        report.inserted = 0;
+        let error_response = Response {
+            status: false,
+            message: format!("import aborted at line {line}"),
+            data: Some(report),
+            meta: None,
+        };
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Json(ErrorResponse::new(error_response))).into_response());
+    }
+    // imports skip the feed, which would otherwise empty the cache
+    state.list_cache.invalidate();
+
+    tracing::info!(client = actor, inserted = report.inserted, skipped = report.skipped, "orders imported");
+    let data = Response {
+        status: true,
+        message: format!("imported {} orders, skipped {} rows", report.inserted, report.skipped),
+        data: Some(report),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ).into_response())
+}
@@ -0,0 +1,531 @@
+//! Customers and the orders linked to them.
+use async_graphql::SimpleObject;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::{Path, Query, State}, http::{header::LOCATION, StatusCode}};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+use chrono::{DateTime, Utc};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::{AuthContext, RequireAdmin};
+use crate::db::{finish, ReadPool};
+use crate::errors::{ApiError, FieldError, FieldErrorCode, JsonBody};
+use crate::feed::{OrderEventKind, OrderFeed};
+use crate::handlers::orders::{escape_like, validate_order_fields};
+use crate::models::{Orders, PageMeta, Response, Size};
+use crate::pagination::{PageParams, Pagination};
+
+pub(crate) const MAX_EMAIL_LENGTH: usize = 255;
+pub(crate) const MAX_PHONE_LENGTH: usize = 50;
+
+#[derive(Clone, sqlx::FromRow, Serialize, ToSchema, SimpleObject)]
+pub(crate) struct Customer {
+    #[schema(example = 7)]
+    pub(crate) id: i32,
+    /// Unique, ignoring case.
+    #[schema(example = "Ada")]
+    pub(crate) name: String,
+    #[schema(example = "ada@example.com")]
+    email: Option<String>,
+    #[schema(example = "+44 20 7946 0000")]
+    phone: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CreateCustomerReq {
+    #[schema(example = "Ada")]
+    name: String,
+    email: Option<String>,
+    phone: Option<String>,
+}
+
+/// Only the fields sent are changed; an empty email or phone clears it.
+/// Renaming a customer renames their orders too.
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateCustomerReq {
+    name: Option<String>,
+    email: Option<String>,
+    phone: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct CustomerListParams {
+    /// Case-insensitive substring of the name.
+    q: Option<String>,
+}
+
+/// A basic syntax check: one `@` between a local part and a dotted domain,
+/// with no whitespace. Blank is taken as absent.
+pub(crate) fn validate_email(email: Option<&str>) -> Option<FieldError> {
+    let email = email.map(str::trim).filter(|email| !email.is_empty())?;
+    if email.chars().count() > MAX_EMAIL_LENGTH {
+        return Some(FieldError::new("email", FieldErrorCode::TooLong, format!("must be at most {MAX_EMAIL_LENGTH} characters")));
+    }
+    let well_formed = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.split('.').count() > 1
+                && domain.split('.').all(|label| !label.is_empty())
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+    (!well_formed).then(|| FieldError::new("email", FieldErrorCode::Invalid, "must be an email address"))
+}
+
+pub(crate) fn validate_customer_fields(
+    name: Option<&str>,
+    email: Option<&str>,
+    phone: Option<&str>,
+) -> Vec<FieldError> {
+    let mut errors = validate_order_fields(name, None, None);
+
+    errors.extend(validate_email(email));
+
+    if let Some(phone) = phone.map(str::trim).filter(|phone| !phone.is_empty()) {
+        if phone.chars().count() > MAX_PHONE_LENGTH {
+            errors.push(FieldError::new("phone", FieldErrorCode::TooLong, format!("must be at most {MAX_PHONE_LENGTH} characters")));
+        } else if !phone.chars().all(|c| c.is_ascii_digit() || " +-().".contains(c)) {
+            errors.push(FieldError::new("phone", FieldErrorCode::Invalid, "may only contain digits, spaces and + - ( ) ."));
+        }
+    }
+
+    errors
+}
+
+/// Trimmed, with an empty value meaning none.
+pub(crate) fn optional_contact(value: Option<String>) -> Option<String> {
+    value
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+/// The case-insensitive name index surfaces as a 409, anything else as usual.
+pub(crate) fn customer_write_error(err: sqlx::Error) -> ApiError {
+    if let sqlx::Error::Database(db_err) = &err {
+        if db_err.constraint() == Some("customers_name_key") {
+            return ApiError::AlreadyExists("a customer with that name already exists".to_owned());
+        }
+    }
+    err.into()
+}
+
+/// Looks the name up case-insensitively, inserting it when nobody matches.
+pub(crate) async fn find_or_create_customer(conn: &mut PgConnection, name: &str) -> Result<Customer, sqlx::Error> {
+    // the no-op update makes RETURNING yield the existing row on conflict
+    sqlx::query_as!(
+        Customer,
+        "
+        INSERT INTO customers (name) VALUES ($1)
+        ON CONFLICT ((LOWER(name))) DO UPDATE SET name = customers.name
+        RETURNING *
+        ",
+        name
+    )
+    .fetch_one(conn)
+    .await
+}
+
+/// Every customer among `ids`, in no particular order.
+pub(crate) async fn customers_by_id(db: &PgPool, ids: &[i32]) -> Result<Vec<Customer>, sqlx::Error> {
+    sqlx::query_as!(Customer, "SELECT * FROM customers WHERE id = ANY($1)", ids)
+        .fetch_all(db)
+        .await
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers",
+    tag = "customers",
+    params(
+        CustomerListParams,
+        PageParams,
+    ),
+    responses(
+        (status = 200, description = "Customers ordered by name", body = CustomerListResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_customers(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    Query(params): Query<CustomerListParams>,
+    Pagination { limit, offset, .. }: Pagination,
+) -> Result<impl IntoResponse, ApiError> {
+
+    let mut q = QueryBuilder::<Postgres>::new("SELECT * FROM customers");
+    let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM customers");
+    if let Some(term) = params.q.as_deref().map(str::trim).filter(|term| !term.is_empty()) {
+        let pattern = format!("%{}%", escape_like(term));
+        q.push(" WHERE name ILIKE ").push_bind(pattern.clone());
+        count.push(" WHERE name ILIKE ").push_bind(pattern);
+    }
+    q.push(" ORDER BY LOWER(name), id LIMIT ").push_bind(limit);
+    q.push(" OFFSET ").push_bind(offset);
+
+    let customers = q.build_query_as::<Customer>().fetch_all(&pg_pool).await?;
+    let total = count.build_query_scalar::<i64>().fetch_one(&pg_pool).await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} customers (limit {limit}, offset {offset})", customers.len()),
+        data: Some(customers),
+        meta: Some(PageMeta::offset(limit, offset, total)),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/customers",
+    tag = "customers",
+    request_body = CreateCustomerReq,
+    responses(
+        (status = 201, description = "Customer created, Location points at it", body = CustomerResponse),
+        (status = 409, description = "A customer with that name already exists", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn add_customer(
+    State(pg_pool): State<PgPool>,
+    JsonBody(customer): JsonBody<CreateCustomerReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    let errors = validate_customer_fields(
+        Some(&customer.name),
+        customer.email.as_deref(),
+        customer.phone.as_deref(),
+    );
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let created = sqlx::query_as!(
+        Customer,
+        "INSERT INTO customers (name, email, phone) VALUES ($1, $2, $3) RETURNING *",
+        customer.name.trim(),
+        optional_contact(customer.email),
+        optional_contact(customer.phone)
+    )
+    .fetch_one(&pg_pool)
+    .await
+    .map_err(customer_write_error)?;
+
+    let location = format!("/customers/{}", created.id);
+    let data = Response {
+        status: true,
+        message: "added successfully".to_owned(),
+        data: Some(created),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        [(LOCATION, location)],
+        Json(data),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}",
+    tag = "customers",
+    params(
+        ("id" = i32, Path, description = "Customer id"),
+    ),
+    responses(
+        (status = 200, description = "The customer", body = CustomerResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Customer not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_customer(
+    Path(id): Path<i32>,
+    State(ReadPool(pg_pool)): State<ReadPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let customer = sqlx::query_as!(Customer, "SELECT * FROM customers WHERE id = $1", id)
+        .fetch_optional(&pg_pool)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("customer not found".to_owned()))?;
+
+    let data = Response {
+        status: true,
+        message: "found customer".to_owned(),
+        data: Some(customer),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/customers/{id}",
+    tag = "customers",
+    params(
+        ("id" = i32, Path, description = "Customer id"),
+    ),
+    request_body = UpdateCustomerReq,
+    responses(
+        (status = 200, description = "Customer updated", body = CustomerResponse),
+        (status = 409, description = "A customer with that name already exists", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Customer not found", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn update_customer(
+    Path(id): Path<i32>,
+    State(pg_pool): State<PgPool>,
+    State(feed): State<OrderFeed>,
+    auth: AuthContext,
+    JsonBody(customer): JsonBody<UpdateCustomerReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    if customer.name.is_none() && customer.email.is_none() && customer.phone.is_none() {
+        return Err(ApiError::BadRequest("no fields provided to update".to_owned()));
+    }
+
+    let errors = validate_customer_fields(
+        customer.name.as_deref(),
+        customer.email.as_deref(),
+        customer.phone.as_deref(),
+    );
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        let current = sqlx::query_as!(Customer, "SELECT * FROM customers WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or_else(|| ApiError::NotFound("customer not found".to_owned()))?;
+
+        let mut q = QueryBuilder::<Postgres>::new("UPDATE customers SET ");
+        let mut fields = q.separated(", ");
+
+        if let Some(name) = &customer.name {
+            fields.push("name = ").push_bind_unseparated(name.trim().to_owned());
+        }
+
+        if customer.email.is_some() {
+            fields.push("email = ").push_bind_unseparated(optional_contact(customer.email));
+        }
+
+        if customer.phone.is_some() {
+            fields.push("phone = ").push_bind_unseparated(optional_contact(customer.phone));
+        }
+
+        fields.push("updated_at = now()");
+
+        q.push(" WHERE id = ").push_bind(id);
+        q.push(" RETURNING *");
+
+        let updated = q
+            .build_query_as::<Customer>()
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(customer_write_error)?;
+
+        // orders carry the name too, so a rename is an update to each of them
+        let mut renamed = Vec::new();
+        if updated.name != current.name {
+            renamed = sqlx::query_as!(
+                Orders,
+                r#"
+                UPDATE orders SET name = $2, version = version + 1, updated_at = now()
+                WHERE customer_id = $1
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                "#,
+                id,
+                updated.name
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let ids: Vec<i32> = renamed.iter().filter_map(|order| order.id).collect();
+            sqlx::query!(
+                "
+                INSERT INTO order_events (order_id, action, actor, changes)
+                SELECT id, 'updated', $2, jsonb_build_object('name', jsonb_build_object('from', $3::text, 'to', $4::text))
+                FROM UNNEST($1::int[]) AS id
+                ",
+                &ids,
+                auth.subject,
+                current.name,
+                updated.name
+            )
+            .execute(&mut *tx)
+            .await?;
+            feed.outbox().enqueue(&mut tx, OrderEventKind::Updated, &renamed).await?;
+        }
+        Ok((updated, renamed))
+    }
+    .await;
+    let (updated, renamed) = finish(tx, result).await?;
+
+    for order in &renamed {
+        feed.publish(OrderEventKind::Updated, order);
+    }
+
+    let data = Response {
+        status: true,
+        message: "updated successfully".to_owned(),
+        data: Some(updated),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+/// Customers with orders, deleted ones included, cannot be removed.
+#[utoipa::path(
+    delete,
+    path = "/customers/{id}",
+    tag = "customers",
+    params(
+        ("id" = i32, Path, description = "Customer id"),
+    ),
+    responses(
+        (status = 200, description = "Customer deleted", body = CustomerResponse),
+        (status = 409, description = "Customer still has orders", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Customer not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn delete_customer(
+    Path(id): Path<i32>,
+    State(pg_pool): State<PgPool>,
+    RequireAdmin(auth): RequireAdmin,
+) -> Result<impl IntoResponse, ApiError> {
+    let deleted = sqlx::query_as!(Customer, "DELETE FROM customers WHERE id = $1 RETURNING *", id)
+        .fetch_optional(&pg_pool)
+        .await
+        .map_err(|err| match &err {
+            sqlx::Error::Database(db_err) if db_err.constraint() == Some("orders_customer_id_fkey") => {
+                ApiError::Conflict("customer still has orders".to_owned())
+            }
+            _ => err.into(),
+        })?
+        .ok_or_else(|| ApiError::NotFound("customer not found".to_owned()))?;
+    tracing::info!(client = auth.subject, id, "customer deleted");
+
+    let data = Response {
+        status: true,
+        message: "deleted successfully".to_owned(),
+        data: Some(deleted),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/orders",
+    tag = "customers",
+    params(
+        ("id" = i32, Path, description = "Customer id"),
+        PageParams,
+    ),
+    responses(
+        (status = 200, description = "The customer's orders, newest first", body = OrderListResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Customer not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_customer_orders(
+    Path(id): Path<i32>,
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    Pagination { limit, offset, .. }: Pagination,
+) -> Result<impl IntoResponse, ApiError> {
+
+    let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM customers WHERE id = $1)", id)
+        .fetch_one(&pg_pool)
+        .await?
+        .unwrap_or(false);
+    if !exists {
+        return Err(ApiError::NotFound("customer not found".to_owned()));
+    }
+
+    let orders = sqlx::query_as!(
+        Orders,
+        r#"
+        SELECT id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+            status, cancellation_reason, version, created_at, updated_at, deleted_at
+        FROM orders
+        WHERE customer_id = $1 AND deleted_at IS NULL
+        ORDER BY id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        id,
+        limit,
+        offset
+    )
+    .fetch_all(&pg_pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM orders WHERE customer_id = $1 AND deleted_at IS NULL"#,
+        id
+    )
+    .fetch_one(&pg_pool)
+    .await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} orders (limit {limit}, offset {offset})", orders.len()),
+        data: Some(orders),
+        meta: Some(PageMeta::offset(limit, offset, total)),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
@@ -0,0 +1,91 @@
+//! Routes for local development and end-to-end tests. They are only added to
+//! the router when dev routes are enabled, and left out of the OpenAPI
+//! document, which production serves too.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::{Query, State}, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::auth::RequireAdmin;
+use crate::cache::OrderListCache;
+use crate::errors::ApiError;
+use crate::models::Response;
+use crate::reload::{ConfigReloader, ReloadReport};
+use crate::seed::{seed, SeedReport};
+
+/// Left open by maintenance mode, which a reload is how to turn off.
+pub(crate) const RELOAD_CONFIG_PATH: &str = "/admin/reload-config";
+
+/// Everything orders write to. The menu and stock levels are configuration
+/// and survive a reset.
+pub(crate) const RESET_TABLES: [&str; 8] = [
+    "orders", "order_items", "order_events", "orders_archive", "customers", "idempotency_keys", "outbox_events", "exports",
+];
+
+#[derive(Deserialize)]
+pub(crate) struct ResetParams {
+    /// Refill the sample data after clearing.
+    seed: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ResetReport {
+    tables: [&'static str; 8],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seeded: Option<SeedReport>,
+}
+
+/// Empties the order tables and restarts their ids at 1, in one transaction
+/// with the reseed when `?seed=true`.
+pub(crate) async fn reset_database(
+    State(db): State<PgPool>,
+    State(cache): State<OrderListCache>,
+    RequireAdmin(auth): RequireAdmin,
+    Query(params): Query<ResetParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut tx = db.begin().await?;
+    sqlx::query(&format!("TRUNCATE {} RESTART IDENTITY", RESET_TABLES.join(", ")))
+        .execute(&mut *tx)
+        .await?;
+    let seeded = if params.seed.unwrap_or(false) {
+        Some(seed(&mut tx).await?)
+    } else {
+        None
+    };
+    tx.commit().await?;
+    cache.invalidate();
+    tracing::warn!(client = auth.subject, seeded = seeded.is_some(), "database reset");
+
+    let data = Response {
+        status: true,
+        message: format!("cleared {}", RESET_TABLES.join(", ")),
+        data: Some(ResetReport { tables: RESET_TABLES, seeded }),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+/// Does what SIGHUP does: re-reads the config and applies its reloadable
+/// settings, or answers 422 with every problem and keeps the current ones.
+pub(crate) async fn reload_config(
+    State(reloader): State<ConfigReloader>,
+    RequireAdmin(auth): RequireAdmin,
+) -> Result<impl IntoResponse, ApiError> {
+    let report = reloader
+        .reload()
+        .map_err(|errors| ApiError::Unprocessable(format!("config is invalid, nothing reloaded: {}", errors.join("; "))))?;
+    tracing::info!(client = auth.subject, "config reload requested");
+
+    let data: Response<ReloadReport> = Response {
+        status: true,
+        message: "config reloaded".to_owned(),
+        data: Some(report),
+        meta: None,
+    };
+    Ok((StatusCode::OK, Json(data)))
+}
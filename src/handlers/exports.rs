@@ -0,0 +1,198 @@
+//! Starting order exports by hand and looking back at past runs.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::RequireAdmin;
+use crate::errors::{ApiError, FieldError, FieldErrorCode};
+use crate::exports::Exporter;
+use crate::models::{PageMeta, Response};
+use crate::pagination::{PageParams, Pagination};
+
+/// Days one manual run may cover.
+pub(crate) const MAX_EXPORT_DAYS: i64 = 31;
+
+#[derive(Clone, sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct ExportRun {
+    pub(crate) id: i64,
+    /// The UTC day whose orders are exported.
+    #[schema(example = "2024-05-21")]
+    pub(crate) export_date: NaiveDate,
+    /// csv or ndjson.
+    pub(crate) format: String,
+    #[schema(example = "orders/2024-05-21.csv")]
+    pub(crate) object_key: String,
+    /// running, succeeded or failed.
+    pub(crate) status: String,
+    /// Run although the day was already exported.
+    pub(crate) forced: bool,
+    pub(crate) row_count: Option<i64>,
+    pub(crate) byte_count: Option<i64>,
+    pub(crate) error: Option<String>,
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct RunExportParams {
+    /// First day to export, YYYY-MM-DD in UTC; defaults to yesterday.
+    from: Option<String>,
+    /// Last day to export, inclusive; defaults to `from`.
+    to: Option<String>,
+    /// Export days that already have a succeeded run again.
+    force: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct StartedExports {
+    /// Runs now uploading in the background; see `GET /admin/exports`.
+    started: Vec<ExportRun>,
+    /// Days already exported, or being exported by another run.
+    #[schema(value_type = Vec<String>, example = json!(["2024-05-20"]))]
+    skipped: Vec<NaiveDate>,
+}
+
+fn parse_day(name: &str, value: &str) -> Result<NaiveDate, FieldError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| FieldError::new(name, FieldErrorCode::Invalid, format!("must be a YYYY-MM-DD date, got '{value}'")))
+}
+
+/// Exports every day from `from` through `to`, each to its own object, as
+/// the nightly job does for yesterday. Answers once the runs are recorded;
+/// the uploads carry on in the background.
+#[utoipa::path(
+    post,
+    path = "/admin/exports/run",
+    tag = "admin",
+    params(
+        RunExportParams,
+    ),
+    responses(
+        (status = 202, description = "Exports started, skipping days already exported", body = StartedExportsResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 409, description = "Exports are not configured, EXPORT_S3_BUCKET is unset", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn run_exports(
+    State(pg_pool): State<PgPool>,
+    State(exporter): State<Option<Exporter>>,
+    RequireAdmin(auth): RequireAdmin,
+    Query(params): Query<RunExportParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let Some(exporter) = exporter else {
+        return Err(ApiError::Conflict("exports are not configured, set EXPORT_S3_BUCKET".to_owned()));
+    };
+
+    let today = Utc::now().date_naive();
+    let from = params.from.as_deref().map(|from| parse_day("from", from)).transpose();
+    let to = params.to.as_deref().map(|to| parse_day("to", to)).transpose();
+    let (from, to) = match (from, to) {
+        (Ok(from), Ok(to)) => {
+            let from = from.unwrap_or(today - TimeDelta::days(1));
+            (from, to.unwrap_or(from))
+        }
+        (from, to) => return Err(ApiError::Validation([from.err(), to.err()].into_iter().flatten().collect())),
+    };
+    let mut errors = Vec::new();
+    if to < from {
+        errors.push(FieldError::new("to", FieldErrorCode::OutOfRange, "must not be before from"));
+    } else if (to - from).num_days() >= MAX_EXPORT_DAYS {
+        errors.push(FieldError::new("to", FieldErrorCode::OutOfRange, format!("may be at most {MAX_EXPORT_DAYS} days after from")));
+    }
+    if to >= today {
+        errors.push(FieldError::new("to", FieldErrorCode::OutOfRange, "must be before today, whose orders are still coming in"));
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let forced = params.force.unwrap_or(false);
+    let mut started = Vec::new();
+    let mut skipped = Vec::new();
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        match exporter.claim(&pg_pool, date, forced).await? {
+            Some(run) => started.push(run),
+            None => skipped.push(date),
+        }
+    }
+    tracing::info!(client = auth.subject, %from, %to, forced, started = started.len(), skipped = skipped.len(), "order exports started");
+
+    exporter.spawn_runs(pg_pool, started.clone());
+
+    let data = Response {
+        status: true,
+        message: format!("started {} exports, skipped {}", started.len(), skipped.len()),
+        data: Some(StartedExports { started, skipped }),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(data),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/exports",
+    tag = "admin",
+    params(
+        PageParams,
+    ),
+    responses(
+        (status = 200, description = "Export runs, newest first", body = ExportRunsResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_exports(
+    State(pg_pool): State<PgPool>,
+    _admin: RequireAdmin,
+    Pagination { limit, offset, .. }: Pagination,
+) -> Result<impl IntoResponse, ApiError> {
+    let runs = sqlx::query_as!(
+        ExportRun,
+        "
+        SELECT id, export_date, format, object_key, status, forced, row_count, byte_count, error,
+            started_at, finished_at
+        FROM exports
+        ORDER BY id DESC
+        LIMIT $1 OFFSET $2
+        ",
+        limit,
+        offset
+    )
+    .fetch_all(&pg_pool)
+    .await?;
+    let total = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "count!" FROM exports"#)
+        .fetch_one(&pg_pool)
+        .await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} export runs (limit {limit}, offset {offset})", runs.len()),
+        data: Some(runs),
+        meta: Some(PageMeta::offset(limit, offset, total)),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
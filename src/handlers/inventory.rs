@@ -0,0 +1,242 @@
+//! Stock levels taken by open orders.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::{Path, State}, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use chrono::{DateTime, Utc};
+use utoipa::ToSchema;
+
+use crate::auth::AuthContext;
+use crate::db::{finish, ReadPool};
+use crate::errors::{ApiError, FieldError, FieldErrorCode, JsonBody};
+use crate::models::Response;
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct InventoryItem {
+    #[schema(example = "latte")]
+    coffee_name: String,
+    /// Cups left to sell, across all sizes.
+    #[schema(example = 40)]
+    stock: i32,
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateInventoryReq {
+    /// Sets the level, and starts tracking a coffee that was not tracked.
+    #[schema(example = 40)]
+    stock: Option<i32>,
+    /// Changes the level by this many cups instead, e.g. 24 for a delivery.
+    #[schema(example = 24)]
+    adjust: Option<i32>,
+}
+
+/// Takes the cups of the given lines out of stock, failing with 409 when a
+/// tracked coffee has too few left. The guarded UPDATE row-locks each coffee,
+/// so two orders for the last cup queue on it and only the first succeeds.
+pub(crate) async fn take_stock(conn: &mut PgConnection, coffee_names: &[String], quantities: &[i32]) -> Result<(), ApiError> {
+    if coffee_names.is_empty() {
+        return Ok(());
+    }
+
+    // the final SELECT sees the rows as they were, so it lists the coffees the UPDATE skipped
+    let short = sqlx::query!(
+        r#"
+        WITH wanted AS (
+            SELECT LOWER(coffee_name) AS key, SUM(quantity) AS quantity
+            FROM UNNEST($1::text[], $2::int[]) AS t(coffee_name, quantity)
+            GROUP BY 1
+        ), taken AS (
+            UPDATE inventory SET stock = inventory.stock - wanted.quantity, updated_at = now()
+            FROM wanted
+            WHERE LOWER(inventory.coffee_name) = wanted.key AND inventory.stock >= wanted.quantity
+            RETURNING inventory.coffee_name
+        )
+        SELECT inventory.coffee_name, inventory.stock
+        FROM inventory JOIN wanted ON LOWER(inventory.coffee_name) = wanted.key
+        WHERE inventory.coffee_name NOT IN (SELECT coffee_name FROM taken)
+        ORDER BY LOWER(inventory.coffee_name)
+        "#,
+        coffee_names,
+        quantities
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    if short.is_empty() {
+        return Ok(());
+    }
+    let coffees: Vec<String> = short
+        .iter()
+        .map(|row| format!("{} ({} left)", row.coffee_name, row.stock))
+        .collect();
+    Err(ApiError::Conflict(format!("out of stock: {}", coffees.join(", "))))
+}
+
+/// Puts the cups of the open orders among `ids` back into stock, before they
+/// are cancelled or deleted. Completed orders were served and keep theirs;
+/// cancelled and deleted ones have already given theirs back.
+pub(crate) async fn restock_orders(conn: &mut PgConnection, ids: &[i32]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "
+        WITH open AS (
+            SELECT id FROM orders
+            WHERE id = ANY($1) AND deleted_at IS NULL AND status IN ('pending', 'preparing', 'ready')
+            FOR UPDATE
+        ), returned AS (
+            SELECT LOWER(coffee_name) AS key, SUM(quantity) AS quantity
+            FROM order_items
+            WHERE order_id IN (SELECT id FROM open)
+            GROUP BY 1
+        )
+        UPDATE inventory SET stock = inventory.stock + returned.quantity, updated_at = now()
+        FROM returned
+        WHERE LOWER(inventory.coffee_name) = returned.key
+        ",
+        ids
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/inventory",
+    tag = "orders",
+    responses(
+        (status = 200, description = "Stock of every tracked coffee; others never run out", body = InventoryListResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_inventory(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let items = sqlx::query_as!(InventoryItem, "SELECT * FROM inventory ORDER BY LOWER(coffee_name)")
+        .fetch_all(&pg_pool)
+        .await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} tracked coffees", items.len()),
+        data: Some(items),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/inventory/{name}",
+    tag = "orders",
+    params(
+        ("name" = String, Path, description = "Coffee name as on the menu, any case"),
+    ),
+    request_body = UpdateInventoryReq,
+    responses(
+        (status = 200, description = "Stock updated", body = InventoryResponse),
+        (status = 409, description = "The adjustment would take stock below zero", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Not on the menu, or adjusting a coffee that is not tracked", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn update_inventory(
+    Path(name): Path<String>,
+    State(pg_pool): State<PgPool>,
+    auth: AuthContext,
+    JsonBody(req): JsonBody<UpdateInventoryReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    match (req.stock, req.adjust) {
+        (None, None) => return Err(ApiError::BadRequest("no fields provided to update".to_owned())),
+        (Some(_), Some(_)) => {
+            return Err(ApiError::Validation(vec![FieldError::new("adjust", FieldErrorCode::Conflict, "send either stock or adjust, not both")]))
+        }
+        (Some(stock), None) if stock < 0 => {
+            return Err(ApiError::Validation(vec![FieldError::new("stock", FieldErrorCode::OutOfRange, "must not be negative")]))
+        }
+        _ => {}
+    }
+
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        // stored with the menu's spelling, whatever case the path used
+        let coffee_name = sqlx::query_scalar!(
+            "SELECT coffee_name FROM menu_items WHERE LOWER(coffee_name) = LOWER($1) LIMIT 1",
+            name.trim()
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ApiError::NotFound(format!("no {} on the menu", name.trim())))?;
+
+        let item = match (req.stock, req.adjust) {
+            (Some(stock), _) => sqlx::query_as!(
+                InventoryItem,
+                "
+                INSERT INTO inventory (coffee_name, stock) VALUES ($1, $2)
+                ON CONFLICT ((LOWER(coffee_name))) DO UPDATE SET stock = EXCLUDED.stock, updated_at = now()
+                RETURNING *
+                ",
+                coffee_name,
+                stock
+            )
+            .fetch_one(&mut *tx)
+            .await?,
+            (None, adjust) => {
+                let adjust = adjust.unwrap_or_default();
+                let current = sqlx::query_as!(
+                    InventoryItem,
+                    "SELECT * FROM inventory WHERE LOWER(coffee_name) = LOWER($1) FOR UPDATE",
+                    coffee_name
+                )
+                .fetch_optional(&mut *tx)
+                .await?
+                .ok_or_else(|| ApiError::NotFound(format!("{coffee_name} is not tracked, set its stock first")))?;
+
+                let stock = current
+                    .stock
+                    .checked_add(adjust)
+                    .filter(|stock| *stock >= 0)
+                    .ok_or_else(|| ApiError::Conflict(format!("cannot adjust by {adjust}, {} left", current.stock)))?;
+                sqlx::query_as!(
+                    InventoryItem,
+                    "UPDATE inventory SET stock = $2, updated_at = now() WHERE coffee_name = $1 RETURNING *",
+                    current.coffee_name,
+                    stock
+                )
+                .fetch_one(&mut *tx)
+                .await?
+            }
+        };
+        Ok(item)
+    }
+    .await;
+    let item = finish(tx, result).await?;
+    tracing::info!(client = auth.subject, coffee_name = item.coffee_name, stock = item.stock, "inventory updated");
+
+    let data = Response {
+        status: true,
+        message: "inventory updated".to_owned(),
+        data: Some(item),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
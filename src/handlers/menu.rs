@@ -0,0 +1,167 @@
+//! The menu orders are priced from.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::PgConnection;
+use rust_decimal::Decimal;
+use utoipa::ToSchema;
+
+use crate::db::ReadPool;
+use crate::errors::{ApiError, FieldError, FieldErrorCode};
+use crate::handlers::orders::{CreateOrdersReq, MAX_TOTAL, NewItem, PricedOrder};
+use crate::models::{Money, Response, Size};
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct MenuItem {
+    id: i32,
+    #[schema(example = "flat white")]
+    pub(crate) coffee_name: String,
+    #[schema(example = "medium")]
+    pub(crate) size: Size,
+    /// Price in the smallest currency unit.
+    #[schema(example = 400)]
+    pub(crate) price: Money,
+}
+
+/// How far below the menu price a submitted `total` may be, as a
+/// percentage, for discounts given at the till. Zero requires an exact match.
+#[derive(Clone, Copy)]
+pub(crate) struct PriceTolerance(pub(crate) Decimal);
+
+impl PriceTolerance {
+    /// The amount to charge for a client's `total` against the menu's, which
+    /// is the submitted one when it is the menu price or an allowed discount.
+    pub(crate) fn check(self, submitted: Money, expected: Money) -> Result<Money, FieldError> {
+        let discount = expected.amount() - submitted.amount();
+        if discount >= Decimal::ZERO && discount * Decimal::ONE_HUNDRED <= expected.amount() * self.0 {
+            return Ok(submitted);
+        }
+        let message = if self.0.is_zero() {
+            format!("submitted {submitted} but the menu price is {expected}")
+        } else {
+            format!("submitted {submitted} but the menu price is {expected}, discounts of up to {}% are allowed", self.0)
+        };
+        Err(FieldError::new("total", FieldErrorCode::Invalid, message))
+    }
+}
+
+/// Every menu item, loaded once per request and priced against in memory;
+/// the menu is a few dozen rows.
+pub(crate) struct Menu {
+    items: Vec<MenuItem>,
+}
+
+impl Menu {
+    pub(crate) async fn load(conn: &mut PgConnection) -> Result<Menu, sqlx::Error> {
+        let items = sqlx::query_as!(
+            MenuItem,
+            r#"SELECT id, coffee_name, size AS "size: Size", price FROM menu_items ORDER BY LOWER(coffee_name), price"#
+        )
+        .fetch_all(conn)
+        .await?;
+        Ok(Menu { items })
+    }
+
+    /// Matches the coffee name case-insensitively. The error lists what is
+    /// on the menu so the client can correct the order.
+    pub(crate) fn find(&self, coffee_name: &str, size: Size) -> Result<&MenuItem, FieldError> {
+        let coffee_name = coffee_name.trim();
+        self.items
+            .iter()
+            .find(|item| item.size == size && item.coffee_name.eq_ignore_ascii_case(coffee_name))
+            .ok_or_else(|| {
+                FieldError::new(
+                    "coffee_name",
+                    FieldErrorCode::NotFound,
+                    format!("no {size} {coffee_name} on the menu, available: {}", self.available()),
+                )
+            })
+    }
+
+    /// Prices every line of a validated order, spelling coffee names as the
+    /// menu does. The total is the sum of the lines unless an admin's
+    /// `total_override` replaces it; a submitted `total` must agree with it.
+    pub(crate) fn price_order(&self, order: &CreateOrdersReq, tolerance: PriceTolerance) -> Result<PricedOrder, Vec<FieldError>> {
+        let mut items = Vec::new();
+        let mut errors = Vec::new();
+        for line in order.lines() {
+            match self.find(line.coffee_name, line.size) {
+                Ok(item) => items.push(NewItem {
+                    coffee_name: item.coffee_name.clone(),
+                    size: item.size,
+                    quantity: line.quantity,
+                    unit_price: item.price,
+                }),
+                Err(error) => errors.push(error.prefixed(&line.field_prefix)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let sum = items.iter().try_fold(Money::ZERO, |sum, item| {
+            item.unit_price.checked_mul(item.quantity).and_then(|line| sum.checked_add(line))
+        });
+        let total = match (order.total_override, sum) {
+            (Some(total), _) => total,
+            (None, Some(sum)) if sum <= MAX_TOTAL => match order.total {
+                Some(submitted) => tolerance.check(submitted, sum).map_err(|error| vec![error])?,
+                None => sum,
+            },
+            (None, _) => {
+                return Err(vec![FieldError::new("items", FieldErrorCode::OutOfRange, format!("order total may be at most {MAX_TOTAL}"))])
+            }
+        };
+        Ok(PricedOrder { items, total })
+    }
+
+    /// `latte (small, medium, large), mocha (small)`.
+    fn available(&self) -> String {
+        let mut entries: Vec<(&str, Vec<&str>)> = Vec::new();
+        for item in &self.items {
+            match entries.last_mut() {
+                Some((coffee_name, sizes)) if coffee_name.eq_ignore_ascii_case(&item.coffee_name) => {
+                    sizes.push(item.size.as_str())
+                }
+                _ => entries.push((&item.coffee_name, vec![item.size.as_str()])),
+            }
+        }
+        entries
+            .iter()
+            .map(|(coffee_name, sizes)| format!("{coffee_name} ({})", sizes.join(", ")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/menu",
+    tag = "orders",
+    responses(
+        (status = 200, description = "Everything that can be ordered, with prices", body = MenuResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_menu(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+) -> Result<impl IntoResponse, ApiError> {
+    let menu = Menu::load(&mut *pg_pool.acquire().await?).await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} menu items", menu.items.len()),
+        data: Some(menu.items),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
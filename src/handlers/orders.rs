@@ -0,0 +1,2379 @@
+//! Order CRUD: listing, creation, updates and deletes.
+use std::collections::HashMap;
+use std::sync::Arc;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{
+  async_trait,
+  extract::{rejection::QueryRejection, FromRequestParts, OriginalUri, Path, Query, State},
+  http::{header::{CONTENT_TYPE, ETAG, HOST, IF_MATCH, LINK, LOCATION, VARY}, request::Parts, HeaderMap, HeaderName, HeaderValue, StatusCode, Uri},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgDatabaseError;
+use sqlx::{Connection, PgConnection, PgPool, Postgres, QueryBuilder};
+use rust_decimal::Decimal;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::auth::{AuthContext, RequireAdmin};
+use crate::cache::{ListKey, X_CACHE};
+use crate::db::{finish, ReadPool, reading_from_primary};
+use crate::errors::{ApiError, FieldError, FieldErrorCode, JsonBody, OptionalJsonBody, UNIQUE_VIOLATION};
+use crate::feed::{OrderEventKind, OrderFeed};
+use crate::handlers::audit::{order_diff, record_order_event};
+use crate::handlers::csv::{CSV_COLUMNS, csv_line, order_csv_fields};
+use crate::handlers::customers::validate_email;
+use crate::handlers::inventory::{restock_orders, take_stock};
+use crate::handlers::menu::{Menu, PriceTolerance};
+use crate::handlers::reports::TimeRange;
+use crate::idempotency::{idempotency_key, request_hash};
+use crate::middleware::entity_tags;
+use crate::pagination::{PageParams, Pagination};
+use crate::receipts::Receipts;
+use crate::negotiate::{APPLICATION_JSON, APPLICATION_MSGPACK, TEXT_CSV, negotiate};
+use crate::repository::{Created, IdempotencyClaim, OrderPage, OrderRepository, PageRequest};
+use crate::models::{
+    CursorResponse, Money, OrderDetail, OrderItem, OrderStatus, Orders, PageMeta, Response, Size,
+    explicit_null, parse_size, parse_status,
+};
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct ListOrdersParams {
+    /// Keyset cursor from `next_cursor`; requires sorting by id ascending
+    /// and cannot be combined with `offset`.
+    after_id: Option<i32>,
+    /// Comma-separated fields from id, name, coffee_name, size, quantity,
+    /// total, status, created_at and updated_at, e.g. `status,-created_at`;
+    /// a leading `-` sorts that field descending.
+    sort: Option<String>,
+    /// asc or desc, for the fields in `sort` without a `-`.
+    dir: Option<String>,
+    /// `items` nests each order's line items.
+    include: Option<String>,
+}
+
+/// What `?fields=` may name: the order's own fields and `items`.
+pub(crate) const ORDER_FIELDS: [&str; 16] = [
+    "id", "name", "customer_id", "coffee_name", "size", "quantity", "total", "notes", "tags", "status",
+    "cancellation_reason", "version", "created_at", "updated_at", "deleted_at", "items",
+];
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct FieldsParams {
+    /// Comma-separated fields to return instead of all of them, e.g.
+    /// `id,coffee_name,size,status`.
+    fields: Option<String>,
+}
+
+impl FieldsParams {
+    /// The requested fields, checked against `ORDER_FIELDS`; `None` keeps
+    /// every field.
+    pub(crate) fn selection(&self) -> Result<Option<Vec<&str>>, ApiError> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+        let selected: Vec<&str> = fields.split(',').map(str::trim).filter(|field| !field.is_empty()).collect();
+        if let Some(unknown) = selected.iter().find(|field| !ORDER_FIELDS.contains(field)) {
+            return Err(ApiError::BadRequest(format!(
+                "invalid field '{unknown}', expected one of: {}",
+                ORDER_FIELDS.join(", ")
+            )));
+        }
+        if selected.is_empty() {
+            return Err(ApiError::BadRequest("fields must name at least one field".to_owned()));
+        }
+        Ok(Some(selected))
+    }
+}
+
+/// A response body as stored, or cut down to the fields `?fields=` named.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub(crate) enum Selected<T> {
+    All(T),
+    Fields(serde_json::Value),
+}
+
+/// Keeps only `fields` of an order, or of every order in a list.
+pub(crate) fn select_fields<T: Serialize>(value: T, fields: Option<&[&str]>) -> Selected<T> {
+    fn retain(value: serde_json::Value, fields: &[&str]) -> serde_json::Value {
+        match value {
+            serde_json::Value::Array(values) => values.into_iter().map(|value| retain(value, fields)).collect(),
+            serde_json::Value::Object(map) => map.into_iter().filter(|(field, _)| fields.contains(&field.as_str())).collect(),
+            other => other,
+        }
+    }
+
+    match fields {
+        Some(fields) => Selected::Fields(retain(serde_json::to_value(value).unwrap_or_default(), fields)),
+        None => Selected::All(value),
+    }
+}
+
+pub(crate) const SORT_FIELDS: [&str; 9] =
+    ["id", "name", "coffee_name", "size", "quantity", "total", "status", "created_at", "updated_at"];
+
+/// Statuses sort in the order an order moves through them, not by name.
+const STATUS_RANK: &str = "array_position(ARRAY['pending', 'preparing', 'ready', 'completed', 'cancelled'], status)";
+
+/// Maps the `sort` and `dir` query parameters onto a whitelisted ORDER BY
+/// expression. `sort` is a comma-separated list of fields, each descending
+/// with a leading `-` and otherwise in `dir`'s direction. id ends the
+/// expression as the tiebreaker unless listed, so pages are stable.
+pub(crate) fn order_by_clause(sort: Option<&str>, dir: Option<&str>) -> Result<String, String> {
+    let direction = match dir.unwrap_or("asc") {
+        "asc" => "ASC",
+        "desc" => "DESC",
+        other => return Err(format!("invalid sort direction '{other}', expected asc or desc")),
+    };
+
+    let mut fields: Vec<&str> = Vec::new();
+    let mut terms = Vec::new();
+    for entry in sort.unwrap_or("id").split(',').map(str::trim) {
+        let (field, direction) = match entry.strip_prefix('-') {
+            Some(field) => (field, "DESC"),
+            None => (entry, direction),
+        };
+        // the whitelisted name goes into the SQL, never the input
+        let column = match SORT_FIELDS.iter().find(|known| **known == field) {
+            Some(&"status") => STATUS_RANK,
+            Some(known) => known,
+            None if field.is_empty() => return Err("sort must not contain an empty field".to_owned()),
+            None => {
+                return Err(format!(
+                    "invalid sort field '{field}', expected one of: {}",
+                    SORT_FIELDS.join(", ")
+                ))
+            }
+        };
+        if fields.contains(&field) {
+            return Err(format!("sort field '{field}' is given more than once"));
+        }
+        fields.push(field);
+        terms.push(format!("{column} {direction}"));
+    }
+
+    if !fields.contains(&"id") {
+        terms.push("id ASC".to_owned());
+    }
+    Ok(terms.join(", "))
+}
+
+#[derive(Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct OrderFilter {
+    /// Exact match, case-insensitive.
+    pub(crate) name: Option<String>,
+    /// Exact match, case-insensitive.
+    pub(crate) coffee_name: Option<String>,
+    /// small, medium or large, in any case.
+    pub(crate) size: Option<String>,
+    pub(crate) status: Option<String>,
+    /// Substring search over name and coffee_name.
+    pub(crate) q: Option<String>,
+    /// Search notes with `q` as well.
+    pub(crate) search_notes: Option<bool>,
+    /// Admin only, surfaces soft-deleted orders for audits.
+    pub(crate) include_deleted: Option<bool>,
+    /// Earliest `created_at`, an RFC 3339 timestamp or a date meaning its start.
+    pub(crate) created_from: Option<String>,
+    /// Latest `created_at`, an RFC 3339 timestamp or a date meaning its end.
+    pub(crate) created_to: Option<String>,
+    /// `size` once `validate` has parsed it.
+    #[serde(skip)]
+    parsed_size: Option<Size>,
+    /// `created_from`/`created_to` once `validate` has parsed them.
+    #[serde(skip)]
+    created: TimeRange,
+    /// Every `tag` parameter, normalized; orders must carry all of them.
+    #[serde(skip)]
+    pub(crate) tags: Vec<String>,
+}
+
+/// Escapes the LIKE wildcards in user input so they match literally.
+pub(crate) fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Documents `tag`, which `OrderFilter` collects itself because a derived
+/// query struct cannot take a repeated parameter.
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct TagFilter {
+    /// Repeatable; orders must carry every tag given.
+    #[param(explode = true)]
+    #[allow(dead_code)]
+    tag: Option<Vec<String>>,
+}
+
+/// The filter parameters, plus the repeated `tag` ones, which axum's `Query`
+/// would reject as duplicate fields. Malformed queries get the JSON 400.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for OrderFilter {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let bad_query = |rejection: QueryRejection| ApiError::BadRequest(rejection.body_text());
+        let Query(mut filter) = Query::<OrderFilter>::try_from_uri(&parts.uri).map_err(bad_query)?;
+        let Query(pairs) = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri).map_err(bad_query)?;
+        let tags: Vec<String> = pairs.into_iter().filter(|(key, _)| key == "tag").map(|(_, tag)| tag).collect();
+        filter.tags = normalize_tags(&tags);
+        Ok(filter)
+    }
+}
+
+impl OrderFilter {
+    pub(crate) fn filters_status(&self) -> bool {
+        self.status.is_some()
+    }
+
+    /// Rejects filters the caller may not use or that could never match,
+    /// and parses the size and the date range for `push_order_filters`.
+    pub(crate) fn validate(&mut self, auth: &AuthContext) -> Result<(), ApiError> {
+        if self.include_deleted.unwrap_or(false) {
+            auth.require_admin()?;
+        }
+
+        if let Some(status) = &self.status {
+            parse_status(status)?;
+        }
+        self.parsed_size = self.size.as_deref().map(parse_size).transpose()?;
+
+        if self.tags.iter().any(String::is_empty) {
+            return Err(ApiError::BadRequest("tag must not be empty".to_owned()));
+        }
+
+        self.created = TimeRange::parse(
+            ("created_from", self.created_from.as_deref()),
+            ("created_to", self.created_to.as_deref()),
+        )?;
+        Ok(())
+    }
+
+    /// The cache key of `page` under this filter, once `validate` has run.
+    /// Case-insensitive filters are lowercased and the tags sorted, as
+    /// neither changes what matches.
+    pub(crate) fn list_key(&self, page: &PageRequest) -> ListKey {
+        let q = self.q.clone().filter(|term| !term.is_empty());
+        let mut tags = self.tags.clone();
+        tags.sort();
+        ListKey {
+            name: self.name.as_deref().map(str::to_lowercase),
+            coffee_name: self.coffee_name.as_deref().map(str::to_lowercase),
+            size: self.parsed_size,
+            status: self.status.clone(),
+            search_notes: q.is_some() && self.search_notes.unwrap_or(false),
+            q: q.map(|term| term.to_lowercase()),
+            include_deleted: self.include_deleted.unwrap_or(false),
+            created: self.created,
+            tags,
+            limit: page.limit,
+            offset: if page.after_id.is_some() { 0 } else { page.offset },
+            after_id: page.after_id,
+            order_by: page.order_by.clone(),
+            include_items: page.include_items,
+            from_primary: reading_from_primary(),
+        }
+    }
+}
+
+/// Appends an `AND` condition for every filter that is set. The query must
+/// already contain a `WHERE` clause.
+pub(crate) fn push_order_filters(q: &mut QueryBuilder<'_, Postgres>, filter: &OrderFilter) {
+    if !filter.include_deleted.unwrap_or(false) {
+        q.push(" AND deleted_at IS NULL");
+    }
+
+    if let Some(name) = &filter.name {
+        q.push(" AND LOWER(name) = LOWER(").push_bind(name.clone()).push(")");
+    }
+
+    if let Some(coffee_name) = &filter.coffee_name {
+        q.push(" AND LOWER(coffee_name) = LOWER(").push_bind(coffee_name.clone()).push(")");
+    }
+
+    if let Some(size) = filter.parsed_size {
+        q.push(" AND size = ").push_bind(size);
+    }
+
+    if let Some(status) = &filter.status {
+        q.push(" AND status = ").push_bind(status.clone());
+    }
+
+    if let Some(term) = filter.q.as_deref().filter(|term| !term.is_empty()) {
+        let term = escape_like(term);
+        q.push(" AND (name ILIKE '%' || ").push_bind(term.clone());
+        q.push(" || '%' OR coffee_name ILIKE '%' || ").push_bind(term.clone());
+        if filter.search_notes.unwrap_or(false) {
+            q.push(" || '%' OR notes ILIKE '%' || ").push_bind(term);
+        }
+        q.push(" || '%')");
+    }
+
+    if !filter.tags.is_empty() {
+        q.push(" AND tags @> ").push_bind(filter.tags.clone()).push("::text[]");
+    }
+
+    filter.created.push_conditions(q, "created_at");
+}
+
+pub(crate) static X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+
+/// Orders matching `filter`, ignoring pagination.
+pub(crate) async fn count_orders(conn: &mut PgConnection, filter: &OrderFilter) -> Result<i64, sqlx::Error> {
+    let mut q = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM orders WHERE TRUE");
+    push_order_filters(&mut q, filter);
+    q.build_query_scalar::<i64>().fetch_one(conn).await
+}
+
+pub(crate) static X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+pub(crate) static X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+pub(crate) static X_FORWARDED_PREFIX: HeaderName = HeaderName::from_static("x-forwarded-prefix");
+
+/// The request's URL as the client addressed it, so links built from it
+/// are followable behind the proxy, which sets the X-Forwarded-* headers.
+pub(crate) fn public_url(headers: &HeaderMap, uri: &Uri) -> Option<reqwest::Url> {
+    // a proxy chain appends to these, the first entry is the client-facing one
+    let header = |name: &HeaderName| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let proto = header(&X_FORWARDED_PROTO).unwrap_or("http");
+    let host = header(&X_FORWARDED_HOST).or_else(|| header(&HOST))?;
+    let prefix = header(&X_FORWARDED_PREFIX).unwrap_or_default().trim_end_matches('/');
+
+    let mut url = reqwest::Url::parse(&format!("{proto}://{host}{prefix}{}", uri.path())).ok()?;
+    url.set_query(uri.query());
+    Some(url)
+}
+
+/// RFC 5988 `Link` value with one entry per relation. Each page is the
+/// current URL with its paging parameter replaced, or removed when `None`;
+/// filters, sort and limit carry over as sent.
+pub(crate) fn pagination_links(url: &reqwest::Url, pages: &[(&str, Option<(&str, i64)>)]) -> Option<HeaderValue> {
+    let links: Vec<String> = pages
+        .iter()
+        .map(|(rel, page)| {
+            let kept: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(key, _)| key != "offset" && key != "after_id")
+                .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                .collect();
+            let mut link = url.clone();
+            link.set_query(None);
+            {
+                let mut query = link.query_pairs_mut();
+                query.extend_pairs(kept);
+                if let Some((key, value)) = page {
+                    query.append_pair(key, &value.to_string());
+                }
+            }
+            if link.query() == Some("") {
+                link.set_query(None);
+            }
+            format!("<{link}>; rel=\"{rel}\"")
+        })
+        .collect();
+    HeaderValue::from_str(&links.join(", ")).ok()
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrderCount {
+    #[schema(example = 42)]
+    count: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/count",
+    tag = "orders",
+    params(
+        OrderFilter,
+        TagFilter,
+    ),
+    responses(
+        (status = 200, description = "How many orders match the filters", body = OrderCountResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_order_count(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    auth: AuthContext,
+    mut filter: OrderFilter,
+) -> Result<impl IntoResponse, ApiError> {
+    filter.validate(&auth)?;
+
+    let count = count_orders(&mut *pg_pool.acquire().await?, &filter).await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {count} orders"),
+        data: Some(OrderCount { count }),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct TagCount {
+    #[schema(example = "loyalty")]
+    tag: String,
+    /// Live orders carrying the tag.
+    #[schema(example = 12)]
+    count: i64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/orders/tags",
+    tag = "orders",
+    responses(
+        (status = 200, description = "Every tag on a live order, most used first", body = TagCountsResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_order_tags(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    _auth: AuthContext,
+) -> Result<impl IntoResponse, ApiError> {
+    let tags = sqlx::query_as!(
+        TagCount,
+        r#"
+        SELECT tag AS "tag!", COUNT(*) AS "count!"
+        FROM orders, UNNEST(tags) AS tag
+        WHERE deleted_at IS NULL
+        GROUP BY tag
+        ORDER BY COUNT(*) DESC, tag
+        "#
+    )
+    .fetch_all(&pg_pool)
+    .await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} tags", tags.len()),
+        data: Some(tags),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+
+#[utoipa::path(
+    get,
+    path = "/orders",
+    tag = "orders",
+    params(
+        PageParams,
+        ListOrdersParams,
+        FieldsParams,
+        OrderFilter,
+        TagFilter,
+    ),
+    responses(
+        (status = 200, description = "A page of orders; with `after_id` the body is an OrderCursorResponse. \
+            With `Accept: text/csv` the page is CSV in the export's columns, regardless of `fields`",
+            content(
+                ("application/json" = OrderListResponse),
+                ("text/csv" = String),
+            ),
+            headers(
+                ("X-Total-Count" = i64, description = "Orders matching the filters across all pages"),
+                ("Link" = String, description = "first, prev, next and last pages; with `after_id` only first and next"),
+                ("X-Cache" = String, description = "hit or miss; absent when the list cache is off"),
+                ("ETag" = String, description = "Hash of the response body, for If-None-Match"),
+            )),
+        (status = 304, description = "The page is unchanged since the ETag in If-None-Match"),
+        (status = 406, description = "Accept allows none of application/json, text/csv and application/msgpack", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn get_orders(
+    State(state): State<AppState>,
+    auth: AuthContext,
+    headers: HeaderMap,
+    OriginalUri(uri): OriginalUri,
+    Pagination { limit, offset, explicit_offset }: Pagination,
+    Query(params): Query<ListOrdersParams>,
+    Query(fields): Query<FieldsParams>,
+    mut filter: OrderFilter,
+) -> Result<axum::response::Response, ApiError> {
+
+    filter.validate(&auth)?;
+    let fields = fields.selection()?;
+    // MessagePack is JSON re-encoded on the way out by `msgpack_responses`
+    let csv = negotiate(&headers, &[APPLICATION_JSON, TEXT_CSV, APPLICATION_MSGPACK])? == TEXT_CSV;
+
+    if explicit_offset && params.after_id.is_some() {
+        return Err(ApiError::BadRequest("offset and after_id are mutually exclusive".to_owned()));
+    }
+
+    let order_by = order_by_clause(params.sort.as_deref(), params.dir.as_deref())
+        .and_then(|order_by| {
+            if params.after_id.is_some() && order_by != "id ASC" {
+                Err("after_id can only be used when sorting by id ascending".to_owned())
+            } else {
+                Ok(order_by)
+            }
+        })
+        .map_err(ApiError::BadRequest)?;
+
+    let mut include_items = false;
+    for include in params.include.as_deref().unwrap_or_default().split(',').map(str::trim) {
+        match include {
+            "" => {}
+            "items" => include_items = true,
+            other => return Err(ApiError::BadRequest(format!("invalid include '{other}', expected items"))),
+        }
+    }
+
+    let page = PageRequest {
+        limit,
+        offset,
+        after_id: params.after_id,
+        order_by,
+        include_items,
+    };
+    let cache = &state.list_cache;
+    let key = filter.list_key(&page);
+    let (cached, generation) = (cache.get(&key), cache.generation());
+    let hit = cached.is_some();
+    let OrderPage { orders: tr, total: total_count, next_cursor } = match cached {
+        Some(page) => page,
+        None => {
+            let fresh = state.orders.list(&filter, &page).await?;
+            cache.insert(generation, key, fresh.clone());
+            fresh
+        }
+    };
+
+    // keyset pages only run forward, so cursor mode has no prev or last
+    let pages = if params.after_id.is_some() {
+        let mut pages = vec![("first", None)];
+        if let Some(next_cursor) = next_cursor {
+            pages.push(("next", Some(("after_id", i64::from(next_cursor)))));
+        }
+        pages
+    } else {
+        let mut pages = vec![("first", Some(("offset", 0)))];
+        if offset > 0 {
+            pages.push(("prev", Some(("offset", (offset - limit).max(0)))));
+        }
+        if offset + limit < total_count {
+            pages.push(("next", Some(("offset", offset + limit))));
+        }
+        pages.push(("last", Some(("offset", (total_count - 1).max(0) / limit * limit))));
+        pages
+    };
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(X_TOTAL_COUNT.clone(), HeaderValue::from(total_count));
+    if let Some(links) = public_url(&headers, &uri).and_then(|url| pagination_links(&url, &pages)) {
+        response_headers.insert(LINK, links);
+    }
+    // the same URL answers in JSON or CSV, so caches must key on Accept
+    response_headers.insert(VARY, HeaderValue::from_static("accept"));
+    if cache.enabled() {
+        response_headers.insert(X_CACHE.clone(), HeaderValue::from_static(if hit { "hit" } else { "miss" }));
+    }
+
+    if csv {
+        let lines: Result<Vec<Vec<u8>>, csv::Error> = std::iter::once(csv_line(CSV_COLUMNS))
+            .chain(tr.into_iter().map(|detail| csv_line(order_csv_fields(detail.order))))
+            .collect();
+        // records of a fixed width written to memory cannot fail
+        let body = lines.expect("csv lines are well formed").concat();
+        response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"));
+        return Ok((StatusCode::OK, response_headers, body).into_response());
+    }
+    let tr = select_fields(tr, fields.as_deref());
+
+    if let Some(after_id) = params.after_id {
+        let data = CursorResponse {
+            response: Response {
+                status: true,
+                message: format!("found orders (limit {limit}, after_id {after_id})"),
+                data: Some(tr),
+                meta: Some(PageMeta { page: None, per_page: limit, total: Some(total_count), next_cursor }),
+            },
+            next_cursor,
+        };
+
+        return Ok((StatusCode::OK, response_headers, Json(data)).into_response());
+    }
+
+
+    let data = Response {
+        status: true,
+        message: format!("found orders (limit {limit}, offset {offset})"),
+        data: Some(tr),
+        meta: Some(PageMeta::offset(limit, offset, total_count)),
+    };
+
+
+    Ok((
+        StatusCode::OK,
+        response_headers,
+        Json(data),
+    ).into_response())
+}
+
+pub(crate) const MAX_NAME_LENGTH: usize = 100;
+pub(crate) const MAX_NOTES_LENGTH: usize = 1000;
+pub(crate) const MAX_TAGS: usize = 10;
+pub(crate) const MAX_TAG_LENGTH: usize = 32;
+pub(crate) const MAX_TOTAL: Money = Money(Decimal::from_parts(100_000, 0, 0, false, 2));
+
+/// Checks whichever order fields are present and collects every problem
+/// rather than stopping at the first one.
+pub(crate) fn validate_order_fields(
+    name: Option<&str>,
+    coffee_name: Option<&str>,
+    total: Option<Money>,
+) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    for (field, value) in [("name", name), ("coffee_name", coffee_name)] {
+        match value.map(str::trim) {
+            Some("") => errors.push(FieldError::new(field, FieldErrorCode::Required, "must not be empty")),
+            Some(value) if value.chars().count() > MAX_NAME_LENGTH => errors.push(FieldError::new(
+                field,
+                FieldErrorCode::TooLong,
+                format!("must be at most {MAX_NAME_LENGTH} characters"),
+            )),
+            _ => {}
+        }
+    }
+
+    if let Some(total) = total {
+        // zero is allowed so a comped drink can be recorded
+        if total > MAX_TOTAL {
+            errors.push(FieldError::new("total_override", FieldErrorCode::OutOfRange, format!("must be at most {MAX_TOTAL}")));
+        }
+    }
+
+    errors
+}
+
+/// Notes past `MAX_NOTES_LENGTH`, counted after trimming.
+pub(crate) fn validate_notes(notes: Option<&str>) -> Option<FieldError> {
+    notes
+        .filter(|notes| notes.trim().chars().count() > MAX_NOTES_LENGTH)
+        .map(|_| FieldError::new("notes", FieldErrorCode::TooLong, format!("must be at most {MAX_NOTES_LENGTH} characters")))
+}
+
+/// A `status` that names no status, with the ones that do.
+pub(crate) fn validate_status(status: Option<&str>) -> Option<FieldError> {
+    status
+        .and_then(|status| status.parse::<OrderStatus>().err())
+        .map(|message| FieldError::new("status", FieldErrorCode::Invalid, message))
+}
+
+/// Trimmed notes, with blank ones stored as none at all.
+pub(crate) fn clean_notes(notes: Option<String>) -> Option<String> {
+    notes.map(|notes| notes.trim().to_owned()).filter(|notes| !notes.is_empty())
+}
+
+/// Trimmed, lowercased and deduplicated, keeping the first occurrence.
+pub(crate) fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Checks the tags as they will be stored, so duplicates that differ only in
+/// case or spacing count once towards `MAX_TAGS`.
+pub(crate) fn validate_tags(tags: &[String]) -> Vec<FieldError> {
+    let tags = normalize_tags(tags);
+    let mut errors = Vec::new();
+    if tags.len() > MAX_TAGS {
+        errors.push(FieldError::new("tags", FieldErrorCode::OutOfRange, format!("may contain at most {MAX_TAGS} tags")));
+    }
+    for (index, tag) in tags.iter().enumerate() {
+        if tag.is_empty() {
+            errors.push(FieldError::new(&format!("tags[{index}]"), FieldErrorCode::Required, "must not be empty"));
+        } else if tag.chars().count() > MAX_TAG_LENGTH {
+            errors.push(FieldError::new(
+                &format!("tags[{index}]"),
+                FieldErrorCode::TooLong,
+                format!("must be at most {MAX_TAG_LENGTH} characters"),
+            ));
+        }
+    }
+    errors
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub(crate) struct CreateOrdersReq {
+    /// Customer name, matched case-insensitively and created when new.
+    /// Also accepted as `customer_name`.
+    #[serde(default, alias = "customer_name")]
+    #[schema(example = "Ada")]
+    pub(crate) name: Option<String>,
+    /// An existing customer, instead of `name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) customer_id: Option<i32>,
+    /// Single-item shorthand for `items`, ordering one of this coffee.
+    #[schema(example = "flat white")]
+    pub(crate) coffee_name: Option<String>,
+    /// small, medium or large, in any case.
+    #[schema(example = "medium")]
+    pub(crate) size: Option<Size>,
+    /// Cups of `coffee_name`, defaults to 1. Items carry their own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 1)]
+    pub(crate) quantity: Option<i32>,
+    /// The order's lines, instead of `coffee_name` and `size`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) items: Option<Vec<OrderItemReq>>,
+    /// The total the client expects to pay, rejected when it does not match
+    /// the menu. A discount within PRICE_TOLERANCE_PERCENT is charged as sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) total: Option<Money>,
+    /// Admin only. Charged instead of the sum of the menu prices, without
+    /// checking it, e.g. "0" for a comped drink.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) total_override: Option<Money>,
+    /// Defaults to pending.
+    pub(crate) status: Option<String>,
+    /// Free text, at most 1000 characters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "extra hot, oat milk")]
+    pub(crate) notes: Option<String>,
+    /// Labels, stored trimmed and lowercase; at most 10 of up to 32 characters.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[schema(example = json!(["loyalty"]))]
+    pub(crate) tags: Vec<String>,
+    /// Where to send a receipt once the order is placed. Not stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "ada@example.com")]
+    pub(crate) email: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub(crate) struct OrderItemReq {
+    #[schema(example = "latte")]
+    pub(crate) coffee_name: String,
+    /// small, medium or large, in any case.
+    #[schema(example = "large")]
+    pub(crate) size: Size,
+    /// Defaults to 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 2)]
+    pub(crate) quantity: Option<i32>,
+}
+
+/// A line of a create request, whichever form it was sent in.
+pub(crate) struct OrderLine<'a> {
+    /// Prefix for the line's validation errors, e.g. `items[1].`.
+    pub(crate) field_prefix: String,
+    pub(crate) coffee_name: &'a str,
+    pub(crate) size: Size,
+    pub(crate) quantity: i32,
+}
+
+impl CreateOrdersReq {
+    /// The flat `coffee_name`/`size`/`quantity` form is a single line. Only
+    /// meaningful once `validate_new_order` has found both present.
+    pub(crate) fn lines(&self) -> Vec<OrderLine<'_>> {
+        match &self.items {
+            Some(items) => items
+                .iter()
+                .enumerate()
+                .map(|(index, item)| OrderLine {
+                    field_prefix: format!("items[{index}]."),
+                    coffee_name: &item.coffee_name,
+                    size: item.size,
+                    quantity: item.quantity.unwrap_or(1),
+                })
+                .collect(),
+            None => vec![OrderLine {
+                field_prefix: String::new(),
+                coffee_name: self.coffee_name.as_deref().unwrap_or_default(),
+                size: self.size.unwrap_or(Size::Medium),
+                quantity: self.quantity.unwrap_or(1),
+            }],
+        }
+    }
+}
+
+pub(crate) const MAX_ORDER_ITEMS: usize = 50;
+pub(crate) const MAX_QUANTITY: i32 = 100;
+
+/// Field checks plus the rules that an order names exactly one customer and
+/// is given either as `items` or as a flat coffee_name and size.
+pub(crate) fn validate_new_order(order: &CreateOrdersReq) -> Vec<FieldError> {
+    let mut errors = validate_order_fields(order.name.as_deref(), None, order.total_override);
+    errors.extend(validate_status(order.status.as_deref()));
+    errors.extend(validate_notes(order.notes.as_deref()));
+    errors.extend(validate_tags(&order.tags));
+    errors.extend(validate_email(order.email.as_deref()));
+
+    if order.total.is_some() && order.total_override.is_some() {
+        errors.push(FieldError::new("total", FieldErrorCode::Conflict, "send either total or total_override, not both"));
+    }
+
+    match (&order.name, order.customer_id) {
+        (Some(_), Some(_)) => errors.push(FieldError::new("customer_id", FieldErrorCode::Conflict, "send either customer_id or name, not both")),
+        (None, None) => errors.push(FieldError::new("name", FieldErrorCode::Required, "is required unless customer_id is given")),
+        _ => {}
+    }
+
+    match &order.items {
+        Some(_) if order.coffee_name.is_some() || order.size.is_some() || order.quantity.is_some() => {
+            errors.push(FieldError::new("items", FieldErrorCode::Conflict, "send either items or coffee_name, size and quantity, not both"));
+            return errors;
+        }
+        Some(items) if items.is_empty() => {
+            errors.push(FieldError::new("items", FieldErrorCode::OutOfRange, "must contain at least one item"));
+            return errors;
+        }
+        Some(items) if items.len() > MAX_ORDER_ITEMS => {
+            errors.push(FieldError::new("items", FieldErrorCode::OutOfRange, format!("may contain at most {MAX_ORDER_ITEMS} items")));
+            return errors;
+        }
+        Some(_) => {}
+        None => {
+            for (field, missing) in [("coffee_name", order.coffee_name.is_none()), ("size", order.size.is_none())] {
+                if missing {
+                    errors.push(FieldError::new(field, FieldErrorCode::Required, "is required unless items are given"));
+                }
+            }
+            if order.coffee_name.is_none() || order.size.is_none() {
+                return errors;
+            }
+        }
+    }
+
+    for line in order.lines() {
+        errors.extend(
+            validate_order_fields(None, Some(line.coffee_name), None)
+                .into_iter()
+                .map(|error| error.prefixed(&line.field_prefix)),
+        );
+        if !(1..=MAX_QUANTITY).contains(&line.quantity) {
+            errors.push(FieldError::new(
+                &format!("{}quantity", line.field_prefix),
+                FieldErrorCode::OutOfRange,
+                format!("must be between 1 and {MAX_QUANTITY}"),
+            ));
+        }
+    }
+
+    errors
+}
+
+/// A line priced from the menu, ready to insert.
+pub(crate) struct NewItem {
+    pub(crate) coffee_name: String,
+    pub(crate) size: Size,
+    pub(crate) quantity: i32,
+    pub(crate) unit_price: Money,
+}
+
+/// Menu-priced lines and the total to charge for them.
+pub(crate) struct PricedOrder {
+    pub(crate) items: Vec<NewItem>,
+    pub(crate) total: Money,
+}
+
+/// Pairs each order with its lines, fetched in one query.
+pub(crate) async fn with_items(conn: &mut PgConnection, orders: Vec<Orders>) -> Result<Vec<OrderDetail>, sqlx::Error> {
+    let ids: Vec<i32> = orders.iter().filter_map(|order| order.id).collect();
+    let mut items = order_items(conn, &ids).await?;
+
+    Ok(orders
+        .into_iter()
+        .map(|order| {
+            let own = order.id.and_then(|id| items.remove(&id)).unwrap_or_default();
+            OrderDetail { order, items: Some(own) }
+        })
+        .collect())
+}
+
+/// The lines of each of `ids` that has any, in id order.
+pub(crate) async fn order_items(conn: &mut PgConnection, ids: &[i32]) -> Result<HashMap<i32, Vec<OrderItem>>, sqlx::Error> {
+    let rows = sqlx::query_as!(
+        OrderItem,
+        r#"
+        SELECT id, order_id, coffee_name, size AS "size: Size", quantity, unit_price
+        FROM order_items WHERE order_id = ANY($1) ORDER BY id
+        "#,
+        ids
+    )
+    .fetch_all(conn)
+    .await?;
+
+    let mut items: HashMap<i32, Vec<OrderItem>> = HashMap::new();
+    for item in rows {
+        items.entry(item.order_id).or_default().push(item);
+    }
+    Ok(items)
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct CreateOrdersRow {
+    id: i32
+}
+
+/// Validated orders laid out column-wise for a single UNNEST insert.
+#[derive(Default)]
+pub(crate) struct NewOrders {
+    names: Vec<Option<String>>,
+    customer_ids: Vec<Option<i32>>,
+    coffee_names: Vec<String>,
+    sizes: Vec<Size>,
+    quantities: Vec<i32>,
+    totals: Vec<Decimal>,
+    statuses: Vec<String>,
+    notes: Vec<Option<String>>,
+    /// Each order's tags as a JSON array, since Postgres arrays cannot be
+    /// ragged and so cannot hold one text[] per order.
+    tags: Vec<serde_json::Value>,
+    /// 1-based position in the columns above of the order each line belongs to.
+    item_orders: Vec<i32>,
+    item_coffee_names: Vec<String>,
+    item_sizes: Vec<Size>,
+    item_quantities: Vec<i32>,
+    item_unit_prices: Vec<Decimal>,
+}
+
+impl NewOrders {
+    pub(crate) fn push(&mut self, order: CreateOrdersReq, priced: PricedOrder, status: OrderStatus) {
+        // the header mirrors the first line for clients that predate items
+        let first = &priced.items[0];
+        self.names.push(order.name.map(|name| name.trim().to_owned()));
+        self.customer_ids.push(order.customer_id);
+        self.coffee_names.push(first.coffee_name.clone());
+        self.sizes.push(first.size);
+        self.quantities.push(priced.items.iter().map(|item| item.quantity).sum());
+        self.totals.push(priced.total.amount());
+        self.statuses.push(status.as_str().to_owned());
+        self.notes.push(clean_notes(order.notes));
+        self.tags.push(normalize_tags(&order.tags).into());
+
+        let position = self.coffee_names.len() as i32;
+        for item in priced.items {
+            self.item_orders.push(position);
+            self.item_coffee_names.push(item.coffee_name);
+            self.item_sizes.push(item.size);
+            self.item_quantities.push(item.quantity);
+            self.item_unit_prices.push(item.unit_price.amount());
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.coffee_names.len()
+    }
+
+    /// Coffee names and quantities of the lines that take stock, those of
+    /// orders created open.
+    fn open_lines(&self) -> (Vec<String>, Vec<i32>) {
+        self.item_orders
+            .iter()
+            .zip(&self.item_coffee_names)
+            .zip(&self.item_quantities)
+            .filter(|((position, _), _)| {
+                self.statuses[**position as usize - 1]
+                    .parse::<OrderStatus>()
+                    .is_ok_and(OrderStatus::is_open)
+            })
+            .map(|((_, coffee_name), quantity)| (coffee_name.clone(), *quantity))
+            .unzip()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.coffee_names.is_empty()
+    }
+}
+
+/// Ids from `ids` with no customer row. The rest are key-share locked so
+/// they cannot be deleted before the orders referencing them commit.
+pub(crate) async fn missing_customers(conn: &mut PgConnection, ids: &[i32]) -> Result<Vec<i32>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let found = sqlx::query_scalar!(
+        "SELECT id FROM customers WHERE id = ANY($1) FOR KEY SHARE",
+        ids
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(ids.iter().copied().filter(|id| !found.contains(id)).collect())
+}
+
+// the order columns a unique rule may be declared on
+const ORDER_KEY_COLUMNS: [&str; 6] = ["name", "customer_id", "coffee_name", "size", "quantity", "status"];
+
+/// The columns of the unique rule on orders that `err` violated, read from
+/// Postgres' `Key (a, b)=(...) already exists` detail. `None` for any other
+/// error, and for rules on expressions or columns outside `ORDER_KEY_COLUMNS`.
+fn unique_key_columns(err: &sqlx::Error) -> Option<Vec<&'static str>> {
+    let db_err = err.as_database_error()?.try_downcast_ref::<PgDatabaseError>()?;
+    if db_err.code() != UNIQUE_VIOLATION || db_err.table() != Some("orders") {
+        return None;
+    }
+    let key = db_err.detail()?.strip_prefix("Key (")?.split_once(")=(")?.0;
+    key.split(',')
+        .map(|column| ORDER_KEY_COLUMNS.iter().copied().find(|known| *known == column.trim()))
+        .collect()
+}
+
+/// The conflict to report once inserting `orders` broke the unique rule on
+/// `columns`: each order that matches an existing one, with that order's id,
+/// and each that repeats an earlier order of the same batch.
+async fn duplicate_orders(conn: &mut PgConnection, orders: &NewOrders, columns: &[&str]) -> Result<ApiError, sqlx::Error> {
+    let same = |left: &str, right: &str| {
+        columns
+            .iter()
+            .map(|column| format!("{left}.{column} = {right}.{column}"))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    };
+    // the same rows the insert would have written
+    let sql = format!(
+        "
+        WITH t AS (
+            SELECT customers.name, customers.id AS customer_id, u.coffee_name, u.size, u.quantity, u.status, u.ord
+            FROM UNNEST($1::text[], $2::int[], $3::text[], $4::order_size[], $5::int[], $6::text[])
+                WITH ORDINALITY AS u(name, customer_id, coffee_name, size, quantity, status, ord)
+            JOIN customers ON customers.id = COALESCE(
+                u.customer_id,
+                (SELECT c.id FROM customers c WHERE LOWER(c.name) = LOWER(u.name))
+            )
+        )
+        SELECT t.ord - 1, (SELECT o.id FROM orders o WHERE {existing} ORDER BY o.id LIMIT 1)
+        FROM t
+        WHERE EXISTS (SELECT 1 FROM orders o WHERE {existing})
+            OR EXISTS (SELECT 1 FROM t AS earlier WHERE earlier.ord < t.ord AND {earlier})
+        ORDER BY t.ord
+        ",
+        existing = same("o", "t"),
+        earlier = same("earlier", "t"),
+    );
+    let conflicts: Vec<(i64, Option<i32>)> = sqlx::query_as(&sql)
+        .bind(&orders.names)
+        .bind(&orders.customer_ids)
+        .bind(&orders.coffee_names)
+        .bind(&orders.sizes)
+        .bind(&orders.quantities)
+        .bind(&orders.statuses)
+        .fetch_all(conn)
+        .await?;
+
+    let fields = columns.join(", ");
+    if let ([(_, Some(id))], 1) = (conflicts.as_slice(), orders.names.len()) {
+        return Ok(ApiError::AlreadyExists(format!("an order with the same {fields} already exists: order {id}")));
+    }
+    let conflicts: Vec<String> = conflicts
+        .iter()
+        .map(|(index, existing)| match existing {
+            Some(id) => format!("order at index {index} matches order {id}"),
+            None => format!("order at index {index} repeats an earlier one"),
+        })
+        .collect();
+    Ok(ApiError::AlreadyExists(format!("orders must be unique on {fields}: {}", conflicts.join("; "))))
+}
+
+/// Inserts every order in one statement, then their lines and `created`
+/// audit events, returning the new rows in input order. Customers named inline are
+/// created first when no existing one matches case-insensitively; orders
+/// given a `customer_id` must reference a customer that exists, see
+/// `missing_customers`. Open orders take their cups out of stock first.
+pub(crate) async fn insert_orders(
+    conn: &mut PgConnection,
+    orders: &NewOrders,
+    actor: &str,
+) -> Result<Vec<Orders>, ApiError> {
+    let (coffee_names, quantities) = orders.open_lines();
+    take_stock(&mut *conn, &coffee_names, &quantities).await?;
+
+    // first spelling wins when the same new customer appears more than once
+    sqlx::query!(
+        "
+        INSERT INTO customers (name)
+        SELECT DISTINCT ON (LOWER(name)) name
+        FROM UNNEST($1::text[]) WITH ORDINALITY AS t(name, ord)
+        WHERE name IS NOT NULL
+        ORDER BY LOWER(name), ord
+        ON CONFLICT ((LOWER(name))) DO NOTHING
+        ",
+        &orders.names as &[Option<String>]
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    // a savepoint keeps the transaction usable after a unique violation, so the
+    // orders it conflicts with can still be looked up
+    let mut savepoint = conn.begin().await?;
+    let inserted = sqlx::query_as!(
+        Orders,
+        r#"
+        INSERT INTO orders (name, customer_id, coffee_name, size, quantity, total, status, notes, tags)
+        SELECT customers.name, customers.id, t.coffee_name, t.size, t.quantity, t.total, t.status, t.notes,
+            ARRAY(SELECT jsonb_array_elements_text(t.tags))
+        FROM UNNEST($1::text[], $2::int[], $3::text[], $4::order_size[], $5::int[], $6::numeric[], $7::text[], $8::text[], $9::jsonb[])
+            WITH ORDINALITY AS t(name, customer_id, coffee_name, size, quantity, total, status, notes, tags, ord)
+        JOIN customers ON customers.id = COALESCE(
+            t.customer_id,
+            (SELECT c.id FROM customers c WHERE LOWER(c.name) = LOWER(t.name))
+        )
+        ORDER BY ord
+        RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+            status, cancellation_reason, version, created_at, updated_at, deleted_at
+        "#,
+        &orders.names as &[Option<String>],
+        &orders.customer_ids as &[Option<i32>],
+        &orders.coffee_names,
+        &orders.sizes as &[Size],
+        &orders.quantities,
+        &orders.totals,
+        &orders.statuses,
+        &orders.notes as &[Option<String>],
+        &orders.tags
+    )
+    .fetch_all(&mut *savepoint)
+    .await;
+    let mut rows = match inserted {
+        Ok(rows) => {
+            savepoint.commit().await?;
+            rows
+        }
+        Err(err) => {
+            savepoint.rollback().await?;
+            return Err(match unique_key_columns(&err) {
+                Some(columns) => duplicate_orders(conn, orders, &columns).await?,
+                None => err.into(),
+            });
+        }
+    };
+
+    // ids are assigned in insertion order, which follows the input order
+    rows.sort_by_key(|row| row.id);
+    let ids: Vec<i32> = rows.iter().filter_map(|row| row.id).collect();
+
+    sqlx::query!(
+        "
+        INSERT INTO order_items (order_id, coffee_name, size, quantity, unit_price)
+        SELECT ($1::int[])[t.position], t.coffee_name, t.size, t.quantity, t.unit_price
+        FROM UNNEST($2::int[], $3::text[], $4::order_size[], $5::int[], $6::numeric[])
+            WITH ORDINALITY AS t(position, coffee_name, size, quantity, unit_price, ord)
+        ORDER BY ord
+        ",
+        &ids,
+        &orders.item_orders,
+        &orders.item_coffee_names,
+        &orders.item_sizes as &[Size],
+        &orders.item_quantities,
+        &orders.item_unit_prices
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query!(
+        "
+        INSERT INTO order_events (order_id, action, actor, changes)
+        SELECT id, 'created', $2, to_jsonb(orders) || jsonb_build_object('items', (
+            SELECT COALESCE(jsonb_agg(to_jsonb(order_items) - 'order_id' ORDER BY order_items.id), '[]')
+            FROM order_items WHERE order_items.order_id = orders.id
+        ))
+        FROM orders WHERE id = ANY($1)
+        ",
+        &ids,
+        actor
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(rows)
+}
+
+
+/// The body of a 201 from `add_order`, also what an `Idempotency-Key` replays.
+pub(crate) fn order_created(order: OrderDetail) -> Response<OrderDetail> {
+    Response {
+        status: true,
+        message: "added successfully".to_owned(),
+        data: Some(order),
+        meta: None,
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/orders",
+    tag = "orders",
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Replays the original response when the same key and body are sent again"),
+    ),
+    request_body = CreateOrdersReq,
+    responses(
+        (status = 201, description = "Order created, Location points at it", body = OrderResponse),
+        (status = 409, description = "Idempotency-Key reused with a different body, out of stock, or a duplicate of an existing order", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 413, description = "Body over MAX_BODY_BYTES", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn add_order(
+    State(state): State<AppState>,
+    auth: AuthContext,
+    headers: HeaderMap,
+    JsonBody(order): JsonBody<CreateOrdersReq>,
+) -> Result<axum::response::Response, ApiError> {
+    let idempotency_key = idempotency_key(&headers)?;
+    let data = match create_order(&state, &auth, order, idempotency_key.as_deref()).await? {
+        Created::Fresh(data) => *data,
+        Created::Replay(replay) => return Ok(replay),
+    };
+    let id = data.data.as_ref().and_then(|detail| detail.order.id).unwrap_or_default();
+    let location = format!("/orders/{id}");
+
+    Ok((
+        StatusCode::CREATED,
+        [(LOCATION, location)],
+        Json(data),
+    ).into_response())
+
+}
+
+
+/// Validates, stores and announces one order, for `add_order` and the gRPC
+/// service alike. A fresh order placed with an `email` is sent a receipt
+/// and one matching the Slack rules is posted there; a replay does neither
+/// again.
+pub(crate) async fn create_order(
+    state: &AppState,
+    auth: &AuthContext,
+    order: CreateOrdersReq,
+    idempotency_key: Option<&str>,
+) -> Result<Created, ApiError> {
+    let errors = validate_new_order(&order);
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    let status = match &order.status {
+        Some(status) => parse_status(status)?,
+        None => OrderStatus::Pending,
+    };
+
+    let claim = idempotency_key.map(|key| IdempotencyClaim {
+        key,
+        request_hash: request_hash(&order),
+        ttl: state.idempotency_ttl,
+    });
+    let email = order.email.as_deref().map(str::trim).filter(|email| !email.is_empty()).map(str::to_owned);
+    let created = state.orders.create(auth, order, status, claim).await?;
+    if let Created::Fresh(data) = &created {
+        if let Some(detail) = &data.data {
+            state.feed.publish(OrderEventKind::Created, &detail.order);
+            state.slack.notify(detail);
+            if let Some(email) = &email {
+                state.receipts.send(email, detail);
+            }
+        }
+    }
+    Ok(created)
+}
+
+pub(crate) const MAX_BATCH_SIZE: usize = 500;
+
+/// Inserts every order in the body in one statement, or none of them.
+#[utoipa::path(
+    post,
+    path = "/orders/batch",
+    tag = "orders",
+    request_body = Vec<CreateOrdersReq>,
+    responses(
+        (status = 201, description = "Every order was inserted", body = CreatedOrdersResponse),
+        (status = 409, description = "Out of stock or duplicate orders, nothing was inserted", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 413, description = "More than 500 orders, or a body over MAX_BULK_BODY_BYTES", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn add_orders_batch(
+    State(pg_pool): State<PgPool>,
+    State(feed): State<OrderFeed>,
+    State(receipts): State<Receipts>,
+    State(tolerance): State<PriceTolerance>,
+    RequireAdmin(auth): RequireAdmin,
+    JsonBody(orders): JsonBody<Vec<CreateOrdersReq>>,
+) -> Result<impl IntoResponse, ApiError> {
+    if orders.is_empty() {
+        return Err(ApiError::BadRequest("batch must contain at least one order".to_owned()));
+    }
+
+    if orders.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "batch may contain at most {MAX_BATCH_SIZE} orders"
+        )));
+    }
+
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        let menu = Menu::load(&mut tx).await?;
+
+        // the orders that pass validation are priced too, so one response lists
+        // everything wrong with the batch
+        let mut new_orders = NewOrders::default();
+        // in the order of new_orders, and so of the inserted rows
+        let mut emails = Vec::new();
+        let mut errors = Vec::new();
+        for (index, order) in orders.into_iter().enumerate() {
+            let prefix = format!("[{index}].");
+            let invalid = validate_new_order(&order);
+            if !invalid.is_empty() {
+                errors.extend(invalid.into_iter().map(|error| error.prefixed(&prefix)));
+                continue;
+            }
+            // validate_new_order has refused unknown statuses
+            let status = order.status.as_deref().and_then(|status| status.parse().ok()).unwrap_or(OrderStatus::Pending);
+            match menu.price_order(&order, tolerance) {
+                Ok(priced) => {
+                    emails.push(order.email.as_deref().map(str::trim).filter(|email| !email.is_empty()).map(str::to_owned));
+                    new_orders.push(order, priced, status);
+                }
+                Err(line_errors) => errors.extend(line_errors.into_iter().map(|error| error.prefixed(&prefix))),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(ApiError::Validation(errors));
+        }
+
+        let customer_ids: Vec<i32> = new_orders.customer_ids.iter().flatten().copied().collect();
+        let missing = missing_customers(&mut tx, &customer_ids).await?;
+        if !missing.is_empty() {
+            let errors = new_orders
+                .customer_ids
+                .iter()
+                .enumerate()
+                .filter(|(_, id)| id.is_some_and(|id| missing.contains(&id)))
+                .map(|(index, _)| FieldError::new(&format!("[{index}].customer_id"), FieldErrorCode::NotFound, "customer not found"))
+                .collect();
+            return Err(ApiError::Validation(errors));
+        }
+
+        let orders = insert_orders(&mut tx, &new_orders, &auth.subject).await?;
+        feed.outbox().enqueue(&mut tx, OrderEventKind::Created, &orders).await?;
+        // the lines are only loaded when a receipt will list them
+        let receipts_for = if emails.iter().any(Option::is_some) {
+            with_items(&mut tx, orders.clone()).await?
+        } else {
+            Vec::new()
+        };
+        Ok((orders, emails, receipts_for))
+    }
+    .await;
+    let (orders, emails, receipts_for) = finish(tx, result).await?;
+
+    for order in &orders {
+        feed.publish(OrderEventKind::Created, order);
+    }
+    for (email, detail) in emails.iter().zip(&receipts_for) {
+        if let Some(email) = email {
+            receipts.send(email, detail);
+        }
+    }
+    let rows: Vec<CreateOrdersRow> = orders
+        .iter()
+        .map(|order| CreateOrdersRow { id: order.id.unwrap_or_default() })
+        .collect();
+
+    tracing::info!(client = auth.subject, added = rows.len(), "orders added in batch");
+
+    let data = Response {
+        status: true,
+        message: format!("added {} orders", rows.len()),
+        data: Some(rows),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(data),
+    ))
+}
+
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct IncludeDeletedParams {
+    /// Admin only.
+    include_deleted: Option<bool>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct DeleteParams {
+    /// Removes the rows for good instead of soft deleting them.
+    hard: Option<bool>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct DeleteOrdersReq {
+    ids: Vec<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DeleteOrdersRow {
+    deleted: u64,
+}
+
+
+#[utoipa::path(
+    delete,
+    path = "/orders",
+    tag = "orders",
+    params(
+        DeleteParams,
+    ),
+    request_body = DeleteOrdersReq,
+    responses(
+        (status = 200, description = "Orders deleted", body = DeleteOrdersResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn delete_orders(
+    State(pg_pool): State<PgPool>,
+    State(feed): State<OrderFeed>,
+    RequireAdmin(auth): RequireAdmin,
+    Query(params): Query<DeleteParams>,
+    JsonBody(req): JsonBody<DeleteOrdersReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    if req.ids.is_empty() {
+        return Err(ApiError::BadRequest("ids must not be empty".to_owned()));
+    }
+
+    if req.ids.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "at most {MAX_BATCH_SIZE} ids may be deleted at once"
+        )));
+    }
+
+    let hard = params.hard.unwrap_or(false);
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        restock_orders(&mut tx, &req.ids).await?;
+
+        let orders = if hard {
+            sqlx::query_as!(
+                Orders,
+                r#"
+                DELETE FROM orders WHERE id = ANY($1)
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                "#,
+                &req.ids
+            )
+            .fetch_all(&mut *tx)
+            .await?
+        } else {
+            sqlx::query_as!(
+                Orders,
+                r#"UPDATE orders SET deleted_at = now(), updated_at = now(), version = version + 1
+                WHERE id = ANY($1) AND deleted_at IS NULL
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at"#,
+                &req.ids
+            )
+            .fetch_all(&mut *tx)
+            .await?
+        };
+        let ids: Vec<i32> = orders.iter().filter_map(|order| order.id).collect();
+
+        sqlx::query!(
+            "
+            INSERT INTO order_events (order_id, action, actor, changes)
+            SELECT id, 'deleted', $2, jsonb_build_object('hard', $3::bool) FROM UNNEST($1::int[]) AS id
+            ",
+            &ids,
+            auth.subject,
+            hard
+        )
+        .execute(&mut *tx)
+        .await?;
+        feed.outbox().enqueue(&mut tx, OrderEventKind::Deleted, &orders).await?;
+        Ok((orders, ids))
+    }
+    .await;
+    let (orders, ids) = finish(tx, result).await?;
+
+    for order in &orders {
+        feed.publish(OrderEventKind::Deleted, order);
+    }
+    let deleted = ids.len() as u64;
+    tracing::info!(client = auth.subject, deleted, requested = req.ids.len(), hard, "orders deleted");
+    let data = Response {
+        status: true,
+        message: format!("deleted {deleted} of {} orders", req.ids.len()),
+        data: Some(DeleteOrdersRow { deleted }),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+
+pub(crate) fn entity_tag(version: i32) -> String {
+    format!("\"{version}\"")
+}
+
+pub(crate) fn etag(version: i32) -> HeaderValue {
+    HeaderValue::from_str(&entity_tag(version)).expect("quoted integer is a valid header value")
+}
+
+/// Parsed `If-Match` header. Comparison is strong, so weak tags never match.
+pub(crate) enum IfMatch {
+    Any,
+    Tags(Vec<String>),
+}
+
+impl IfMatch {
+    fn from_headers(headers: &HeaderMap) -> Option<IfMatch> {
+        let value = headers.get(IF_MATCH)?.to_str().unwrap_or_default().trim();
+        if value == "*" {
+            return Some(IfMatch::Any);
+        }
+        Some(IfMatch::Tags(entity_tags(value).map(str::to_owned).collect()))
+    }
+
+    pub(crate) fn matches(&self, version: i32) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => tags.contains(&entity_tag(version)),
+        }
+    }
+}
+
+/// The `:id` of an order route. Anything but a positive `i32` is refused
+/// with the same 400 in the JSON envelope, instead of axum's plain-text
+/// path rejection.
+pub(crate) struct OrderId(pub(crate) i32);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for OrderId {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Path::<String>::from_request_parts(parts, state)
+            .await
+            .ok()
+            .and_then(|Path(raw)| raw.parse::<i32>().ok())
+            .filter(|id| *id > 0)
+            .map(OrderId)
+            .ok_or_else(|| ApiError::BadRequest("order id must be a positive integer".to_owned()))
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateOrdersReq {
+    pub(crate) name: Option<String>,
+    /// Changing coffee_name or size re-prices the order from the menu.
+    pub(crate) coffee_name: Option<String>,
+    pub(crate) size: Option<Size>,
+    /// Re-prices the order as well.
+    pub(crate) quantity: Option<i32>,
+    /// Checked against the menu price of the order as updated, as on create.
+    pub(crate) total: Option<Money>,
+    /// Admin only. Sets the total regardless of the menu.
+    pub(crate) total_override: Option<Money>,
+    pub(crate) status: Option<String>,
+    /// `null` or an empty string clears the notes.
+    #[serde(default, deserialize_with = "explicit_null")]
+    #[schema(value_type = Option<String>, example = "extra hot, oat milk")]
+    pub(crate) notes: Option<Option<String>>,
+    /// Replaces the tags; an empty array removes them all.
+    #[schema(example = json!(["loyalty"]))]
+    pub(crate) tags: Option<Vec<String>>,
+}
+
+
+/// PUT replaces the order: name, coffee_name and size are required, and a
+/// quantity, notes or tags left out go back to 1, none and none. Status and
+/// total only change when sent.
+#[utoipa::path(
+    put,
+    path = "/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from a previous read; the write fails with 412 when it is stale"),
+    ),
+    request_body = UpdateOrdersReq,
+    responses(
+        (status = 200, description = "Order replaced", body = OrderResponse),
+        (status = 409, description = "Status change not allowed, item change on a multi-item order, or out of stock", body = ErrorBody),
+        (status = 412, description = "If-Match did not match the current ETag", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn update_order(
+    State(orders): State<Arc<dyn OrderRepository>>,
+    State(feed): State<OrderFeed>,
+    OrderId(id): OrderId,
+    auth: AuthContext,
+    headers: HeaderMap,
+    JsonBody(order): JsonBody<UpdateOrdersReq>,
+
+) -> Result<impl IntoResponse, ApiError> {
+    let missing: Vec<FieldError> = [
+        ("name", order.name.is_none()),
+        ("coffee_name", order.coffee_name.is_none()),
+        ("size", order.size.is_none()),
+    ]
+    .into_iter()
+    .filter(|(_, missing)| *missing)
+    .map(|(field, _)| FieldError::new(field, FieldErrorCode::Required, "is required"))
+    .collect();
+
+    if !missing.is_empty() {
+        return Err(ApiError::Validation(missing));
+    }
+
+    let mut order = order;
+    order.quantity.get_or_insert(1);
+    order.notes.get_or_insert(None);
+    order.tags.get_or_insert_with(Vec::new);
+    write_order_update(orders.as_ref(), &feed, id, &auth, IfMatch::from_headers(&headers), order).await
+}
+
+/// PATCH only touches the fields present in the body.
+#[utoipa::path(
+    patch,
+    path = "/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from a previous read; the write fails with 412 when it is stale"),
+    ),
+    request_body = UpdateOrdersReq,
+    responses(
+        (status = 200, description = "Order updated", body = OrderResponse),
+        (status = 409, description = "Status change not allowed, item change on a multi-item order, or out of stock", body = ErrorBody),
+        (status = 412, description = "If-Match did not match the current ETag", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn patch_order(
+    State(orders): State<Arc<dyn OrderRepository>>,
+    State(feed): State<OrderFeed>,
+    OrderId(id): OrderId,
+    auth: AuthContext,
+    headers: HeaderMap,
+    JsonBody(order): JsonBody<UpdateOrdersReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    write_order_update(orders.as_ref(), &feed, id, &auth, IfMatch::from_headers(&headers), order).await
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateOrderStatusReq {
+    #[schema(example = "ready")]
+    pub(crate) status: String,
+}
+
+/// Moves the order along pending, preparing, ready and completed, or
+/// cancels it, leaving every other field alone.
+#[utoipa::path(
+    patch,
+    path = "/orders/{id}/status",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from a previous read; the write fails with 412 when it is stale"),
+    ),
+    request_body = UpdateOrderStatusReq,
+    responses(
+        (status = 200, description = "Status changed", body = OrderResponse),
+        (status = 409, description = "The transition from the current status is not allowed", body = ErrorBody),
+        (status = 412, description = "If-Match did not match the current ETag", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 422, description = "Unknown status", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn update_order_status(
+    State(orders): State<Arc<dyn OrderRepository>>,
+    State(feed): State<OrderFeed>,
+    OrderId(id): OrderId,
+    auth: AuthContext,
+    headers: HeaderMap,
+    JsonBody(req): JsonBody<UpdateOrderStatusReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    let order = UpdateOrdersReq {
+        name: None,
+        coffee_name: None,
+        size: None,
+        quantity: None,
+        total: None,
+        total_override: None,
+        status: Some(req.status),
+        notes: None,
+        tags: None,
+    };
+    write_order_update(orders.as_ref(), &feed, id, &auth, IfMatch::from_headers(&headers), order).await
+}
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct UpdateStatusesReq {
+    #[schema(example = json!([12, 13, 14]))]
+    ids: Vec<i32>,
+    #[schema(example = "completed")]
+    status: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct UpdateStatusesParams {
+    /// Change nothing when any order cannot move to the status.
+    strict: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SkippedOrder {
+    #[schema(example = 14)]
+    id: i32,
+    #[schema(example = "cannot change status from pending to completed")]
+    reason: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct UpdateStatusesRow {
+    /// In the order they were requested.
+    updated: Vec<i32>,
+    skipped: Vec<SkippedOrder>,
+}
+
+/// Moves many orders to one status in a single UPDATE, e.g. every ready
+/// order to completed at closing time. Orders that cannot make the move are
+/// skipped with the reason, or with `strict` fail the whole request.
+#[utoipa::path(
+    post,
+    path = "/orders/status",
+    tag = "orders",
+    params(
+        UpdateStatusesParams,
+    ),
+    request_body = UpdateStatusesReq,
+    responses(
+        (status = 200, description = "Which orders moved and why the others did not", body = UpdateStatusesResponse),
+        (status = 409, description = "With `strict`, an order cannot make the transition", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 413, description = "More than 500 ids", body = ErrorBody),
+        (status = 422, description = "Unknown status", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn update_order_statuses(
+    State(pg_pool): State<PgPool>,
+    State(feed): State<OrderFeed>,
+    RequireAdmin(auth): RequireAdmin,
+    Query(params): Query<UpdateStatusesParams>,
+    JsonBody(req): JsonBody<UpdateStatusesReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    if req.ids.is_empty() {
+        return Err(ApiError::BadRequest("ids must not be empty".to_owned()));
+    }
+
+    if req.ids.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::PayloadTooLarge(format!(
+            "at most {MAX_BATCH_SIZE} orders may be updated at once"
+        )));
+    }
+
+    let next = req.status.parse().map_err(|message| {
+        ApiError::Validation(vec![FieldError::new("status", FieldErrorCode::Invalid, message)])
+    })?;
+    let mut ids: Vec<i32> = Vec::with_capacity(req.ids.len());
+    for id in req.ids {
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        // locked so nothing moves the orders between the checks and the update
+        let current: HashMap<i32, Orders> = sqlx::query_as!(
+            Orders,
+            r#"
+            SELECT id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                status, cancellation_reason, version, created_at, updated_at, deleted_at
+            FROM orders WHERE id = ANY($1) AND deleted_at IS NULL ORDER BY id FOR UPDATE
+            "#,
+            &ids
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .filter_map(|order| Some((order.id?, order)))
+        .collect();
+
+        let mut movable = Vec::new();
+        let mut skipped = Vec::new();
+        let mut blocked = false;
+        for &id in &ids {
+            let from = match current.get(&id) {
+                Some(order) => parse_status(order.status.as_deref().unwrap_or_default())?,
+                None => {
+                    blocked = true;
+                    skipped.push(SkippedOrder { id, reason: "order not found".to_owned() });
+                    continue;
+                }
+            };
+            if from == next {
+                skipped.push(SkippedOrder { id, reason: format!("already {}", next.as_str()) });
+            } else if from.can_transition_to(next) {
+                movable.push(id);
+            } else {
+                blocked = true;
+                skipped.push(SkippedOrder {
+                    id,
+                    reason: format!("cannot change status from {} to {}", from.as_str(), next.as_str()),
+                });
+            }
+        }
+
+        // nothing has been written yet, so rolling back undoes nothing
+        if blocked && params.strict.unwrap_or(false) {
+            let reasons: Vec<String> = skipped
+                .iter()
+                .map(|skip| format!("order {}: {}", skip.id, skip.reason))
+                .collect();
+            return Err(ApiError::Conflict(format!("no orders updated; {}", reasons.join("; "))));
+        }
+
+        if next == OrderStatus::Cancelled {
+            restock_orders(&mut tx, &movable).await?;
+        }
+
+        let sources: Vec<String> = next.sources().into_iter().map(|from| from.as_str().to_owned()).collect();
+        let updated = sqlx::query_as!(
+            Orders,
+            r#"
+            UPDATE orders SET status = $2, updated_at = now(), version = version + 1
+            WHERE id = ANY($1) AND deleted_at IS NULL AND status = ANY($3)
+            RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                status, cancellation_reason, version, created_at, updated_at, deleted_at
+            "#,
+            &movable,
+            next.as_str(),
+            &sources
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let (event_ids, changes): (Vec<i32>, Vec<serde_json::Value>) = updated
+            .iter()
+            .filter_map(|order| {
+                let id = order.id?;
+                Some((id, order_diff(current.get(&id)?, order)))
+            })
+            .unzip();
+        sqlx::query!(
+            "
+            INSERT INTO order_events (order_id, action, actor, changes)
+            SELECT id, 'status_changed', $3, changes FROM UNNEST($1::int[], $2::jsonb[]) AS t(id, changes)
+            ",
+            &event_ids,
+            &changes,
+            auth.subject
+        )
+        .execute(&mut *tx)
+        .await?;
+        feed.outbox().enqueue(&mut tx, OrderEventKind::Updated, &updated).await?;
+        Ok((updated, movable, skipped, event_ids))
+    }
+    .await;
+    let (updated, movable, skipped, event_ids) = finish(tx, result).await?;
+
+    for order in &updated {
+        feed.publish(OrderEventKind::Updated, order);
+    }
+    let updated: Vec<i32> = movable.into_iter().filter(|id| event_ids.contains(id)).collect();
+    tracing::info!(
+        client = auth.subject,
+        status = next.as_str(),
+        updated = updated.len(),
+        requested = ids.len(),
+        "order statuses changed"
+    );
+
+    let data = Response {
+        status: true,
+        message: format!("updated {} of {} orders", updated.len(), ids.len()),
+        data: Some(UpdateStatusesRow { updated, skipped }),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+pub(crate) async fn write_order_update(
+    orders: &dyn OrderRepository,
+    feed: &OrderFeed,
+    id: i32,
+    auth: &AuthContext,
+    if_match: Option<IfMatch>,
+    order: UpdateOrdersReq,
+) -> Result<(StatusCode, [(HeaderName, HeaderValue); 1], Json<Response<Orders>>), ApiError> {
+
+    if order.name.is_none()
+        && order.coffee_name.is_none()
+        && order.size.is_none()
+        && order.quantity.is_none()
+        && order.total.is_none()
+        && order.total_override.is_none()
+        && order.status.is_none()
+        && order.notes.is_none()
+        && order.tags.is_none()
+    {
+        return Err(ApiError::BadRequest("no fields provided to update".to_owned()));
+    }
+
+    let mut errors = validate_order_fields(
+        order.name.as_deref(),
+        order.coffee_name.as_deref(),
+        order.total_override,
+    );
+    errors.extend(validate_status(order.status.as_deref()));
+    errors.extend(validate_notes(order.notes.as_ref().and_then(Option::as_deref)));
+    errors.extend(validate_tags(order.tags.as_deref().unwrap_or_default()));
+    if order.total.is_some() && order.total_override.is_some() {
+        errors.push(FieldError::new("total", FieldErrorCode::Conflict, "send either total or total_override, not both"));
+    }
+    if order.quantity.is_some_and(|quantity| !(1..=MAX_QUANTITY).contains(&quantity)) {
+        errors.push(FieldError::new(
+            "quantity",
+            FieldErrorCode::OutOfRange,
+            format!("must be between 1 and {MAX_QUANTITY}"),
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(ApiError::Validation(errors));
+    }
+
+    if order.total_override.is_some() {
+        auth.require_admin()?;
+    }
+
+    let status = match &order.status {
+        Some(status) => Some(parse_status(status)?),
+        None => None,
+    };
+
+    let mut order = order;
+    order.notes = order.notes.map(clean_notes);
+    order.tags = order.tags.as_deref().map(normalize_tags);
+    let updated = orders.update(id, auth, if_match, order, status).await?;
+    feed.publish(OrderEventKind::Updated, &updated);
+
+    let tag = etag(updated.version.unwrap_or_default());
+
+    let data = Response {
+        status: true,
+        message: "updated successfully".to_owned(),
+        data: Some(updated),
+        meta: None,
+    };
+
+
+    Ok((
+        StatusCode::OK,
+        [(ETAG, tag)],
+        Json(data),
+    ))
+}
+
+
+#[utoipa::path(
+    delete,
+    path = "/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+        DeleteParams,
+    ),
+    responses(
+        (status = 200, description = "Order deleted", body = MessageResponse,
+            example = json!({"status": true, "message": "order deleted"})),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn delete_order(
+    OrderId(id): OrderId,
+    State(orders): State<Arc<dyn OrderRepository>>,
+    State(feed): State<OrderFeed>,
+    RequireAdmin(auth): RequireAdmin,
+    Query(params): Query<DeleteParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let hard = params.hard.unwrap_or(false);
+        let Some(order) = orders.delete(id, hard, &auth.subject).await? else {
+            return Err(ApiError::NotFound("order not found".to_owned()));
+        };
+        tracing::info!(client = auth.subject, id, hard, "order deleted");
+        feed.publish(OrderEventKind::Deleted, &order);
+
+        let data: Response<()> = Response {
+            status: true,
+            message: "order deleted".to_owned(),
+            data: None,
+            meta: None,
+        };
+    
+    
+        Ok((
+            StatusCode::OK,
+            Json(data),
+        ))
+}
+#[utoipa::path(
+    post,
+    path = "/orders/{id}/restore",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+    ),
+    responses(
+        (status = 200, description = "Order restored", body = OrderResponse),
+        (status = 409, description = "Order is not deleted, or there is no longer stock for it", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn restore_order(
+    OrderId(id): OrderId,
+    State(pg_pool): State<PgPool>,
+    State(feed): State<OrderFeed>,
+    RequireAdmin(auth): RequireAdmin,
+) -> Result<impl IntoResponse, ApiError> {
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        let restored = sqlx::query_as!(
+            Orders,
+            r#"
+            UPDATE orders SET deleted_at = NULL, updated_at = now(), version = version + 1
+            WHERE id = $1 AND deleted_at IS NOT NULL
+            RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                status, cancellation_reason, version, created_at, updated_at, deleted_at
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(order) = restored else {
+            let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM orders WHERE id = $1)", id)
+                .fetch_one(&mut *tx)
+                .await?
+                .unwrap_or(false);
+            return Err(if exists {
+                ApiError::Conflict("order is not deleted".to_owned())
+            } else {
+                ApiError::NotFound("order not found".to_owned())
+            });
+        };
+
+        // deleting gave an open order's cups back, so restoring takes them again
+        if parse_status(order.status.as_deref().unwrap_or_default())?.is_open() {
+            let (coffee_names, quantities): (Vec<String>, Vec<i32>) = sqlx::query!(
+                "SELECT coffee_name, quantity FROM order_items WHERE order_id = $1",
+                id
+            )
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .map(|line| (line.coffee_name, line.quantity))
+            .unzip();
+            take_stock(&mut tx, &coffee_names, &quantities).await?;
+        }
+        record_order_event(&mut tx, id, "restored", &auth.subject, serde_json::json!({})).await?;
+        feed.outbox().enqueue(&mut tx, OrderEventKind::Updated, std::slice::from_ref(&order)).await?;
+        Ok(order)
+    }
+    .await;
+    let order = finish(tx, result).await?;
+    tracing::info!(client = auth.subject, id, "order restored");
+    feed.publish(OrderEventKind::Updated, &order);
+
+    let tag = etag(order.version.unwrap_or_default());
+    let data = Response {
+        status: true,
+        message: "order restored".to_owned(),
+        data: Some(order),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(ETAG, tag)],
+        Json(data),
+    ))
+}
+
+
+pub(crate) const MAX_CANCELLATION_REASON_LENGTH: usize = 500;
+
+#[derive(Deserialize, ToSchema)]
+pub(crate) struct CancelOrderReq {
+    #[schema(example = "customer changed their mind")]
+    reason: Option<String>,
+}
+
+/// Cancels an open order and gives its cups back, keeping the record. The
+/// body is optional. Cancelling a cancelled order changes nothing and
+/// returns it as it is.
+#[utoipa::path(
+    post,
+    path = "/orders/{id}/cancel",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+    ),
+    request_body(content = Option<CancelOrderReq>, description = "Why the order was cancelled"),
+    responses(
+        (status = 200, description = "Order cancelled, or already cancelled", body = OrderResponse),
+        (status = 409, description = "Order is already completed", body = ErrorBody),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn cancel_order(
+    OrderId(id): OrderId,
+    State(pg_pool): State<PgPool>,
+    State(feed): State<OrderFeed>,
+    auth: AuthContext,
+    OptionalJsonBody(req): OptionalJsonBody<CancelOrderReq>,
+) -> Result<impl IntoResponse, ApiError> {
+    let reason = req
+        .and_then(|req| req.reason)
+        .map(|reason| reason.trim().to_owned())
+        .filter(|reason| !reason.is_empty());
+    if reason.as_ref().is_some_and(|reason| reason.chars().count() > MAX_CANCELLATION_REASON_LENGTH) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "reason",
+            FieldErrorCode::TooLong,
+            format!("may be at most {MAX_CANCELLATION_REASON_LENGTH} characters"),
+        )]));
+    }
+
+    let mut tx = pg_pool.begin().await?;
+    let result = async {
+        let current = sqlx::query_as!(
+            Orders,
+            r#"
+            SELECT id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                status, cancellation_reason, version, created_at, updated_at, deleted_at
+            FROM orders WHERE id = $1 AND deleted_at IS NULL FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("order not found".to_owned()))?;
+
+        let status = parse_status(current.status.as_deref().unwrap_or_default())?;
+        let already_cancelled = status == OrderStatus::Cancelled;
+        let order = if already_cancelled {
+            current
+        } else {
+            if !status.can_transition_to(OrderStatus::Cancelled) {
+                return Err(ApiError::Conflict(format!("cannot cancel a {} order", status.as_str())));
+            }
+            restock_orders(&mut tx, &[id]).await?;
+            let cancelled = sqlx::query_as!(
+                Orders,
+                r#"
+                UPDATE orders SET status = 'cancelled', cancellation_reason = $2, updated_at = now(), version = version + 1
+                WHERE id = $1
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                "#,
+                id,
+                reason
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            record_order_event(&mut tx, id, "status_changed", &auth.subject, order_diff(&current, &cancelled)).await?;
+            feed.outbox().enqueue(&mut tx, OrderEventKind::Updated, std::slice::from_ref(&cancelled)).await?;
+            cancelled
+        };
+        Ok((order, already_cancelled))
+    }
+    .await;
+    let (order, already_cancelled) = finish(tx, result).await?;
+
+    let message = if already_cancelled {
+        "order already cancelled"
+    } else {
+        tracing::info!(client = auth.subject, id, "order cancelled");
+        feed.publish(OrderEventKind::Updated, &order);
+        "order cancelled"
+    };
+
+    let tag = etag(order.version.unwrap_or_default());
+    let data = Response {
+        status: true,
+        message: message.to_owned(),
+        data: Some(order),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(ETAG, tag)],
+        Json(data),
+    ))
+}
+
+
+#[utoipa::path(
+    get,
+    path = "/orders/{id}",
+    tag = "orders",
+    params(
+        ("id" = i32, Path, description = "Order id"),
+        IncludeDeletedParams,
+        FieldsParams,
+    ),
+    responses(
+        (status = 200, description = "The order, with its ETag", body = OrderResponse,
+            headers(("ETag" = String, description = "Current version of the order"))),
+        (status = 304, description = "The order is unchanged since the ETag in If-None-Match"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Order not found", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_order(
+    OrderId(id): OrderId,
+    State(orders): State<Arc<dyn OrderRepository>>,
+    auth: AuthContext,
+    Query(params): Query<IncludeDeletedParams>,
+    Query(fields): Query<FieldsParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let include_deleted = params.include_deleted.unwrap_or(false);
+    if include_deleted {
+        auth.require_admin()?;
+    }
+    let fields = fields.selection()?;
+
+    let order = orders
+        .get(id, include_deleted)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("order not found".to_owned()))?;
+
+    let tag = etag(order.order.version.unwrap_or_default());
+    let data = Response {
+        status: true,
+        message: "found order".to_owned(),
+        data: Some(select_fields(order, fields.as_deref())),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(ETAG, tag)],
+        Json(data),
+    ))
+}
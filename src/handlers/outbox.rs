@@ -0,0 +1,178 @@
+//! Inspecting and requeueing webhook events stuck in the outbox.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::RequireAdmin;
+use crate::errors::{ApiError, FieldError, FieldErrorCode};
+use crate::models::{PageMeta, Response};
+use crate::outbox::OUTBOX_STATUSES;
+use crate::pagination::{PageParams, Pagination};
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct OutboxEvent {
+    id: i64,
+    order_id: i32,
+    /// order.created, order.updated or order.deleted.
+    event_type: String,
+    /// The webhook body, `{id, event, occurred_at, data}`.
+    #[schema(value_type = Object)]
+    payload: serde_json::Value,
+    /// pending, delivered or failed.
+    status: String,
+    attempts: i32,
+    /// When a pending event is next tried.
+    next_attempt_at: DateTime<Utc>,
+    /// Targets that took the event and are skipped on retries.
+    delivered_to: Vec<String>,
+    last_error: Option<String>,
+    created_at: DateTime<Utc>,
+    delivered_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct OutboxListParams {
+    /// pending, delivered or failed; every event when absent.
+    status: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/outbox",
+    tag = "admin",
+    params(
+        OutboxListParams,
+        PageParams,
+    ),
+    responses(
+        (status = 200, description = "Outbox events, oldest first", body = OutboxEventsResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 422, description = "Field validation failed", body = ValidationErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_outbox_events(
+    State(pg_pool): State<PgPool>,
+    _admin: RequireAdmin,
+    Query(params): Query<OutboxListParams>,
+    Pagination { limit, offset, .. }: Pagination,
+) -> Result<impl IntoResponse, ApiError> {
+    if let Some(status) = params.status.as_deref().filter(|status| !OUTBOX_STATUSES.contains(status)) {
+        return Err(ApiError::Validation(vec![FieldError::new(
+            "status",
+            FieldErrorCode::Invalid,
+            format!("unknown status '{status}', expected one of {}", OUTBOX_STATUSES.join(", ")),
+        )]));
+    }
+
+    let events = sqlx::query_as!(
+        OutboxEvent,
+        "
+        SELECT id, order_id, event_type, payload, status, attempts, next_attempt_at, delivered_to, last_error,
+            created_at, delivered_at
+        FROM outbox_events
+        WHERE $1::text IS NULL OR status = $1
+        ORDER BY id
+        LIMIT $2 OFFSET $3
+        ",
+        params.status,
+        limit,
+        offset
+    )
+    .fetch_all(&pg_pool)
+    .await?;
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM outbox_events WHERE $1::text IS NULL OR status = $1"#,
+        params.status
+    )
+    .fetch_one(&pg_pool)
+    .await?;
+
+    let data = Response {
+        status: true,
+        message: format!("found {} outbox events (limit {limit}, offset {offset})", events.len()),
+        data: Some(events),
+        meta: Some(PageMeta::offset(limit, offset, total)),
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
+
+/// Makes a failed or pending event due now with a fresh set of attempts.
+/// Targets that already took it are still skipped.
+#[utoipa::path(
+    post,
+    path = "/admin/outbox/{id}/retry",
+    tag = "admin",
+    params(
+        ("id" = i64, Path, description = "Outbox event id"),
+    ),
+    responses(
+        (status = 200, description = "Event requeued", body = OutboxEventResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 404, description = "Outbox event not found", body = ErrorBody),
+        (status = 409, description = "The event was already delivered", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn retry_outbox_event(
+    Path(id): Path<i64>,
+    State(pg_pool): State<PgPool>,
+    RequireAdmin(auth): RequireAdmin,
+) -> Result<impl IntoResponse, ApiError> {
+    let event = sqlx::query_as!(
+        OutboxEvent,
+        "
+        UPDATE outbox_events SET status = 'pending', attempts = 0, next_attempt_at = now()
+        WHERE id = $1 AND status <> 'delivered'
+        RETURNING id, order_id, event_type, payload, status, attempts, next_attempt_at, delivered_to, last_error,
+            created_at, delivered_at
+        ",
+        id
+    )
+    .fetch_optional(&pg_pool)
+    .await?;
+
+    let Some(event) = event else {
+        // only a delivered event is left out of the update
+        let exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM outbox_events WHERE id = $1)", id)
+            .fetch_one(&pg_pool)
+            .await?
+            .unwrap_or(false);
+        return Err(if exists {
+            ApiError::Conflict("outbox event was already delivered".to_owned())
+        } else {
+            ApiError::NotFound("outbox event not found".to_owned())
+        });
+    };
+    tracing::info!(client = auth.subject, outbox_id = id, "outbox event requeued");
+
+    let data = Response {
+        status: true,
+        message: "requeued outbox event".to_owned(),
+        data: Some(event),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
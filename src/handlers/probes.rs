@@ -0,0 +1,164 @@
+//! Health, liveness, readiness and the running build.
+use std::time::Duration;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::PgPool;
+use utoipa::ToSchema;
+
+use crate::AppState;
+use crate::models::Response;
+
+pub(crate) const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct HealthResponse {
+    status: bool,
+    database: &'static str,
+    connections: PoolStats,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PoolStats {
+    size: u32,
+    idle: usize,
+    active: usize,
+}
+
+/// Which build is running where, fixed at build time apart from the
+/// environment.
+#[derive(Clone, Serialize, ToSchema)]
+pub struct BuildInfo {
+    /// The crate version.
+    #[schema(example = "0.1.0")]
+    pub version: &'static str,
+    /// Short git hash, `unknown` when built outside a checkout without `GIT_COMMIT`.
+    #[schema(example = "3c144d1a9f2e")]
+    pub commit: &'static str,
+    #[schema(example = "2026-10-14T09:30:00Z")]
+    pub built_at: &'static str,
+    #[schema(example = "production")]
+    pub environment: String,
+}
+
+impl BuildInfo {
+    pub fn new(environment: &str) -> BuildInfo {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            commit: env!("BUILD_COMMIT"),
+            built_at: env!("BUILD_TIMESTAMP"),
+            environment: environment.to_owned(),
+        }
+    }
+}
+
+/// Pings the database with a short timeout so a degraded database fails the
+/// probe quickly instead of piling up waiting connections.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Database reachable", body = HealthResponse),
+        (status = 503, description = "Database unavailable", body = HealthResponse),
+    ),
+)]
+pub(crate) async fn health(State(pg_pool): State<PgPool>) -> impl IntoResponse {
+    let (code, database) = if ping_database(&pg_pool).await {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+
+    let size = pg_pool.size();
+    let idle = pg_pool.num_idle();
+    let data = HealthResponse {
+        status: code == StatusCode::OK,
+        database,
+        connections: PoolStats {
+            size,
+            idle,
+            active: (size as usize).saturating_sub(idle),
+        },
+    };
+
+    (code, Json(data))
+}
+
+pub(crate) async fn ping_database(pg_pool: &PgPool) -> bool {
+    let ping = tokio::time::timeout(
+        HEALTH_CHECK_TIMEOUT,
+        sqlx::query("SELECT 1").execute(pg_pool),
+    )
+    .await;
+
+    matches!(ping, Ok(Ok(_)))
+}
+
+/// Liveness only proves the runtime is serving requests; it never touches the
+/// database so an outage does not get the process restarted.
+#[utoipa::path(
+    get,
+    path = "/livez",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Process is serving requests", body = MessageResponse,
+            example = json!({"status": true, "message": "alive"})),
+    ),
+)]
+pub(crate) async fn livez() -> impl IntoResponse {
+    let data: Response<()> = Response {
+        status: true,
+        message: "alive".to_owned(),
+        data: None,
+        meta: None,
+    };
+    (StatusCode::OK, Json(data))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Ready for traffic", body = MessageResponse,
+            example = json!({"status": true, "message": "ready"})),
+        (status = 503, description = "Shutting down or database unavailable", body = MessageResponse,
+            example = json!({"status": false, "message": "shutting down"})),
+    ),
+)]
+pub(crate) async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let (code, message) = if state.shutting_down.is_cancelled() {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting down")
+    } else if !ping_database(&state.db).await {
+        (StatusCode::SERVICE_UNAVAILABLE, "database unavailable")
+    } else {
+        (StatusCode::OK, "ready")
+    };
+
+    let data: Response<()> = Response {
+        status: code == StatusCode::OK,
+        message: message.to_owned(),
+        data: None,
+        meta: None,
+    };
+    (code, Json(data))
+}
+
+/// Never touches the database, so it answers while Postgres is down too.
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "probes",
+    responses((status = 200, description = "The running build", body = VersionResponse)),
+)]
+pub(crate) async fn version(State(state): State<AppState>) -> impl IntoResponse {
+    let data = Response {
+        status: true,
+        message: "build information".to_owned(),
+        data: Some(state.build.clone()),
+        meta: None,
+    };
+    (StatusCode::OK, Json(data))
+}
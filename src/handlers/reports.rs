@@ -0,0 +1,223 @@
+//! Revenue reports and date range parsing.
+use std::str::FromStr;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{
+  extract::{Query, State},
+  http::{header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE}, HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use chrono::{DateTime, NaiveDate, Utc};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::db::ReadPool;
+use crate::errors::ApiError;
+use crate::handlers::csv::csv_line;
+use crate::models::{Money, Response};
+
+/// Grouping keys for the revenue report, each mapped to a fixed column so
+/// the query never interpolates user input.
+#[derive(Clone, Copy)]
+pub(crate) enum RevenueGroup {
+    CoffeeName,
+    Size,
+    Name,
+}
+
+impl RevenueGroup {
+    fn column(self) -> &'static str {
+        match self {
+            RevenueGroup::CoffeeName => "coffee_name",
+            RevenueGroup::Size => "size",
+            RevenueGroup::Name => "name",
+        }
+    }
+
+    /// The grouped `key`, the revenue it earned and where both come from.
+    /// Coffee and size are per line, so an order of several lines counts
+    /// towards each of them; the customer's name is per order.
+    fn select(self) -> &'static str {
+        match self {
+            RevenueGroup::CoffeeName => {
+                "SELECT i.coffee_name::text AS key, COUNT(DISTINCT o.id) AS order_count, \
+                COALESCE(SUM(i.quantity * i.unit_price), 0) AS revenue \
+                FROM order_items i JOIN orders o ON o.id = i.order_id"
+            }
+            RevenueGroup::Size => {
+                "SELECT i.size::text AS key, COUNT(DISTINCT o.id) AS order_count, \
+                COALESCE(SUM(i.quantity * i.unit_price), 0) AS revenue \
+                FROM order_items i JOIN orders o ON o.id = i.order_id"
+            }
+            RevenueGroup::Name => {
+                "SELECT o.name::text AS key, COUNT(*) AS order_count, COALESCE(SUM(o.total), 0) AS revenue \
+                FROM orders o"
+            }
+        }
+    }
+}
+
+impl FromStr for RevenueGroup {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "coffee_name" => Ok(RevenueGroup::CoffeeName),
+            "size" => Ok(RevenueGroup::Size),
+            "name" => Ok(RevenueGroup::Name),
+            _ => Err(format!("invalid group_by '{value}', expected one of: coffee_name, size, name")),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct RevenueReportParams {
+    /// coffee_name (default), size or name.
+    group_by: Option<String>,
+    /// Earliest `created_at`, an RFC 3339 timestamp or a date meaning its start.
+    from: Option<String>,
+    /// Latest `created_at`, an RFC 3339 timestamp or a date meaning its end.
+    to: Option<String>,
+    /// json or csv; without it, `Accept: text/csv` selects csv.
+    format: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct RevenueRow {
+    key: Option<String>,
+    order_count: i64,
+    revenue: Money,
+}
+
+/// Parses an RFC 3339 timestamp or a plain `YYYY-MM-DD` date. A date stands
+/// for its first microsecond, or its last one when `end_of_day` is set, so
+/// an inclusive range over dates covers both whole days.
+pub(crate) fn parse_time_bound(name: &str, value: &str, end_of_day: bool) -> Result<DateTime<Utc>, ApiError> {
+    // an unencoded `+02:00` offset arrives as ` 02:00` after query decoding
+    if let Ok(time) = DateTime::parse_from_rfc3339(&value.replace(' ', "+")) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        ApiError::BadRequest(format!("{name} must be an RFC 3339 timestamp or a YYYY-MM-DD date, got '{value}'"))
+    })?;
+    let time = if end_of_day {
+        date.and_hms_micro_opt(23, 59, 59, 999_999)
+    } else {
+        date.and_hms_opt(0, 0, 0)
+    };
+    Ok(time.unwrap_or_default().and_utc())
+}
+
+/// Inclusive bounds on a timestamp column; either side may be open.
+#[derive(Clone, Copy, Default, Hash, PartialEq, Eq)]
+pub(crate) struct TimeRange {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    /// Parses both bounds, named as in the query string for error messages,
+    /// and rejects a range that ends before it starts.
+    pub(crate) fn parse(
+        (from_name, from): (&str, Option<&str>),
+        (to_name, to): (&str, Option<&str>),
+    ) -> Result<TimeRange, ApiError> {
+        let from = from.map(|value| parse_time_bound(from_name, value, false)).transpose()?;
+        let to = to.map(|value| parse_time_bound(to_name, value, true)).transpose()?;
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(ApiError::BadRequest(format!("{from_name} must not be after {to_name}")));
+            }
+        }
+        Ok(TimeRange { from, to })
+    }
+
+    /// Appends `AND` conditions on `column`, which must be a literal.
+    pub(crate) fn push_conditions(&self, q: &mut QueryBuilder<'_, Postgres>, column: &'static str) {
+        if let Some(from) = self.from {
+            q.push(format!(" AND {column} >= ")).push_bind(from);
+        }
+        if let Some(to) = self.to {
+            q.push(format!(" AND {column} <= ")).push_bind(to);
+        }
+    }
+}
+
+/// Revenue per group over live, uncancelled orders, highest first, from a
+/// single GROUP BY. By coffee or size it is what each line was priced at,
+/// so admin overrides of an order's total only show up by name.
+#[utoipa::path(
+    get,
+    path = "/reports/revenue",
+    tag = "reports",
+    params(
+        RevenueReportParams,
+    ),
+    responses(
+        (status = 200, description = "One row per group; text/csv when requested", body = RevenueReportResponse),
+        (status = 400, description = "Unknown group_by or format, or an invalid date range", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn revenue_report(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    headers: HeaderMap,
+    Query(params): Query<RevenueReportParams>,
+) -> Result<axum::response::Response, ApiError> {
+    let group = match params.group_by.as_deref() {
+        Some(group) => group.parse::<RevenueGroup>().map_err(ApiError::BadRequest)?,
+        None => RevenueGroup::CoffeeName,
+    };
+    let csv = match params.format.as_deref() {
+        Some("csv") => true,
+        Some("json") => false,
+        Some(other) => return Err(ApiError::BadRequest(format!("invalid format '{other}', expected json or csv"))),
+        None => headers
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/csv")),
+    };
+    let range = TimeRange::parse(("from", params.from.as_deref()), ("to", params.to.as_deref()))?;
+
+    let column = group.column();
+    let mut q = QueryBuilder::new(group.select());
+    q.push(" WHERE o.deleted_at IS NULL AND o.status <> 'cancelled'");
+    range.push_conditions(&mut q, "o.created_at");
+    q.push(" GROUP BY key ORDER BY revenue DESC, key");
+    let rows: Vec<RevenueRow> = q.build_query_as().fetch_all(&pg_pool).await?;
+
+    if csv {
+        let lines: Result<Vec<Vec<u8>>, csv::Error> = std::iter::once(csv_line(["key", "order_count", "revenue"]))
+            .chain(rows.iter().map(|row| {
+                csv_line([row.key.clone().unwrap_or_default(), row.order_count.to_string(), row.revenue.to_string()])
+            }))
+            .collect();
+        // records of a fixed width written to memory cannot fail
+        let body = lines.expect("csv lines are well formed").concat();
+        return Ok((
+            StatusCode::OK,
+            [
+                (CONTENT_TYPE, "text/csv; charset=utf-8".to_owned()),
+                (CONTENT_DISPOSITION, format!("attachment; filename=\"revenue-by-{column}.csv\"")),
+            ],
+            body,
+        ).into_response());
+    }
+
+    let data = Response {
+        status: true,
+        message: format!("revenue by {column}"),
+        data: Some(rows),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ).into_response())
+}
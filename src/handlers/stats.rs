@@ -0,0 +1,137 @@
+//! Order counts, cups and revenue.
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::{extract::State, http::StatusCode};
+use serde::Serialize;
+use sqlx::{PgConnection, Postgres, QueryBuilder};
+use utoipa::ToSchema;
+
+use crate::auth::AuthContext;
+use crate::db::ReadPool;
+use crate::errors::ApiError;
+use crate::handlers::orders::{OrderFilter, TagFilter, push_order_filters};
+use crate::models::{Money, Response};
+
+#[derive(sqlx::FromRow)]
+pub(crate) struct OrderTotals {
+    order_count: i64,
+    quantity: i64,
+    revenue: Money,
+    average_order_value: Option<Money>,
+}
+
+#[derive(sqlx::FromRow, Serialize, ToSchema)]
+pub(crate) struct StatsBucket {
+    key: Option<String>,
+    /// Orders with at least one item under this key.
+    order_count: i64,
+    /// Cups ordered under this key.
+    quantity: i64,
+    /// What those cups were priced at, so a breakdown never credits one
+    /// line with the whole order's total.
+    revenue: Money,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct OrderStats {
+    order_count: i64,
+    /// Cups over the matching orders.
+    quantity: i64,
+    /// Sum of `total` over the matching orders.
+    revenue: Money,
+    /// Rounded to cents, null when no orders match.
+    average_order_value: Option<Money>,
+    by_size: Vec<StatsBucket>,
+    by_coffee_name: Vec<StatsBucket>,
+}
+
+/// `push_order_filters`, leaving cancelled orders out unless the filter
+/// names a status.
+fn push_stats_filters(q: &mut QueryBuilder<'_, Postgres>, filter: &OrderFilter) {
+    push_order_filters(q, filter);
+    if !filter.filters_status() {
+        q.push(" AND status <> 'cancelled'");
+    }
+}
+
+/// Cups of the matching orders' items per distinct value of the item's
+/// `column`, largest first, keyed by the value as text. `column` is always a literal from the caller,
+/// never user input.
+pub(crate) async fn order_buckets(
+    conn: &mut PgConnection,
+    column: &'static str,
+    filter: &OrderFilter,
+) -> Result<Vec<StatsBucket>, sqlx::Error> {
+    let mut q = QueryBuilder::new(format!(
+        "SELECT {column}::text AS key, COUNT(DISTINCT order_id) AS order_count, SUM(quantity)::BIGINT AS quantity, \
+        SUM(quantity * unit_price) AS revenue FROM order_items WHERE order_id IN (SELECT id FROM orders WHERE TRUE"
+    ));
+    push_stats_filters(&mut q, filter);
+    q.push(format!(") GROUP BY {column} ORDER BY quantity DESC, key"));
+    q.build_query_as().fetch_all(conn).await
+}
+
+/// Aggregates are computed in postgres, all from one snapshot so the
+/// breakdowns add up to the totals. Cancelled orders earn nothing and are
+/// left out unless `status` asks for them.
+#[utoipa::path(
+    get,
+    path = "/orders/stats",
+    tag = "orders",
+    params(
+        OrderFilter,
+        TagFilter,
+    ),
+    responses(
+        (status = 200, description = "Aggregates over the matching orders", body = OrderStatsResponse),
+        (status = 400, description = "Malformed query or body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorBody),
+        (status = 403, description = "Credentials lack the required scope or role", body = ErrorBody),
+        (status = 429, description = "Rate limit exceeded, see Retry-After", body = ErrorBody),
+        (status = 503, description = "Request timed out", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub(crate) async fn get_order_stats(
+    State(ReadPool(pg_pool)): State<ReadPool>,
+    auth: AuthContext,
+    mut filter: OrderFilter,
+) -> Result<impl IntoResponse, ApiError> {
+    filter.validate(&auth)?;
+
+    let mut tx = pg_pool.begin().await?;
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *tx)
+        .await?;
+
+    // sums stay NUMERIC end to end, so they are exact and cannot wrap
+    let mut q = QueryBuilder::new(
+        "SELECT COUNT(*) AS order_count, COALESCE(SUM(quantity), 0)::BIGINT AS quantity, \
+        COALESCE(SUM(total), 0) AS revenue, \
+        ROUND(AVG(total), 2) AS average_order_value FROM orders WHERE TRUE",
+    );
+    push_stats_filters(&mut q, &filter);
+    let totals: OrderTotals = q.build_query_as().fetch_one(&mut *tx).await?;
+
+    let stats = OrderStats {
+        order_count: totals.order_count,
+        quantity: totals.quantity,
+        revenue: totals.revenue,
+        average_order_value: totals.average_order_value,
+        by_size: order_buckets(&mut tx, "size", &filter).await?,
+        by_coffee_name: order_buckets(&mut tx, "coffee_name", &filter).await?,
+    };
+    tx.commit().await?;
+
+    let data = Response {
+        status: true,
+        message: format!("stats for {} orders", stats.order_count),
+        data: Some(stats),
+        meta: None,
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(data),
+    ))
+}
@@ -0,0 +1,147 @@
+//! `Idempotency-Key` replay for order creation.
+use std::time::Duration;
+use axum::response::IntoResponse;
+use axum::http::{header::{CONTENT_TYPE, LOCATION}, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+use sha2::{Digest, Sha256};
+
+use crate::errors::ApiError;
+
+pub(crate) static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub(crate) static IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+pub(crate) const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+pub(crate) const IDEMPOTENCY_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get(&IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH => Ok(Some(key.to_owned())),
+        _ => Err(ApiError::BadRequest(format!(
+            "Idempotency-Key must be 1 to {MAX_IDEMPOTENCY_KEY_LENGTH} visible ascii characters"
+        ))),
+    }
+}
+
+pub(crate) fn request_hash<T: Serialize>(body: &T) -> String {
+    let bytes = serde_json::to_vec(body).unwrap_or_default();
+    hex(&Sha256::digest(bytes))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Claims `key` for this request on the caller's transaction. Returns the
+/// stored response when the key was already used with the same body.
+///
+/// A concurrent retry blocks on the primary key until the first transaction
+/// finishes, then sees its committed response.
+pub(crate) async fn claim_idempotency_key(
+    conn: &mut PgConnection,
+    client: &str,
+    key: &str,
+    hash: &str,
+    ttl: Duration,
+) -> Result<Option<axum::response::Response>, ApiError> {
+    // an expired key is free to be reused
+    sqlx::query!(
+        "DELETE FROM idempotency_keys
+        WHERE client = $1 AND key = $2 AND created_at < now() - make_interval(secs => $3)",
+        client,
+        key,
+        ttl.as_secs_f64()
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    let claimed = sqlx::query!(
+        "INSERT INTO idempotency_keys (client, key, request_hash) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING",
+        client,
+        key,
+        hash
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected()
+        == 1;
+    if claimed {
+        return Ok(None);
+    }
+
+    let stored = sqlx::query!(
+        "SELECT request_hash, status_code, response_body FROM idempotency_keys WHERE client = $1 AND key = $2",
+        client,
+        key
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if stored.request_hash != hash {
+        return Err(ApiError::Conflict(
+            "Idempotency-Key was already used with a different request body".to_owned(),
+        ));
+    }
+
+    let (Some(code), Some(body)) = (stored.status_code, stored.response_body) else {
+        return Err(ApiError::Conflict("a request with this Idempotency-Key is still in progress".to_owned()));
+    };
+    let status = u16::try_from(code)
+        .ok()
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .unwrap_or(StatusCode::OK);
+
+    let id = serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body.pointer("/data/id").and_then(serde_json::Value::as_i64));
+    let mut response = (status, [(CONTENT_TYPE, "application/json")], body).into_response();
+    if let Some(id) = id {
+        if let Ok(location) = HeaderValue::from_str(&format!("/orders/{id}")) {
+            response.headers_mut().insert(LOCATION, location);
+        }
+    }
+    response.headers_mut().insert(IDEMPOTENT_REPLAYED.clone(), HeaderValue::from_static("true"));
+    Ok(Some(response))
+}
+
+pub(crate) async fn store_idempotent_response<T: Serialize>(
+    conn: &mut PgConnection,
+    client: &str,
+    key: &str,
+    status: StatusCode,
+    body: &T,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "UPDATE idempotency_keys SET status_code = $3, response_body = $4 WHERE client = $1 AND key = $2",
+        client,
+        key,
+        i32::from(status.as_u16()),
+        serde_json::to_string(body).unwrap_or_default()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn purge_idempotency_keys(db: PgPool, ttl: Duration) {
+    let mut interval = tokio::time::interval(IDEMPOTENCY_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let purged = sqlx::query!(
+            "DELETE FROM idempotency_keys WHERE created_at < now() - make_interval(secs => $1)",
+            ttl.as_secs_f64()
+        )
+        .execute(&db)
+        .await;
+        match purged {
+            Ok(result) if result.rows_affected() > 0 => {
+                tracing::info!(purged = result.rows_affected(), "purged expired idempotency keys")
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!(error = %err, "could not purge idempotency keys"),
+        }
+    }
+}
@@ -0,0 +1,423 @@
+//! Coffee order service. `main` loads the config and serves the router
+//! built here; tests build the same router against their own pool.
+mod auth;
+mod cache;
+mod config;
+mod db;
+mod docs;
+mod errors;
+mod exports;
+mod feed;
+mod graphql;
+pub mod grpc;
+mod handlers;
+mod idempotency;
+mod listen;
+mod metrics;
+mod middleware;
+mod models;
+mod negotiate;
+mod outbox;
+mod pagination;
+mod receipts;
+mod reload;
+mod repository;
+mod retention;
+mod s3;
+mod seed;
+mod slack;
+mod tls;
+#[cfg(unix)]
+mod unix_socket;
+mod webhooks;
+mod worker;
+
+pub use config::{Config, DEFAULT_LOG_FILTER};
+pub use db::{connect_pool, connect_read_pool, run_migrations};
+pub use grpc::serve_grpc;
+pub use handlers::probes::BuildInfo;
+pub use idempotency::purge_idempotency_keys;
+pub use listen::bind_http_listeners;
+pub use metrics::install_metrics_recorder;
+pub use exports::spawn_export_job;
+pub use outbox::{spawn_outbox_dispatcher, DispatchSummary, OutboxDispatcher};
+pub use reload::{env_file, ConfigReloader, LogFilterHandle, ReloadReport};
+pub use repository::MemoryOrderRepository;
+pub use retention::{purge_old_orders, spawn_retention_job, RetentionSettings};
+pub use seed::{seed_sample_data, SeedReport};
+#[cfg(unix)]
+pub use unix_socket::UnixSocket;
+pub use worker::{spawn_order_worker, sweep_orders, SweepSummary, WorkerSettings};
+
+use std::sync::Arc;
+use std::time::Duration;
+use axum::{
+  extract::{DefaultBodyLimit, FromRef, MatchedPath, Request},
+  http::{header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH, LINK}, HeaderName, Method},
+  routing::{get, patch, post},Router,
+};
+use sqlx::PgPool;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::Level;
+use tokio_util::sync::CancellationToken;
+use metrics_exporter_prometheus::PrometheusHandle;
+
+use crate::auth::{Authenticator, authenticate};
+use crate::cache::{OrderListCache, X_CACHE};
+use crate::config::CorsOrigins;
+use crate::db::{ReadPool, read_pool};
+use crate::docs::{banner, openapi_json, swagger_ui};
+use crate::errors::route_not_found;
+use crate::feed::{OrderFeed, order_socket, stream_orders};
+use crate::graphql::graphql_router;
+use crate::handlers::audit::get_order_events;
+use crate::handlers::csv::{export_orders_csv, import_orders_csv};
+use crate::handlers::customers::{
+    add_customer, delete_customer, get_customer, get_customer_orders, get_customers,
+    update_customer,
+};
+use crate::handlers::dev::{RELOAD_CONFIG_PATH, reload_config, reset_database};
+use crate::handlers::inventory::{get_inventory, update_inventory};
+use crate::handlers::menu::{PriceTolerance, get_menu};
+use crate::handlers::orders::{
+    X_TOTAL_COUNT, add_order, add_orders_batch, cancel_order, delete_order, delete_orders, get_order,
+    get_order_count, get_order_tags, get_orders, patch_order, restore_order, update_order, update_order_status,
+    update_order_statuses,
+};
+use crate::handlers::exports::{get_exports, run_exports};
+use crate::handlers::outbox::{get_outbox_events, retry_outbox_event};
+use crate::handlers::probes::{health, livez, readyz, version};
+use crate::handlers::reports::revenue_report;
+use crate::handlers::stats::get_order_stats;
+use crate::idempotency::IDEMPOTENCY_KEY;
+use crate::metrics::{metrics, track_metrics};
+use crate::middleware::{
+    RateLimiter, RateLimiters, RequestId, X_READ_FROM, X_REQUEST_ID, conditional_get, cors, maintenance,
+    method_not_allowed, payload_too_large, rate_limit, read_from, request_id, timeout,
+};
+use crate::negotiate::msgpack_responses;
+use crate::exports::Exporter;
+use crate::outbox::Outbox;
+use crate::pagination::PageLimits;
+use crate::receipts::Receipts;
+use crate::repository::{OrderRepository, PgOrderRepository};
+use crate::slack::SlackNotifier;
+
+/// Shared state handed to every handler.
+#[derive(Clone)]
+pub struct AppState {
+    db: PgPool,
+    /// `DATABASE_READ_URL`, for the handlers that only read.
+    replica: Option<PgPool>,
+    /// Set once graceful shutdown begins so readiness probes fail while
+    /// connections drain.
+    shutting_down: CancellationToken,
+    /// How long a stored `Idempotency-Key` response is replayed.
+    idempotency_ttl: Duration,
+    /// Upper bound on data rows in one CSV import.
+    import_max_rows: usize,
+    price_tolerance: PriceTolerance,
+    metrics: PrometheusHandle,
+    page_limits: PageLimits,
+    feed: OrderFeed,
+    /// Emails a receipt for orders placed with an `email`.
+    receipts: Receipts,
+    /// Pings Slack about orders matching the `SLACK_ORDER_*` rules.
+    slack: SlackNotifier,
+    /// `None` without `EXPORT_S3_BUCKET`.
+    exporter: Option<Exporter>,
+    /// `GET /orders` pages, emptied by every `feed` publish.
+    list_cache: OrderListCache,
+    orders: Arc<dyn OrderRepository>,
+    build: BuildInfo,
+    /// Rate limits, CORS origins and maintenance mode, swapped on reload.
+    reloader: ConfigReloader,
+}
+
+impl AppState {
+    pub fn new(db: PgPool, metrics: PrometheusHandle, config: &Config) -> AppState {
+        let list_cache = OrderListCache::new(config.list_cache_ttl, config.list_cache_max_entries);
+        let shutting_down = CancellationToken::new();
+        // handler and repository writes queue webhook events through the same handle
+        let outbox = Outbox::new(config);
+        AppState {
+            db: db.clone(),
+            replica: None,
+            shutting_down: shutting_down.clone(),
+            idempotency_ttl: config.idempotency_ttl,
+            import_max_rows: config.import_max_rows,
+            price_tolerance: config.price_tolerance,
+            page_limits: config.page_limits,
+            metrics,
+            feed: OrderFeed::new(outbox, list_cache.clone()),
+            receipts: Receipts::start(config.mail.as_ref()),
+            slack: SlackNotifier::start(config.slack.as_ref()),
+            exporter: config.export.as_ref().map(|settings| Exporter::new(settings, shutting_down)),
+            list_cache,
+            orders: Arc::new(PgOrderRepository::new(db, None, outbox, config.price_tolerance)),
+            build: BuildInfo::new(&config.environment),
+            reloader: ConfigReloader::new(config),
+        }
+    }
+
+    /// Lets a reload change `RUST_LOG` through the subscriber's `handle`.
+    pub fn with_log_filter(mut self, handle: LogFilterHandle) -> AppState {
+        self.reloader = self.reloader.with_log_filter(handle);
+        self
+    }
+
+    /// Reloads the config for every clone of this state, e.g. on SIGHUP.
+    pub fn config_reloader(&self) -> ConfigReloader {
+        self.reloader.clone()
+    }
+
+    /// Sends reads to `replica` unless a request sets `X-Read-From: primary`.
+    pub fn with_read_pool(mut self, replica: PgPool) -> AppState {
+        self.orders = Arc::new(PgOrderRepository::new(
+            self.db.clone(),
+            Some(replica.clone()),
+            self.feed.outbox(),
+            self.price_tolerance,
+        ));
+        self.replica = Some(replica);
+        self
+    }
+
+    /// Serves the order CRUD routes from `orders` instead of the database.
+    pub fn with_memory_orders(mut self, orders: Arc<MemoryOrderRepository>) -> AppState {
+        self.orders = orders;
+        self
+    }
+
+    /// Cancelled by the caller once graceful shutdown begins.
+    pub fn shutting_down(&self) -> CancellationToken {
+        self.shutting_down.clone()
+    }
+
+    /// Waits for export runs started through `POST /admin/exports/run`,
+    /// which fail themselves once `shutting_down` is cancelled. Await it
+    /// before closing the pool so every run is recorded.
+    pub async fn finish_exports(&self) {
+        if let Some(exporter) = &self.exporter {
+            exporter.finish_runs().await;
+        }
+    }
+}
+
+impl FromRef<AppState> for OrderFeed {
+    fn from_ref(state: &AppState) -> Self {
+        state.feed.clone()
+    }
+}
+
+impl FromRef<AppState> for Receipts {
+    fn from_ref(state: &AppState) -> Self {
+        state.receipts.clone()
+    }
+}
+
+impl FromRef<AppState> for Option<Exporter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.exporter.clone()
+    }
+}
+
+impl FromRef<AppState> for ConfigReloader {
+    fn from_ref(state: &AppState) -> Self {
+        state.reloader.clone()
+    }
+}
+
+impl FromRef<AppState> for OrderListCache {
+    fn from_ref(state: &AppState) -> Self {
+        state.list_cache.clone()
+    }
+}
+
+impl FromRef<AppState> for PageLimits {
+    fn from_ref(state: &AppState) -> Self {
+        state.page_limits
+    }
+}
+
+impl FromRef<AppState> for PriceTolerance {
+    fn from_ref(state: &AppState) -> Self {
+        state.price_tolerance
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn OrderRepository> {
+    fn from_ref(state: &AppState) -> Self {
+        state.orders.clone()
+    }
+}
+
+impl FromRef<AppState> for PgPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.db.clone()
+    }
+}
+
+impl FromRef<AppState> for ReadPool {
+    fn from_ref(state: &AppState) -> Self {
+        ReadPool(read_pool(&state.db, state.replica.as_ref()).clone())
+    }
+}
+
+/// Every route and layer of the service, ready for `axum::serve` or `oneshot`.
+pub fn build_router(state: AppState, config: &Config) -> Router {
+    if config.auth_disabled {
+        tracing::warn!("AUTH_DISABLED is set, order endpoints are unauthenticated");
+    }
+
+    // bulk routes take bigger bodies than the default layered on below
+    let bulk_limit = DefaultBodyLimit::max(config.bulk_body_limit);
+    let mut orders = Router::new()
+    .route("/orders", get(get_orders).post(add_order).delete(delete_orders))
+    .route("/orders/batch", post(add_orders_batch).layer(bulk_limit))
+    .route("/orders/status", post(update_order_statuses))
+    .route("/orders/count", get(get_order_count))
+    .route("/orders/tags", get(get_order_tags))
+    .route("/orders/export.csv", get(export_orders_csv))
+    .route("/orders/stats", get(get_order_stats))
+    .route("/orders/stream", get(stream_orders))
+    .route("/ws", get(order_socket))
+    .route("/orders/import", post(import_orders_csv).layer(bulk_limit))
+    .route("/orders/:id", get(get_order).put(update_order).patch(patch_order).delete(delete_order))
+    .route("/orders/:id/status", patch(update_order_status))
+    .route("/orders/:id/cancel", post(cancel_order))
+    .route("/orders/:id/restore", post(restore_order))
+    .route("/orders/:id/events", get(get_order_events))
+    .route("/menu", get(get_menu))
+    .route("/inventory", get(get_inventory))
+    .route("/inventory/:name", patch(update_inventory))
+    .route("/customers", get(get_customers).post(add_customer))
+    .route("/customers/:id", get(get_customer).patch(update_customer).delete(delete_customer))
+    .route("/customers/:id/orders", get(get_customer_orders))
+    .route("/reports/revenue", get(revenue_report))
+    .route("/admin/outbox", get(get_outbox_events))
+    .route("/admin/outbox/:id/retry", post(retry_outbox_event))
+    .route("/admin/exports", get(get_exports))
+    .route("/admin/exports/run", post(run_exports));
+    // absent rather than refused, so production does not even expose them
+    if config.dev_routes {
+        orders = orders
+        .route("/dev/reset", post(reset_database))
+        .route(RELOAD_CONFIG_PATH, post(reload_config));
+    }
+
+    let orders = orders
+    // route_layer so unknown paths still 404 instead of 401
+    .route_layer(axum::middleware::from_fn_with_state(
+        Arc::new(Authenticator::new(config)),
+        authenticate,
+    ))
+    .route_layer(axum::middleware::from_fn_with_state(config.request_timeout, timeout));
+
+    // probes answer fast or not at all, so they get a much shorter budget
+    let probes = Router::new()
+    .route("/health", get(health))
+    .route("/livez", get(livez))
+    .route("/readyz", get(readyz))
+    .route("/version", get(version))
+    .route("/metrics", get(metrics))
+    .route_layer(axum::middleware::from_fn_with_state(config.health_timeout, timeout));
+
+    // probes, the banner, the spec and the docs stay open
+    let mut router = Router::new()
+    .route("/", get(banner))
+    .route("/api-docs/openapi.json", get(openapi_json));
+    if config.docs_enabled {
+        router = router.route("/docs", get(swagger_ui));
+    }
+
+    let settings = state.reloader.settings();
+    router
+    .merge(probes)
+    .merge(orders)
+    .merge(graphql_router(state.clone(), config))
+    .fallback(route_not_found)
+    .layer(axum::middleware::from_fn(read_from))
+    .layer(axum::middleware::from_fn_with_state(settings.clone(), maintenance))
+    .layer(DefaultBodyLimit::max(config.body_limit))
+    .layer(axum::middleware::from_fn(method_not_allowed))
+    .layer(axum::middleware::from_fn(payload_too_large))
+    .layer(axum::middleware::from_fn_with_state(
+        Arc::new(RateLimiters { write: RateLimiter::new(), read: RateLimiter::new(), settings: settings.clone() }),
+        rate_limit,
+    ))
+    // answers preflights itself, so OPTIONS never reaches a handler
+    .layer(axum::middleware::from_fn_with_state(settings, cors))
+    // outside the layers that reject requests, so their errors are re-encoded too
+    .layer(axum::middleware::from_fn(msgpack_responses))
+    // tags the bytes as sent, after re-encoding but before compression
+    .layer(axum::middleware::from_fn(conditional_get))
+    // outside rate limiting and cors so rejected requests are counted too
+    .layer(axum::middleware::from_fn(track_metrics))
+    .layer(
+        TraceLayer::new_for_http()
+            .make_span_with(|request: &Request| {
+                let matched_path = request
+                    .extensions()
+                    .get::<MatchedPath>()
+                    .map(MatchedPath::as_str);
+                let request_id = request
+                    .extensions()
+                    .get::<RequestId>()
+                    .map(|id| id.0.as_str());
+                tracing::info_span!(
+                    "request",
+                    request_id,
+                    method = %request.method(),
+                    path = %request.uri().path(),
+                    matched_path,
+                    client = tracing::field::Empty,
+                )
+            })
+            .on_response(
+                DefaultOnResponse::new()
+                    .level(Level::INFO)
+                    .latency_unit(LatencyUnit::Millis),
+            ),
+    )
+    // gzip or brotli per Accept-Encoding; small bodies are not worth the cpu
+    .layer(
+        CompressionLayer::new().compress_when(
+            SizeAbove::new(config.compression_min_bytes)
+                .and(NotForContentType::GRPC)
+                .and(NotForContentType::IMAGES)
+                .and(NotForContentType::SSE),
+        ),
+    )
+    // outermost so the trace span and every handler (fallback included) can see the id
+    .layer(axum::middleware::from_fn(request_id))
+    .with_state(state)
+}
+
+
+pub(crate) fn cors_layer(origins: &CorsOrigins) -> CorsLayer {
+    let allow_origin = match origins {
+        CorsOrigins::Any => AllowOrigin::any(),
+        CorsOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([
+            IDEMPOTENCY_KEY.clone(),
+            CONTENT_TYPE,
+            AUTHORIZATION,
+            IF_MATCH,
+            IF_NONE_MATCH,
+            X_READ_FROM.clone(),
+            HeaderName::from_static("x-api-key"),
+            X_REQUEST_ID.clone(),
+        ])
+        .expose_headers([X_REQUEST_ID.clone(), ETAG, X_TOTAL_COUNT.clone(), LINK, X_CACHE.clone()])
+        .max_age(Duration::from_secs(600))
+}
@@ -0,0 +1,40 @@
+//! The TCP listeners the HTTP router is served on: `HOST`:`PORT`, or every
+//! address in `BIND_ADDRS`.
+use std::net::SocketAddr;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+use crate::Config;
+
+/// Binds every configured address, failing on the first one that cannot be
+/// bound with an error naming it.
+pub async fn bind_http_listeners(config: &Config) -> Result<Vec<TcpListener>, String> {
+    if config.bind_addrs.is_empty() {
+        let listener = TcpListener::bind((config.host.as_str(), config.port))
+            .await
+            .map_err(|err| format!("could not listen on {}:{}: {err}", config.host, config.port))?;
+        return Ok(vec![listener]);
+    }
+
+    config
+        .bind_addrs
+        .iter()
+        .map(|addr| bind(*addr).map_err(|err| format!("could not listen on {addr}: {err}")))
+        .collect()
+}
+
+// IPv6 sockets are IPv6 only, so `[::]:3000` does not also claim the IPv4
+// port and clash with a `0.0.0.0:3000` in the same list; list both for both
+fn bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // as TcpListener::bind does, so a restart need not wait out TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
@@ -1,19 +1,21 @@
-use std::time::Duration;
 use std::env;
-use axum::response::IntoResponse;
-use axum::Json;
-
-use axum::{
-  extract::{path, Path, State},
-  http::StatusCode,
-  routing::{get, patch},Router,
-};
-use axum::http::header::IF_MATCH;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use futures::future;
+use tokio_util::sync::CancellationToken;
 use tokio::net::TcpListener;
-
+use tokio::signal;
+use tokio::sync::oneshot;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter};
+#[cfg(unix)]
+use rust_orders::UnixSocket;
+use rust_orders::{bind_http_listeners, build_router, env_file, BuildInfo, ConfigReloader, DEFAULT_LOG_FILTER, connect_pool, connect_read_pool, install_metrics_recorder, purge_idempotency_keys, run_migrations, seed_sample_data, serve_grpc, spawn_export_job, spawn_order_worker, spawn_outbox_dispatcher, spawn_retention_job, AppState, Config};
 
 #[tokio::main]
 async fn main() {
@@ -21,277 +23,278 @@ async fn main() {
     //ENV SETUP
     // Only load `.env` file in development
 
-    if env::var("ENVIRONMENT").unwrap_or_else(|_| "development".to_string()) == "development" {
-        dotenvy::dotenv().ok();  // Load `.env` in development environment
-        println!("Loaded .env file");
-    } else {
-        println!("Loading prod env");
+    let development = env::var("ENVIRONMENT").unwrap_or_else(|_| "development".to_string()) == "development";
+    // `.env` in development or ENV_FILE, which a reload reads again
+    if let Some(path) = env_file(development) {
+        dotenvy::from_path(path).ok();
     }
 
+    //LOGGING
+    // RUST_LOG may come from `.env`, so this runs after it is loaded; the
+    // reload layer lets SIGHUP swap the filter
+    let (log_filter, log_filter_handle) = reload::Layer::new(
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER)),
+    );
+    tracing_subscriber::registry().with(log_filter).with(fmt::layer()).init();
 
-    //VARIABLES FROM ENV
-    let url = std::env::var("DATABASE_URL")
-        .expect("database url is empty or not provided");
-        
-    //DB POOL
-    let db = PgPoolOptions::new()
-    .max_connections(16)
-    .connect(&url)
-    .await
-    .expect("cannot connect to database");
-
-    //TCP
-    let lis = TcpListener::bind("0.0.0.0:3000".to_owned())
-    .await
-    .expect("could not create tcp listener");
-
-    println!("listening on {}", lis.local_addr().unwrap());
-
-    //ROUTES
-   let r = Router::new()
-    .route("/", get(|| async {"MAY THE FORCE BE WITH YOU"}))
-    .route("/orders", get(get_orders).post(add_order))
-    .route("/orders/:id", get(get_order).put(update_order).delete(delete_order))
-    .with_state(db);
-
-    //SERVER
-    axum::serve(lis, r).await.expect("error starting server");
-    println!("Hello, world!");
-}
-
-#[derive(Serialize)]
-struct Response<T> {
-    status: bool,
-    message: Option<String>,
-    data: Option<T>,
-}
-
-
-#[derive(Serialize)]
-struct Orders {
-    id: Option<i32>,
-    name: Option<String>,
-    coffee_name: Option<String>,
-    size: Option<String>,
-    total: Option<i32>,
-}
-
-
-async fn get_orders(
-    State(pg_pool): State<PgPool>
-) -> Result<
-    (StatusCode, Json<Response<Vec<Orders>>>),
-    (StatusCode, Json<Response<()>>)
-    >
-     {
-
-    let tr = sqlx::query_as!(Orders, "SELECT * FROM orders ORDER BY id")
-    .fetch_all(&pg_pool)
-    .await
-    .map_err(|_| {
-        let error_response = Response {
-            status: false,
-            message: Some("Error retrieving orders".to_owned()),
-            data: None,
-        };
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-    })?;
-
-
-    let data = Response {
-        status: true,
-        message: Some("found orders".to_owned()),
-        data: Some(tr)
-    };
+    if development {
+        tracing::info!("loaded .env file");
+    } else {
+        tracing::info!("loading prod env");
+    }
 
 
-    Ok((
-        StatusCode::OK,
-        Json(data),
-    ))
-}
+    //CONFIG
+    let config = Config::from_env().unwrap_or_else(|errors| {
+        for error in errors {
+            tracing::error!("config error: {error}");
+        }
+        std::process::exit(1);
+    });
+
+    let build = BuildInfo::new(&config.environment);
+    tracing::info!(
+        version = build.version,
+        commit = build.commit,
+        built_at = build.built_at,
+        environment = %build.environment,
+        "starting rust-orders"
+    );
+
+    // --seed loads sample orders before serving, never against production
+    let seed = env::args().skip(1).any(|arg| arg == "--seed");
+    if seed && config.environment == "production" {
+        tracing::error!("--seed refuses to run when ENVIRONMENT is production");
+        std::process::exit(1);
+    }
 
-#[derive(Deserialize)]
-struct CreateOrdersReq {
-    name: String,
-    coffee_name: String,
-    size: String,
-    total: i32,
-}
+    //METRICS
+    let metrics = install_metrics_recorder();
 
-#[derive(sqlx::FromRow, Serialize)]
-struct CreateOrdersRow {
-    id: i32
-}
+    //DB POOL
+    let db = connect_pool(&config).await.unwrap_or_else(|err| {
+        tracing::error!(error = %err, "cannot connect to database, giving up");
+        std::process::exit(1);
+    });
+
+    let replica = connect_read_pool(&config).await.unwrap_or_else(|err| {
+        tracing::error!(error = %err, "cannot connect to the read replica, giving up");
+        std::process::exit(1);
+    });
+
+    //MIGRATIONS
+    if config.run_migrations {
+        run_migrations(&db).await.expect("could not run database migrations");
+    } else {
+        tracing::info!("RUN_MIGRATIONS is false, skipping migrations");
+    }
 
+    //SEED
+    if seed {
+        let report = seed_sample_data(&db).await.unwrap_or_else(|err| {
+            tracing::error!(error = %err, "could not seed sample data");
+            std::process::exit(1);
+        });
+        tracing::info!(
+            orders = report.orders,
+            customers = report.customers,
+            replaced = report.replaced,
+            "seeded sample data"
+        );
+    }
 
-async fn add_order(
-    State(pg_pool): State<PgPool>,
-    Json(order): Json<CreateOrdersReq>,
-) -> Result<
-    (StatusCode, Json<Response<CreateOrdersRow>>),
-    (StatusCode, Json<Response<()>>)
->{
-    let co = sqlx::query_as!(
-    CreateOrdersRow, 
-    "INSERT INTO orders (name, coffee_name, size, total) VALUES ($1, $2, $3, $4) RETURNING id", 
-    order.name, 
-    order.coffee_name,
-    order.size, 
-    order.total)
-    .fetch_one(&pg_pool)
+    //TCP
+    let listeners = bind_http_listeners(&config).await.unwrap_or_else(|err| {
+        tracing::error!("{err}");
+        std::process::exit(1);
+    });
+    let addrs: Vec<String> = listeners.iter().map(|lis| lis.local_addr().unwrap().to_string()).collect();
+    tracing::info!("listening on {} ({})", addrs.join(", "), config.environment);
+
+    let grpc_lis = TcpListener::bind((config.host.as_str(), config.grpc_port))
     .await
-        .map_err(|_| {
-            let error_response = Response {
-                status: false,
-                message: Some("Error adding order".to_owned()),
-                data: None,
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?;
-
-    let data = Response {
-        status: true,
-        message: Some("added successfully".to_owned()),
-        data: Some(co)
+    .expect("could not create grpc listener");
+
+    tracing::info!("serving grpc on {}", grpc_lis.local_addr().unwrap());
+
+    // alongside the TCP listener, not instead of it
+    #[cfg(unix)]
+    let unix_socket = match &config.unix_socket {
+        Some(path) => {
+            let socket = UnixSocket::bind(path, config.unix_socket_mode).await.unwrap_or_else(|err| {
+                tracing::error!(error = %err, "could not listen on unix socket {}", path.display());
+                std::process::exit(1);
+            });
+            tracing::info!("listening on unix socket {}", path.display());
+            Some(socket)
+        }
+        None => None,
     };
 
-    Ok((
-        StatusCode::OK,
-        Json(data),
-    ))
-
-}
-
-
-#[derive(Deserialize)]
-struct UpdateOrdersReq {
-    name: Option<String>,
-    coffee_name: Option<String>,
-    size: Option<String>,
-    total: Option<i32>,
-}
-
-
-async fn update_order(
-    State(pg_pool): State<PgPool>,
-    Path(id): Path<i32>,
-    Json(order): Json<UpdateOrdersReq>,
-
-) -> Result<
-    (StatusCode, Json<Response<CreateOrdersRow>>),
-    (StatusCode, Json<Response<()>>)
->{
-
-    let mut q = "UPDATE orders SET id = $1".to_owned();
+    //ROUTES
+    let mut state = AppState::new(db.clone(), metrics, &config).with_log_filter(log_filter_handle);
+    if let Some(replica) = &replica {
+        state = state.with_read_pool(replica.clone());
+    }
 
-    let mut i = 2;
+    //BACKGROUND
+    tokio::spawn(purge_idempotency_keys(db.clone(), config.idempotency_ttl));
 
-    if order.name.is_some() {
-        q.push_str(&format!(", name = ${i}"));
-        i = i + 1;
-    };
+    let shutting_down = state.shutting_down();
+    let grpc_state = state.clone();
+    let worker = spawn_order_worker(state.clone(), &config, shutting_down.clone());
+    let retention = spawn_retention_job(&state, &config, shutting_down.clone());
+    let outbox = spawn_outbox_dispatcher(db.clone(), &config, shutting_down.clone());
+    let export = spawn_export_job(db.clone(), &config, shutting_down.clone());
+    let manual_exports = state.clone();
+    tokio::spawn(reload_on_hangup(state.config_reloader()));
+    let tls = state.config_reloader().tls();
+    let r = build_router(state, &config);
 
-    if order.coffee_name.is_some() {
-        q.push_str(&format!(", coffee_name = ${i}"));
-        i = i + 1;
+    //SERVER
+    let (draining_tx, draining_rx) = oneshot::channel::<()>();
+    // the gRPC server stops taking calls once the HTTP one starts draining
+    let grpc = serve_grpc(grpc_lis, grpc_state, &config, shutting_down.clone().cancelled_owned());
+    // as is the unix socket, which unlinks its file once it stops
+    #[cfg(unix)]
+    let unix = {
+        let (router, stopped) = (r.clone(), shutting_down.clone().cancelled_owned());
+        async move {
+            match unix_socket {
+                Some(socket) => socket.serve(router, stopped).await,
+                None => Ok(()),
+            }
+        }
     };
-
-    if order.size.is_some() {
-        q.push_str(&format!(", size = ${i}"));
-        i = i + 1;
+    #[cfg(not(unix))]
+    let unix = std::future::ready(std::io::Result::Ok(()));
+    // every listener drains on the same signal
+    tokio::spawn({
+        let shutting_down = shutting_down.clone();
+        async move {
+            shutdown_signal().await;
+            shutting_down.cancel();
+            tracing::info!("shutdown started, draining in-flight requests");
+            let _ = draining_tx.send(());
+        }
+    });
+    if tls.is_some() {
+        tracing::info!("TLS_CERT_PATH is set, serving HTTPS with HTTP/2");
+    }
+    let app = r.into_make_service_with_connect_info::<SocketAddr>();
+    let server = future::try_join_all(
+        listeners.into_iter().map(|lis| serve_http(lis, app.clone(), tls.clone(), shutting_down.clone())),
+    );
+
+    // once draining starts, give in-flight requests a bounded amount of time
+    let drain_deadline = async {
+        match draining_rx.await {
+            Ok(()) => tokio::time::sleep(config.shutdown_timeout).await,
+            Err(_) => std::future::pending().await,
+        }
     };
 
-    if order.total.is_some() {
-        q.push_str(&format!(", total = ${i}"));
+    let servers = async {
+        let (http, grpc, unix) = tokio::join!(server, grpc, unix);
+        http.expect("error starting server");
+        grpc.expect("error starting grpc server");
+        unix.expect("error serving the unix socket");
     };
 
-    q.push_str(&format!(" WHERE id = $1"));
-
-    let mut s = sqlx::query(&q).bind(id);
-
-    if order.name.is_some() {
-        s = s.bind(order.name);
+    tokio::select! {
+        () = servers => {},
+        _ = drain_deadline => tracing::warn!(
+            "drain timeout of {}s elapsed, dropping remaining connections",
+            config.shutdown_timeout.as_secs()
+        ),
     }
 
-    if order.coffee_name.is_some() {
-        s = s.bind(order.coffee_name);
+    // let a sweep, retention batch or outbox batch in progress commit before the pool goes away
+    let (worker, retention, outbox, export) = tokio::join!(worker, retention, outbox, export);
+    if let Err(err) = worker {
+        tracing::error!(error = %err, "order worker panicked");
     }
-
-    if order.size.is_some() {
-        s = s.bind(order.size);
+    if let Err(err) = retention {
+        tracing::error!(error = %err, "retention job panicked");
     }
-
-    if order.total.is_some() {
-        s = s.bind(order.total);
+    if let Err(err) = outbox {
+        tracing::error!(error = %err, "outbox dispatcher panicked");
     }
+    if let Err(err) = export {
+        tracing::error!(error = %err, "export job panicked");
+    }
+    // manual export runs record how they ended, failed if cut short
+    manual_exports.finish_exports().await;
+    db.close().await;
+    if let Some(replica) = replica {
+        replica.close().await;
+    }
+    tracing::info!("shutdown complete");
+}
 
-
-    s.execute(&pg_pool)
-        .await
-        .map_err(|_| {
-            let error_response = Response {
-                status: false,
-                message: Some("Error updating order".to_owned()),
-                data: None,
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?;
-    
-        let data = Response {
-            status: true,
-            message: None,
-            data: None
-        };
-    
-    
-        Ok((
-            StatusCode::OK,
-            Json(data),
-        ))
+/// One listener's server, HTTPS when `tls` is set, draining once `stop`
+/// is cancelled.
+fn serve_http(
+    lis: TcpListener,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    tls: Option<RustlsConfig>,
+    stop: CancellationToken,
+) -> Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>> {
+    let Some(tls) = tls else {
+        return Box::pin(axum::serve(lis, app).with_graceful_shutdown(stop.cancelled_owned()).into_future());
+    };
+    // the drain deadline in main bounds this as it does the plain listener
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            stop.cancelled().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    let lis = lis.into_std().expect("could not hand the listener to the TLS server");
+    Box::pin(axum_server::from_tcp_rustls(lis, tls).handle(handle).serve(app))
 }
-async fn delete_order(
-    Path(id): Path<i32>,
-    State(pg_pool): State<PgPool>
-) -> Result<
-    (StatusCode, Json<Response<CreateOrdersRow>>),
-    (StatusCode, Json<Response<()>>)
->{
-    sqlx::query!(
-        "
-        DELETE FROM orders
-        WHERE id = $1
-         ",
-        id
-        )
-        .execute(&pg_pool)
-        .await
-        .map_err(|_|{
-            let error_response = Response {
-                status: false,
-                message: Some("Error deleting order".to_owned()),
-                data: None,
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(error_response))
-        })?;
-
-        let data = Response {
-            status: true,
-            message: None,
-            data: None
-        };
-    
-    
-        Ok((
-            StatusCode::OK,
-            Json(data),
-        ))
+
+/// Reloads the config on every SIGHUP, logging what was refused.
+async fn reload_on_hangup(reloader: ConfigReloader) {
+    #[cfg(unix)]
+    {
+        let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+            .expect("could not install SIGHUP handler");
+        while hangup.recv().await.is_some() {
+            tracing::info!("SIGHUP received, reloading config");
+            if let Err(errors) = reloader.reload() {
+                for error in errors {
+                    tracing::error!("config error, nothing reloaded: {error}");
+                }
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = reloader;
 }
 
+/// Resolves on Ctrl-C or, on unix, SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c().await.expect("could not install Ctrl-C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("could not install SIGTERM handler")
+            .recv()
+            .await;
+    };
 
-async fn get_order(
-    State(pg_pool): State<PgPool>
-) -> Result<(StatusCode, String),(StatusCode, String)>{
-    todo!()
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
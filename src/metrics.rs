@@ -0,0 +1,74 @@
+//! Prometheus request and pool metrics.
+use std::time::Instant;
+use axum::response::IntoResponse;
+use axum::{extract::{MatchedPath, Request, State}, http::header::CONTENT_TYPE, middleware::Next};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::AppState;
+
+pub(crate) const REQUEST_DURATION_SECONDS: &str = "http_request_duration_seconds";
+pub(crate) const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub fn install_metrics_recorder() -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Full(REQUEST_DURATION_SECONDS.to_owned()), LATENCY_BUCKETS)
+        .expect("latency buckets are not empty")
+        .install_recorder()
+        .expect("could not install metrics recorder");
+    metrics::describe_counter!("http_requests_total", "Requests by method, route template and status");
+    metrics::describe_histogram!(REQUEST_DURATION_SECONDS, metrics::Unit::Seconds, "Request latency by method and route template");
+    metrics::describe_counter!("db_pool_acquire_timeouts_total", "Requests that gave up waiting for a pooled connection");
+    metrics::describe_counter!("receipt_emails_sent_total", "Order receipts the SMTP server accepted");
+    metrics::describe_counter!("receipt_emails_failed_total", "Order receipts dropped or given up on after retries");
+    metrics::describe_counter!("slack_notifications_sent_total", "Order notifications Slack accepted");
+    metrics::describe_counter!("slack_notifications_dropped_total", "Order notifications not posted over the rate limit or a full queue");
+    metrics::describe_counter!("slack_notifications_failed_total", "Order notifications given up on after a retry");
+    // exported from the first scrape rather than after the first timeout
+    metrics::counter!("db_pool_acquire_timeouts_total").absolute(0);
+    handle
+}
+
+/// Counts and times every request by its route template, so `/orders/:id`
+/// is one series no matter how many ids are requested.
+pub(crate) async fn track_metrics(request: Request, next: Next) -> axum::response::Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        // scrapes would otherwise dominate the request counts
+        Some(path) if path.as_str() == "/metrics" => return next.run(request).await,
+        Some(path) => path.as_str().to_owned(),
+        None => "unmatched".to_owned(),
+    };
+    let method = request.method().to_string();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16().to_string();
+    metrics::counter!("http_requests_total", "method" => method.clone(), "route" => route.clone(), "status" => status)
+        .increment(1);
+    metrics::histogram!(REQUEST_DURATION_SECONDS, "method" => method, "route" => route).record(elapsed);
+
+    response
+}
+
+/// Pool gauges are sampled on scrape; sqlx does not report how many tasks are
+/// waiting for a connection, so acquire timeouts are counted instead.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "probes",
+    responses((status = 200, description = "Prometheus text exposition", content_type = "text/plain", body = String)),
+)]
+pub(crate) async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let size = state.db.size();
+    let idle = u32::try_from(state.db.num_idle()).unwrap_or(u32::MAX);
+    metrics::gauge!("db_pool_connections").set(f64::from(size));
+    metrics::gauge!("db_pool_connections_idle").set(f64::from(idle));
+    metrics::gauge!("db_pool_connections_in_use").set(f64::from(size.saturating_sub(idle)));
+    metrics::gauge!("db_pool_connections_max").set(f64::from(state.db.options().get_max_connections()));
+
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
@@ -0,0 +1,313 @@
+//! Request timeouts, rate limiting, CORS, maintenance mode, request ids,
+//! JSON 405s and 413s, and conditional GETs.
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::{
+  body::{Body, HttpBody},
+  extract::{ConnectInfo, Request, State},
+  http::{header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH}, HeaderName, HeaderValue, Method, StatusCode},
+  middleware::Next,
+  response::IntoResponse,
+};
+use sha2::{Digest, Sha256};
+use tower::{Layer, ServiceExt};
+use uuid::Uuid;
+
+use crate::config::RateLimit;
+use crate::db::READ_FROM_PRIMARY;
+use crate::errors::ApiError;
+use crate::graphql::GRAPHQL_PATH;
+use crate::handlers::dev::RELOAD_CONFIG_PATH;
+use crate::idempotency::hex;
+use crate::reload::LiveSettings;
+
+// dropping the handler future on timeout also drops any in-flight sqlx query,
+// which returns the connection to the pool and cancels the statement
+pub(crate) async fn timeout(
+    State(limit): State<Duration>,
+    request: Request,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    tokio::time::timeout(limit, next.run(request))
+        .await
+        .map_err(|_| {
+            tracing::warn!(timeout_ms = limit.as_millis() as u64, "request timed out");
+            ApiError::Timeout
+        })
+}
+
+
+// idle buckets refill to full and are dropped on the next sweep
+pub(crate) const RATE_LIMIT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) struct RateLimiters {
+    pub(crate) write: RateLimiter,
+    pub(crate) read: RateLimiter,
+    /// Where the limits come from, so a reload applies to the next request.
+    pub(crate) settings: LiveSettings,
+}
+
+/// Token bucket per client IP. Buckets outlive a change of limit and are
+/// capped at the new burst on their next request.
+pub(crate) struct RateLimiter {
+    state: Mutex<RateLimiterState>,
+}
+
+pub(crate) struct RateLimiterState {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+pub(crate) struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new() -> Self {
+        RateLimiter {
+            state: Mutex::new(RateLimiterState { buckets: HashMap::new(), last_sweep: Instant::now() }),
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how long until one is available.
+    fn check(&self, ip: IpAddr, limit: RateLimit) -> Result<(), Duration> {
+        let now = Instant::now();
+        let RateLimit { per_second, burst } = limit;
+        let mut state = self.state.lock().unwrap();
+
+        if now.duration_since(state.last_sweep) >= RATE_LIMIT_SWEEP_INTERVAL {
+            state.buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < burst
+            });
+            state.last_sweep = now;
+        }
+
+        let bucket = state.buckets.entry(ip).or_insert(Bucket { tokens: burst, updated: now });
+        let refilled = bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second;
+        bucket.tokens = refilled.min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+pub(crate) async fn rate_limit(
+    State(limiters): State<Arc<RateLimiters>>,
+    request: Request,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    // without a peer address (e.g. a router driven directly in tests) there is nothing to key on
+    let Some(ConnectInfo(addr)) = request.extensions().get::<ConnectInfo<SocketAddr>>().copied() else {
+        return Ok(next.run(request).await);
+    };
+
+    let (limiter, limit) = {
+        let settings = limiters.settings.borrow();
+        match *request.method() {
+            Method::GET | Method::HEAD | Method::OPTIONS => (&limiters.read, settings.read_rate_limit),
+            _ => (&limiters.write, settings.write_rate_limit),
+        }
+    };
+    limiter.check(addr.ip(), limit).map_err(ApiError::TooManyRequests)?;
+
+    Ok(next.run(request).await)
+}
+
+/// The CORS layer for the origins currently configured.
+pub(crate) async fn cors(State(settings): State<LiveSettings>, request: Request, next: Next) -> axum::response::Response {
+    let layer = settings.borrow().cors.clone();
+    match layer.layer(next).oneshot(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    }
+}
+
+/// Refuses everything but reads while maintenance mode is on. The reload
+/// route stays open so maintenance can be turned off again, and GraphQL,
+/// where reads are POSTs too, leaves it to the mutations.
+pub(crate) async fn maintenance(
+    State(settings): State<LiveSettings>,
+    request: Request,
+    next: Next,
+) -> Result<axum::response::Response, ApiError> {
+    let read = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let exempt = matches!(request.uri().path(), RELOAD_CONFIG_PATH | GRAPHQL_PATH);
+    if !read && !exempt && settings.borrow().maintenance {
+        return Err(ApiError::Maintenance);
+    }
+    Ok(next.run(request).await)
+}
+
+
+pub(crate) static X_READ_FROM: HeaderName = HeaderName::from_static("x-read-from");
+
+/// `X-Read-From: primary` sends the request's reads to the primary, for a
+/// client that must see the write it just made before the replica catches up.
+pub(crate) async fn read_from(request: Request, next: Next) -> Result<axum::response::Response, ApiError> {
+    let primary = match request.headers().get(&X_READ_FROM).map(|value| value.to_str()) {
+        None => false,
+        Some(Ok(value)) if value.eq_ignore_ascii_case("primary") => true,
+        Some(Ok(value)) if value.eq_ignore_ascii_case("replica") => false,
+        Some(_) => return Err(ApiError::BadRequest("X-Read-From must be primary or replica".to_owned())),
+    };
+    Ok(READ_FROM_PRIMARY.scope(primary, next.run(request)).await)
+}
+
+
+pub(crate) static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub(crate) const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+#[derive(Clone)]
+pub(crate) struct RequestId(pub(crate) String);
+
+tokio::task_local! {
+    // lets ApiError pick up the id without every handler threading it through
+    pub(crate) static CURRENT_REQUEST_ID: String;
+}
+
+pub(crate) fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub(crate) async fn request_id(mut request: Request, next: Next) -> axum::response::Response {
+    // reuse the caller's id when it is sane, otherwise mint a fresh one
+    let id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    request.extensions_mut().insert(RequestId(id.clone()));
+    let mut response = CURRENT_REQUEST_ID.scope(id.clone(), next.run(request)).await;
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(X_REQUEST_ID.clone(), value);
+    }
+    response
+}
+
+
+/// Gives the router's bodiless 405s the JSON envelope, keeping the `Allow`
+/// header in which it lists the methods the path supports.
+pub(crate) async fn method_not_allowed(request: Request, next: Next) -> axum::response::Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+
+    let allow = response.headers().get(ALLOW).cloned();
+    let mut response = ApiError::MethodNotAllowed(format!("method {method} not allowed on {path}")).into_response();
+    if let Some(allow) = allow {
+        response.headers_mut().insert(ALLOW, allow);
+    }
+    response
+}
+
+/// Gives axum's plain-text 413s, from extractors such as `Bytes` and
+/// `Multipart` that read past the route's `DefaultBodyLimit`, the JSON
+/// envelope. 413s handlers return already have it and pass untouched.
+pub(crate) async fn payload_too_large(request: Request, next: Next) -> axum::response::Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+    ApiError::body_too_large().into_response()
+}
+
+/// Tags every successful GET and answers `304 Not Modified` when the
+/// client's `If-None-Match` already names the tag. Handlers that know a
+/// version, like `GET /orders/:id`, set their own; other bodies are tagged
+/// with a hash of their bytes, so identical responses share a tag and any
+/// changed row changes it. Must sit outside `msgpack_responses` so JSON and
+/// MessagePack get different tags. Streamed bodies, with no exact size, are
+/// left alone rather than buffered.
+pub(crate) async fn conditional_get(request: Request, next: Next) -> axum::response::Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+    let if_none_match = request.headers().get(IF_NONE_MATCH).cloned();
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+
+    let (mut parts, mut body) = response.into_parts();
+    if !parts.headers.contains_key(ETAG) {
+        if body.size_hint().exact().is_none() {
+            return axum::response::Response::from_parts(parts, body);
+        }
+        let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                tracing::error!(error = %err, "cannot read response body to tag");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+        let digest = hex(&Sha256::digest(&bytes)[..16]);
+        let tag = HeaderValue::from_str(&format!("\"{digest}\"")).expect("quoted hex is a valid header value");
+        parts.headers.insert(ETAG, tag);
+        body = Body::from(bytes);
+    }
+
+    let current = parts.headers.get(ETAG).and_then(|tag| tag.to_str().ok()).unwrap_or_default();
+    let unchanged = if_none_match
+        .as_ref()
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| names_tag(value, current));
+    if !unchanged {
+        return axum::response::Response::from_parts(parts, body);
+    }
+
+    // a 304 keeps the validators and Vary but describes no body
+    parts.status = StatusCode::NOT_MODIFIED;
+    parts.headers.remove(CONTENT_TYPE);
+    parts.headers.remove(CONTENT_LENGTH);
+    axum::response::Response::from_parts(parts, Body::empty())
+}
+
+/// Whether `If-None-Match` names `current`: `*` names anything, and tags
+/// compare weakly, so `W/"x"` and `"x"` are the same (RFC 9110, 13.1.2).
+fn names_tag(if_none_match: &str, current: &str) -> bool {
+    if if_none_match.trim() == "*" {
+        return true;
+    }
+    let current = opaque_tag(current);
+    entity_tags(if_none_match).any(|tag| opaque_tag(tag) == current)
+}
+
+/// The tags of an `If-None-Match` or `If-Match` list. Commas may appear
+/// inside a quoted tag, so the list is split on the quotes, not the commas.
+pub(crate) fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        let start = rest.find('"')?;
+        let end = start + 1 + rest[start + 1..].find('"')?;
+        let weak = rest[..start].trim_end().ends_with("W/");
+        let from = if weak { rest[..start].trim_end().len() - 2 } else { start };
+        let tag = &rest[from..=end];
+        rest = &rest[end + 1..];
+        Some(tag)
+    })
+}
+
+/// A tag without its weakness indicator.
+fn opaque_tag(tag: &str) -> &str {
+    tag.trim().strip_prefix("W/").unwrap_or(tag.trim())
+}
@@ -0,0 +1,396 @@
+//! Orders, money and the response envelope shared by the handlers.
+use std::str::FromStr;
+use async_graphql::SimpleObject;
+use serde::{Deserialize, Deserializer, Serialize};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use utoipa::ToSchema;
+
+use crate::errors::{ApiError, FieldError, FieldErrorCode};
+use crate::handlers::audit::OrderEvent;
+use crate::handlers::csv::ImportReport;
+use crate::handlers::customers::Customer;
+use crate::handlers::exports::{ExportRun, StartedExports};
+use crate::handlers::inventory::InventoryItem;
+use crate::handlers::menu::MenuItem;
+use crate::handlers::probes::BuildInfo;
+use crate::handlers::outbox::OutboxEvent;
+use crate::handlers::orders::{CreateOrdersRow, DeleteOrdersRow, OrderCount, TagCount, UpdateStatusesRow};
+use crate::handlers::reports::RevenueRow;
+use crate::handlers::stats::OrderStats;
+
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    OrderResponse = Response<OrderDetail>,
+    OrderListResponse = Response<Vec<OrderDetail>>,
+    OrderCountResponse = Response<OrderCount>,
+    TagCountsResponse = Response<Vec<TagCount>>,
+    CreatedOrdersResponse = Response<Vec<CreateOrdersRow>>,
+    DeleteOrdersResponse = Response<DeleteOrdersRow>,
+    UpdateStatusesResponse = Response<UpdateStatusesRow>,
+    OrderEventsResponse = Response<Vec<OrderEvent>>,
+    OutboxEventResponse = Response<OutboxEvent>,
+    OutboxEventsResponse = Response<Vec<OutboxEvent>>,
+    StartedExportsResponse = Response<StartedExports>,
+    ExportRunsResponse = Response<Vec<ExportRun>>,
+    ImportResponse = Response<ImportReport>,
+    OrderStatsResponse = Response<OrderStats>,
+    RevenueReportResponse = Response<Vec<RevenueRow>>,
+    CustomerResponse = Response<Customer>,
+    CustomerListResponse = Response<Vec<Customer>>,
+    MenuResponse = Response<Vec<MenuItem>>,
+    InventoryResponse = Response<InventoryItem>,
+    InventoryListResponse = Response<Vec<InventoryItem>>,
+    VersionResponse = Response<BuildInfo>,
+    MessageResponse = Response<serde_json::Value>,
+)]
+pub(crate) struct Response<T> {
+    pub(crate) status: bool,
+    pub(crate) message: String,
+    pub(crate) data: Option<T>,
+    /// Paging details on list endpoints, absent elsewhere.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) meta: Option<PageMeta>,
+}
+
+
+/// An exact amount in currency units, at most two fraction digits. Sent
+/// and returned as a decimal string such as `"4.50"` so no precision is
+/// lost to floats; negative amounts are refused when deserializing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, sqlx::Type, ToSchema)]
+#[sqlx(transparent)]
+#[schema(value_type = String, format = "decimal", example = "4.50")]
+pub(crate) struct Money(pub(crate) Decimal);
+
+impl Money {
+    pub(crate) const ZERO: Money = Money(Decimal::ZERO);
+
+    pub(crate) fn amount(self) -> Decimal {
+        self.0
+    }
+
+    pub(crate) fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    pub(crate) fn checked_mul(self, quantity: i32) -> Option<Money> {
+        self.0.checked_mul(Decimal::from(quantity)).map(Money)
+    }
+}
+
+// query_as! hands each decoded column to its field with `into()`
+impl From<Decimal> for Money {
+    fn from(amount: Decimal) -> Self {
+        Money(amount)
+    }
+}
+
+impl FromStr for Money {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        // from_str_exact refuses NaN, infinities and exponents, and never rounds
+        let amount = Decimal::from_str_exact(value.trim())
+            .map_err(|_| format!("'{value}' is not a decimal amount such as \"4.50\""))?
+            .normalize();
+        if amount.is_sign_negative() {
+            return Err(format!("amount must not be negative, got {value}"));
+        }
+        if amount.scale() > 2 {
+            return Err(format!("amount may have at most two fraction digits, got {value}"));
+        }
+        Ok(Money(amount))
+    }
+}
+
+/// Always two fraction digits, `4.50`.
+impl std::fmt::Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2}", self.0)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // a JSON number would already have been through an f64
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+
+#[derive(Clone, sqlx::FromRow, Serialize, ToSchema, SimpleObject)]
+#[graphql(name = "Order", complex)]
+pub(crate) struct Orders {
+    #[schema(example = 42)]
+    pub(crate) id: Option<i32>,
+    /// The customer's name, kept in step with `customer_id`.
+    #[schema(example = "Ada")]
+    pub(crate) name: Option<String>,
+    #[schema(example = 7)]
+    pub(crate) customer_id: Option<i32>,
+    #[schema(example = "flat white")]
+    pub(crate) coffee_name: Option<String>,
+    #[schema(example = "medium")]
+    pub(crate) size: Option<Size>,
+    /// Cups across all of the order's items.
+    #[schema(example = 1)]
+    pub(crate) quantity: Option<i32>,
+    /// Decimal amount in currency units, as a string.
+    #[schema(example = "4.50")]
+    pub(crate) total: Money,
+    #[schema(example = "extra hot, oat milk")]
+    pub(crate) notes: Option<String>,
+    /// Lowercase labels, e.g. loyalty or staff.
+    #[schema(example = json!(["loyalty"]))]
+    pub(crate) tags: Vec<String>,
+    #[schema(example = "pending")]
+    pub(crate) status: Option<String>,
+    /// Given when the order was cancelled, if at all.
+    pub(crate) cancellation_reason: Option<String>,
+    pub(crate) version: Option<i32>,
+    pub(crate) created_at: Option<DateTime<Utc>>,
+    pub(crate) updated_at: Option<DateTime<Utc>>,
+    pub(crate) deleted_at: Option<DateTime<Utc>>,
+}
+
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OrderStatus {
+    Pending,
+    Preparing,
+    Ready,
+    Completed,
+    Cancelled,
+}
+
+impl OrderStatus {
+    const ALL: [OrderStatus; 5] = [
+        OrderStatus::Pending,
+        OrderStatus::Preparing,
+        OrderStatus::Ready,
+        OrderStatus::Completed,
+        OrderStatus::Cancelled,
+    ];
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Preparing => "preparing",
+            OrderStatus::Ready => "ready",
+            OrderStatus::Completed => "completed",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+
+    /// Open orders hold their cups out of stock until served or cancelled.
+    pub(crate) fn is_open(self) -> bool {
+        matches!(self, OrderStatus::Pending | OrderStatus::Preparing | OrderStatus::Ready)
+    }
+
+    /// pending -> preparing -> ready -> completed, and any open order may be
+    /// cancelled. Completed and cancelled orders are final.
+    pub(crate) fn can_transition_to(self, next: OrderStatus) -> bool {
+        use OrderStatus::*;
+
+        self == next
+            || matches!(
+                (self, next),
+                (Pending, Preparing)
+                    | (Preparing, Ready)
+                    | (Ready, Completed)
+                    | (Pending | Preparing | Ready, Cancelled)
+            )
+    }
+
+    /// Statuses an order can move to `self` from, `self` itself excluded.
+    pub(crate) fn sources(self) -> Vec<OrderStatus> {
+        OrderStatus::ALL
+            .into_iter()
+            .filter(|from| *from != self && from.can_transition_to(self))
+            .collect()
+    }
+}
+
+impl FromStr for OrderStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OrderStatus::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| {
+                let allowed: Vec<&str> = OrderStatus::ALL.iter().map(|s| s.as_str()).collect();
+                format!("invalid status '{s}', expected one of: {}", allowed.join(", "))
+            })
+    }
+}
+
+/// A cup size, stored as the `order_size` Postgres enum and sent as
+/// lowercase text. Input is matched case-insensitively, so "LARGE" works.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "order_size", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Size {
+    Small,
+    Medium,
+    Large,
+}
+
+impl Size {
+    pub(crate) const ALL: [Size; 3] = [Size::Small, Size::Medium, Size::Large];
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Size::Small => "small",
+            Size::Medium => "medium",
+            Size::Large => "large",
+        }
+    }
+}
+
+// lets a column of sizes be bound as `order_size[]` for UNNEST inserts
+impl sqlx::postgres::PgHasArrayType for Size {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_order_size")
+    }
+}
+
+impl std::fmt::Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Size::ALL
+            .into_iter()
+            .find(|size| size.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let allowed: Vec<&str> = Size::ALL.iter().map(|size| size.as_str()).collect();
+                format!("invalid size '{s}', expected one of: {}", allowed.join(", "))
+            })
+    }
+}
+
+/// Through `FromStr`, so a body with an unknown size is refused with the
+/// accepted list.
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The `size` field of a request that takes it as free text, GraphQL's and
+/// gRPC's, refused as a validation error as the REST body would be.
+pub(crate) fn parse_size(size: &str) -> Result<Size, ApiError> {
+    size.parse().map_err(|message: String| ApiError::Validation(vec![FieldError::new("size", FieldErrorCode::Invalid, message)]))
+}
+
+/// For update fields where `null` means clear: a field left out
+/// deserializes to `None` through `#[serde(default)]`, one sent as `null` to
+/// `Some(None)`.
+pub(crate) fn explicit_null<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub(crate) fn parse_status(status: &str) -> Result<OrderStatus, ApiError> {
+    status.parse().map_err(ApiError::Unprocessable)
+}
+
+
+/// Where a list response sits among all matching rows.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct PageMeta {
+    /// 1-based, for offset pagination.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = 1)]
+    pub(crate) page: Option<i64>,
+    #[schema(example = 50)]
+    pub(crate) per_page: i64,
+    /// Rows matching the request across all pages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = 120)]
+    pub(crate) total: Option<i64>,
+    /// `after_id` for the next page, for keyset pagination.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) next_cursor: Option<i32>,
+}
+
+impl PageMeta {
+    pub(crate) fn offset(limit: i64, offset: i64, total: i64) -> PageMeta {
+        PageMeta { page: Some(offset / limit + 1), per_page: limit, total: Some(total), next_cursor: None }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct CursorResponse<T> {
+    #[serde(flatten)]
+    pub(crate) response: Response<T>,
+    pub(crate) next_cursor: Option<i32>,
+}
+
+
+#[derive(Clone, sqlx::FromRow, Serialize, ToSchema, SimpleObject)]
+pub(crate) struct OrderItem {
+    pub(crate) id: i32,
+    #[serde(skip)]
+    #[graphql(skip)]
+    pub(crate) order_id: i32,
+    #[schema(example = "latte")]
+    pub(crate) coffee_name: String,
+    #[schema(example = "large")]
+    pub(crate) size: Size,
+    #[schema(example = 2)]
+    pub(crate) quantity: i32,
+    /// Menu price per unit when the order was placed.
+    #[schema(example = "5.50")]
+    pub(crate) unit_price: Money,
+}
+
+/// An order with its lines, when they were asked for.
+#[derive(Clone, Serialize, ToSchema)]
+pub(crate) struct OrderDetail {
+    #[serde(flatten)]
+    pub(crate) order: Orders,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) items: Option<Vec<OrderItem>>,
+}
+
+impl OrderDetail {
+    pub(crate) fn without_items(order: Orders) -> Self {
+        OrderDetail { order, items: None }
+    }
+
+    /// "2 x large latte" and what the line costs, for messages about the
+    /// order. Without its items the order's own summary is the one line.
+    pub(crate) fn line_summaries(&self) -> Vec<(String, Money)> {
+        let mut lines: Vec<(String, Money)> = self
+            .items
+            .iter()
+            .flatten()
+            .map(|item| {
+                let amount = item.unit_price.checked_mul(item.quantity).unwrap_or(Money::ZERO);
+                (format!("{} x {} {}", item.quantity, item.size, item.coffee_name), amount)
+            })
+            .collect();
+        if let (true, Some(coffee_name), Some(size)) = (lines.is_empty(), &self.order.coffee_name, self.order.size) {
+            lines.push((format!("{} x {size} {coffee_name}", self.order.quantity.unwrap_or(1)), self.order.total));
+        }
+        lines
+    }
+}
@@ -0,0 +1,132 @@
+//! `Accept` header content negotiation, and MessagePack as an alternative
+//! to JSON on both request and response bodies.
+use axum::{
+  body::Body,
+  extract::Request,
+  http::{header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE}, HeaderMap, HeaderValue, StatusCode},
+  middleware::Next,
+  response::IntoResponse,
+};
+
+use crate::errors::ApiError;
+
+pub(crate) const APPLICATION_JSON: &str = "application/json";
+pub(crate) const APPLICATION_MSGPACK: &str = "application/msgpack";
+pub(crate) const TEXT_CSV: &str = "text/csv";
+
+/// Whether the request body is MessagePack rather than JSON.
+pub(crate) fn is_msgpack(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(APPLICATION_MSGPACK))
+}
+
+/// Re-encodes JSON responses, error envelopes included, as MessagePack when
+/// the client prefers it. Handlers keep answering in JSON; anything that
+/// cannot be negotiated stays JSON rather than failing.
+pub(crate) async fn msgpack_responses(request: Request, next: Next) -> axum::response::Response {
+    let msgpack = negotiate(request.headers(), &[APPLICATION_JSON, APPLICATION_MSGPACK])
+        .is_ok_and(|media_type| media_type == APPLICATION_MSGPACK);
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(APPLICATION_JSON.as_bytes()));
+    if !msgpack || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::error!(error = %err, "cannot read response body to re-encode");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    // a body that is not valid JSON after all goes out untouched
+    let Some(encoded) = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|value| rmp_serde::to_vec_named(&value).ok())
+    else {
+        return axum::response::Response::from_parts(parts, Body::from(bytes));
+    };
+    parts.headers.insert(CONTENT_TYPE, HeaderValue::from_static(APPLICATION_MSGPACK));
+    parts.headers.remove(CONTENT_LENGTH);
+    axum::response::Response::from_parts(parts, Body::from(encoded))
+}
+
+/// The media type in `supported` that the `Accept` header prefers. Each
+/// type gets the quality of the most specific range matching it, so
+/// `text/*;q=0.5, text/csv` still picks CSV at 1. Ties go to the earlier
+/// entry in `supported`, as does a missing or empty header, so list the
+/// default first.
+pub(crate) fn negotiate(headers: &HeaderMap, supported: &[&'static str]) -> Result<&'static str, ApiError> {
+    let accept: Vec<&str> = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|range| !range.is_empty())
+        .collect();
+    if accept.is_empty() {
+        return Ok(supported[0]);
+    }
+
+    let ranges: Vec<MediaRange> = accept.into_iter().filter_map(MediaRange::parse).collect();
+    let mut best: Option<(&'static str, f32)> = None;
+    for &media_type in supported {
+        let quality = ranges
+            .iter()
+            .filter(|range| range.matches(media_type))
+            .max_by_key(|range| range.specificity())
+            .map_or(0.0, |range| range.quality);
+        if quality > 0.0 && best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((media_type, quality));
+        }
+    }
+
+    best.map(|(media_type, _)| media_type)
+        .ok_or_else(|| ApiError::NotAcceptable(supported.to_vec()))
+}
+
+/// One entry of an `Accept` header, e.g. `text/*;q=0.9`.
+struct MediaRange<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    /// `None` for entries that are not `type/subtype` or carry an invalid
+    /// q, which are ignored rather than failing the request.
+    fn parse(range: &'a str) -> Option<Self> {
+        let mut parts = range.split(';').map(str::trim);
+        let (kind, subtype) = parts.next()?.split_once('/')?;
+        let mut quality = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                }
+            }
+        }
+        Some(MediaRange { kind, subtype, quality })
+    }
+
+    fn matches(&self, media_type: &str) -> bool {
+        let Some((kind, subtype)) = media_type.split_once('/') else {
+            return false;
+        };
+        (self.kind == "*" || self.kind.eq_ignore_ascii_case(kind))
+            && (self.subtype == "*" || self.subtype.eq_ignore_ascii_case(subtype))
+    }
+
+    /// `*/*` is 0, `type/*` 1 and `type/subtype` 2.
+    fn specificity(&self) -> u8 {
+        u8::from(self.kind != "*") + u8::from(self.subtype != "*")
+    }
+}
@@ -0,0 +1,252 @@
+//! The transactional outbox behind webhooks: events are written in the
+//! transaction of the order change they describe, then delivered by a
+//! dispatcher that retries with backoff and sets aside what keeps failing.
+use std::time::Duration;
+use futures::future::join_all;
+use sqlx::{PgConnection, PgPool};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::feed::{order_event_body, OrderEventKind};
+use crate::models::Orders;
+use crate::webhooks::WebhookClient;
+use crate::Config;
+
+pub(crate) const OUTBOX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Events claimed per transaction and delivered concurrently.
+const OUTBOX_BATCH: i64 = 32;
+/// Attempts before an event is marked failed and waits for an admin.
+pub(crate) const OUTBOX_MAX_ATTEMPTS: i32 = 8;
+/// The wait after the first failed attempt, doubled after every other one.
+pub(crate) const OUTBOX_INITIAL_BACKOFF: Duration = Duration::from_secs(5);
+const OUTBOX_MAX_BACKOFF: Duration = Duration::from_secs(15 * 60);
+const OUTBOX_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Delivered events are kept this long for inspection.
+const OUTBOX_KEEP_DELIVERED_DAYS: i32 = 7;
+
+/// The statuses an outbox event moves through.
+pub(crate) const OUTBOX_STATUSES: [&str; 3] = ["pending", "delivered", "failed"];
+
+/// Handle the write paths queue webhook events through.
+#[derive(Clone, Copy)]
+pub(crate) struct Outbox {
+    enabled: bool,
+}
+
+impl Outbox {
+    pub(crate) fn new(config: &Config) -> Outbox {
+        Outbox { enabled: !config.webhook_urls.is_empty() }
+    }
+
+    /// Queues a `kind` event for each of `orders` on the caller's
+    /// transaction, so it commits or rolls back with the change. Without
+    /// `WEBHOOK_URLS` nobody would take them, so nothing is written.
+    pub(crate) async fn enqueue(self, conn: &mut PgConnection, kind: OrderEventKind, orders: &[Orders]) -> Result<(), sqlx::Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let (ids, payloads): (Vec<i32>, Vec<serde_json::Value>) = orders
+            .iter()
+            .filter_map(|order| Some((order.id?, order_event_body(&Uuid::new_v4().to_string(), kind, order))))
+            .unzip();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        sqlx::query!(
+            "
+            INSERT INTO outbox_events (order_id, event_type, payload)
+            SELECT id, $3, payload FROM UNNEST($1::int[], $2::jsonb[]) AS t(id, payload)
+            ",
+            &ids,
+            &payloads,
+            kind.as_str()
+        )
+        .execute(conn)
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct DispatchSummary {
+    pub delivered: usize,
+    /// Failed this time and scheduled for another attempt.
+    pub retrying: usize,
+    /// Out of attempts, left for `POST /admin/outbox/{id}/retry`.
+    pub failed: usize,
+}
+
+struct DueEvent {
+    id: i64,
+    event_type: String,
+    payload: serde_json::Value,
+    attempts: i32,
+    delivered_to: Vec<String>,
+}
+
+/// Delivers due outbox events to every `WEBHOOK_URLS` target.
+pub struct OutboxDispatcher {
+    webhooks: WebhookClient,
+}
+
+impl OutboxDispatcher {
+    /// `None` without `WEBHOOK_URLS`.
+    pub fn new(config: &Config) -> Option<OutboxDispatcher> {
+        WebhookClient::from_config(config).map(|webhooks| OutboxDispatcher { webhooks })
+    }
+
+    /// Delivers every event that is due, a batch per transaction. Events
+    /// are claimed with `SKIP LOCKED`, so replicas dispatching at the same
+    /// time split them instead of delivering twice. A target that took an
+    /// event is not sent it again when another target makes it retry.
+    pub async fn dispatch(&self, db: &PgPool) -> Result<DispatchSummary, sqlx::Error> {
+        let mut summary = DispatchSummary::default();
+        loop {
+            let mut tx = db.begin().await?;
+            let due = sqlx::query_as!(
+                DueEvent,
+                "
+                SELECT id, event_type, payload, attempts, delivered_to FROM outbox_events
+                WHERE status = 'pending' AND next_attempt_at <= now()
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+                ",
+                OUTBOX_BATCH
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+
+            let outcomes = join_all(due.iter().map(|event| self.deliver(event))).await;
+            for (event, (delivered_to, error)) in due.iter().zip(outcomes) {
+                record_attempt(&mut tx, event, delivered_to, error, &mut summary).await?;
+            }
+            tx.commit().await?;
+
+            if (due.len() as i64) < OUTBOX_BATCH {
+                return Ok(summary);
+            }
+        }
+    }
+
+    /// One attempt at every target still missing the event: the targets
+    /// that have it now, and what went wrong with the others.
+    async fn deliver(&self, event: &DueEvent) -> (Vec<String>, Option<String>) {
+        let id = event.payload["id"].as_str().unwrap_or_default();
+        let body = event.payload.to_string();
+        let mut delivered_to = event.delivered_to.clone();
+        let mut errors = Vec::new();
+        for url in &self.webhooks.urls {
+            if delivered_to.iter().any(|done| done == url.as_str()) {
+                continue;
+            }
+            match self.webhooks.post(url, id, &event.event_type, &body).await {
+                Ok(()) => delivered_to.push(url.to_string()),
+                Err(err) => errors.push(format!("{url}: {err}")),
+            }
+        }
+        (delivered_to, (!errors.is_empty()).then(|| errors.join("; ")))
+    }
+}
+
+async fn record_attempt(
+    conn: &mut PgConnection,
+    event: &DueEvent,
+    delivered_to: Vec<String>,
+    error: Option<String>,
+    summary: &mut DispatchSummary,
+) -> Result<(), sqlx::Error> {
+    let attempts = event.attempts + 1;
+    let mut backoff = Duration::ZERO;
+    let status = match &error {
+        None => {
+            tracing::debug!(outbox_id = event.id, attempts, "webhook delivered");
+            summary.delivered += 1;
+            "delivered"
+        }
+        Some(error) if attempts >= OUTBOX_MAX_ATTEMPTS => {
+            tracing::error!(outbox_id = event.id, event = event.event_type, attempts, error, "webhook delivery failed, giving up");
+            summary.failed += 1;
+            "failed"
+        }
+        Some(error) => {
+            backoff = retry_backoff(attempts);
+            tracing::warn!(outbox_id = event.id, attempts, error, "webhook delivery failed, retrying in {}s", backoff.as_secs());
+            summary.retrying += 1;
+            "pending"
+        }
+    };
+
+    sqlx::query!(
+        "
+        UPDATE outbox_events
+        SET status = $2, attempts = $3, delivered_to = $4, last_error = $5,
+            next_attempt_at = now() + make_interval(secs => $6),
+            delivered_at = CASE WHEN $2 = 'delivered' THEN now() END
+        WHERE id = $1
+        ",
+        event.id,
+        status,
+        attempts,
+        &delivered_to,
+        error,
+        backoff.as_secs_f64()
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// How long to wait after the `attempts`-th failure.
+pub(crate) fn retry_backoff(attempts: i32) -> Duration {
+    let doublings = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    (OUTBOX_INITIAL_BACKOFF * 2u32.pow(doublings)).min(OUTBOX_MAX_BACKOFF)
+}
+
+/// Starts the dispatcher, which polls every second until `shutdown` is
+/// cancelled and prunes old delivered events every hour. Does nothing
+/// without `WEBHOOK_URLS`.
+pub fn spawn_outbox_dispatcher(db: PgPool, config: &Config, shutdown: CancellationToken) -> JoinHandle<()> {
+    let dispatcher = OutboxDispatcher::new(config);
+    tokio::spawn(async move {
+        let Some(dispatcher) = dispatcher else {
+            tracing::info!("WEBHOOK_URLS is empty, outbox dispatcher disabled");
+            return;
+        };
+        tracing::info!(targets = dispatcher.webhooks.urls.len(), "webhook delivery enabled");
+        let mut poll = tokio::time::interval(OUTBOX_POLL_INTERVAL);
+        let mut prune = tokio::time::interval(OUTBOX_PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = prune.tick() => match prune_delivered(&db).await {
+                    Ok(pruned) => tracing::debug!(pruned, "outbox pruned"),
+                    Err(err) => tracing::warn!(error = %err, "outbox prune failed"),
+                },
+                _ = poll.tick() => match dispatcher.dispatch(&db).await {
+                    Ok(summary) if summary.delivered + summary.retrying + summary.failed > 0 => tracing::info!(
+                        delivered = summary.delivered,
+                        retrying = summary.retrying,
+                        failed = summary.failed,
+                        "outbox dispatched"
+                    ),
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!(error = %err, "outbox dispatch failed"),
+                },
+            }
+        }
+        tracing::info!("outbox dispatcher stopped");
+    })
+}
+
+async fn prune_delivered(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let pruned = sqlx::query!(
+        "DELETE FROM outbox_events WHERE status = 'delivered' AND delivered_at < now() - make_interval(days => $1)",
+        OUTBOX_KEEP_DELIVERED_DAYS
+    )
+    .execute(db)
+    .await?
+    .rows_affected();
+    Ok(pruned)
+}
@@ -0,0 +1,77 @@
+//! `limit` and `offset`, parsed once for every list endpoint so the default,
+//! the cap and the errors cannot drift between routes.
+use axum::{
+  async_trait,
+  extract::{FromRef, FromRequestParts, Query},
+  http::request::Parts,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::errors::ApiError;
+
+pub(crate) const DEFAULT_PAGE_SIZE: i64 = 50;
+pub(crate) const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(Clone, Copy)]
+pub(crate) struct PageLimits {
+    pub(crate) default: i64,
+    pub(crate) max: i64,
+    /// Lower a `limit` over `max` to it instead of refusing the request.
+    pub(crate) clamp: bool,
+}
+
+impl PageLimits {
+    /// `(limit, offset)` with the default applied and the cap enforced, or
+    /// 400 when either is out of range.
+    pub(crate) fn resolve(&self, limit: Option<i64>, offset: Option<i64>) -> Result<(i64, i64), ApiError> {
+        let mut limit = limit.unwrap_or(self.default);
+        if self.clamp {
+            limit = limit.min(self.max);
+        }
+        let offset = offset.unwrap_or(0);
+
+        if !(1..=self.max).contains(&limit) || offset < 0 {
+            return Err(ApiError::BadRequest(format!(
+                "limit must be between 1 and {} and offset must not be negative",
+                self.max
+            )));
+        }
+        Ok((limit, offset))
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct PageParams {
+    /// Page size, 1 to 500 and 50 when omitted, unless configured otherwise.
+    limit: Option<i64>,
+    /// Rows to skip.
+    offset: Option<i64>,
+}
+
+/// A validated page of a list endpoint.
+pub(crate) struct Pagination {
+    pub(crate) limit: i64,
+    pub(crate) offset: i64,
+    /// Whether the query named an offset, which `GET /orders` refuses
+    /// alongside a cursor.
+    pub(crate) explicit_offset: bool,
+}
+
+/// Values that are not numbers get the JSON 400, as out of range ones do.
+#[async_trait]
+impl<S> FromRequestParts<S> for Pagination
+where
+    PageLimits: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<PageParams>::try_from_uri(&parts.uri)
+            .map_err(|rejection| ApiError::BadRequest(rejection.body_text()))?;
+        let (limit, offset) = PageLimits::from_ref(state).resolve(params.limit, params.offset)?;
+        Ok(Pagination { limit, offset, explicit_offset: params.offset.is_some() })
+    }
+}
@@ -0,0 +1,211 @@
+//! Email receipts for orders placed with an `email`. Rendering happens on
+//! the request, sending on background tasks, so a slow or broken mail
+//! server never fails or holds up an order.
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::config::env_or;
+use crate::models::OrderDetail;
+
+pub(crate) const RECEIPT_QUEUE_SIZE: usize = 256;
+pub(crate) const RECEIPT_CONCURRENCY: usize = 4;
+pub(crate) const RECEIPT_MAX_ATTEMPTS: u32 = 3;
+pub(crate) const RECEIPT_INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const SMTP_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_FROM: &str = "orders@localhost";
+
+/// Where receipts go, from the `SMTP_*` variables.
+#[derive(Clone)]
+pub(crate) struct MailSettings {
+    from: Mailbox,
+    /// `None` under `SMTP_DISABLED`, which logs each receipt instead.
+    transport: Option<AsyncSmtpTransport<Tokio1Executor>>,
+}
+
+/// `SMTP_DISABLED=true` logs receipts; otherwise `SMTP_HOST` turns sending
+/// on and needs `SMTP_FROM`. With neither, no receipts are sent.
+pub(crate) fn mail_from_env(errors: &mut Vec<String>) -> Option<MailSettings> {
+    let disabled: bool = env_or("SMTP_DISABLED", false, errors);
+    let host = env::var("SMTP_HOST").ok().filter(|v| !v.is_empty());
+    let from = env::var("SMTP_FROM").ok().filter(|v| !v.is_empty());
+    if !disabled && host.is_none() {
+        return None;
+    }
+
+    if !disabled && from.is_none() {
+        errors.push("SMTP_FROM is required with SMTP_HOST".to_owned());
+        return None;
+    }
+    let from = from.unwrap_or_else(|| DEFAULT_FROM.to_owned());
+    let Ok(from) = from.parse::<Mailbox>() else {
+        errors.push(format!("SMTP_FROM has an invalid address '{from}'"));
+        return None;
+    };
+    if disabled {
+        return Some(MailSettings { from, transport: None });
+    }
+    let host = host.unwrap_or_default();
+
+    // implicit TLS on 465, STARTTLS on 587 and plain SMTP, e.g. for a local catcher
+    let tls = env::var("SMTP_TLS").unwrap_or_else(|_| "starttls".to_owned());
+    let builder = match tls.as_str() {
+        "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host),
+        "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&host),
+        "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host)),
+        _ => {
+            errors.push(format!("SMTP_TLS has an invalid value '{tls}', expected starttls, tls or none"));
+            return None;
+        }
+    };
+    let mut builder = match builder {
+        Ok(builder) => builder,
+        Err(err) => {
+            errors.push(format!("SMTP_HOST '{host}' cannot be used: {err}"));
+            return None;
+        }
+    };
+    let default_port = match tls.as_str() {
+        "tls" => 465,
+        "starttls" => 587,
+        _ => 25,
+    };
+    builder = builder.port(env_or("SMTP_PORT", default_port, errors)).timeout(Some(SMTP_TIMEOUT));
+
+    let username = env::var("SMTP_USERNAME").ok().filter(|v| !v.is_empty());
+    let password = env::var("SMTP_PASSWORD").ok().filter(|v| !v.is_empty());
+    match (username, password) {
+        (Some(username), Some(password)) => builder = builder.credentials(Credentials::new(username, password)),
+        (None, None) => {}
+        _ => errors.push("set both SMTP_USERNAME and SMTP_PASSWORD, or neither".to_owned()),
+    }
+
+    Some(MailSettings { from, transport: Some(builder.build()) })
+}
+
+/// A rendered receipt waiting to be sent.
+pub(crate) struct Receipt {
+    order_id: i32,
+    message: Message,
+}
+
+/// Handle for queueing receipts. Like everything else about receipts it
+/// never fails the caller: a full queue or a bad address is logged and
+/// counted in `receipt_emails_failed_total`.
+#[derive(Clone)]
+pub(crate) struct Receipts {
+    from: Option<Mailbox>,
+    queue: Option<mpsc::Sender<Receipt>>,
+}
+
+impl Receipts {
+    pub(crate) fn start(settings: Option<&MailSettings>) -> Receipts {
+        let Some(settings) = settings else {
+            return Receipts { from: None, queue: None };
+        };
+        let (tx, rx) = mpsc::channel(RECEIPT_QUEUE_SIZE);
+        tokio::spawn(dispatch_receipts(rx, settings.transport.clone()));
+        tracing::info!(from = %settings.from, logged = settings.transport.is_none(), "email receipts enabled");
+        Receipts { from: Some(settings.from.clone()), queue: Some(tx) }
+    }
+
+    pub(crate) fn send(&self, to: &str, order: &OrderDetail) {
+        let (Some(from), Some(queue)) = (&self.from, &self.queue) else {
+            return;
+        };
+        let order_id = order.order.id.unwrap_or_default();
+        let message = match render_receipt(from.clone(), to, order) {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::warn!(order_id, error = %err, "could not build receipt email");
+                metrics::counter!("receipt_emails_failed_total").increment(1);
+                return;
+            }
+        };
+        if queue.try_send(Receipt { order_id, message }).is_err() {
+            tracing::warn!(order_id, "receipt queue is full, dropping receipt");
+            metrics::counter!("receipt_emails_failed_total").increment(1);
+        }
+    }
+}
+
+/// Sends each queued receipt on its own task, a few at a time.
+async fn dispatch_receipts(mut rx: mpsc::Receiver<Receipt>, transport: Option<AsyncSmtpTransport<Tokio1Executor>>) {
+    let permits = Arc::new(Semaphore::new(RECEIPT_CONCURRENCY));
+    while let Some(receipt) = rx.recv().await {
+        let Some(transport) = &transport else {
+            let text = String::from_utf8_lossy(&receipt.message.formatted()).into_owned();
+            tracing::info!(order_id = receipt.order_id, "SMTP_DISABLED, receipt not sent:\n{text}");
+            continue;
+        };
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            return;
+        };
+        let transport = transport.clone();
+        tokio::spawn(async move {
+            deliver_receipt(&transport, &receipt).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Retries with exponential backoff, then gives up with an error log.
+async fn deliver_receipt(transport: &AsyncSmtpTransport<Tokio1Executor>, receipt: &Receipt) {
+    let mut backoff = RECEIPT_INITIAL_BACKOFF;
+    for attempt in 1..=RECEIPT_MAX_ATTEMPTS {
+        let error = match transport.send(receipt.message.clone()).await {
+            Ok(_) => {
+                tracing::debug!(order_id = receipt.order_id, attempt, "receipt sent");
+                metrics::counter!("receipt_emails_sent_total").increment(1);
+                return;
+            }
+            Err(err) => err,
+        };
+        // a permanent rejection, e.g. an unknown mailbox, will not change on a retry
+        if attempt == RECEIPT_MAX_ATTEMPTS || error.is_permanent() {
+            tracing::error!(order_id = receipt.order_id, attempt, error = %error, "receipt email failed, giving up");
+            metrics::counter!("receipt_emails_failed_total").increment(1);
+            return;
+        }
+        tracing::warn!(order_id = receipt.order_id, attempt, error = %error, "receipt email failed, retrying in {}s", backoff.as_secs());
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Plain text and HTML alternatives listing every line and the total.
+pub(crate) fn render_receipt(from: Mailbox, to: &str, detail: &OrderDetail) -> Result<Message, String> {
+    let to: Mailbox = to.parse().map_err(|err| format!("'{to}' is not a usable address: {err}"))?;
+    let order = &detail.order;
+    let id = order.id.unwrap_or_default();
+    let name = order.name.as_deref().unwrap_or("there");
+    let lines = detail.line_summaries();
+    let total = order.total.to_string();
+
+    let mut text = format!("Thanks for your order, {name}!\n\nOrder #{id}\n\n");
+    for (line, amount) in &lines {
+        text.push_str(&format!("{line}  {amount}\n"));
+    }
+    text.push_str(&format!("\nTotal: {total}\n"));
+
+    let mut html = format!("<p>Thanks for your order, {}!</p>\n<h2>Order #{id}</h2>\n<table>\n", escape_html(name));
+    for (line, amount) in &lines {
+        html.push_str(&format!("<tr><td>{}</td><td>{amount}</td></tr>\n", escape_html(line)));
+    }
+    html.push_str(&format!("<tr><th>Total</th><th>{total}</th></tr>\n</table>\n"));
+
+    Message::builder()
+        .from(from)
+        .to(to)
+        .subject(format!("Your receipt for order #{id}"))
+        .multipart(MultiPart::alternative_plain_html(text, html))
+        .map_err(|err| err.to_string())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,216 @@
+//! The settings that change without a restart, re-read on SIGHUP or
+//! `POST /admin/reload-config` so the kitchen displays keep their streams.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use axum_server::tls_rustls::RustlsConfig;
+use tokio::sync::watch;
+use tower_http::cors::CorsLayer;
+use tracing_subscriber::reload::Handle;
+use tracing_subscriber::{EnvFilter, Registry};
+
+use crate::config::{Config, CorsOrigins, RateLimit};
+
+/// Swaps the `EnvFilter` the subscriber in `main` was built with.
+pub type LogFilterHandle = Handle<EnvFilter, Registry>;
+
+/// What the layers consult on every request; cheap to clone.
+pub(crate) type LiveSettings = watch::Receiver<RuntimeSettings>;
+
+#[derive(Clone)]
+pub(crate) struct RuntimeSettings {
+    pub(crate) log_filter: String,
+    pub(crate) write_rate_limit: RateLimit,
+    pub(crate) read_rate_limit: RateLimit,
+    pub(crate) cors_origins: CorsOrigins,
+    /// Built from `cors_origins` once per reload rather than per request.
+    pub(crate) cors: CorsLayer,
+    /// Refuses writes with 503 while reads keep working.
+    pub(crate) maintenance: bool,
+}
+
+impl RuntimeSettings {
+    fn from_config(config: &Config) -> RuntimeSettings {
+        RuntimeSettings {
+            log_filter: config.log_filter.clone(),
+            write_rate_limit: config.write_rate_limit,
+            read_rate_limit: config.read_rate_limit,
+            cors_origins: config.cors_origins.clone(),
+            cors: crate::cors_layer(&config.cors_origins),
+            maintenance: config.maintenance_mode,
+        }
+    }
+
+    /// The variables behind every setting that differs in `next`.
+    fn changes(&self, next: &RuntimeSettings) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.log_filter != next.log_filter {
+            changed.push("RUST_LOG");
+        }
+        if self.write_rate_limit != next.write_rate_limit {
+            changed.push("RATE_LIMIT_WRITE");
+        }
+        if self.read_rate_limit != next.read_rate_limit {
+            changed.push("RATE_LIMIT_READ");
+        }
+        if self.cors_origins != next.cors_origins {
+            changed.push("CORS_ALLOWED_ORIGINS");
+        }
+        if self.maintenance != next.maintenance {
+            changed.push("MAINTENANCE_MODE");
+        }
+        changed
+    }
+}
+
+/// Settings a reload never applies, kept so one that changed can be
+/// reported as needing a restart. Everything else outside `RuntimeSettings`
+/// is also read once at startup.
+struct StartupSettings {
+    environment: String,
+    tls: bool,
+    database_url: String,
+    database_read_url: Option<String>,
+    host: String,
+    port: u16,
+    bind_addrs: Vec<std::net::SocketAddr>,
+    grpc_port: u16,
+}
+
+impl StartupSettings {
+    fn from_config(config: &Config) -> StartupSettings {
+        StartupSettings {
+            environment: config.environment.clone(),
+            tls: config.tls.is_some(),
+            database_url: config.database_url.clone(),
+            database_read_url: config.database_read_url.clone(),
+            host: config.host.clone(),
+            port: config.port,
+            bind_addrs: config.bind_addrs.clone(),
+            grpc_port: config.grpc_port,
+        }
+    }
+
+    fn changes(&self, next: &StartupSettings) -> Vec<&'static str> {
+        [
+            ("ENVIRONMENT", self.environment != next.environment),
+            ("DATABASE_URL", self.database_url != next.database_url),
+            ("DATABASE_READ_URL", self.database_read_url != next.database_read_url),
+            // turning TLS on or off; a new certificate is reloaded
+            ("TLS_CERT_PATH", self.tls != next.tls),
+            ("HOST", self.host != next.host),
+            ("PORT", self.port != next.port),
+            ("BIND_ADDRS", self.bind_addrs != next.bind_addrs),
+            ("GRPC_PORT", self.grpc_port != next.grpc_port),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+}
+
+/// What a reload did.
+#[derive(serde::Serialize)]
+pub struct ReloadReport {
+    /// Applied, named by their variables.
+    pub changed: Vec<&'static str>,
+    /// Changed but ignored until the next restart.
+    pub restart_required: Vec<&'static str>,
+}
+
+/// `ENV_FILE`, or `.env` in development: the file `main` loads before the
+/// config and every reload reads again.
+pub fn env_file(development: bool) -> Option<PathBuf> {
+    match std::env::var("ENV_FILE") {
+        Ok(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => development.then(|| PathBuf::from(".env")),
+    }
+}
+
+/// Re-reads the config and publishes the reloadable part of it. Clones share
+/// the published settings.
+#[derive(Clone)]
+pub struct ConfigReloader {
+    settings: Arc<watch::Sender<RuntimeSettings>>,
+    startup: Arc<StartupSettings>,
+    env_file: Option<PathBuf>,
+    log_filter: Option<LogFilterHandle>,
+    /// What new TLS handshakes are served, `None` over plain HTTP.
+    tls: Option<RustlsConfig>,
+    /// The certificate PEM behind `tls`.
+    tls_cert: Arc<Mutex<Vec<u8>>>,
+    /// SIGHUP and the admin route may race; one reload runs at a time.
+    reloading: Arc<Mutex<()>>,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new(config: &Config) -> ConfigReloader {
+        ConfigReloader {
+            settings: Arc::new(watch::channel(RuntimeSettings::from_config(config)).0),
+            startup: Arc::new(StartupSettings::from_config(config)),
+            env_file: env_file(config.environment == "development"),
+            log_filter: None,
+            tls: config.tls.as_ref().map(|tls| RustlsConfig::from_config(tls.server_config.clone())),
+            tls_cert: Arc::new(Mutex::new(config.tls.as_ref().map(|tls| tls.cert_pem.clone()).unwrap_or_default())),
+            reloading: Arc::default(),
+        }
+    }
+
+    pub(crate) fn with_log_filter(mut self, handle: LogFilterHandle) -> ConfigReloader {
+        self.log_filter = Some(handle);
+        self
+    }
+
+    pub(crate) fn settings(&self) -> LiveSettings {
+        self.settings.subscribe()
+    }
+
+    /// For the HTTPS listener; a reload swaps the certificate behind it.
+    pub fn tls(&self) -> Option<RustlsConfig> {
+        self.tls.clone()
+    }
+
+    /// Reads the env file over the environment, validates the whole config
+    /// as startup does and swaps in the reloadable settings. On any error
+    /// the current settings stay. A variable removed from the file keeps
+    /// its old value, since the process environment cannot be re-read.
+    pub fn reload(&self) -> Result<ReloadReport, Vec<String>> {
+        let _reloading = self.reloading.lock().unwrap();
+        if let Some(path) = &self.env_file {
+            match dotenvy::from_path_override(path) {
+                Ok(()) => {}
+                Err(err) if err.not_found() && std::env::var("ENV_FILE").is_err() => {}
+                Err(err) => return Err(vec![format!("could not read {}: {err}", path.display())]),
+            }
+        }
+
+        let config = Config::from_env()?;
+        let next = RuntimeSettings::from_config(&config);
+        let mut changed = self.settings.borrow().changes(&next);
+        if changed.contains(&"RUST_LOG") {
+            if let Some(handle) = &self.log_filter {
+                // RUST_LOG was validated with the rest of the config
+                handle
+                    .reload(EnvFilter::new(&next.log_filter))
+                    .map_err(|err| vec![format!("could not apply RUST_LOG: {err}")])?;
+            }
+        }
+        self.settings.send_replace(next);
+
+        // connections already open keep the certificate they shook hands with
+        if let (Some(rustls), Some(tls)) = (&self.tls, &config.tls) {
+            let mut serving = self.tls_cert.lock().unwrap();
+            if *serving != tls.cert_pem {
+                rustls.reload_from_config(tls.server_config.clone());
+                serving.clone_from(&tls.cert_pem);
+                changed.push("TLS_CERT_PATH");
+            }
+        }
+
+        let restart_required = self.startup.changes(&StartupSettings::from_config(&config));
+        if !restart_required.is_empty() {
+            tracing::warn!(settings = ?restart_required, "reload ignored settings that only apply at startup");
+        }
+        tracing::info!(changed = ?changed, "config reloaded");
+        Ok(ReloadReport { changed, restart_required })
+    }
+}
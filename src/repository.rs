@@ -0,0 +1,672 @@
+//! Storage behind the order CRUD handlers, in Postgres or, for tests, in memory.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use axum::async_trait;
+use chrono::Utc;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+
+use crate::auth::AuthContext;
+use crate::db::{finish, read_pool};
+use crate::errors::{ApiError, FieldError, FieldErrorCode};
+use crate::feed::OrderEventKind;
+use crate::handlers::audit::{order_diff, record_order_event};
+use crate::handlers::customers::find_or_create_customer;
+use crate::handlers::inventory::{restock_orders, take_stock};
+use crate::handlers::menu::{Menu, PriceTolerance};
+use crate::handlers::orders::{
+    clean_notes, count_orders, missing_customers, insert_orders, normalize_tags, order_created, push_order_filters, with_items,
+    CreateOrdersReq, IfMatch, NewOrders, OrderFilter, UpdateOrdersReq, MAX_TOTAL,
+};
+use crate::idempotency::{claim_idempotency_key, store_idempotent_response};
+use crate::models::{parse_status, Money, OrderDetail, OrderItem, OrderStatus, Orders, Response, Size};
+use crate::outbox::Outbox;
+
+/// Which page of orders `list` returns, already validated by the handler.
+pub(crate) struct PageRequest {
+    pub(crate) limit: i64,
+    pub(crate) offset: i64,
+    /// Keyset cursor; when set, `offset` is unused and the page is sorted by id.
+    pub(crate) after_id: Option<i32>,
+    /// Whitelisted ORDER BY expression from `order_by_clause`.
+    pub(crate) order_by: String,
+    pub(crate) include_items: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct OrderPage {
+    pub(crate) orders: Vec<OrderDetail>,
+    /// Every order the filter matches, not just this page.
+    pub(crate) total: i64,
+    /// Set in cursor mode when another page follows.
+    pub(crate) next_cursor: Option<i32>,
+}
+
+/// An `Idempotency-Key` to claim in the same transaction as the insert.
+pub(crate) struct IdempotencyClaim<'a> {
+    pub(crate) key: &'a str,
+    pub(crate) request_hash: String,
+    pub(crate) ttl: Duration,
+}
+
+pub(crate) enum Created {
+    Fresh(Box<Response<OrderDetail>>),
+    /// The stored response of an earlier request with the same key.
+    Replay(axum::response::Response),
+}
+
+/// The five operations the order CRUD handlers need. Checks that need no
+/// stored data stay in the handlers; everything that reads or writes orders
+/// happens here, atomically.
+#[async_trait]
+pub(crate) trait OrderRepository: Send + Sync {
+    /// One page of matching orders and the count of all of them, from one snapshot.
+    async fn list(&self, filter: &OrderFilter, page: &PageRequest) -> Result<OrderPage, ApiError>;
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<OrderDetail>, ApiError>;
+
+    /// Prices and stores a validated order, parsed `status` included.
+    async fn create(
+        &self,
+        auth: &AuthContext,
+        order: CreateOrdersReq,
+        status: OrderStatus,
+        idempotency: Option<IdempotencyClaim<'_>>,
+    ) -> Result<Created, ApiError>;
+
+    /// Applies a validated update, failing with 404 for a missing order and
+    /// 412 when `if_match` is stale.
+    async fn update(
+        &self,
+        id: i32,
+        auth: &AuthContext,
+        if_match: Option<IfMatch>,
+        order: UpdateOrdersReq,
+        status: Option<OrderStatus>,
+    ) -> Result<Orders, ApiError>;
+
+    /// The deleted order, or `None` when there is no live order with this id.
+    async fn delete(&self, id: i32, hard: bool, actor: &str) -> Result<Option<Orders>, ApiError>;
+}
+
+pub(crate) struct PgOrderRepository {
+    db: PgPool,
+    /// Where `list` and `get` read from, when set; writes stay on `db`.
+    replica: Option<PgPool>,
+    outbox: Outbox,
+    tolerance: PriceTolerance,
+}
+
+impl PgOrderRepository {
+    pub(crate) fn new(db: PgPool, replica: Option<PgPool>, outbox: Outbox, tolerance: PriceTolerance) -> Self {
+        PgOrderRepository { db, replica, outbox, tolerance }
+    }
+
+}
+
+#[async_trait]
+impl OrderRepository for PgOrderRepository {
+    async fn list(&self, filter: &OrderFilter, page: &PageRequest) -> Result<OrderPage, ApiError> {
+        let mut q = QueryBuilder::<Postgres>::new("SELECT * FROM orders WHERE TRUE");
+        push_order_filters(&mut q, filter);
+
+        if let Some(after_id) = page.after_id {
+            // fetch one extra row to find out whether another page exists
+            q.push(" AND id > ").push_bind(after_id);
+            q.push(" ORDER BY id LIMIT ").push_bind(page.limit + 1);
+        } else {
+            q.push(format!(" ORDER BY {} LIMIT ", page.order_by)).push_bind(page.limit);
+            q.push(" OFFSET ").push_bind(page.offset);
+        }
+
+        // one snapshot for the page and the count, so they agree even while orders are written
+        let mut tx = read_pool(&self.db, self.replica.as_ref()).begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .execute(&mut *tx)
+            .await?;
+        let mut rows = q
+            .build_query_as::<Orders>()
+            .fetch_all(&mut *tx)
+            .await?;
+        let total = count_orders(&mut tx, filter).await?;
+
+        let has_more = rows.len() as i64 > page.limit;
+        rows.truncate(page.limit as usize);
+        let next_cursor = if has_more { rows.last().and_then(|o| o.id) } else { None };
+
+        let orders = if page.include_items {
+            with_items(&mut tx, rows).await?
+        } else {
+            rows.into_iter().map(OrderDetail::without_items).collect()
+        };
+        tx.commit().await?;
+
+        Ok(OrderPage { orders, total, next_cursor })
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<OrderDetail>, ApiError> {
+        let mut conn = read_pool(&self.db, self.replica.as_ref()).acquire().await?;
+        let order = sqlx::query_as!(
+            Orders,
+            r#"
+            SELECT id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                status, cancellation_reason, version, created_at, updated_at, deleted_at
+            FROM orders WHERE id = $1 AND ($2 OR deleted_at IS NULL)
+            "#,
+            id,
+            include_deleted
+        )
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        let Some(order) = order else {
+            return Ok(None);
+        };
+        Ok(with_items(&mut conn, vec![order]).await?.pop())
+    }
+
+    async fn create(
+        &self,
+        auth: &AuthContext,
+        order: CreateOrdersReq,
+        status: OrderStatus,
+        idempotency: Option<IdempotencyClaim<'_>>,
+    ) -> Result<Created, ApiError> {
+        let mut tx = self.db.begin().await?;
+        let result = self.create_in(&mut tx, auth, order, status, idempotency).await;
+        finish(tx, result).await
+    }
+
+    async fn update(
+        &self,
+        id: i32,
+        auth: &AuthContext,
+        if_match: Option<IfMatch>,
+        order: UpdateOrdersReq,
+        status: Option<OrderStatus>,
+    ) -> Result<Orders, ApiError> {
+        let mut tx = self.db.begin().await?;
+        let result = self.update_in(&mut tx, id, auth, if_match, order, status).await;
+        finish(tx, result).await
+    }
+
+    async fn delete(&self, id: i32, hard: bool, actor: &str) -> Result<Option<Orders>, ApiError> {
+        let mut tx = self.db.begin().await?;
+        let result = self.delete_in(&mut tx, id, hard, actor).await;
+        finish(tx, result).await
+    }
+}
+
+// the write paths, each run on a transaction its trait method opens and settles
+impl PgOrderRepository {
+    async fn create_in(
+        &self,
+        tx: &mut PgConnection,
+        auth: &AuthContext,
+        order: CreateOrdersReq,
+        status: OrderStatus,
+        idempotency: Option<IdempotencyClaim<'_>>,
+    ) -> Result<Created, ApiError> {
+        if let Some(claim) = &idempotency {
+            let replay = claim_idempotency_key(tx, &auth.subject, claim.key, &claim.request_hash, claim.ttl).await?;
+            if let Some(replay) = replay {
+                return Ok(Created::Replay(replay));
+            }
+        }
+
+        if let Some(customer_id) = order.customer_id {
+            if !missing_customers(tx, &[customer_id]).await?.is_empty() {
+                return Err(ApiError::Validation(vec![FieldError::new("customer_id", FieldErrorCode::NotFound, "customer not found")]));
+            }
+        }
+
+        if order.total_override.is_some() {
+            auth.require_admin()?;
+        }
+
+        let priced = Menu::load(tx)
+            .await?
+            .price_order(&order, self.tolerance)
+            .map_err(ApiError::Validation)?;
+
+        let mut new_order = NewOrders::default();
+        new_order.push(order, priced, status);
+        let inserted = insert_orders(tx, &new_order, &auth.subject).await?;
+        self.outbox.enqueue(tx, OrderEventKind::Created, &inserted).await?;
+        let co = with_items(tx, inserted)
+            .await?
+            .pop()
+            // the insert returned no row, which is our bug and not a missing order
+            .ok_or(ApiError::Database(sqlx::Error::RowNotFound))?;
+
+        let data = order_created(co);
+        if let Some(claim) = &idempotency {
+            store_idempotent_response(tx, &auth.subject, claim.key, axum::http::StatusCode::CREATED, &data).await?;
+        }
+
+        Ok(Created::Fresh(Box::new(data)))
+    }
+
+    async fn update_in(
+        &self,
+        tx: &mut PgConnection,
+        id: i32,
+        auth: &AuthContext,
+        if_match: Option<IfMatch>,
+        order: UpdateOrdersReq,
+        status: Option<OrderStatus>,
+    ) -> Result<Orders, ApiError> {
+        // lock the row so the checks below and the audit diff see what gets overwritten
+        let current = sqlx::query_as!(
+            Orders,
+            r#"
+            SELECT id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                status, cancellation_reason, version, created_at, updated_at, deleted_at
+            FROM orders WHERE id = $1 AND deleted_at IS NULL FOR UPDATE
+            "#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("order not found".to_owned()))?;
+
+        // a stale tag fails here, before anything is written
+        if let Some(if_match) = &if_match {
+            if !if_match.matches(current.version.unwrap_or_default()) {
+                return Err(ApiError::PreconditionFailed("order has been modified".to_owned()));
+            }
+        }
+
+        if let Some(next) = status {
+            let from = parse_current_status(&current)?;
+            if !from.can_transition_to(next) {
+                return Err(ApiError::Conflict(format!(
+                    "cannot change status from {} to {}",
+                    from.as_str(),
+                    next.as_str()
+                )));
+            }
+        }
+
+        // a new name moves the order to that customer, creating them if needed
+        let customer = match &order.name {
+            Some(name) => Some(find_or_create_customer(tx, name.trim()).await?),
+            None => None,
+        };
+
+        // coffee_name, size and quantity edit the only line of a single-item order,
+        // which is re-priced from the menu; an admin's total_override still wins
+        let mut coffee_name = order.coffee_name;
+        let mut menu_total = None;
+        if coffee_name.is_some() || order.size.is_some() || order.quantity.is_some() {
+            let lines = sqlx::query_as!(
+                OrderItem,
+                r#"
+                SELECT id, order_id, coffee_name, size AS "size: Size", quantity, unit_price
+                FROM order_items WHERE order_id = $1 FOR UPDATE
+                "#,
+                id
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            let [line] = lines.as_slice() else {
+                return Err(ApiError::Conflict(format!(
+                    "order has {} items, coffee_name, size and quantity can only be changed on single-item orders",
+                    lines.len()
+                )));
+            };
+
+            let menu = Menu::load(tx).await?;
+            let item = menu
+                .find(
+                    coffee_name.as_deref().unwrap_or(&line.coffee_name),
+                    order.size.unwrap_or(line.size),
+                )
+                .map_err(|error| ApiError::Validation(vec![error]))?;
+
+            let quantity = order.quantity.unwrap_or(line.quantity);
+
+            // the old cups go back before the new ones are taken, so a change of size can reuse them
+            restock_orders(tx, &[id]).await?;
+            if parse_current_status(&current)?.is_open() {
+                take_stock(tx, std::slice::from_ref(&item.coffee_name), &[quantity]).await?;
+            }
+
+            sqlx::query!(
+                "UPDATE order_items SET coffee_name = $2, size = $3, quantity = $4, unit_price = $5 WHERE id = $1",
+                line.id,
+                item.coffee_name,
+                item.size as Size,
+                quantity,
+                item.price.amount()
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            if coffee_name.is_some() {
+                coffee_name = Some(item.coffee_name.clone());
+            }
+            let line_total = item
+                .price
+                .checked_mul(quantity)
+                .filter(|line_total| *line_total <= MAX_TOTAL)
+                .ok_or_else(|| ApiError::Validation(vec![FieldError::new("quantity", FieldErrorCode::OutOfRange, format!("order total may be at most {MAX_TOTAL}"))]))?;
+            menu_total = Some(line_total);
+        }
+
+        // without a re-price, a submitted total is checked against the lines as priced when ordered
+        let total = match (order.total_override, order.total) {
+            (Some(total), _) => Some(total),
+            (None, Some(submitted)) => {
+                let expected = match menu_total {
+                    Some(expected) => expected,
+                    None => sqlx::query_scalar!(
+                        "SELECT COALESCE(SUM(unit_price * quantity), 0) AS \"total!\" FROM order_items WHERE order_id = $1",
+                        id
+                    )
+                    .fetch_one(&mut *tx)
+                    .await?
+                    .into(),
+                };
+                let total = self
+                    .tolerance
+                    .check(submitted, expected)
+                    .map_err(|error| ApiError::Validation(vec![error]))?;
+                Some(total)
+            }
+            (None, None) => menu_total,
+        };
+
+        if status == Some(OrderStatus::Cancelled) {
+            restock_orders(tx, &[id]).await?;
+        }
+
+        let mut q = QueryBuilder::<Postgres>::new("UPDATE orders SET ");
+        let mut fields = q.separated(", ");
+
+        if let Some(customer) = customer {
+            fields.push("name = ").push_bind_unseparated(customer.name);
+            fields.push("customer_id = ").push_bind_unseparated(customer.id);
+        }
+
+        if let Some(coffee_name) = coffee_name {
+            fields.push("coffee_name = ").push_bind_unseparated(coffee_name);
+        }
+
+        if let Some(size) = order.size {
+            fields.push("size = ").push_bind_unseparated(size);
+        }
+
+        if let Some(quantity) = order.quantity {
+            fields.push("quantity = ").push_bind_unseparated(quantity);
+        }
+
+        if let Some(total) = total {
+            fields.push("total = ").push_bind_unseparated(total);
+        }
+
+        if let Some(status) = status {
+            fields.push("status = ").push_bind_unseparated(status.as_str());
+        }
+
+        if let Some(notes) = order.notes {
+            fields.push("notes = ").push_bind_unseparated(notes);
+        }
+
+        if let Some(tags) = order.tags {
+            fields.push("tags = ").push_bind_unseparated(tags);
+        }
+
+        fields.push("version = version + 1");
+        fields.push("updated_at = now()");
+
+        q.push(" WHERE id = ").push_bind(id);
+        q.push(" RETURNING *");
+
+        let updated = q
+            .build_query_as::<Orders>()
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let action = if updated.status != current.status { "status_changed" } else { "updated" };
+        record_order_event(tx, id, action, &auth.subject, order_diff(&current, &updated)).await?;
+        self.outbox.enqueue(tx, OrderEventKind::Updated, std::slice::from_ref(&updated)).await?;
+
+        Ok(updated)
+    }
+
+    async fn delete_in(
+        &self,
+        tx: &mut PgConnection,
+        id: i32,
+        hard: bool,
+        actor: &str,
+    ) -> Result<Option<Orders>, ApiError> {
+        restock_orders(tx, &[id]).await?;
+        let deleted = if hard {
+            sqlx::query_as!(
+                Orders,
+                r#"
+                DELETE FROM orders
+                WHERE id = $1
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                 "#,
+                id
+                )
+                .fetch_optional(&mut *tx)
+                .await?
+        } else {
+            // already soft-deleted orders count as missing
+            sqlx::query_as!(
+                Orders,
+                r#"
+                UPDATE orders SET deleted_at = now(), updated_at = now(), version = version + 1
+                WHERE id = $1 AND deleted_at IS NULL
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                 "#,
+                id
+                )
+                .fetch_optional(&mut *tx)
+                .await?
+        };
+
+        if let Some(order) = &deleted {
+            record_order_event(tx, id, "deleted", actor, serde_json::json!({ "hard": hard })).await?;
+            self.outbox.enqueue(tx, OrderEventKind::Deleted, std::slice::from_ref(order)).await?;
+        }
+        Ok(deleted)
+    }
+}
+
+fn parse_current_status(order: &Orders) -> Result<OrderStatus, ApiError> {
+    parse_status(order.status.as_deref().unwrap_or_default())
+}
+
+/// Orders kept in memory, for tests that drive the handlers without a
+/// database. Nothing is priced, stocked, audited or replayed: totals are
+/// charged as submitted and every line costs nothing. Lists ignore all
+/// filters but `include_deleted` and are always sorted by id.
+#[derive(Default)]
+pub struct MemoryOrderRepository {
+    orders: Mutex<Vec<Orders>>,
+    failing: AtomicBool,
+}
+
+impl MemoryOrderRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// While set, every call fails the way a lost database connection would.
+    pub fn fail(&self, failing: bool) {
+        self.failing.store(failing, Ordering::SeqCst);
+    }
+
+    fn check(&self) -> Result<(), ApiError> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(ApiError::Database(sqlx::Error::Protocol("memory repository set to fail".to_owned())));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl OrderRepository for MemoryOrderRepository {
+    async fn list(&self, filter: &OrderFilter, page: &PageRequest) -> Result<OrderPage, ApiError> {
+        self.check()?;
+        let orders = self.orders.lock().unwrap();
+        let include_deleted = filter.include_deleted.unwrap_or(false);
+        let matching: Vec<&Orders> = orders
+            .iter()
+            .filter(|order| include_deleted || order.deleted_at.is_none())
+            .collect();
+
+        let (skip, after_id) = match page.after_id {
+            Some(after_id) => (0, after_id),
+            None => (page.offset as usize, i32::MIN),
+        };
+        let mut rows: Vec<Orders> = matching
+            .iter()
+            .filter(|order| order.id.unwrap_or_default() > after_id)
+            .skip(skip)
+            .take(page.limit as usize + 1)
+            .map(|order| (*order).clone())
+            .collect();
+
+        let has_more = rows.len() as i64 > page.limit;
+        rows.truncate(page.limit as usize);
+        let next_cursor = match page.after_id {
+            Some(_) if has_more => rows.last().and_then(|o| o.id),
+            _ => None,
+        };
+
+        Ok(OrderPage {
+            orders: rows.into_iter().map(OrderDetail::without_items).collect(),
+            total: matching.len() as i64,
+            next_cursor,
+        })
+    }
+
+    async fn get(&self, id: i32, include_deleted: bool) -> Result<Option<OrderDetail>, ApiError> {
+        self.check()?;
+        let orders = self.orders.lock().unwrap();
+        Ok(orders
+            .iter()
+            .find(|order| order.id == Some(id) && (include_deleted || order.deleted_at.is_none()))
+            .map(|order| OrderDetail { order: order.clone(), items: Some(Vec::new()) }))
+    }
+
+    async fn create(
+        &self,
+        auth: &AuthContext,
+        order: CreateOrdersReq,
+        status: OrderStatus,
+        _idempotency: Option<IdempotencyClaim<'_>>,
+    ) -> Result<Created, ApiError> {
+        self.check()?;
+        if order.total_override.is_some() {
+            auth.require_admin()?;
+        }
+
+        let lines = order.lines();
+        let first = &lines[0];
+        let now = Utc::now();
+        let mut orders = self.orders.lock().unwrap();
+        let created = Orders {
+            id: Some(orders.iter().filter_map(|order| order.id).max().unwrap_or_default() + 1),
+            name: order.name.as_deref().map(|name| name.trim().to_owned()),
+            customer_id: order.customer_id,
+            coffee_name: Some(first.coffee_name.to_owned()),
+            size: Some(first.size),
+            quantity: Some(lines.iter().map(|line| line.quantity).sum()),
+            total: order.total_override.or(order.total).unwrap_or(Money::ZERO),
+            notes: clean_notes(order.notes),
+            tags: normalize_tags(&order.tags),
+            status: Some(status.as_str().to_owned()),
+            cancellation_reason: None,
+            version: Some(1),
+            created_at: Some(now),
+            updated_at: Some(now),
+            deleted_at: None,
+        };
+        orders.push(created.clone());
+
+        Ok(Created::Fresh(Box::new(order_created(OrderDetail { order: created, items: Some(Vec::new()) }))))
+    }
+
+    async fn update(
+        &self,
+        id: i32,
+        _auth: &AuthContext,
+        if_match: Option<IfMatch>,
+        order: UpdateOrdersReq,
+        status: Option<OrderStatus>,
+    ) -> Result<Orders, ApiError> {
+        self.check()?;
+        let mut orders = self.orders.lock().unwrap();
+        let current = orders
+            .iter_mut()
+            .find(|current| current.id == Some(id) && current.deleted_at.is_none())
+            .ok_or_else(|| ApiError::NotFound("order not found".to_owned()))?;
+
+        if let Some(if_match) = &if_match {
+            if !if_match.matches(current.version.unwrap_or_default()) {
+                return Err(ApiError::PreconditionFailed("order has been modified".to_owned()));
+            }
+        }
+
+        if let Some(next) = status {
+            let from = parse_current_status(current)?;
+            if !from.can_transition_to(next) {
+                return Err(ApiError::Conflict(format!(
+                    "cannot change status from {} to {}",
+                    from.as_str(),
+                    next.as_str()
+                )));
+            }
+            current.status = Some(next.as_str().to_owned());
+        }
+
+        if let Some(name) = order.name {
+            current.name = Some(name.trim().to_owned());
+        }
+        current.coffee_name = order.coffee_name.or(current.coffee_name.take());
+        current.size = order.size.or(current.size.take());
+        current.quantity = order.quantity.or(current.quantity);
+        if let Some(notes) = order.notes {
+            current.notes = notes;
+        }
+        if let Some(tags) = order.tags {
+            current.tags = tags;
+        }
+        if let Some(total) = order.total_override.or(order.total) {
+            current.total = total;
+        }
+        current.version = current.version.map(|version| version + 1);
+        current.updated_at = Some(Utc::now());
+
+        Ok(current.clone())
+    }
+
+    async fn delete(&self, id: i32, hard: bool, _actor: &str) -> Result<Option<Orders>, ApiError> {
+        self.check()?;
+        let mut orders = self.orders.lock().unwrap();
+        let Some(position) = orders
+            .iter()
+            .position(|order| order.id == Some(id) && (hard || order.deleted_at.is_none()))
+        else {
+            return Ok(None);
+        };
+
+        if hard {
+            return Ok(Some(orders.remove(position)));
+        }
+        let order = &mut orders[position];
+        order.deleted_at = Some(Utc::now());
+        order.updated_at = order.deleted_at;
+        order.version = order.version.map(|version| version + 1);
+        Ok(Some(order.clone()))
+    }
+}
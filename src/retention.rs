@@ -0,0 +1,109 @@
+//! The retention job, which moves finished orders the service no longer
+//! serves out of `orders`, a bounded batch at a time.
+use std::time::Duration;
+use sqlx::PgPool;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::models::OrderStatus;
+use crate::{AppState, Config};
+
+pub(crate) const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Orders moved per statement, so no run holds many row locks at once.
+const RETENTION_BATCH: i64 = 1000;
+/// Breathing room between batches for the rest of the traffic.
+const RETENTION_PAUSE: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy)]
+pub struct RetentionSettings {
+    /// Completed and cancelled orders untouched for this many days go.
+    pub after_days: i32,
+    /// Copy them to `orders_archive` first rather than only deleting them.
+    pub archive: bool,
+}
+
+/// Starts the retention job, which runs every hour until `shutdown` is
+/// cancelled. Does nothing when `ORDER_RETENTION_DAYS` is unset.
+pub fn spawn_retention_job(state: &AppState, config: &Config, shutdown: CancellationToken) -> JoinHandle<()> {
+    let settings = config.retention;
+    let db = state.db.clone();
+    let list_cache = state.list_cache.clone();
+    tokio::spawn(async move {
+        let Some(settings) = settings else {
+            tracing::info!("ORDER_RETENTION_DAYS is unset, retention job disabled");
+            return;
+        };
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            match purge_old_orders(&db, &settings, &shutdown).await {
+                Ok(moved) => {
+                    // nothing goes through the feed here, so cached pages would keep serving purged orders
+                    if moved > 0 {
+                        list_cache.invalidate();
+                    }
+                    tracing::info!(
+                        moved,
+                        archived = settings.archive,
+                        after_days = settings.after_days,
+                        "retention run finished"
+                    );
+                }
+                Err(err) => tracing::warn!(error = %err, "retention run failed"),
+            }
+        }
+        tracing::info!("retention job stopped");
+    })
+}
+
+/// Archives or deletes every expired order, returning how many left
+/// `orders`. Stops between batches once `shutdown` is cancelled; the next
+/// run picks up the rest. Items go with their order, while its audit events
+/// stay, as they do for a hard delete.
+pub async fn purge_old_orders(
+    db: &PgPool,
+    settings: &RetentionSettings,
+    shutdown: &CancellationToken,
+) -> Result<u64, sqlx::Error> {
+    let mut total = 0;
+    loop {
+        // the archive copy and the delete are one statement, so a crash never leaves an order in both
+        let moved = sqlx::query!(
+            "
+            WITH expired AS (
+                SELECT id FROM orders
+                WHERE status IN ($1, $2) AND updated_at < now() - make_interval(days => $3)
+                ORDER BY id
+                LIMIT $4
+                FOR UPDATE SKIP LOCKED
+            ), archived AS (
+                INSERT INTO orders_archive (id, status, created_at, data)
+                SELECT o.id, o.status, o.created_at, to_jsonb(o) || jsonb_build_object('items', COALESCE(
+                    (SELECT jsonb_agg(to_jsonb(i) - 'order_id' ORDER BY i.id) FROM order_items i WHERE i.order_id = o.id),
+                    '[]'
+                ))
+                FROM orders o JOIN expired USING (id)
+                WHERE $5
+            )
+            DELETE FROM orders WHERE id IN (SELECT id FROM expired)
+            ",
+            OrderStatus::Completed.as_str(),
+            OrderStatus::Cancelled.as_str(),
+            settings.after_days,
+            RETENTION_BATCH,
+            settings.archive
+        )
+        .execute(db)
+        .await?
+        .rows_affected();
+
+        total += moved;
+        if moved < RETENTION_BATCH as u64 || shutdown.is_cancelled() {
+            return Ok(total);
+        }
+        tokio::time::sleep(RETENTION_PAUSE).await;
+    }
+}
@@ -0,0 +1,216 @@
+//! Just enough of the S3 API for exports, signed with SigV4: a single put
+//! for small objects and a multipart upload for the rest. Objects are
+//! addressed path-style, which AWS, MinIO and R2 all accept.
+use std::env;
+use std::time::Duration;
+use axum::http::header::CONTENT_TYPE;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, Url};
+use sha2::{Digest, Sha256};
+
+use crate::idempotency::hex;
+
+const S3_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub(crate) struct S3Settings {
+    endpoint: Url,
+    region: String,
+    bucket: String,
+    access_key_id: String,
+    secret_access_key: String,
+}
+
+/// `EXPORT_S3_BUCKET` and its credentials; `EXPORT_S3_ENDPOINT` points
+/// elsewhere than AWS, e.g. at MinIO.
+pub(crate) fn s3_from_env(errors: &mut Vec<String>) -> Option<S3Settings> {
+    let bucket = env::var("EXPORT_S3_BUCKET").ok().filter(|v| !v.is_empty())?;
+    let region = env::var("EXPORT_S3_REGION").ok().filter(|v| !v.is_empty()).unwrap_or_else(|| "us-east-1".to_owned());
+    let endpoint = env::var("EXPORT_S3_ENDPOINT")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| format!("https://s3.{region}.amazonaws.com"));
+    let endpoint = match Url::parse(&endpoint) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => {
+            errors.push(format!("EXPORT_S3_ENDPOINT must be an http or https URL, got '{endpoint}'"));
+            return None;
+        }
+    };
+    let access_key_id = env::var("EXPORT_S3_ACCESS_KEY_ID").ok().filter(|v| !v.is_empty());
+    let secret_access_key = env::var("EXPORT_S3_SECRET_ACCESS_KEY").ok().filter(|v| !v.is_empty());
+    let (Some(access_key_id), Some(secret_access_key)) = (access_key_id, secret_access_key) else {
+        errors.push("EXPORT_S3_ACCESS_KEY_ID and EXPORT_S3_SECRET_ACCESS_KEY are required with EXPORT_S3_BUCKET".to_owned());
+        return None;
+    };
+    Some(S3Settings { endpoint, region, bucket, access_key_id, secret_access_key })
+}
+
+#[derive(Clone)]
+pub(crate) struct S3Client {
+    http: reqwest::Client,
+    settings: S3Settings,
+}
+
+impl S3Client {
+    pub(crate) fn new(settings: S3Settings) -> S3Client {
+        let http = reqwest::Client::builder()
+            .timeout(S3_TIMEOUT)
+            .build()
+            .expect("could not build s3 http client");
+        S3Client { http, settings }
+    }
+
+    pub(crate) fn bucket(&self) -> &str {
+        &self.settings.bucket
+    }
+
+    pub(crate) async fn put_object(&self, key: &str, content_type: &str, body: Vec<u8>) -> Result<(), String> {
+        self.send(Method::PUT, key, &[], Some(content_type), body).await?;
+        Ok(())
+    }
+
+    /// Starts a multipart upload and returns its upload id.
+    pub(crate) async fn create_multipart_upload(&self, key: &str, content_type: &str) -> Result<String, String> {
+        let body = self.send(Method::POST, key, &[("uploads", "")], Some(content_type), Vec::new()).await?.1;
+        xml_element(&body, "UploadId").ok_or_else(|| format!("no UploadId in the answer to starting {key}"))
+    }
+
+    /// Uploads part `part_number`, counting from 1, and returns its ETag.
+    /// Every part but the last must be at least 5 MiB.
+    pub(crate) async fn upload_part(&self, key: &str, upload_id: &str, part_number: usize, body: Vec<u8>) -> Result<String, String> {
+        let part_number = part_number.to_string();
+        let (headers, _) = self
+            .send(Method::PUT, key, &[("partNumber", &part_number), ("uploadId", upload_id)], None, body)
+            .await?;
+        headers
+            .get("etag")
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned)
+            .ok_or_else(|| format!("no ETag for part {part_number} of {key}"))
+    }
+
+    /// Joins the parts, whose ETags are in part order, into the object.
+    pub(crate) async fn complete_multipart_upload(&self, key: &str, upload_id: &str, etags: &[String]) -> Result<(), String> {
+        let parts: String = etags
+            .iter()
+            .enumerate()
+            .map(|(index, etag)| format!("<Part><PartNumber>{}</PartNumber><ETag>{etag}</ETag></Part>", index + 1))
+            .collect();
+        let body = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>").into_bytes();
+        let answer = self.send(Method::POST, key, &[("uploadId", upload_id)], Some("application/xml"), body).await?.1;
+        // a failed completion can still answer 200, with the error in the body
+        match xml_element(&answer, "Code") {
+            Some(code) => Err(format!("completing {key} failed: {code}")),
+            None => Ok(()),
+        }
+    }
+
+    /// Drops the parts of an upload that will not be completed.
+    pub(crate) async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), String> {
+        self.send(Method::DELETE, key, &[("uploadId", upload_id)], None, Vec::new()).await?;
+        Ok(())
+    }
+
+    /// One signed request; anything but a 2xx is an error naming the code
+    /// S3 gave. Returns the headers and body of the answer.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        content_type: Option<&str>,
+        body: Vec<u8>,
+    ) -> Result<(reqwest::header::HeaderMap, String), String> {
+        let mut request = self.signed_request(method.clone(), key, query, body);
+        if let Some(content_type) = content_type {
+            request = request.header(CONTENT_TYPE, content_type);
+        }
+        let response = request.send().await.map_err(|err| format!("{method} {key}: {}", err.without_url()))?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let code = xml_element(&body, "Code").unwrap_or_default();
+            return Err(format!("{method} {key}: storage answered {status} {code}").trim_end().to_owned());
+        }
+        Ok((headers, body))
+    }
+
+    fn signed_request(&self, method: Method, key: &str, query: &[(&str, &str)], body: Vec<u8>) -> reqwest::RequestBuilder {
+        let settings = &self.settings;
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+
+        let path = format!(
+            "{}/{}/{}",
+            settings.endpoint.path().trim_end_matches('/'),
+            uri_encode(&settings.bucket, true),
+            uri_encode(key, false)
+        );
+        let mut pairs: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k, true), uri_encode(v, true))).collect();
+        pairs.sort();
+        let canonical_query = pairs.iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>().join("&");
+        let mut url = settings.endpoint.clone();
+        url.set_path(&path);
+        url.set_query((!canonical_query.is_empty()).then_some(canonical_query.as_str()));
+        // what reqwest sends as Host: the port only when it is not the scheme's default
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+
+        let payload_hash = hex(&Sha256::digest(&body));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{method}\n{path}\n{canonical_query}\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{payload_hash}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", settings.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}", hex(&Sha256::digest(canonical_request)));
+        let signing_key = [date, settings.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", settings.secret_access_key).into_bytes(), |key, part| hmac_sha256(&key, part.as_bytes()));
+        let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+        self.http
+            .request(method, url)
+            .header("x-amz-date", &amz_date)
+            .header("x-amz-content-sha256", &payload_hash)
+            .header(
+                "authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+                    settings.access_key_id
+                ),
+            )
+            .body(body)
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes all but the unreserved characters, as SigV4 wants,
+/// leaving `/` alone in object keys.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if !encode_slash => "/".to_owned(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// The text of the first `<name>` element; the answers used here are
+/// small and flat enough not to need an XML parser.
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{name}>"))?;
+    Some(xml[start..end].to_owned())
+}
@@ -0,0 +1,132 @@
+//! Sample data for local development and demos, loaded with `--seed` or
+//! `POST /dev/reset?seed=true`.
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgPool};
+
+use crate::models::Size;
+
+/// Every seeded order carries this tag, which is how a re-run finds and
+/// replaces them without touching anything else.
+pub(crate) const SAMPLE_TAG: &str = "sample";
+
+const SAMPLE_ORDERS: usize = 40;
+const NAMES: [&str; 12] = [
+    "Ada", "Grace", "Linus", "Margaret", "Alan", "Barbara", "Dennis", "Frances", "Ken", "Radia", "Edsger", "Hedy",
+];
+const COFFEES: [&str; 6] = ["espresso", "americano", "cappuccino", "flat white", "latte", "mocha"];
+const QUANTITIES: [i32; 5] = [1, 1, 2, 1, 3];
+const OPEN_STATUSES: [&str; 3] = ["pending", "preparing", "ready"];
+
+#[derive(Serialize)]
+pub struct SeedReport {
+    /// Sample orders inserted.
+    pub orders: u64,
+    /// Customers that did not exist yet.
+    pub customers: u64,
+    /// Sample orders from an earlier run, deleted first.
+    pub replaced: u64,
+}
+
+/// Replaces the sample orders with a fresh set spread over the last two
+/// weeks: older ones completed or cancelled, recent ones still open. Totals
+/// come from the menu, so coffees no longer on it are skipped. Stock is
+/// left alone. Runs in one transaction, so it can be re-run at any time.
+pub async fn seed_sample_data(db: &PgPool) -> Result<SeedReport, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let report = seed(&mut tx).await?;
+    tx.commit().await?;
+    Ok(report)
+}
+
+/// `seed_sample_data` on a connection the caller holds a transaction on.
+pub(crate) async fn seed(conn: &mut PgConnection) -> Result<SeedReport, sqlx::Error> {
+    let now = Utc::now();
+    let mut names = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut coffee_names = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut sizes = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut quantities = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut statuses = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut reasons = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut notes = Vec::with_capacity(SAMPLE_ORDERS);
+    let mut created = Vec::with_capacity(SAMPLE_ORDERS);
+    for i in 0..SAMPLE_ORDERS {
+        // strides coprime with each list so the combinations keep changing
+        names.push(NAMES[i * 7 % NAMES.len()].to_owned());
+        coffee_names.push(COFFEES[i * 5 % COFFEES.len()].to_owned());
+        sizes.push(Size::ALL[i % Size::ALL.len()]);
+        quantities.push(QUANTITIES[i % QUANTITIES.len()]);
+
+        let age = Duration::hours(8 * (SAMPLE_ORDERS - i) as i64);
+        let (status, reason) = if age < Duration::days(1) {
+            (OPEN_STATUSES[i % OPEN_STATUSES.len()], None)
+        } else if i % 9 == 4 {
+            ("cancelled", Some("customer left before it was ready".to_owned()))
+        } else {
+            ("completed", None)
+        };
+        statuses.push(status.to_owned());
+        reasons.push(reason);
+        notes.push(match i % 10 {
+            0 => Some("oat milk".to_owned()),
+            3 => Some("extra hot".to_owned()),
+            _ => None,
+        });
+        created.push(now - age);
+    }
+
+    sqlx::query!(
+        "DELETE FROM order_events WHERE order_id IN (SELECT id FROM orders WHERE tags @> ARRAY[$1::text])",
+        SAMPLE_TAG
+    )
+    .execute(&mut *conn)
+    .await?;
+    let replaced = sqlx::query!("DELETE FROM orders WHERE tags @> ARRAY[$1::text]", SAMPLE_TAG)
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+    let customers = sqlx::query!(
+        "INSERT INTO customers (name) SELECT DISTINCT UNNEST($1::text[]) ON CONFLICT ((LOWER(name))) DO NOTHING",
+        &names
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    // items mirror the single line each order is, as a POST would store it
+    let orders = sqlx::query!(
+        r#"
+        WITH seeded AS (
+            INSERT INTO orders (
+                name, customer_id, coffee_name, size, quantity, total, status,
+                cancellation_reason, notes, tags, created_at, updated_at
+            )
+            SELECT c.name, c.id, m.coffee_name, m.size, o.quantity, m.price * o.quantity, o.status,
+                   o.reason, o.notes, ARRAY[$9::text], o.created_at, o.created_at
+            FROM UNNEST($1::text[], $2::text[], $3::order_size[], $4::int[], $5::text[], $6::text[], $7::text[], $8::timestamptz[])
+                AS o (name, coffee_name, size, quantity, status, reason, notes, created_at)
+            JOIN customers c ON LOWER(c.name) = LOWER(o.name)
+            JOIN menu_items m ON LOWER(m.coffee_name) = LOWER(o.coffee_name) AND m.size = o.size
+            ORDER BY o.created_at
+            RETURNING id, coffee_name, size, quantity, total
+        )
+        INSERT INTO order_items (order_id, coffee_name, size, quantity, unit_price)
+        SELECT id, coffee_name, size, quantity, total / quantity FROM seeded
+        "#,
+        &names,
+        &coffee_names,
+        &sizes as &[Size],
+        &quantities,
+        &statuses,
+        &reasons as &[Option<String>],
+        &notes as &[Option<String>],
+        &created,
+        SAMPLE_TAG,
+    )
+    .execute(&mut *conn)
+    .await?
+    .rows_affected();
+
+    Ok(SeedReport { orders, customers, replaced })
+}
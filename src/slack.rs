@@ -0,0 +1,190 @@
+//! Slack pings for the orders management wants to hear about: big ones and
+//! ones carrying a watched tag. Posted from a background task, so Slack
+//! being slow or down never reaches the caller placing the order.
+use std::env;
+use std::time::Duration;
+use axum::http::header::CONTENT_TYPE;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::config::env_or;
+use crate::models::{Money, OrderDetail};
+
+pub(crate) const SLACK_QUEUE_SIZE: usize = 64;
+pub(crate) const SLACK_MAX_ATTEMPTS: u32 = 2;
+pub(crate) const SLACK_RETRY_AFTER: Duration = Duration::from_secs(1);
+const SLACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where and when to post, from the `SLACK_*` variables.
+#[derive(Clone)]
+pub(crate) struct SlackSettings {
+    url: reqwest::Url,
+    /// Orders totalling at least this much; `None` leaves totals out of it.
+    min_total: Option<Money>,
+    /// Orders carrying any of these, lowercase like stored tags.
+    tags: Vec<String>,
+    /// Posts allowed in any minute; matches past it are counted, not posted.
+    per_minute: u32,
+}
+
+/// `SLACK_WEBHOOK_URL` turns notifications on. `SLACK_ORDER_MIN_TOTAL`
+/// and `SLACK_ORDER_TAGS` (default `event`) choose the orders, and
+/// `SLACK_MAX_PER_MINUTE` (default 10) caps the posts.
+pub(crate) fn slack_from_env(errors: &mut Vec<String>) -> Option<SlackSettings> {
+    let url = env::var("SLACK_WEBHOOK_URL").ok().filter(|v| !v.is_empty())?;
+    let url = match reqwest::Url::parse(&url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => {
+            errors.push("SLACK_WEBHOOK_URL must be an http or https URL".to_owned());
+            return None;
+        }
+    };
+    let min_total = env::var("SLACK_ORDER_MIN_TOTAL").ok().filter(|v| !v.is_empty()).and_then(|value| {
+        value
+            .parse::<Money>()
+            .map_err(|err| errors.push(format!("SLACK_ORDER_MIN_TOTAL is invalid: {err}")))
+            .ok()
+    });
+    let tags: Vec<String> = env::var("SLACK_ORDER_TAGS")
+        .unwrap_or_else(|_| "event".to_owned())
+        .split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    if min_total.is_none() && tags.is_empty() {
+        errors.push("SLACK_WEBHOOK_URL is set but neither SLACK_ORDER_MIN_TOTAL nor SLACK_ORDER_TAGS picks any orders".to_owned());
+    }
+    let per_minute = env_or("SLACK_MAX_PER_MINUTE", 10, errors);
+    if per_minute == 0 {
+        errors.push("SLACK_MAX_PER_MINUTE must be at least 1".to_owned());
+    }
+    Some(SlackSettings { url, min_total, tags, per_minute })
+}
+
+impl SlackSettings {
+    /// Why `order` is worth a ping, empty when it is not.
+    fn reasons(&self, detail: &OrderDetail) -> Vec<String> {
+        let order = &detail.order;
+        let mut reasons = Vec::new();
+        if let Some(min_total) = self.min_total.filter(|min_total| order.total >= *min_total) {
+            reasons.push(format!("total of at least {min_total}"));
+        }
+        for tag in order.tags.iter().filter(|tag| self.tags.contains(tag)) {
+            reasons.push(format!("tagged {tag}"));
+        }
+        reasons
+    }
+}
+
+/// Handle the create path notifies through; a no-op without
+/// `SLACK_WEBHOOK_URL`.
+#[derive(Clone)]
+pub(crate) struct SlackNotifier {
+    settings: Option<SlackSettings>,
+    queue: Option<mpsc::Sender<serde_json::Value>>,
+}
+
+impl SlackNotifier {
+    pub(crate) fn start(settings: Option<&SlackSettings>) -> SlackNotifier {
+        let Some(settings) = settings else {
+            return SlackNotifier { settings: None, queue: None };
+        };
+        let client = reqwest::Client::builder()
+            .timeout(SLACK_TIMEOUT)
+            .build()
+            .expect("could not build slack http client");
+        let (tx, rx) = mpsc::channel(SLACK_QUEUE_SIZE);
+        tokio::spawn(post_messages(rx, client, settings.url.clone(), settings.per_minute));
+        tracing::info!(min_total = ?settings.min_total.map(|total| total.to_string()), tags = ?settings.tags, "slack notifications enabled");
+        SlackNotifier { settings: Some(settings.clone()), queue: Some(tx) }
+    }
+
+    /// Queues a message when `detail` matches a rule.
+    pub(crate) fn notify(&self, detail: &OrderDetail) {
+        let (Some(settings), Some(queue)) = (&self.settings, &self.queue) else {
+            return;
+        };
+        let reasons = settings.reasons(detail);
+        if reasons.is_empty() {
+            return;
+        }
+        if queue.try_send(slack_message(detail, &reasons)).is_err() {
+            tracing::warn!(order_id = detail.order.id, "slack queue is full, dropping notification");
+            metrics::counter!("slack_notifications_dropped_total").increment(1);
+        }
+    }
+}
+
+/// Posts queued messages in order, at most `per_minute` of them in any
+/// minute. The ones over the limit are dropped and mentioned in the next
+/// message that goes out.
+async fn post_messages(mut rx: mpsc::Receiver<serde_json::Value>, client: reqwest::Client, url: reqwest::Url, per_minute: u32) {
+    let refill = Duration::from_secs(60) / per_minute;
+    let mut tokens = per_minute;
+    let mut refilled_at = Instant::now();
+    let mut held_back = 0u64;
+    while let Some(mut message) = rx.recv().await {
+        let earned = (refilled_at.elapsed().as_nanos() / refill.as_nanos()) as u32;
+        if earned > 0 {
+            tokens = (tokens + earned).min(per_minute);
+            refilled_at += refill * earned;
+        }
+        if tokens == 0 {
+            held_back += 1;
+            tracing::warn!("slack rate limit reached, dropping notification");
+            metrics::counter!("slack_notifications_dropped_total").increment(1);
+            continue;
+        }
+        tokens -= 1;
+
+        if held_back > 0 {
+            let text = format!("{}\n_{held_back} more matching orders were not posted, over SLACK_MAX_PER_MINUTE_", message["text"].as_str().unwrap_or_default());
+            message["text"] = json!(text);
+            held_back = 0;
+        }
+        post_message(&client, &url, &message).await;
+    }
+}
+
+async fn post_message(client: &reqwest::Client, url: &reqwest::Url, message: &serde_json::Value) {
+    for attempt in 1..=SLACK_MAX_ATTEMPTS {
+        let error = match client.post(url.clone()).header(CONTENT_TYPE, "application/json").body(message.to_string()).send().await {
+            Ok(response) if response.status().is_success() => {
+                metrics::counter!("slack_notifications_sent_total").increment(1);
+                return;
+            }
+            Ok(response) => format!("slack answered {}", response.status()),
+            Err(err) => err.without_url().to_string(),
+        };
+        if attempt == SLACK_MAX_ATTEMPTS {
+            tracing::error!(attempt, error, "slack notification failed, giving up");
+            metrics::counter!("slack_notifications_failed_total").increment(1);
+            return;
+        }
+        tracing::warn!(attempt, error, "slack notification failed, retrying");
+        tokio::time::sleep(SLACK_RETRY_AFTER).await;
+    }
+}
+
+/// An incoming-webhook body in Slack's mrkdwn: the order, who it is for,
+/// why it was posted, every line and the total.
+pub(crate) fn slack_message(detail: &OrderDetail, reasons: &[String]) -> serde_json::Value {
+    let order = &detail.order;
+    let mut text = format!(
+        "*Order #{}* for {} ({})\n",
+        order.id.unwrap_or_default(),
+        escape_mrkdwn(order.name.as_deref().unwrap_or("no name")),
+        reasons.join(", ")
+    );
+    for (line, amount) in detail.line_summaries() {
+        text.push_str(&format!("• {}  {amount}\n", escape_mrkdwn(&line)));
+    }
+    text.push_str(&format!("*Total: {}*", order.total));
+    json!({ "text": text })
+}
+
+/// The three characters Slack asks to be escaped in message text.
+fn escape_mrkdwn(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
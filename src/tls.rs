@@ -0,0 +1,59 @@
+//! HTTPS for deployments without a proxy in front: the certificate and key
+//! named by `TLS_CERT_PATH` and `TLS_KEY_PATH`, read at startup and again on
+//! every reload so a renewed certificate is served without a restart.
+use std::path::PathBuf;
+use std::sync::Arc;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+
+#[derive(Clone)]
+pub(crate) struct TlsCertificate {
+    pub(crate) server_config: Arc<ServerConfig>,
+    /// The PEM as read, which tells a rotated certificate from the same one.
+    pub(crate) cert_pem: Vec<u8>,
+}
+
+/// Both paths or neither; anything unreadable, or a key that does not
+/// belong to the certificate, is an error rather than a plain HTTP fallback.
+pub(crate) fn tls_from_env(errors: &mut Vec<String>) -> Option<TlsCertificate> {
+    let cert_path = std::env::var("TLS_CERT_PATH").ok().filter(|v| !v.is_empty());
+    let key_path = std::env::var("TLS_KEY_PATH").ok().filter(|v| !v.is_empty());
+    match (cert_path, key_path) {
+        (None, None) => None,
+        (Some(cert_path), Some(key_path)) => load_certificate(cert_path.into(), key_path.into())
+            .map_err(|err| errors.push(err))
+            .ok(),
+        _ => {
+            errors.push("set both TLS_CERT_PATH and TLS_KEY_PATH, or neither".to_owned());
+            None
+        }
+    }
+}
+
+fn load_certificate(cert_path: PathBuf, key_path: PathBuf) -> Result<TlsCertificate, String> {
+    let cert_pem = std::fs::read(&cert_path)
+        .map_err(|err| format!("TLS_CERT_PATH '{}' could not be read: {err}", cert_path.display()))?;
+    let certs = CertificateDer::pem_slice_iter(&cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("TLS_CERT_PATH '{}' is not a PEM certificate chain: {err}", cert_path.display()))?;
+    if certs.is_empty() {
+        return Err(format!("TLS_CERT_PATH '{}' contains no certificates", cert_path.display()));
+    }
+
+    let key_pem = std::fs::read(&key_path)
+        .map_err(|err| format!("TLS_KEY_PATH '{}' could not be read: {err}", key_path.display()))?;
+    let key = PrivateKeyDer::from_pem_slice(&key_pem)
+        .map_err(|err| format!("TLS_KEY_PATH '{}' is not a PEM private key: {err}", key_path.display()))?;
+
+    // with_single_cert checks the key against the certificate's public key
+    let mut server_config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|err| format!("TLS_CERT_PATH and TLS_KEY_PATH do not make a usable certificate: {err}"))?;
+    // offer HTTP/2 first, as browsers expect
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsCertificate { server_config: Arc::new(server_config), cert_pem })
+}
@@ -0,0 +1,116 @@
+//! Serving the router on a Unix domain socket, for a proxy on the same host.
+//! It runs next to the TCP listener rather than instead of it, and always in
+//! plain HTTP: TLS only applies to TCP.
+use std::future::Future;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use axum::Router;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto::Builder;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::UnixListener;
+
+/// How long to wait after a failed accept, e.g. out of file descriptors,
+/// before trying again; axum's own accept loop waits as long.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A bound socket, whose file goes when it stops serving or is dropped.
+pub struct UnixSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl UnixSocket {
+    /// Binds `path` with `mode` permissions, first removing a socket file a
+    /// crashed run left behind. A socket something still answers on is an
+    /// error rather than taken over.
+    pub async fn bind(path: &Path, mode: u32) -> std::io::Result<UnixSocket> {
+        match std::fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => {
+                if tokio::net::UnixStream::connect(path).await.is_ok() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AddrInUse,
+                        format!("{} is in use by another process", path.display()),
+                    ));
+                }
+                tracing::warn!(path = %path.display(), "removing stale unix socket");
+                std::fs::remove_file(path)?;
+            }
+            // never delete a regular file someone pointed LISTEN_UNIX_SOCKET at
+            Ok(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        let socket = UnixSocket { listener, path: path.to_owned() };
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        Ok(socket)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Serves `router` until `shutdown` resolves, then waits for open
+    /// connections to finish. Requests carry no peer address, so rate
+    /// limiting leaves them to the proxy.
+    pub async fn serve(self, router: Router, shutdown: impl Future<Output = ()>) -> std::io::Result<()> {
+        let graceful = GracefulShutdown::new();
+        let builder = Builder::new(TokioExecutor::new());
+        tokio::pin!(shutdown);
+        loop {
+            let stream = tokio::select! {
+                accepted = self.listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    // the peer gave up on this one connection, the next may be fine
+                    Err(err) if is_connection_error(&err) => continue,
+                    // a persistent error would otherwise spin, flooding the log
+                    Err(err) => {
+                        tracing::warn!(error = %err, "could not accept on the unix socket, retrying shortly");
+                        tokio::select! {
+                            () = tokio::time::sleep(ACCEPT_RETRY_DELAY) => continue,
+                            () = &mut shutdown => break,
+                        }
+                    }
+                },
+                () = &mut shutdown => break,
+            };
+            let service = TowerToHyperService::new(router.clone());
+            // with upgrades, so the order WebSocket works over the socket too
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service).into_owned();
+            let connection = graceful.watch(connection);
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    tracing::debug!(error = %err, "unix socket connection failed");
+                }
+            });
+        }
+
+        graceful.shutdown().await;
+        Ok(())
+    }
+}
+
+fn is_connection_error(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::ConnectionReset
+    )
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        // the drain deadline in `main` may drop the server mid-shutdown, so this runs then too
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            tracing::warn!(path = %self.path.display(), error = %err, "could not remove the unix socket");
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Signed webhook requests; the outbox decides what to send and when.
+use axum::http::{header::CONTENT_TYPE, HeaderName};
+use chrono::Utc;
+use sha2::Sha256;
+use hmac::{Hmac, Mac};
+
+use crate::Config;
+use crate::idempotency::hex;
+
+pub(crate) static X_WEBHOOK_ID: HeaderName = HeaderName::from_static("x-webhook-id");
+pub(crate) static X_WEBHOOK_EVENT: HeaderName = HeaderName::from_static("x-webhook-event");
+pub(crate) static X_WEBHOOK_TIMESTAMP: HeaderName = HeaderName::from_static("x-webhook-timestamp");
+pub(crate) static X_WEBHOOK_SIGNATURE: HeaderName = HeaderName::from_static("x-webhook-signature");
+
+/// What the outbox posts to every `WEBHOOK_URLS` target.
+#[derive(Clone)]
+pub(crate) struct WebhookClient {
+    client: reqwest::Client,
+    pub(crate) urls: Vec<reqwest::Url>,
+    secret: Option<String>,
+}
+
+impl WebhookClient {
+    /// `None` without `WEBHOOK_URLS`, when there is nobody to deliver to.
+    pub(crate) fn from_config(config: &Config) -> Option<WebhookClient> {
+        if config.webhook_urls.is_empty() {
+            return None;
+        }
+        if config.webhook_secret.is_none() {
+            tracing::warn!("WEBHOOK_SECRET is not set, webhook deliveries are unsigned");
+        }
+        let client = reqwest::Client::builder()
+            .timeout(config.webhook_timeout)
+            .build()
+            .expect("could not build webhook http client");
+        Some(WebhookClient { client, urls: config.webhook_urls.clone(), secret: config.webhook_secret.clone() })
+    }
+
+    /// One attempt; retrying is up to the caller. Anything but a 2xx is an
+    /// error, described well enough to store and show an admin.
+    pub(crate) async fn post(&self, url: &reqwest::Url, id: &str, event: &str, body: &str) -> Result<(), String> {
+        // signed per attempt so receivers can reject stale timestamps
+        let timestamp = Utc::now().timestamp().to_string();
+        let mut request = self
+            .client
+            .post(url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(&X_WEBHOOK_ID, id)
+            .header(&X_WEBHOOK_EVENT, event)
+            .header(&X_WEBHOOK_TIMESTAMP, &timestamp)
+            .body(body.to_owned());
+        if let Some(secret) = &self.secret {
+            request = request.header(&X_WEBHOOK_SIGNATURE, webhook_signature(secret, &timestamp, body.as_bytes()));
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("receiver answered {}", response.status())),
+            // reqwest's own message hides the cause (refused, dns, timeout) in its sources
+            Err(err) => Err(std::iter::successors(Some(&err.without_url() as &dyn std::error::Error), |err| err.source())
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": ")),
+        }
+    }
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `{timestamp}.{body}`.
+pub(crate) fn webhook_signature(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!("sha256={}", hex(&mac.finalize().into_bytes()))
+}
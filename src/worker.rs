@@ -0,0 +1,193 @@
+//! The background sweep that moves pending orders along without a client
+//! asking: long-waiting ones are flagged, then old enough ones accepted.
+use std::collections::HashMap;
+use std::time::Duration;
+use sqlx::PgConnection;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::feed::OrderEventKind;
+use crate::handlers::audit::order_diff;
+use crate::models::{OrderStatus, Orders, Size};
+use crate::{AppState, Config};
+
+/// The actor recorded on the audit events the worker writes.
+pub(crate) const WORKER_ACTOR: &str = "order-worker";
+/// Added to pending orders that have waited past `stale_after`.
+pub(crate) const STALE_TAG: &str = "stale";
+/// Orders locked per transaction, so a big backlog never holds many locks.
+const SWEEP_BATCH: i64 = 100;
+
+#[derive(Clone, Copy)]
+pub struct WorkerSettings {
+    /// Pending orders this old move to preparing. `None` leaves them.
+    pub accept_after: Option<Duration>,
+    /// Pending orders this old are tagged stale. `None` never flags.
+    pub stale_after: Option<Duration>,
+}
+
+pub struct SweepSummary {
+    pub accepted: usize,
+    pub flagged: usize,
+}
+
+/// Starts the worker, which sweeps every `WORKER_INTERVAL_SECS` until
+/// `shutdown` is cancelled. A sweep in progress finishes first, so await
+/// the handle before closing the pool.
+pub fn spawn_order_worker(state: AppState, config: &Config, shutdown: CancellationToken) -> JoinHandle<()> {
+    let interval = config.worker_interval;
+    let settings = config.worker;
+    tokio::spawn(async move {
+        let Some(every) = interval else {
+            tracing::info!("WORKER_INTERVAL_SECS is 0, order worker disabled");
+            return;
+        };
+        let mut interval = tokio::time::interval(every);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = interval.tick() => {}
+            }
+            match sweep_orders(&state, &settings).await {
+                Ok(summary) => tracing::info!(
+                    accepted = summary.accepted,
+                    flagged = summary.flagged,
+                    "order sweep finished"
+                ),
+                Err(err) => tracing::warn!(error = %err, "order sweep failed"),
+            }
+        }
+        tracing::info!("order worker stopped");
+    })
+}
+
+/// One pass over the pending orders. Rows are claimed with `SKIP LOCKED`,
+/// so replicas sweeping at the same time split the work instead of
+/// repeating it, and a request holding an order is never waited on.
+/// Stale orders are flagged before accepting, so one that sat too long is
+/// marked even when it is accepted in the same pass.
+pub async fn sweep_orders(state: &AppState, settings: &WorkerSettings) -> Result<SweepSummary, sqlx::Error> {
+    let mut summary = SweepSummary { accepted: 0, flagged: 0 };
+    if let Some(age) = settings.stale_after {
+        summary.flagged = sweep(state, age, Step::Flag).await?;
+    }
+    if let Some(age) = settings.accept_after {
+        summary.accepted = sweep(state, age, Step::Accept).await?;
+    }
+    Ok(summary)
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    Flag,
+    Accept,
+}
+
+/// Runs `step` in batches until no eligible orders are left.
+async fn sweep(state: &AppState, age: Duration, step: Step) -> Result<usize, sqlx::Error> {
+    let mut total = 0;
+    loop {
+        let mut tx = state.db.begin().await?;
+        let changed = apply(&mut tx, age, step).await?;
+        state.feed.outbox().enqueue(&mut tx, OrderEventKind::Updated, &changed).await?;
+        tx.commit().await?;
+
+        for order in &changed {
+            state.feed.publish(OrderEventKind::Updated, order);
+        }
+        total += changed.len();
+        if (changed.len() as i64) < SWEEP_BATCH {
+            return Ok(total);
+        }
+    }
+}
+
+/// One batch of `step`, with an audit event per order as the HTTP handlers
+/// write them.
+async fn apply(conn: &mut PgConnection, age: Duration, step: Step) -> Result<Vec<Orders>, sqlx::Error> {
+    let flag = matches!(step, Step::Flag);
+    let claimed: HashMap<i32, Orders> = sqlx::query_as!(
+        Orders,
+        r#"
+        SELECT id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+            status, cancellation_reason, version, created_at, updated_at, deleted_at
+        FROM orders
+        WHERE status = $1 AND deleted_at IS NULL AND created_at < now() - make_interval(secs => $2)
+          AND NOT ($3 AND tags @> ARRAY[$4::text])
+        ORDER BY id
+        LIMIT $5
+        FOR UPDATE SKIP LOCKED
+        "#,
+        OrderStatus::Pending.as_str(),
+        age.as_secs_f64(),
+        flag,
+        STALE_TAG,
+        SWEEP_BATCH
+    )
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .filter_map(|order| Some((order.id?, order)))
+    .collect();
+    if claimed.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ids: Vec<i32> = claimed.keys().copied().collect();
+    let (changed, action) = match step {
+        Step::Flag => (
+            sqlx::query_as!(
+                Orders,
+                r#"
+                UPDATE orders SET tags = array_append(tags, $2), updated_at = now(), version = version + 1
+                WHERE id = ANY($1)
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                "#,
+                &ids,
+                STALE_TAG
+            )
+            .fetch_all(&mut *conn)
+            .await?,
+            "updated",
+        ),
+        Step::Accept => (
+            sqlx::query_as!(
+                Orders,
+                r#"
+                UPDATE orders SET status = $2, updated_at = now(), version = version + 1
+                WHERE id = ANY($1)
+                RETURNING id, name, customer_id, coffee_name, size AS "size: Size", quantity, total, notes, tags,
+                    status, cancellation_reason, version, created_at, updated_at, deleted_at
+                "#,
+                &ids,
+                OrderStatus::Preparing.as_str()
+            )
+            .fetch_all(&mut *conn)
+            .await?,
+            "status_changed",
+        ),
+    };
+
+    let (event_ids, changes): (Vec<i32>, Vec<serde_json::Value>) = changed
+        .iter()
+        .filter_map(|order| {
+            let id = order.id?;
+            Some((id, order_diff(claimed.get(&id)?, order)))
+        })
+        .unzip();
+    sqlx::query!(
+        "
+        INSERT INTO order_events (order_id, action, actor, changes)
+        SELECT id, $3, $4, changes FROM UNNEST($1::int[], $2::jsonb[]) AS t(id, changes)
+        ",
+        &event_ids,
+        &changes,
+        action,
+        WORKER_ACTOR
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(changed)
+}
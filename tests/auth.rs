@@ -0,0 +1,64 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use sqlx::PgPool;
+
+use common::{app, create_order, send, ADMIN_KEY, BARISTA_KEY};
+
+#[sqlx::test]
+async fn only_admins_delete_orders(pool: PgPool) {
+    let app = app(pool);
+    let order = json!({ "name": "Ada", "coffee_name": "espresso", "size": "small" });
+    let id = create_order(&app, order).await["id"].as_i64().unwrap();
+    let uri = format!("/orders/{id}");
+
+    let anonymous = send(&app, Method::DELETE, &uri, None, None).await;
+    assert_eq!(anonymous.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(anonymous.body["status"], false);
+
+    let barista = send(&app, Method::DELETE, &uri, Some(BARISTA_KEY), None).await;
+    assert_eq!(barista.status, StatusCode::FORBIDDEN);
+    assert_eq!(barista.body["status"], false);
+    assert_eq!(barista.body["message"], "admin role required");
+    // refused before anything was deleted
+    let fetched = send(&app, Method::GET, &uri, Some(BARISTA_KEY), None).await;
+    assert_eq!(fetched.status, StatusCode::OK);
+
+    let admin = send(&app, Method::DELETE, &uri, Some(ADMIN_KEY), None).await;
+    assert_eq!(admin.status, StatusCode::OK);
+    assert_eq!(admin.body["message"], "order deleted");
+}
+
+#[sqlx::test]
+async fn only_admins_move_orders_in_bulk(pool: PgPool) {
+    let app = app(pool);
+    let order = json!({ "name": "Ada", "coffee_name": "espresso", "size": "small" });
+    let id = create_order(&app, order).await["id"].as_i64().unwrap();
+    let body = json!({ "ids": [id], "status": "preparing" });
+
+    let barista = send(&app, Method::POST, "/orders/status", Some(BARISTA_KEY), Some(body.clone())).await;
+    assert_eq!(barista.status, StatusCode::FORBIDDEN);
+    assert_eq!(barista.body["message"], "admin role required");
+    let fetched = send(&app, Method::GET, &format!("/orders/{id}"), Some(BARISTA_KEY), None).await;
+    assert_eq!(fetched.body["data"]["status"], "pending");
+
+    let admin = send(&app, Method::POST, "/orders/status", Some(ADMIN_KEY), Some(body)).await;
+    assert_eq!(admin.status, StatusCode::OK, "{}", admin.body);
+    assert_eq!(admin.body["data"]["updated"], json!([id]));
+}
+
+#[sqlx::test]
+async fn unknown_keys_are_unauthorized(pool: PgPool) {
+    let app = app(pool);
+    let response = send(&app, Method::GET, "/orders", Some("not-a-key"), None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body["message"], "invalid credentials");
+}
+
+#[sqlx::test]
+async fn probes_stay_open(pool: PgPool) {
+    let app = app(pool);
+    assert_eq!(send(&app, Method::GET, "/livez", None, None).await.status, StatusCode::OK);
+    assert_eq!(send(&app, Method::GET, "/health", None, None).await.status, StatusCode::OK);
+}
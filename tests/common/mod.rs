@@ -0,0 +1,104 @@
+//! Builds the router against a `#[sqlx::test]` database and drives it with
+//! `oneshot`, so every test gets its own freshly migrated database.
+#![allow(dead_code)]
+
+use std::sync::{Arc, OnceLock};
+
+use axum::body::Body;
+use axum::http::{header::CONTENT_TYPE, HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use rust_orders::grpc::proto::orders_service_client::OrdersServiceClient;
+use rust_orders::{build_router, serve_grpc, AppState, Config, MemoryOrderRepository};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+use tonic::transport::Channel;
+use tower::ServiceExt;
+
+pub const ADMIN_KEY: &str = "admin-key";
+pub const BARISTA_KEY: &str = "barista-key";
+
+/// The config every test runs with, read from the environment once so tests
+/// in the same binary never race on `set_var`.
+pub fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| {
+        std::env::set_var("API_KEYS", format!("admin:{ADMIN_KEY}:admin,barista:{BARISTA_KEY}:barista"));
+        std::env::remove_var("AUTH_DISABLED");
+        Config::from_env().unwrap_or_else(|errors| panic!("test config is invalid: {errors:?}"))
+    })
+}
+
+pub fn state(pool: PgPool) -> AppState {
+    // a recorder that is never installed, so tests do not share one global
+    let metrics = PrometheusBuilder::new().build_recorder().handle();
+    AppState::new(pool, metrics, config())
+}
+
+pub fn app(pool: PgPool) -> Router {
+    build_router(state(pool), config())
+}
+
+/// The router reading from `replica` wherever it may.
+pub fn replica_app(pool: PgPool, replica: PgPool) -> Router {
+    build_router(state(pool).with_read_pool(replica), config())
+}
+
+/// The router with orders held in `orders` and a pool that never connects,
+/// for tests that must not reach a database.
+pub fn memory_app(orders: Arc<MemoryOrderRepository>) -> Router {
+    let pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+    build_router(state(pool).with_memory_orders(orders), config())
+}
+
+/// Serves gRPC on a free port for the rest of the test and connects to it.
+pub async fn grpc_client(pool: PgPool) -> OrdersServiceClient<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_grpc(listener, state(pool), config(), std::future::pending()));
+    OrdersServiceClient::connect(format!("http://{addr}")).await.unwrap()
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The JSON body, or the raw text as a string when it is not JSON.
+    pub body: Value,
+}
+
+pub async fn send(app: &Router, method: Method, uri: &str, key: Option<&str>, body: Option<Value>) -> TestResponse {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(key) = key {
+        request = request.header("x-api-key", key);
+    }
+    let request = match body {
+        Some(body) => request
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+    send_request(app, request).await
+}
+
+pub async fn send_request(app: &Router, request: Request<Body>) -> TestResponse {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    TestResponse { status, headers, body }
+}
+
+pub fn flat_white(name: &str) -> Value {
+    json!({ "name": name, "coffee_name": "flat white", "size": "medium" })
+}
+
+/// Creates an order as the admin key and returns the created order.
+pub async fn create_order(app: &Router, order: Value) -> Value {
+    let response = send(app, Method::POST, "/orders", Some(ADMIN_KEY), Some(order)).await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+    response.body["data"].clone()
+}
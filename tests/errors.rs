@@ -0,0 +1,123 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::body::Body;
+use axum::http::{header::CONTENT_TYPE, Method, Request, StatusCode};
+use serde_json::json;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::PgPool;
+
+use common::{app, create_order, flat_white, send, send_request, ADMIN_KEY, BARISTA_KEY};
+
+#[sqlx::test]
+async fn a_missing_row_is_not_found(pool: PgPool) {
+    let app = app(pool.clone());
+    let set = send(&app, Method::PATCH, "/inventory/espresso", Some(ADMIN_KEY), Some(json!({ "stock": 5 }))).await;
+    assert_eq!(set.status, StatusCode::OK, "{}", set.body);
+
+    // the row is found and locked, then the update skips it
+    sqlx::raw_sql(
+        "CREATE FUNCTION skip_update() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN RETURN NULL; END $$;
+        CREATE TRIGGER skip_update BEFORE UPDATE ON inventory FOR EACH ROW EXECUTE FUNCTION skip_update();",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let response = send(&app, Method::PATCH, "/inventory/espresso", Some(ADMIN_KEY), Some(json!({ "adjust": 1 }))).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(response.body["message"], "record not found");
+}
+
+#[sqlx::test]
+async fn a_unique_violation_is_a_conflict_naming_the_constraint(pool: PgPool) {
+    let app = app(pool.clone());
+    sqlx::query("CREATE UNIQUE INDEX customers_email_key ON customers (email)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let ada = json!({ "name": "Ada", "email": "ada@example.com" });
+    assert_eq!(send(&app, Method::POST, "/customers", Some(BARISTA_KEY), Some(ada)).await.status, StatusCode::CREATED);
+
+    let grace = json!({ "name": "Grace", "email": "ada@example.com" });
+    let response = send(&app, Method::POST, "/customers", Some(BARISTA_KEY), Some(grace)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(response.body["message"], "conflicts with an existing record (constraint customers_email_key)");
+}
+
+#[sqlx::test]
+async fn a_foreign_key_violation_is_unprocessable_naming_the_constraint(pool: PgPool) {
+    let app = app(pool.clone());
+    sqlx::raw_sql(
+        "CREATE TABLE actors (name TEXT PRIMARY KEY);
+        ALTER TABLE order_events ADD CONSTRAINT order_events_actor_fkey FOREIGN KEY (actor) REFERENCES actors (name);",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let response = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["message"], "refers to a missing record (constraint order_events_actor_fkey)");
+}
+
+#[sqlx::test]
+async fn an_exhausted_pool_is_unavailable_with_retry_after(pool_options: PgPoolOptions, connect_options: PgConnectOptions) {
+    let pool = pool_options
+        .max_connections(1)
+        .acquire_timeout(Duration::from_millis(200))
+        .connect_with(connect_options)
+        .await
+        .unwrap();
+    let app = app(pool.clone());
+    let _held = pool.acquire().await.unwrap();
+
+    let response = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers["retry-after"], "1");
+    assert_eq!(response.body["message"], "database is busy");
+}
+
+#[sqlx::test]
+async fn other_database_errors_stay_internal(pool: PgPool) {
+    let app = app(pool.clone());
+    create_order(&app, flat_white("Ada")).await;
+    sqlx::query("ALTER TABLE orders RENAME COLUMN coffee_name TO drink").execute(&pool).await.unwrap();
+
+    let response = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.body["message"], "internal server error");
+}
+
+#[sqlx::test]
+async fn oversized_bodies_get_the_json_413(pool: PgPool) {
+    let app = app(pool);
+    let order = create_order(&app, flat_white("Ada")).await;
+    // whitespace is valid JSON, so only the size differs from a good body
+    let padded = |json: String| format!("{}{json}", " ".repeat(100 * 1024));
+    let post = |uri: String, body: String| {
+        Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header("x-api-key", ADMIN_KEY)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let response = send_request(&app, post("/orders".to_owned(), padded(flat_white("Grace").to_string()))).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.body["status"], false);
+    assert_eq!(response.body["message"], "request body is too large");
+
+    // an optional body, buffered before it is decoded
+    let cancel = format!("/orders/{}/cancel", order["id"]);
+    let response = send_request(&app, post(cancel, padded("{}".to_owned()))).await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.body["message"], "request body is too large");
+
+    let batch = json!([flat_white("Grace")]).to_string();
+    let response = send_request(&app, post("/orders/batch".to_owned(), padded(batch))).await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+}
@@ -0,0 +1,208 @@
+//! Exports need EXPORT_S3_BUCKET set before the config is read, so they
+//! have a binary to themselves. A small in-process stand-in for S3 takes
+//! the uploads.
+mod common;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, Query, State};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::put;
+use axum::Router;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+
+use rust_orders::build_router;
+
+use common::{app, config, create_order, flat_white, send, state, ADMIN_KEY, BARISTA_KEY};
+
+/// Parts by number, per upload id.
+type Uploads = HashMap<String, BTreeMap<usize, Vec<u8>>>;
+
+#[derive(Clone, Default)]
+struct Bucket {
+    objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    uploads: Arc<Mutex<Uploads>>,
+    /// Parts in every completed multipart upload.
+    completed_parts: Arc<Mutex<Vec<usize>>>,
+    down: Arc<AtomicBool>,
+    /// Parts never finish uploading while set.
+    stall_parts: Arc<AtomicBool>,
+    aborted: Arc<Mutex<Vec<String>>>,
+}
+
+async fn object(
+    State(bucket): State<Bucket>,
+    Path((name, key)): Path<(String, String)>,
+    Query(query): Query<HashMap<String, String>>,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl IntoResponse {
+    let authorization = headers["authorization"].to_str().unwrap();
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=test-key/"), "{authorization}");
+    let payload_hash = format!("{:x}", Sha256::digest(&body));
+    assert_eq!(headers["x-amz-content-sha256"].to_str().unwrap(), payload_hash);
+    assert_eq!(name, "exports");
+    if bucket.down.load(Ordering::SeqCst) {
+        return (StatusCode::INTERNAL_SERVER_ERROR, HeaderMap::new(), "<Error><Code>InternalError</Code></Error>".to_owned());
+    }
+
+    let mut reply = HeaderMap::new();
+    let upload_id = query.get("uploadId").cloned();
+    let text = match (method, upload_id) {
+        (Method::POST, None) if query.contains_key("uploads") => {
+            let id = format!("upload-{}", bucket.uploads.lock().unwrap().len() + 1);
+            bucket.uploads.lock().unwrap().insert(id.clone(), BTreeMap::new());
+            format!("<InitiateMultipartUploadResult><UploadId>{id}</UploadId></InitiateMultipartUploadResult>")
+        }
+        (Method::PUT, Some(_)) if bucket.stall_parts.load(Ordering::SeqCst) => std::future::pending().await,
+        (Method::PUT, Some(id)) => {
+            let part: usize = query["partNumber"].parse().unwrap();
+            bucket.uploads.lock().unwrap().get_mut(&id).unwrap().insert(part, body.to_vec());
+            reply.insert("etag", format!("\"etag-{part}\"").parse().unwrap());
+            String::new()
+        }
+        (Method::POST, Some(id)) => {
+            let parts = bucket.uploads.lock().unwrap().remove(&id).unwrap();
+            bucket.completed_parts.lock().unwrap().push(parts.len());
+            bucket.objects.lock().unwrap().insert(key, parts.into_values().flatten().collect());
+            "<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".to_owned()
+        }
+        (Method::DELETE, Some(id)) => {
+            bucket.uploads.lock().unwrap().remove(&id);
+            bucket.aborted.lock().unwrap().push(id);
+            String::new()
+        }
+        (Method::PUT, None) => {
+            bucket.objects.lock().unwrap().insert(key, body.to_vec());
+            String::new()
+        }
+        (method, _) => panic!("unexpected {method} {key}"),
+    };
+    (StatusCode::OK, reply, text)
+}
+
+/// The export runs once none of them is still running.
+async fn finished_runs(app: &Router) -> Value {
+    for _ in 0..200 {
+        let runs = send(app, Method::GET, "/admin/exports", Some(ADMIN_KEY), None).await;
+        assert_eq!(runs.status, StatusCode::OK, "{}", runs.body);
+        if runs.body["data"].as_array().unwrap().iter().all(|run| run["status"] != "running") {
+            return runs.body["data"].clone();
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("exports did not finish");
+}
+
+#[sqlx::test]
+async fn exports_land_each_day_once_unless_forced(pool: PgPool) {
+    let bucket = Bucket::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let routes = Router::new()
+        .route("/:bucket/*key", put(object).post(object).delete(object))
+        .layer(DefaultBodyLimit::disable())
+        .with_state(bucket.clone());
+    tokio::spawn(async move { axum::serve(listener, routes).await.unwrap() });
+    std::env::set_var("EXPORT_S3_BUCKET", "exports");
+    std::env::set_var("EXPORT_S3_ENDPOINT", format!("http://{addr}"));
+    std::env::set_var("EXPORT_S3_ACCESS_KEY_ID", "test-key");
+    std::env::set_var("EXPORT_S3_SECRET_ACCESS_KEY", "test-secret");
+    let app = app(pool.clone());
+
+    let first = create_order(&app, flat_white("Ada")).await;
+    let second = create_order(&app, flat_white("Grace")).await;
+    create_order(&app, flat_white("Linus")).await;
+    sqlx::query("UPDATE orders SET created_at = CASE WHEN id = $1 THEN '2024-05-21T23:59:00Z'::timestamptz ELSE '2024-05-22T00:00:00Z' END")
+        .bind(first["id"].as_i64().unwrap() as i32)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let second_id = second["id"].as_i64().unwrap() as i32;
+    sqlx::query("UPDATE orders SET deleted_at = now() WHERE id = $1").bind(second_id).execute(&pool).await.unwrap();
+
+    let forbidden = send(&app, Method::POST, "/admin/exports/run", Some(BARISTA_KEY), None).await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+    let today = chrono::Utc::now().date_naive();
+    let unfinished = send(&app, Method::POST, &format!("/admin/exports/run?to={today}"), Some(ADMIN_KEY), None).await;
+    assert_eq!(unfinished.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let backwards = send(&app, Method::POST, "/admin/exports/run?from=2024-05-22&to=2024-05-21", Some(ADMIN_KEY), None).await;
+    assert_eq!(backwards.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let started = send(&app, Method::POST, "/admin/exports/run?from=2024-05-21&to=2024-05-22", Some(ADMIN_KEY), None).await;
+    assert_eq!(started.status, StatusCode::ACCEPTED, "{}", started.body);
+    assert_eq!(started.body["data"]["started"].as_array().unwrap().len(), 2);
+    let runs = finished_runs(&app).await;
+    assert!(runs.as_array().unwrap().iter().all(|run| run["status"] == "succeeded"), "{runs}");
+    // soft-deleted orders stay out of the file
+    assert_eq!(runs[0]["object_key"], "orders/2024-05-22.csv");
+    assert_eq!(runs[0]["row_count"], 1);
+    let objects = bucket.objects.lock().unwrap().clone();
+    let file = String::from_utf8(objects["orders/2024-05-21.csv"].clone()).unwrap();
+    let lines: Vec<&str> = file.lines().collect();
+    assert_eq!(lines.len(), 2, "{file}");
+    assert!(lines[0].starts_with("id,name,coffee_name"), "{file}");
+    assert!(lines[1].starts_with(&format!("{},Ada,flat white", first["id"])), "{file}");
+
+    // done days are skipped, unless forced
+    let again = send(&app, Method::POST, "/admin/exports/run?from=2024-05-21", Some(ADMIN_KEY), None).await;
+    assert_eq!(again.body["data"]["started"].as_array().unwrap().len(), 0);
+    assert_eq!(again.body["data"]["skipped"], serde_json::json!(["2024-05-21"]));
+    bucket.down.store(true, Ordering::SeqCst);
+    let forced = send(&app, Method::POST, "/admin/exports/run?from=2024-05-21&force=true", Some(ADMIN_KEY), None).await;
+    assert_eq!(forced.body["data"]["started"][0]["forced"], true);
+    let runs = finished_runs(&app).await;
+    assert_eq!(runs[0]["status"], "failed");
+    assert!(runs[0]["error"].as_str().unwrap().contains("500"), "{}", runs[0]);
+    bucket.down.store(false, Ordering::SeqCst);
+
+    // a day too big for one part goes up as a multipart upload
+    sqlx::query(
+        "
+        INSERT INTO orders (name, coffee_name, size, total, created_at)
+        SELECT 'Customer ' || n, 'flat white', 'medium', 4.00, '2024-05-23T08:00:00Z'::timestamptz + n * interval '1 ms'
+        FROM generate_series(1, 100000) AS n
+        ",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let big = send(&app, Method::POST, "/admin/exports/run?from=2024-05-23", Some(ADMIN_KEY), None).await;
+    assert_eq!(big.status, StatusCode::ACCEPTED);
+    let runs = finished_runs(&app).await;
+    assert_eq!(runs[0]["status"], "succeeded", "{}", runs[0]);
+    assert_eq!(runs[0]["row_count"], 100000);
+    assert_eq!(*bucket.completed_parts.lock().unwrap(), [2]);
+    let file = bucket.objects.lock().unwrap()["orders/2024-05-23.csv"].clone();
+    assert_eq!(runs[0]["byte_count"], file.len());
+    assert_eq!(file.iter().filter(|byte| **byte == b'\n').count(), 100001);
+
+    // shutdown mid-upload aborts it and fails the run, not leaving it running
+    let state = state(pool.clone());
+    let app = build_router(state.clone(), config());
+    bucket.stall_parts.store(true, Ordering::SeqCst);
+    let cut = send(&app, Method::POST, "/admin/exports/run?from=2024-05-23&force=true", Some(ADMIN_KEY), None).await;
+    assert_eq!(cut.status, StatusCode::ACCEPTED);
+    for _ in 0..200 {
+        if !bucket.uploads.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    state.shutting_down().cancel();
+    tokio::time::timeout(Duration::from_secs(10), state.finish_exports()).await.unwrap();
+    let runs = finished_runs(&app).await;
+    assert_eq!(runs[0]["status"], "failed", "{}", runs[0]);
+    assert_eq!(runs[0]["error"], "interrupted by shutdown");
+    assert_eq!(bucket.aborted.lock().unwrap().len(), 1);
+    assert!(bucket.uploads.lock().unwrap().is_empty());
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use common::{app, create_order, flat_white, send, ADMIN_KEY, BARISTA_KEY};
+
+async fn graphql(app: &Router, key: &str, query: &str, variables: Value) -> Value {
+    let body = json!({ "query": query, "variables": variables });
+    let response = send(app, Method::POST, "/graphql", Some(key), Some(body)).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    response.body
+}
+
+#[sqlx::test]
+async fn orders_resolve_with_customers_and_items(pool: PgPool) {
+    let app = app(pool);
+    create_order(&app, flat_white("Ada")).await;
+    create_order(&app, flat_white("Grace")).await;
+
+    let body = graphql(
+        &app,
+        BARISTA_KEY,
+        "query($filter: OrderFilterInput) {
+            orders(filter: $filter, limit: 10) {
+                total
+                orders { name total customer { name } items { coffeeName size quantity unitPrice } }
+            }
+        }",
+        json!({ "filter": { "coffeeName": "flat white" } }),
+    )
+    .await;
+    assert!(body["errors"].is_null(), "{body}");
+    let page = &body["data"]["orders"];
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["orders"][0]["customer"]["name"], "Ada");
+    assert_eq!(page["orders"][1]["customer"]["name"], "Grace");
+    assert_eq!(page["orders"][0]["items"][0]["coffeeName"], "flat white");
+    assert_eq!(page["orders"][0]["items"][0]["unitPrice"], page["orders"][0]["total"]);
+
+    let body = graphql(&app, BARISTA_KEY, "{ order(id: 999) { id } }", json!({})).await;
+    assert!(body["data"]["order"].is_null(), "{body}");
+}
+
+#[sqlx::test]
+async fn order_mutations_reuse_rest_validation(pool: PgPool) {
+    let app = app(pool);
+
+    let body = graphql(
+        &app,
+        ADMIN_KEY,
+        "mutation($input: CreateOrderInput!) { createOrder(input: $input) { id status tags } }",
+        json!({ "input": { "name": "Ada", "coffeeName": "flat white", "size": "medium", "tags": ["Loyalty"] } }),
+    )
+    .await;
+    let created = &body["data"]["createOrder"];
+    assert_eq!(created["status"], "pending", "{body}");
+    assert_eq!(created["tags"], json!(["loyalty"]));
+    let id = created["id"].clone();
+
+    let body = graphql(
+        &app,
+        BARISTA_KEY,
+        "mutation($id: Int!) { updateOrder(id: $id, input: { status: \"preparing\", notes: \"oat milk\" }) { status notes } }",
+        json!({ "id": id }),
+    )
+    .await;
+    assert_eq!(body["data"]["updateOrder"], json!({ "status": "preparing", "notes": "oat milk" }), "{body}");
+
+    let body = graphql(
+        &app,
+        ADMIN_KEY,
+        "mutation { createOrder(input: { name: \"Ada\", coffeeName: \"flat white\", size: \"huge\" }) { id } }",
+        json!({}),
+    )
+    .await;
+    let error = &body["errors"][0];
+    assert_eq!(error["extensions"]["status"], 422, "{body}");
+    assert_eq!(error["extensions"]["code"], "UNPROCESSABLE_ENTITY");
+    assert_eq!(error["extensions"]["fields"][0]["field"], "size");
+
+    let body = graphql(&app, BARISTA_KEY, "mutation($id: Int!) { deleteOrder(id: $id) { id } }", json!({ "id": id })).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN", "{body}");
+
+    let body = graphql(&app, ADMIN_KEY, "mutation($id: Int!) { deleteOrder(id: $id) { id } }", json!({ "id": id })).await;
+    assert_eq!(body["data"]["deleteOrder"]["id"], id, "{body}");
+
+    let body = graphql(&app, ADMIN_KEY, "mutation($id: Int!) { deleteOrder(id: $id) { id } }", json!({ "id": id })).await;
+    assert_eq!(body["errors"][0]["extensions"]["status"], 404, "{body}");
+    assert_eq!(body["errors"][0]["extensions"]["code"], "NOT_FOUND");
+}
+
+#[sqlx::test]
+async fn graphql_requires_credentials(pool: PgPool) {
+    let app = app(pool);
+    let body = json!({ "query": "{ orders { total } }" });
+    let response = send(&app, Method::POST, "/graphql", None, Some(body)).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(response.body["message"], "missing credentials");
+
+    // development serves the playground
+    let response = send(&app, Method::GET, "/graphql", None, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{grpc_client, ADMIN_KEY, BARISTA_KEY};
+use rust_orders::grpc::proto::{
+    CreateOrderRequest, DeleteOrderRequest, GetOrderRequest, ListOrdersRequest, UpdateOrderRequest,
+};
+use sqlx::PgPool;
+use tonic::{Code, Request};
+
+fn with_key<T>(message: T, key: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("x-api-key", key.parse().unwrap());
+    request
+}
+
+fn flat_white(name: &str) -> CreateOrderRequest {
+    CreateOrderRequest {
+        name: Some(name.to_owned()),
+        coffee_name: "flat white".to_owned(),
+        size: "medium".to_owned(),
+        ..Default::default()
+    }
+}
+
+#[sqlx::test]
+async fn orders_can_be_managed_over_grpc(pool: PgPool) {
+    let mut client = grpc_client(pool).await;
+
+    let created = client
+        .create_order(with_key(CreateOrderRequest { tags: vec!["Loyalty".to_owned()], ..flat_white("Ada") }, ADMIN_KEY))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(created.name, "Ada");
+    assert_eq!(created.status, "pending");
+    assert_eq!(created.tags, ["loyalty"]);
+
+    let fetched = client.get_order(with_key(GetOrderRequest { id: created.id }, BARISTA_KEY)).await.unwrap().into_inner();
+    assert_eq!(fetched, created);
+
+    let updated = client
+        .update_order(with_key(
+            UpdateOrderRequest { id: created.id, status: Some("preparing".to_owned()), ..Default::default() },
+            BARISTA_KEY,
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(updated.status, "preparing");
+    assert_eq!(updated.tags, ["loyalty"], "tags are kept unless set_tags is true");
+
+    let listed = client
+        .list_orders(with_key(ListOrdersRequest { tags: vec!["loyalty".to_owned()], ..Default::default() }, BARISTA_KEY))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(listed.total, 1);
+    assert_eq!(listed.orders[0].id, created.id);
+
+    client
+        .delete_order(with_key(DeleteOrderRequest { id: created.id, hard: false }, ADMIN_KEY))
+        .await
+        .unwrap();
+    let missing = client.get_order(with_key(GetOrderRequest { id: created.id }, BARISTA_KEY)).await.unwrap_err();
+    assert_eq!(missing.code(), Code::NotFound);
+}
+
+#[sqlx::test]
+async fn grpc_errors_map_to_status_codes(pool: PgPool) {
+    let mut client = grpc_client(pool).await;
+
+    let unauthenticated = client.get_order(Request::new(GetOrderRequest { id: 1 })).await.unwrap_err();
+    assert_eq!(unauthenticated.code(), Code::Unauthenticated);
+
+    let invalid = client
+        .create_order(with_key(CreateOrderRequest { size: "huge".to_owned(), ..flat_white("Ada") }, ADMIN_KEY))
+        .await
+        .unwrap_err();
+    assert_eq!(invalid.code(), Code::InvalidArgument);
+    assert!(invalid.message().contains("size"), "{}", invalid.message());
+
+    let bad_limit = client
+        .list_orders(with_key(ListOrdersRequest { limit: Some(0), ..Default::default() }, ADMIN_KEY))
+        .await
+        .unwrap_err();
+    assert_eq!(bad_limit.code(), Code::InvalidArgument);
+
+    let created = client.create_order(with_key(flat_white("Grace"), ADMIN_KEY)).await.unwrap().into_inner();
+    let forbidden = client
+        .delete_order(with_key(DeleteOrderRequest { id: created.id, hard: false }, BARISTA_KEY))
+        .await
+        .unwrap_err();
+    assert_eq!(forbidden.code(), Code::PermissionDenied);
+}
+
+#[sqlx::test]
+async fn invalid_status_changes_fail_the_precondition(pool: PgPool) {
+    let mut client = grpc_client(pool).await;
+    let created = client.create_order(with_key(flat_white("Grace"), ADMIN_KEY)).await.unwrap().into_inner();
+
+    let skipped = client
+        .update_order(with_key(
+            UpdateOrderRequest { id: created.id, status: Some("completed".to_owned()), ..Default::default() },
+            ADMIN_KEY,
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(skipped.code(), Code::FailedPrecondition, "{}", skipped.message());
+    assert_eq!(skipped.message(), "cannot change status from pending to completed");
+
+    let fetched = client.get_order(with_key(GetOrderRequest { id: created.id }, BARISTA_KEY)).await.unwrap().into_inner();
+    assert_eq!(fetched.status, "pending");
+}
@@ -0,0 +1,37 @@
+//! Reads its own config from the environment, so it has a binary to itself.
+use rust_orders::{bind_http_listeners, Config};
+
+#[tokio::test]
+async fn binds_every_address_in_bind_addrs() {
+    std::env::set_var("AUTH_DISABLED", "true");
+    std::env::set_var("BIND_ADDRS", "127.0.0.1:0, [::1]:0");
+    let config = Config::from_env().unwrap_or_else(|errors| panic!("{errors:?}"));
+
+    let listeners = bind_http_listeners(&config).await.unwrap();
+    let addrs: Vec<_> = listeners.iter().map(|lis| lis.local_addr().unwrap()).collect();
+    assert_eq!(addrs.len(), 2);
+    assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6(), "{addrs:?}");
+
+    // the IPv6 wildcard is IPv6 only, so it shares a port with the IPv4 one
+    let port = addrs[0].port();
+    std::env::set_var("BIND_ADDRS", format!("[::]:{port}"));
+    let config = Config::from_env().unwrap_or_else(|errors| panic!("{errors:?}"));
+    assert_eq!(bind_http_listeners(&config).await.unwrap().len(), 1);
+
+    // a taken address fails by name
+    let taken = addrs[1];
+    std::env::set_var("BIND_ADDRS", taken.to_string());
+    let config = Config::from_env().unwrap_or_else(|errors| panic!("{errors:?}"));
+    let err = bind_http_listeners(&config).await.err().unwrap();
+    assert!(err.starts_with(&format!("could not listen on {taken}: ")), "{err}");
+
+    std::env::set_var("BIND_ADDRS", "0.0.0.0:3000,localhost:3000,0.0.0.0:3000");
+    let errors = Config::from_env().err().unwrap();
+    assert_eq!(
+        errors,
+        [
+            "BIND_ADDRS has an invalid address 'localhost:3000', expected ip:port such as [::]:3000",
+            "BIND_ADDRS lists '0.0.0.0:3000' twice",
+        ]
+    );
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::Value;
+use sqlx::PgPool;
+use utoipa::openapi::{OpenApi, OpenApiVersion};
+
+use common::{app, send};
+
+/// Every `$ref` in the document, wherever it appears.
+fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::String(target)) = map.get("$ref") {
+                found.push(target);
+            }
+            map.values().for_each(|value| refs(value, found));
+        }
+        Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+        _ => {}
+    }
+}
+
+#[sqlx::test]
+async fn spec_references_resolve(pool: PgPool) {
+    let app = app(pool);
+    let response = send(&app, Method::GET, "/api-docs/openapi.json", None, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let spec = response.body;
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(spec["paths"]["/orders/{id}"]["get"].is_object());
+
+    let mut found = Vec::new();
+    refs(&spec, &mut found);
+    assert!(!found.is_empty());
+    for target in found {
+        let pointer = target.strip_prefix('#').unwrap_or_else(|| panic!("external $ref {target}"));
+        assert!(spec.pointer(pointer).is_some(), "$ref {target} does not resolve");
+    }
+}
+
+#[sqlx::test]
+async fn spec_parses_as_openapi_3(pool: PgPool) {
+    let app = app(pool);
+    let response = send(&app, Method::GET, "/api-docs/openapi.json", None, None).await;
+    assert_eq!(response.headers["content-type"], "application/json");
+    let spec: OpenApi = serde_json::from_value(response.body).expect("spec is not a valid OpenAPI document");
+    assert!(matches!(spec.openapi, OpenApiVersion::Version3));
+    assert!(!spec.info.title.is_empty());
+
+    let components = spec.components.expect("spec has no components");
+    for schema in ["CreateOrdersReq", "UpdateOrdersReq", "ErrorBody", "ValidationErrorBody"] {
+        assert!(components.schemas.contains_key(schema), "{schema} is missing");
+    }
+    for (path, item) in &spec.paths.paths {
+        assert!(!item.operations.is_empty(), "{path} has no operations");
+        for operation in item.operations.values() {
+            assert!(!operation.responses.responses.is_empty(), "an operation on {path} documents no responses");
+        }
+    }
+}
@@ -0,0 +1,831 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{header::CONTENT_TYPE, Method, Request, StatusCode};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tower::ServiceExt;
+
+use common::{app, create_order, flat_white, send, send_request, ADMIN_KEY, BARISTA_KEY};
+
+#[sqlx::test]
+async fn order_crud_flow(pool: PgPool) {
+    let app = app(pool);
+
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    let id = created.body["data"]["id"].as_i64().unwrap();
+    assert_eq!(created.headers["location"], format!("/orders/{id}").as_str());
+    assert_eq!(created.body["data"]["total"], "4.00");
+    assert_eq!(created.body["data"]["status"], "pending");
+
+    let list = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(list.status, StatusCode::OK);
+    assert_eq!(list.body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(list.body["data"][0]["id"], id);
+    assert_eq!(list.headers["x-total-count"], "1");
+
+    let fetched = send(&app, Method::GET, &format!("/orders/{id}"), Some(BARISTA_KEY), None).await;
+    assert_eq!(fetched.status, StatusCode::OK);
+    assert_eq!(fetched.body["data"]["name"], "Ada");
+
+    let updated = send(
+        &app,
+        Method::PUT,
+        &format!("/orders/{id}"),
+        Some(BARISTA_KEY),
+        Some(json!({ "name": "Ada", "coffee_name": "cappuccino", "size": "large" })),
+    )
+    .await;
+    assert_eq!(updated.status, StatusCode::OK, "{}", updated.body);
+    assert_eq!(updated.body["data"]["coffee_name"], "cappuccino");
+    assert_eq!(updated.body["data"]["total"], "4.50");
+
+    let deleted = send(&app, Method::DELETE, &format!("/orders/{id}"), Some(ADMIN_KEY), None).await;
+    assert_eq!(deleted.status, StatusCode::OK);
+
+    let gone = send(&app, Method::GET, &format!("/orders/{id}"), Some(BARISTA_KEY), None).await;
+    assert_eq!(gone.status, StatusCode::NOT_FOUND);
+    assert_eq!(gone.body["status"], false);
+    assert_eq!(gone.body["message"], "order not found");
+
+    let patch = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(json!({ "name": "Ada" }))).await;
+    assert_eq!(patch.status, StatusCode::NOT_FOUND);
+
+    let list = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(list.body["data"], json!([]));
+}
+
+#[sqlx::test]
+async fn invalid_json_is_rejected(pool: PgPool) {
+    let app = app(pool);
+    let request = Request::post("/orders")
+        .header("x-api-key", BARISTA_KEY)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"name":"#))
+        .unwrap();
+
+    let response = send_request(&app, request).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.body["status"], false);
+    assert!(response.body["message"].as_str().unwrap().contains("JSON"));
+}
+
+#[sqlx::test]
+async fn missing_fields_are_listed(pool: PgPool) {
+    let app = app(pool);
+    let response = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(json!({ "name": "Ada" }))).await;
+
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<&str> = response.body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["coffee_name", "size"]);
+}
+
+#[sqlx::test]
+async fn non_numeric_id_is_a_bad_request(pool: PgPool) {
+    let app = app(pool);
+    let methods = [Method::GET, Method::PUT, Method::PATCH, Method::DELETE];
+    for id in ["abc", "-1", "0", "2147483648", "1.5"] {
+        for method in methods.clone() {
+            let body = (method == Method::PUT || method == Method::PATCH).then(|| flat_white("Ada"));
+            let response = send(&app, method.clone(), &format!("/orders/{id}"), Some(ADMIN_KEY), body).await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "{method} /orders/{id}");
+            assert_eq!(response.body["status"], false);
+            assert_eq!(response.body["message"], "order id must be a positive integer");
+        }
+    }
+}
+
+#[sqlx::test]
+async fn updating_a_missing_order_is_not_found(pool: PgPool) {
+    let app = app(pool);
+    let put = send(&app, Method::PUT, "/orders/999", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(put.status, StatusCode::NOT_FOUND);
+
+    let patch = send(&app, Method::PATCH, "/orders/999", Some(BARISTA_KEY), Some(json!({ "name": "Ada" }))).await;
+    assert_eq!(patch.status, StatusCode::NOT_FOUND);
+    assert_eq!(patch.body["message"], "order not found");
+}
+
+#[sqlx::test]
+async fn patch_applies_exactly_the_fields_sent(pool: PgPool) {
+    let app = app(pool);
+    let changes = [
+        ("name", json!("Grace")),
+        ("coffee_name", json!("cappuccino")),
+        ("size", json!("large")),
+        ("quantity", json!(2)),
+    ];
+
+    // every non-empty subset of the fields
+    for mask in 1..(1u32 << changes.len()) {
+        let before = create_order(&app, flat_white("Ada")).await;
+        let id = before["id"].as_i64().unwrap();
+        let body: serde_json::Map<String, Value> = changes
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .map(|(_, (field, value))| (field.to_string(), value.clone()))
+            .collect();
+
+        let response = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(Value::Object(body.clone()))).await;
+        assert_eq!(response.status, StatusCode::OK, "PATCH {body:?}: {}", response.body);
+
+        let after = &response.body["data"];
+        for (field, value) in &changes {
+            let expected = if body.contains_key(*field) { value } else { &before[*field] };
+            assert_eq!(&after[*field], expected, "PATCH {body:?} left {field} wrong");
+        }
+    }
+}
+
+#[sqlx::test]
+async fn patch_without_fields_is_a_bad_request(pool: PgPool) {
+    let app = app(pool);
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+
+    let response = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(json!({}))).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.body["message"], "no fields provided to update");
+}
+
+#[sqlx::test]
+async fn put_requires_the_whole_order(pool: PgPool) {
+    let app = app(pool);
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+    let partial = json!({ "name": "Grace" });
+
+    let put = send(&app, Method::PUT, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(partial.clone())).await;
+    assert_eq!(put.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let patch = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(partial)).await;
+    assert_eq!(patch.status, StatusCode::OK);
+    assert_eq!(patch.body["data"]["name"], "Grace");
+
+    // what a PUT leaves out goes back to its default rather than staying
+    let extras = json!({ "quantity": 3, "notes": "extra hot", "tags": ["loyalty"] });
+    let patch = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(extras)).await;
+    assert_eq!(patch.body["data"]["quantity"], 3, "{}", patch.body);
+    let put = send(&app, Method::PUT, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(flat_white("Grace"))).await;
+    assert_eq!(put.status, StatusCode::OK, "{}", put.body);
+    assert_eq!(put.body["data"]["quantity"], 1);
+    assert_eq!(put.body["data"]["total"], "4.00");
+    assert_eq!(put.body["data"]["notes"], Value::Null);
+    assert_eq!(put.body["data"]["tags"], json!([]));
+}
+
+#[sqlx::test]
+async fn deleting_twice_is_not_found(pool: PgPool) {
+    let app = app(pool);
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+    let uri = format!("/orders/{id}");
+
+    assert_eq!(send(&app, Method::DELETE, &uri, Some(ADMIN_KEY), None).await.status, StatusCode::OK);
+    assert_eq!(send(&app, Method::DELETE, &uri, Some(ADMIN_KEY), None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(send(&app, Method::DELETE, "/orders/999", Some(ADMIN_KEY), None).await.status, StatusCode::NOT_FOUND);
+}
+
+#[sqlx::test]
+async fn soft_deleted_orders_can_be_restored(pool: PgPool) {
+    let app = app(pool.clone());
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+    let uri = format!("/orders/{id}");
+
+    send(&app, Method::DELETE, &uri, Some(ADMIN_KEY), None).await;
+    assert_eq!(send(&app, Method::GET, &uri, Some(ADMIN_KEY), None).await.status, StatusCode::NOT_FOUND);
+    let list = send(&app, Method::GET, "/orders", Some(ADMIN_KEY), None).await;
+    assert_eq!(list.body["data"], json!([]));
+    let row: Option<i32> = sqlx::query_scalar("SELECT id FROM orders WHERE id = $1").bind(id as i32).fetch_optional(&pool).await.unwrap();
+    assert_eq!(row, Some(id as i32), "soft delete keeps the row");
+
+    let audit = send(&app, Method::GET, &format!("{uri}?include_deleted=true"), Some(ADMIN_KEY), None).await;
+    assert_eq!(audit.status, StatusCode::OK);
+    assert!(audit.body["data"]["deleted_at"].is_string());
+    let audit = send(&app, Method::GET, &format!("{uri}?include_deleted=true"), Some(BARISTA_KEY), None).await;
+    assert_eq!(audit.status, StatusCode::FORBIDDEN);
+
+    let restored = send(&app, Method::POST, &format!("{uri}/restore"), Some(ADMIN_KEY), None).await;
+    assert_eq!(restored.status, StatusCode::OK);
+    let fetched = send(&app, Method::GET, &uri, Some(BARISTA_KEY), None).await;
+    assert_eq!(fetched.status, StatusCode::OK);
+    assert!(fetched.body["data"]["deleted_at"].is_null());
+    let again = send(&app, Method::POST, &format!("{uri}/restore"), Some(ADMIN_KEY), None).await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+}
+
+#[sqlx::test]
+async fn search_matches_wildcards_literally(pool: PgPool) {
+    let app = app(pool);
+    create_order(&app, flat_white("Ada")).await;
+    create_order(&app, flat_white("100% Grace")).await;
+
+    let count = |body: &Value| body["data"].as_array().unwrap().len();
+
+    let percent = send(&app, Method::GET, "/orders?q=100%25", Some(BARISTA_KEY), None).await;
+    assert_eq!(percent.status, StatusCode::OK);
+    assert_eq!(count(&percent.body), 1);
+    assert_eq!(percent.body["data"][0]["name"], "100% Grace");
+
+    let wildcard = send(&app, Method::GET, "/orders?q=%25", Some(BARISTA_KEY), None).await;
+    assert_eq!(count(&wildcard.body), 1);
+
+    let empty = send(&app, Method::GET, "/orders?q=", Some(BARISTA_KEY), None).await;
+    assert_eq!(count(&empty.body), 2);
+}
+
+#[sqlx::test]
+async fn a_failed_write_leaves_no_partial_rows(pool: PgPool) {
+    let app = app(pool.clone());
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+
+    // the audit insert is the last statement of both write paths
+    sqlx::raw_sql(
+        "CREATE FUNCTION fail_audit() RETURNS trigger LANGUAGE plpgsql AS $$ BEGIN RAISE EXCEPTION 'audit is down'; END $$;
+        CREATE TRIGGER fail_audit BEFORE INSERT ON order_events FOR EACH ROW EXECUTE FUNCTION fail_audit();",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Grace"))).await;
+    assert_eq!(created.status, StatusCode::INTERNAL_SERVER_ERROR);
+    let patched = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(BARISTA_KEY), Some(json!({ "name": "Grace", "size": "large" }))).await;
+    assert_eq!(patched.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let orders: Vec<(String, i32)> = sqlx::query_as("SELECT name, version FROM orders").fetch_all(&pool).await.unwrap();
+    assert_eq!(orders, [("Ada".to_owned(), 1)]);
+    let sizes: Vec<String> = sqlx::query_scalar("SELECT size::text FROM order_items").fetch_all(&pool).await.unwrap();
+    assert_eq!(sizes, ["medium"]);
+    let graces: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM customers WHERE name = 'Grace'").fetch_one(&pool).await.unwrap();
+    assert_eq!(graces, 0);
+}
+
+#[sqlx::test]
+async fn duplicates_under_a_unique_rule_are_conflicts(pool: PgPool) {
+    let app = app(pool.clone());
+    sqlx::query("CREATE UNIQUE INDEX orders_name_coffee_name_size_key ON orders (name, coffee_name, size)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+
+    let again = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+    assert_eq!(again.body["status"], false);
+    assert_eq!(again.body["message"], format!("an order with the same name, coffee_name, size already exists: order {id}"));
+
+    let batch = json!([flat_white("Grace"), flat_white("Ada"), flat_white("Grace")]);
+    let response = send(&app, Method::POST, "/orders/batch", Some(ADMIN_KEY), Some(batch)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(
+        response.body["message"],
+        format!("orders must be unique on name, coffee_name, size: order at index 1 matches order {id}; order at index 2 repeats an earlier one")
+    );
+
+    let list = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(list.headers["x-total-count"], "1");
+
+    let batch = json!([flat_white("Grace"), flat_white("Linus")]);
+    let response = send(&app, Method::POST, "/orders/batch", Some(ADMIN_KEY), Some(batch)).await;
+    assert_eq!(response.status, StatusCode::CREATED, "{}", response.body);
+    assert_eq!(response.body["data"].as_array().unwrap().len(), 2);
+}
+
+#[sqlx::test]
+async fn status_moves_only_along_allowed_transitions(pool: PgPool) {
+    let app = app(pool);
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+    let uri = format!("/orders/{id}/status");
+
+    let skipped = send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": "ready" }))).await;
+    assert_eq!(skipped.status, StatusCode::CONFLICT);
+    assert_eq!(skipped.body["message"], "cannot change status from pending to ready");
+
+    let unknown = send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": "lost" }))).await;
+    assert_eq!(unknown.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    for status in ["preparing", "ready", "completed"] {
+        let response = send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": status }))).await;
+        assert_eq!(response.status, StatusCode::OK, "{status}: {}", response.body);
+        assert_eq!(response.body["data"]["status"], status);
+        assert_eq!(response.body["data"]["name"], "Ada");
+        assert_eq!(response.body["data"]["total"], "4.00");
+    }
+
+    let cancelled = send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": "cancelled" }))).await;
+    assert_eq!(cancelled.status, StatusCode::CONFLICT);
+    assert_eq!(cancelled.body["message"], "cannot change status from completed to cancelled");
+}
+
+#[sqlx::test]
+async fn cancelling_keeps_the_order_and_is_idempotent(pool: PgPool) {
+    let app = app(pool);
+    let cancelled_id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+    let completed_id = create_order(&app, flat_white("Grace")).await["id"].as_i64().unwrap();
+    let cancel = format!("/orders/{cancelled_id}/cancel");
+
+    let first = send(&app, Method::POST, &cancel, Some(BARISTA_KEY), Some(json!({ "reason": " changed their mind " }))).await;
+    assert_eq!(first.status, StatusCode::OK, "{}", first.body);
+    assert_eq!(first.body["message"], "order cancelled");
+    assert_eq!(first.body["data"]["status"], "cancelled");
+    assert_eq!(first.body["data"]["cancellation_reason"], "changed their mind");
+
+    let again = send(&app, Method::POST, &cancel, Some(BARISTA_KEY), None).await;
+    assert_eq!(again.status, StatusCode::OK);
+    assert_eq!(again.body["message"], "order already cancelled");
+    assert_eq!(again.body["data"], first.body["data"]);
+
+    for status in ["preparing", "ready", "completed"] {
+        let uri = format!("/orders/{completed_id}/status");
+        send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": status }))).await;
+    }
+    let served = send(&app, Method::POST, &format!("/orders/{completed_id}/cancel"), Some(BARISTA_KEY), None).await;
+    assert_eq!(served.status, StatusCode::CONFLICT);
+    assert_eq!(served.body["message"], "cannot cancel a completed order");
+
+    let listed = send(&app, Method::GET, "/orders?status=cancelled", Some(BARISTA_KEY), None).await;
+    assert_eq!(listed.body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(listed.body["data"][0]["id"], cancelled_id);
+
+    let stats = send(&app, Method::GET, "/orders/stats", Some(BARISTA_KEY), None).await;
+    assert_eq!(stats.body["data"]["order_count"], 1);
+    assert_eq!(stats.body["data"]["revenue"], "4.00");
+}
+
+#[sqlx::test]
+async fn notes_are_capped_searchable_and_clearable(pool: PgPool) {
+    let app = app(pool);
+    let order = json!({ "name": "Ada", "coffee_name": "flat white", "size": "medium", "notes": " extra hot " });
+    let id = create_order(&app, order).await["id"].as_i64().unwrap();
+    let uri = format!("/orders/{id}");
+
+    let long = json!({ "name": "Grace", "coffee_name": "flat white", "size": "medium", "notes": "x".repeat(1001) });
+    let rejected = send(&app, Method::POST, "/orders", Some(ADMIN_KEY), Some(long)).await;
+    assert_eq!(rejected.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let plain = send(&app, Method::GET, "/orders?q=hot", Some(BARISTA_KEY), None).await;
+    assert_eq!(plain.body["data"].as_array().unwrap().len(), 0);
+    let with_notes = send(&app, Method::GET, "/orders?q=hot&search_notes=true", Some(BARISTA_KEY), None).await;
+    assert_eq!(with_notes.body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(with_notes.body["data"][0]["notes"], "extra hot");
+
+    let csv = send(&app, Method::GET, "/orders/export.csv", Some(BARISTA_KEY), None).await;
+    assert!(csv.body.as_str().unwrap().contains(",extra hot"), "{}", csv.body);
+
+    let kept = send(&app, Method::PATCH, &uri, Some(ADMIN_KEY), Some(json!({ "quantity": 2 }))).await;
+    assert_eq!(kept.body["data"]["notes"], "extra hot");
+
+    let nulled = send(&app, Method::PATCH, &uri, Some(ADMIN_KEY), Some(json!({ "notes": null }))).await;
+    assert_eq!(nulled.status, StatusCode::OK, "{}", nulled.body);
+    assert_eq!(nulled.body["data"]["notes"], Value::Null);
+
+    send(&app, Method::PATCH, &uri, Some(ADMIN_KEY), Some(json!({ "notes": "oat milk" }))).await;
+    let emptied = send(&app, Method::PATCH, &uri, Some(ADMIN_KEY), Some(json!({ "notes": "" }))).await;
+    assert_eq!(emptied.status, StatusCode::OK, "{}", emptied.body);
+    assert_eq!(emptied.body["data"]["notes"], Value::Null);
+}
+
+#[sqlx::test]
+async fn tags_are_normalized_filterable_and_counted(pool: PgPool) {
+    let app = app(pool);
+    let tagged = |name: &str, tags: Value| json!({ "name": name, "coffee_name": "flat white", "size": "medium", "tags": tags });
+
+    let ada = create_order(&app, tagged("Ada", json!([" Staff ", "loyalty", "STAFF"]))).await;
+    assert_eq!(ada["tags"], json!(["staff", "loyalty"]));
+    create_order(&app, tagged("Grace", json!(["staff"]))).await;
+    let untagged = create_order(&app, flat_white("Linus")).await;
+    assert_eq!(untagged["tags"], json!([]));
+
+    let too_many: Vec<String> = (0..11).map(|n| format!("tag{n}")).collect();
+    let rejected = send(&app, Method::POST, "/orders", Some(ADMIN_KEY), Some(tagged("Edsger", json!(too_many)))).await;
+    assert_eq!(rejected.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let too_long = send(&app, Method::POST, "/orders", Some(ADMIN_KEY), Some(tagged("Edsger", json!(["x".repeat(33)])))).await;
+    assert_eq!(too_long.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let staff = send(&app, Method::GET, "/orders?tag=Staff", Some(BARISTA_KEY), None).await;
+    assert_eq!(staff.status, StatusCode::OK, "{}", staff.body);
+    assert_eq!(staff.body["data"].as_array().unwrap().len(), 2);
+    let both = send(&app, Method::GET, "/orders?tag=staff&tag=loyalty", Some(BARISTA_KEY), None).await;
+    assert_eq!(both.body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(both.body["data"][0]["name"], "Ada");
+
+    let tags = send(&app, Method::GET, "/orders/tags", Some(BARISTA_KEY), None).await;
+    assert_eq!(tags.status, StatusCode::OK, "{}", tags.body);
+    assert_eq!(tags.body["data"], json!([{ "tag": "staff", "count": 2 }, { "tag": "loyalty", "count": 1 }]));
+
+    let uri = format!("/orders/{}", ada["id"]);
+    let cleared = send(&app, Method::PATCH, &uri, Some(ADMIN_KEY), Some(json!({ "tags": [] }))).await;
+    assert_eq!(cleared.status, StatusCode::OK, "{}", cleared.body);
+    assert_eq!(cleared.body["data"]["tags"], json!([]));
+    let count = send(&app, Method::GET, "/orders/count?tag=loyalty", Some(BARISTA_KEY), None).await;
+    assert_eq!(count.body["data"]["count"], 0);
+}
+
+#[sqlx::test]
+async fn batch_status_updates_skip_or_refuse_blocked_orders(pool: PgPool) {
+    let app = app(pool);
+    let mut ids = Vec::new();
+    for name in ["Ada", "Grace", "Linus"] {
+        ids.push(create_order(&app, flat_white(name)).await["id"].as_i64().unwrap());
+    }
+    for id in &ids[..2] {
+        for status in ["preparing", "ready"] {
+            let uri = format!("/orders/{id}/status");
+            send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": status }))).await;
+        }
+    }
+    let body = json!({ "ids": [ids[0], ids[1], ids[2], 999], "status": "completed" });
+
+    let strict = send(&app, Method::POST, "/orders/status?strict=true", Some(ADMIN_KEY), Some(body.clone())).await;
+    assert_eq!(strict.status, StatusCode::CONFLICT, "{}", strict.body);
+    let ready = send(&app, Method::GET, "/orders/count?status=ready", Some(BARISTA_KEY), None).await;
+    assert_eq!(ready.body["data"]["count"], 2);
+
+    let partial = send(&app, Method::POST, "/orders/status", Some(ADMIN_KEY), Some(body.clone())).await;
+    assert_eq!(partial.status, StatusCode::OK, "{}", partial.body);
+    assert_eq!(partial.body["data"]["updated"], json!([ids[0], ids[1]]));
+    assert_eq!(
+        partial.body["data"]["skipped"],
+        json!([
+            { "id": ids[2], "reason": "cannot change status from pending to completed" },
+            { "id": 999, "reason": "order not found" },
+        ])
+    );
+    let events = send(&app, Method::GET, &format!("/orders/{}/events", ids[0]), Some(ADMIN_KEY), None).await;
+    assert_eq!(events.body["data"][0]["changes"]["status"], json!({ "from": "ready", "to": "completed" }));
+
+    let again = send(&app, Method::POST, "/orders/status", Some(ADMIN_KEY), Some(json!({ "ids": [ids[0]], "status": "completed" }))).await;
+    assert_eq!(again.body["data"]["updated"], json!([]));
+    assert_eq!(again.body["data"]["skipped"][0]["reason"], "already completed");
+
+    let unknown = send(&app, Method::POST, "/orders/status", Some(ADMIN_KEY), Some(json!({ "ids": [ids[2]], "status": "lost" }))).await;
+    assert_eq!(unknown.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[sqlx::test]
+async fn fields_limits_the_order_fields_returned(pool: PgPool) {
+    let app = app(pool);
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+
+    let listed = send(&app, Method::GET, "/orders?fields=id,coffee_name,size", Some(BARISTA_KEY), None).await;
+    assert_eq!(listed.status, StatusCode::OK, "{}", listed.body);
+    assert_eq!(listed.body["data"], json!([{ "id": id, "coffee_name": "flat white", "size": "medium" }]));
+    assert_eq!(listed.body["meta"]["total"], 1);
+
+    let one = send(&app, Method::GET, &format!("/orders/{id}?fields=status"), Some(BARISTA_KEY), None).await;
+    assert_eq!(one.status, StatusCode::OK, "{}", one.body);
+    assert_eq!(one.body["data"], json!({ "status": "pending" }));
+
+    let unknown = send(&app, Method::GET, "/orders?fields=id,secret", Some(BARISTA_KEY), None).await;
+    assert_eq!(unknown.status, StatusCode::BAD_REQUEST);
+    assert!(unknown.body["message"].as_str().unwrap().starts_with("invalid field 'secret', expected one of: id, name"));
+}
+
+#[sqlx::test]
+async fn the_list_answers_in_the_accepted_type(pool: PgPool) {
+    let app = app(pool);
+    create_order(&app, flat_white("Ada")).await;
+    create_order(&app, flat_white("Grace")).await;
+    let list = |accept: &'static str| {
+        Request::get("/orders?sort=name&dir=desc&limit=1")
+            .header("x-api-key", BARISTA_KEY)
+            .header("accept", accept)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let csv = send_request(&app, list("application/json;q=0.5, text/csv;q=0.9")).await;
+    assert_eq!(csv.status, StatusCode::OK);
+    assert_eq!(csv.headers[CONTENT_TYPE], "text/csv; charset=utf-8");
+    assert_eq!(csv.headers["x-total-count"], "2");
+    let body = csv.body.as_str().unwrap();
+    assert_eq!(body.lines().count(), 2, "{body}");
+    assert!(body.starts_with("id,name,coffee_name,size,"));
+    assert!(body.lines().nth(1).unwrap().contains(",Grace,flat white,medium,"));
+
+    let json = send_request(&app, list("text/*;q=0.2, */*")).await;
+    assert_eq!(json.status, StatusCode::OK);
+    assert_eq!(json.body["data"][0]["name"], "Grace");
+
+    let refused = send_request(&app, list("application/xml, text/csv;q=0")).await;
+    assert_eq!(refused.status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(refused.body["data"], json!(["application/json", "text/csv", "application/msgpack"]));
+}
+
+#[sqlx::test]
+async fn orders_can_be_sent_and_received_as_msgpack(pool: PgPool) {
+    let app = app(pool);
+    let msgpack = |body: Vec<u8>| {
+        Request::post("/orders")
+            .header("x-api-key", ADMIN_KEY)
+            .header(CONTENT_TYPE, "application/msgpack")
+            .header("accept", "application/msgpack")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let order = rmp_serde::to_vec_named(&flat_white("Ada")).unwrap();
+    let response = app.clone().oneshot(msgpack(order)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/msgpack");
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let body: Value = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(body["data"]["name"], "Ada");
+    assert_eq!(body["data"]["total"], "4.00");
+
+    let malformed = app.clone().oneshot(msgpack(vec![0xc1])).await.unwrap();
+    assert_eq!(malformed.status(), StatusCode::BAD_REQUEST);
+    let bytes = axum::body::to_bytes(malformed.into_body(), usize::MAX).await.unwrap();
+    let body: Value = rmp_serde::from_slice(&bytes).unwrap();
+    assert_eq!(body["status"], false);
+    assert!(body["message"].as_str().unwrap().starts_with("invalid MessagePack body"));
+
+    let json = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(json.headers[CONTENT_TYPE], "application/json");
+}
+
+#[sqlx::test]
+async fn cancel_bodies_are_read_like_any_other(pool: PgPool) {
+    let app = app(pool);
+    let id = create_order(&app, flat_white("Ada")).await["id"].as_i64().unwrap();
+    let cancel = |content_type: &str, body: Vec<u8>| {
+        Request::post(format!("/orders/{id}/cancel"))
+            .header("x-api-key", BARISTA_KEY)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let malformed = send_request(&app, cancel("application/json", b"{\"reason\":".to_vec())).await;
+    assert_eq!(malformed.status, StatusCode::BAD_REQUEST);
+    assert_eq!(malformed.body["status"], false);
+    assert!(malformed.body["message"].is_string(), "{}", malformed.body);
+
+    let reason = rmp_serde::to_vec_named(&json!({ "reason": "spilled" })).unwrap();
+    let cancelled = send_request(&app, cancel("application/msgpack", reason)).await;
+    assert_eq!(cancelled.status, StatusCode::OK, "{}", cancelled.body);
+    assert_eq!(cancelled.body["data"]["cancellation_reason"], "spilled");
+}
+
+#[sqlx::test]
+async fn order_lists_are_cached_until_a_write(pool: PgPool) {
+    let app = app(pool.clone());
+    let order = create_order(&app, flat_white("Ada")).await;
+
+    let response = send(&app, Method::GET, "/orders?status=pending&name=Ada", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.headers["x-cache"], "miss");
+    // the same filter in another case shares the entry
+    let response = send(&app, Method::GET, "/orders?status=pending&name=ada", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.headers["x-cache"], "hit");
+    assert_eq!(response.body["meta"]["total"], 1);
+
+    let status = json!({ "status": "preparing" });
+    let response = send(&app, Method::PATCH, &format!("/orders/{}/status", order["id"]), Some(BARISTA_KEY), Some(status)).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+
+    let response = send(&app, Method::GET, "/orders?status=pending&name=Ada", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.headers["x-cache"], "miss");
+    assert_eq!(response.body["meta"]["total"], 0);
+}
+
+#[sqlx::test]
+async fn conditional_gets_answer_not_modified(pool: PgPool) {
+    let app = app(pool);
+    let order = create_order(&app, flat_white("Ada")).await;
+    let get = |uri: &str, if_none_match: &str| {
+        Request::builder()
+            .uri(uri)
+            .header("x-api-key", BARISTA_KEY)
+            .header("if-none-match", if_none_match)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let list = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    let tag = list.headers["etag"].to_str().unwrap().to_owned();
+    let again = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(again.headers["etag"], tag.as_str());
+
+    // weak comparison, so the W/ form of the tag matches too
+    let response = send_request(&app, get("/orders", &format!("\"other\", W/{tag}"))).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(response.body, "");
+    assert_eq!(response.headers["etag"], tag.as_str());
+
+    let uri = format!("/orders/{}", order["id"]);
+    let response = send_request(&app, get(&uri, "\"1\"")).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+    let status = json!({ "status": "preparing" });
+    send(&app, Method::PATCH, &format!("{uri}/status"), Some(BARISTA_KEY), Some(status)).await;
+    let response = send_request(&app, get(&uri, "\"1\"")).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.headers["etag"], "\"2\"");
+    let response = send_request(&app, get("/orders", &tag)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_ne!(response.headers["etag"], tag.as_str());
+}
+
+#[sqlx::test]
+async fn every_list_applies_the_same_page_rules(pool: PgPool) {
+    let app = app(pool);
+    let order = create_order(&app, flat_white("Ada")).await;
+    let events = format!("/orders/{}/events", order["id"]);
+
+    for path in ["/orders", "/customers", events.as_str()] {
+        let response = send(&app, Method::GET, path, Some(BARISTA_KEY), None).await;
+        assert_eq!(response.body["meta"]["per_page"], 50, "{path}: {}", response.body);
+
+        for query in ["limit=1000000", "limit=0", "offset=-1", "limit=ten"] {
+            let response = send(&app, Method::GET, &format!("{path}?{query}"), Some(BARISTA_KEY), None).await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "{path}?{query}");
+            assert_eq!(response.body["status"], false, "{path}?{query}: {}", response.body);
+        }
+    }
+}
+
+#[sqlx::test]
+async fn orders_sort_by_several_fields(pool: PgPool) {
+    let app = app(pool);
+    let mut ids = Vec::new();
+    for name in ["Ada", "Grace", "Linus"] {
+        ids.push(create_order(&app, flat_white(name)).await["id"].clone());
+    }
+    let status = json!({ "status": "preparing" });
+    send(&app, Method::PATCH, &format!("/orders/{}/status", ids[0]), Some(BARISTA_KEY), Some(status)).await;
+
+    // pending before preparing, newest first within each status
+    let request = Request::get("/orders?sort=status,-created_at,-id&limit=2")
+        .header("host", "orders.test")
+        .header("x-api-key", BARISTA_KEY)
+        .body(Body::empty())
+        .unwrap();
+    let response = send_request(&app, request).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    let page: Vec<Value> = response.body["data"].as_array().unwrap().iter().map(|order| order["id"].clone()).collect();
+    assert_eq!(page, [ids[2].clone(), ids[1].clone()]);
+    let link = response.headers["link"].to_str().unwrap();
+    assert!(link.contains("sort=status%2C-created_at%2C-id"), "{link}");
+
+    let response = send(&app, Method::GET, "/orders?sort=status,-created_at,-id&offset=2", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["data"][0]["id"], ids[0]);
+
+    let response = send(&app, Method::GET, "/orders?sort=status,-colour", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(response.body["message"].as_str().unwrap().starts_with("invalid sort field 'colour'"), "{}", response.body);
+    let response = send(&app, Method::GET, "/orders?sort=name,-name", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[sqlx::test]
+async fn sizes_are_matched_case_insensitively(pool: PgPool) {
+    let app = app(pool);
+    let order = json!({ "name": "Ada", "coffee_name": "latte", "size": "LARGE" });
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(order)).await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.body);
+    assert_eq!(created.body["data"]["size"], "large");
+    create_order(&app, flat_white("Grace")).await;
+
+    let huge = json!({ "name": "Linus", "coffee_name": "latte", "size": "huge" });
+    let response = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(huge)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["errors"][0]["field"], "size");
+    assert_eq!(response.body["errors"][0]["message"], "invalid size 'huge', expected one of: small, medium, large");
+
+    let response = send(&app, Method::GET, "/orders?size=Large", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["meta"]["total"], 1);
+    let response = send(&app, Method::GET, "/orders?size=grande", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["errors"][0]["field"], "size");
+
+    let response = send(&app, Method::GET, "/orders/stats", Some(BARISTA_KEY), None).await;
+    let buckets = response.body["data"]["by_size"].as_array().unwrap();
+    let keys: Vec<&str> = buckets.iter().map(|bucket| bucket["key"].as_str().unwrap()).collect();
+    assert_eq!(keys, ["large", "medium"]);
+}
+
+#[sqlx::test]
+async fn validation_lists_every_failing_field(pool: PgPool) {
+    let app = app(pool);
+    let order = json!({
+        "name": "Ada",
+        "customer_id": 1,
+        "coffee_name": "latte",
+        "size": "small",
+        "total": "1.00",
+        "total_override": "5000.00",
+        "status": "brewing",
+    });
+    let response = send(&app, Method::POST, "/orders", Some(ADMIN_KEY), Some(order)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["message"], "validation failed");
+    assert!(response.body["data"].is_null());
+    let errors: Vec<(&str, &str)> = response.body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| (error["field"].as_str().unwrap(), error["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        errors,
+        [("total_override", "out_of_range"), ("status", "invalid"), ("total", "conflict"), ("customer_id", "conflict")]
+    );
+
+    let id = create_order(&app, flat_white("Grace")).await["id"].clone();
+    let patch = json!({ "name": " ", "quantity": 0, "total": "1.00", "total_override": "1.00" });
+    let response = send(&app, Method::PATCH, &format!("/orders/{id}"), Some(ADMIN_KEY), Some(patch)).await;
+    let fields: Vec<&str> = response.body["errors"].as_array().unwrap().iter().map(|error| error["field"].as_str().unwrap()).collect();
+    assert_eq!(fields, ["name", "total", "quantity"]);
+
+    // the first batch order is fine, the others fail validation and pricing respectively
+    let batch = json!([flat_white("Linus"), { "coffee_name": "latte", "size": "small" }, { "name": "Alan", "coffee_name": "tea", "size": "small" }]);
+    let response = send(&app, Method::POST, "/orders/batch", Some(ADMIN_KEY), Some(batch)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let errors: Vec<(&str, &str)> = response.body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| (error["field"].as_str().unwrap(), error["code"].as_str().unwrap()))
+        .collect();
+    assert_eq!(errors, [("[1].name", "required"), ("[2].coffee_name", "not_found")]);
+
+    // a value that does not fit the body's type names its path too
+    let batch = json!([flat_white("Linus"), { "name": "Alan", "items": [{ "coffee_name": "latte", "size": "venti" }] }]);
+    let response = send(&app, Method::POST, "/orders/batch", Some(ADMIN_KEY), Some(batch)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["errors"][0]["field"], "[1].items[0].size");
+    assert_eq!(response.body["errors"][0]["code"], "invalid");
+    assert_eq!(response.body["errors"][0]["message"], "invalid size 'venti', expected one of: small, medium, large");
+}
+
+#[sqlx::test]
+async fn breakdowns_split_orders_of_several_lines(pool: PgPool) {
+    let app = app(pool);
+    let order = json!({
+        "name": "Ada",
+        "items": [
+            { "coffee_name": "latte", "size": "large" },
+            { "coffee_name": "espresso", "size": "small", "quantity": 2 },
+        ],
+    });
+    let created = create_order(&app, order).await;
+    assert_eq!(created["total"], "10.50");
+    create_order(&app, flat_white("Grace")).await;
+
+    let report = send(&app, Method::GET, "/reports/revenue", Some(ADMIN_KEY), None).await;
+    assert_eq!(report.status, StatusCode::OK, "{}", report.body);
+    assert_eq!(
+        report.body["data"],
+        json!([
+            { "key": "latte", "order_count": 1, "revenue": "5.50" },
+            { "key": "espresso", "order_count": 1, "revenue": "5.00" },
+            { "key": "flat white", "order_count": 1, "revenue": "4.00" },
+        ])
+    );
+    let report = send(&app, Method::GET, "/reports/revenue?group_by=size", Some(ADMIN_KEY), None).await;
+    let keys: Vec<&str> = report.body["data"].as_array().unwrap().iter().map(|row| row["key"].as_str().unwrap()).collect();
+    assert_eq!(keys, ["large", "small", "medium"]);
+    let report = send(&app, Method::GET, "/reports/revenue?group_by=name", Some(ADMIN_KEY), None).await;
+    assert_eq!(report.body["data"][0], json!({ "key": "Ada", "order_count": 1, "revenue": "10.50" }));
+
+    let stats = send(&app, Method::GET, "/orders/stats", Some(BARISTA_KEY), None).await;
+    assert_eq!(stats.body["data"]["revenue"], "14.50");
+    assert_eq!(
+        stats.body["data"]["by_coffee_name"],
+        json!([
+            { "key": "espresso", "order_count": 1, "quantity": 2, "revenue": "5.00" },
+            { "key": "flat white", "order_count": 1, "quantity": 1, "revenue": "4.00" },
+            { "key": "latte", "order_count": 1, "quantity": 1, "revenue": "5.50" },
+        ])
+    );
+}
+
+#[sqlx::test]
+async fn strict_import_keeps_no_flushed_chunks(pool: PgPool) {
+    let app = app(pool.clone());
+    // more good rows than one chunk, so some are written before the bad one
+    let mut csv = "name,coffee_name,size\n".to_owned();
+    for index in 0..501 {
+        csv.push_str(&format!("Grace {index},flat white,medium\n"));
+    }
+    csv.push_str("Ada,flat white,huge\n");
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri("/orders/import?mode=strict")
+        .header("x-api-key", ADMIN_KEY)
+        .header(CONTENT_TYPE, "text/csv")
+        .body(Body::from(csv))
+        .unwrap();
+
+    let response = send_request(&app, request).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", response.body);
+    assert_eq!(response.body["message"], "import aborted at line 503");
+    assert_eq!(response.body["data"]["inserted"], 0);
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders").fetch_one(&pool).await.unwrap();
+    assert_eq!(stored, 0);
+}
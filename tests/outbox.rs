@@ -0,0 +1,108 @@
+//! The outbox only fills up with WEBHOOK_URLS set before the config is
+//! read, so it has a binary to itself.
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::routing::post;
+use axum::Router;
+use rust_orders::OutboxDispatcher;
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+
+use common::{app, config, create_order, flat_white, send, ADMIN_KEY, BARISTA_KEY};
+
+#[derive(Clone, Default)]
+struct Receiver {
+    /// Target name, webhook id header and body of every delivery taken.
+    received: Arc<Mutex<Vec<(String, String, Value)>>>,
+    flaky_down: Arc<AtomicBool>,
+}
+
+async fn receive(State(receiver): State<Receiver>, Path(target): Path<String>, headers: HeaderMap, body: String) -> StatusCode {
+    if target == "flaky" && receiver.flaky_down.load(Ordering::SeqCst) {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    let id = headers["x-webhook-id"].to_str().unwrap().to_owned();
+    receiver.received.lock().unwrap().push((target, id, serde_json::from_str(&body).unwrap()));
+    StatusCode::NO_CONTENT
+}
+
+#[sqlx::test]
+async fn outbox_retries_failed_deliveries_until_they_are_dead_lettered(pool: PgPool) {
+    let receiver = Receiver::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let routes = Router::new().route("/:target", post(receive)).with_state(receiver.clone());
+    tokio::spawn(async move { axum::serve(listener, routes).await.unwrap() });
+    std::env::set_var("WEBHOOK_URLS", format!("http://{addr}/steady,http://{addr}/flaky"));
+    let app = app(pool.clone());
+    let dispatcher = OutboxDispatcher::new(config()).unwrap();
+
+    // queued with the order, delivered only once the dispatcher runs
+    let order = create_order(&app, flat_white("Ada")).await;
+    let pending = send(&app, Method::GET, "/admin/outbox?status=pending", Some(ADMIN_KEY), None).await;
+    assert_eq!(pending.status, StatusCode::OK, "{}", pending.body);
+    assert_eq!(pending.body["meta"]["total"], 1);
+    let event = &pending.body["data"][0];
+    assert_eq!(event["event_type"], "order.created");
+    assert_eq!(event["payload"]["data"]["id"], order["id"]);
+    let id = event["id"].as_i64().unwrap();
+    let forbidden = send(&app, Method::GET, "/admin/outbox", Some(BARISTA_KEY), None).await;
+    assert_eq!(forbidden.status, StatusCode::FORBIDDEN);
+
+    receiver.flaky_down.store(true, Ordering::SeqCst);
+    let summary = dispatcher.dispatch(&pool).await.unwrap();
+    assert_eq!((summary.delivered, summary.retrying, summary.failed), (0, 1, 0));
+    let pending = send(&app, Method::GET, "/admin/outbox?status=pending", Some(ADMIN_KEY), None).await;
+    let event = &pending.body["data"][0];
+    assert_eq!(event["attempts"], 1);
+    assert_eq!(event["delivered_to"], json!([format!("http://{addr}/steady")]));
+    assert!(event["last_error"].as_str().unwrap().contains("receiver answered 500"), "{event}");
+    // backing off, so an immediate pass leaves it alone
+    let summary = dispatcher.dispatch(&pool).await.unwrap();
+    assert_eq!((summary.delivered, summary.retrying, summary.failed), (0, 0, 0));
+
+    sqlx::query("UPDATE outbox_events SET attempts = 7, next_attempt_at = now()")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let summary = dispatcher.dispatch(&pool).await.unwrap();
+    assert_eq!(summary.failed, 1);
+    let failed = send(&app, Method::GET, "/admin/outbox?status=failed", Some(ADMIN_KEY), None).await;
+    assert_eq!(failed.body["data"][0]["id"], id);
+
+    // requeued, the event goes to the target that missed it and only that one
+    receiver.flaky_down.store(false, Ordering::SeqCst);
+    let retry = send(&app, Method::POST, &format!("/admin/outbox/{id}/retry"), Some(ADMIN_KEY), None).await;
+    assert_eq!(retry.status, StatusCode::OK, "{}", retry.body);
+    assert_eq!(retry.body["data"]["status"], "pending");
+    assert_eq!(retry.body["data"]["attempts"], 0);
+    let summary = dispatcher.dispatch(&pool).await.unwrap();
+    assert_eq!(summary.delivered, 1);
+
+    let received = receiver.received.lock().unwrap().clone();
+    let targets: Vec<&str> = received.iter().map(|(target, _, _)| target.as_str()).collect();
+    assert_eq!(targets, ["steady", "flaky"]);
+    let (_, webhook_id, body) = &received[1];
+    assert_eq!(body["event"], "order.created");
+    assert_eq!(&body["id"], webhook_id);
+    assert_eq!(body, &received[0].2);
+
+    let again = send(&app, Method::POST, &format!("/admin/outbox/{id}/retry"), Some(ADMIN_KEY), None).await;
+    assert_eq!(again.status, StatusCode::CONFLICT);
+    let missing = send(&app, Method::POST, "/admin/outbox/9999/retry", Some(ADMIN_KEY), None).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+    let invalid = send(&app, Method::GET, "/admin/outbox?status=stuck", Some(ADMIN_KEY), None).await;
+    assert_eq!(invalid.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // a write that rolls back queues nothing
+    let rejected = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(json!({ "name": "Ada", "coffee_name": "tea" }))).await;
+    assert!(rejected.status.is_client_error());
+    let all = send(&app, Method::GET, "/admin/outbox", Some(ADMIN_KEY), None).await;
+    assert_eq!(all.body["meta"]["total"], 1);
+}
@@ -0,0 +1,118 @@
+//! Receipts need SMTP_HOST set before the config is read, so they have a
+//! binary to themselves.
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use sqlx::PgPool;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+use common::{app, send, ADMIN_KEY, BARISTA_KEY};
+
+/// Just enough SMTP to take messages, refusing the first one with a
+/// temporary error so the sender has to retry.
+async fn smtp_server(listener: TcpListener, received: Arc<Mutex<Vec<String>>>) {
+    let mut refused_one = false;
+    loop {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut message: Option<String> = None;
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(body) = message.as_mut() {
+                if line != "." {
+                    body.push_str(&line);
+                    body.push('\n');
+                    continue;
+                }
+                let body = message.take().unwrap();
+                if refused_one {
+                    received.lock().unwrap().push(body);
+                    write.write_all(b"250 queued\r\n").await.unwrap();
+                } else {
+                    refused_one = true;
+                    write.write_all(b"451 try again later\r\n").await.unwrap();
+                }
+                continue;
+            }
+            let reply: &[u8] = match line.get(..4).unwrap_or_default().to_ascii_uppercase().as_str() {
+                "EHLO" | "HELO" => b"250 localhost\r\n",
+                "DATA" => {
+                    message = Some(String::new());
+                    b"354 go ahead\r\n"
+                }
+                "QUIT" => {
+                    write.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                }
+                _ => b"250 ok\r\n",
+            };
+            write.write_all(reply).await.unwrap();
+        }
+    }
+}
+
+#[sqlx::test]
+async fn orders_placed_with_an_email_are_sent_a_receipt(pool: PgPool) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    std::env::set_var("SMTP_HOST", "127.0.0.1");
+    std::env::set_var("SMTP_PORT", listener.local_addr().unwrap().port().to_string());
+    std::env::set_var("SMTP_TLS", "none");
+    std::env::set_var("SMTP_FROM", "Coffee Bar <orders@example.com>");
+    tokio::spawn(smtp_server(listener, received.clone()));
+    let app = app(pool);
+
+    let invalid = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(json!({
+        "name": "Ada", "coffee_name": "flat white", "size": "medium", "email": "ada@example",
+    })))
+    .await;
+    assert_eq!(invalid.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(invalid.body["errors"][0]["field"], "email", "{}", invalid.body);
+
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(json!({
+        "name": "Ada <3", "items": [{ "coffee_name": "flat white", "size": "medium", "quantity": 2 }],
+        "email": " ada@example.com ",
+    })))
+    .await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.body);
+    let id = created.body["data"]["id"].as_i64().unwrap();
+    let total = created.body["data"]["total"].as_str().unwrap().to_owned();
+
+    // the first attempt is refused, the retry two seconds later goes through
+    for _ in 0..100 {
+        if !received.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let receipt = received.lock().unwrap().first().cloned().expect("no receipt arrived");
+    assert!(receipt.contains("To: ada@example.com"), "{receipt}");
+    assert!(receipt.contains(&format!("Subject: Your receipt for order #{id}")), "{receipt}");
+    assert!(receipt.contains("2 x medium flat white"), "{receipt}");
+    assert!(receipt.contains(&format!("Total: {total}")), "{receipt}");
+    assert!(receipt.contains("text/html") && receipt.contains("Ada &lt;3"), "{receipt}");
+
+    // orders without an email, and batches, still go through as before
+    let plain = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(common::flat_white("Grace"))).await;
+    assert_eq!(plain.status, StatusCode::CREATED);
+    let batch = send(&app, Method::POST, "/orders/batch", Some(ADMIN_KEY), Some(json!([
+        { "name": "Linus", "coffee_name": "latte", "size": "large", "email": "linus@example.com" },
+    ])))
+    .await;
+    assert_eq!(batch.status, StatusCode::CREATED, "{}", batch.body);
+    for _ in 0..100 {
+        if received.lock().unwrap().len() == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    assert!(received[1].contains("To: linus@example.com"), "{}", received[1]);
+}
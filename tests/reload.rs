@@ -0,0 +1,77 @@
+//! Reloading changes the environment, so it has a binary to itself.
+mod common;
+
+use axum::http::{Method, StatusCode};
+use serde_json::json;
+use sqlx::PgPool;
+
+use common::{app, flat_white, send, ADMIN_KEY, BARISTA_KEY};
+
+#[sqlx::test]
+async fn reload_applies_maintenance_mode_and_reports_startup_settings(pool: PgPool) {
+    let app = app(pool);
+
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(ADMIN_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["data"], json!({ "changed": [], "restart_required": [] }));
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    std::env::set_var("MAINTENANCE_MODE", "true");
+    std::env::set_var("RATE_LIMIT_WRITE_BURST", "5");
+    std::env::set_var("PORT", "3999");
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(ADMIN_KEY), None).await;
+    assert_eq!(
+        response.body["data"],
+        json!({ "changed": ["RATE_LIMIT_WRITE", "MAINTENANCE_MODE"], "restart_required": ["PORT"] })
+    );
+
+    let response = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers["retry-after"], "30");
+    assert_eq!(response.body["message"], "in maintenance, writes are paused");
+    let response = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    // GraphQL reads are POSTs as well, so only its mutations are refused
+    let query = json!({ "query": "{ orders { total } }" });
+    let response = send(&app, Method::POST, "/graphql", Some(BARISTA_KEY), Some(query)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["data"]["orders"]["total"], 0, "{}", response.body);
+    let mutation = json!({
+        "query": "mutation { createOrder(input: { name: \"Ada\", coffeeName: \"flat white\", size: \"medium\" }) { id } }",
+    });
+    let response = send(&app, Method::POST, "/graphql", Some(BARISTA_KEY), Some(mutation)).await;
+    assert_eq!(response.body["errors"][0]["extensions"]["status"], 503, "{}", response.body);
+    assert_eq!(response.body["errors"][0]["message"], "in maintenance, writes are paused");
+
+    // a broken config is refused as a whole, so maintenance stays on
+    std::env::set_var("MAINTENANCE_MODE", "false");
+    std::env::set_var("RATE_LIMIT_READ_PER_SEC", "lots");
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(ADMIN_KEY), None).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        response.body["message"],
+        "config is invalid, nothing reloaded: RATE_LIMIT_READ_PER_SEC has an invalid value 'lots'"
+    );
+    let response = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+
+    std::env::remove_var("RATE_LIMIT_READ_PER_SEC");
+    std::env::set_var("TLS_CERT_PATH", "/nonexistent/cert.pem");
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(ADMIN_KEY), None).await;
+    assert_eq!(
+        response.body["message"],
+        "config is invalid, nothing reloaded: set both TLS_CERT_PATH and TLS_KEY_PATH, or neither"
+    );
+    std::env::set_var("TLS_KEY_PATH", "/nonexistent/key.pem");
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(ADMIN_KEY), None).await;
+    let message = response.body["message"].as_str().unwrap();
+    assert!(message.contains("TLS_CERT_PATH '/nonexistent/cert.pem' could not be read"), "{message}");
+
+    std::env::remove_var("TLS_CERT_PATH");
+    std::env::remove_var("TLS_KEY_PATH");
+    let response = send(&app, Method::POST, "/admin/reload-config", Some(ADMIN_KEY), None).await;
+    assert_eq!(response.body["data"]["changed"], json!(["MAINTENANCE_MODE"]));
+    let response = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Ada"))).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use rust_orders::MemoryOrderRepository;
+use serde_json::json;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::PgPool;
+
+use common::{create_order, flat_white, memory_app, replica_app, send, send_request, ADMIN_KEY, BARISTA_KEY};
+
+#[tokio::test]
+async fn handlers_run_against_the_memory_repository() {
+    let orders = Arc::new(MemoryOrderRepository::new());
+    let app = memory_app(orders);
+    let order = json!({ "name": "Ada", "coffee_name": "latte", "size": "small", "total": "3.50" });
+
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(order)).await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.body);
+    assert_eq!(created.body["data"]["total"], "3.50");
+    let uri = format!("/orders/{}", created.body["data"]["id"]);
+
+    let patched = send(&app, Method::PATCH, &uri, Some(BARISTA_KEY), Some(json!({ "status": "preparing" }))).await;
+    assert_eq!(patched.status, StatusCode::OK);
+    assert_eq!(patched.body["data"]["status"], "preparing");
+    assert_eq!(patched.headers["etag"], "\"2\"");
+
+    let list = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(list.headers["x-total-count"], "1");
+
+    assert_eq!(send(&app, Method::DELETE, &uri, Some(ADMIN_KEY), None).await.status, StatusCode::OK);
+    assert_eq!(send(&app, Method::GET, &uri, Some(BARISTA_KEY), None).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn repository_errors_are_a_500_envelope() {
+    let orders = Arc::new(MemoryOrderRepository::new());
+    let app = memory_app(orders.clone());
+    orders.fail(true);
+
+    for (method, uri) in [(Method::GET, "/orders"), (Method::GET, "/orders/1"), (Method::DELETE, "/orders/1")] {
+        let response = send(&app, method, uri, Some(ADMIN_KEY), None).await;
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.body["status"], false);
+        assert_eq!(response.body["message"], "internal server error");
+        assert!(response.body["request_id"].is_string());
+    }
+
+    orders.fail(false);
+    let response = send(&app, Method::GET, "/orders", Some(ADMIN_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn reads_go_to_the_replica_unless_the_request_asks_for_the_primary(pool: PgPool) {
+    // a replica that has not caught up: an empty orders table first on its search_path
+    sqlx::raw_sql("CREATE SCHEMA replica; CREATE TABLE replica.orders (LIKE public.orders INCLUDING ALL)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    let options = (*pool.connect_options()).clone().options([("search_path", "replica,public")]);
+    let replica = PgPoolOptions::new().connect_with(options).await.unwrap();
+    let app = replica_app(pool, replica);
+
+    let created = create_order(&app, flat_white("Ada")).await;
+    let read = |uri: String, from: &'static str| {
+        Request::get(uri)
+            .header("x-api-key", BARISTA_KEY)
+            .header("x-read-from", from)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let lagging = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(lagging.body["data"], json!([]));
+    let fresh = send_request(&app, read("/orders".to_owned(), "primary")).await;
+    assert_eq!(fresh.body["data"][0]["id"], created["id"]);
+
+    let uri = format!("/orders/{}", created["id"]);
+    let lagging = send_request(&app, read(uri.clone(), "replica")).await;
+    assert_eq!(lagging.status, StatusCode::NOT_FOUND);
+    let fresh = send_request(&app, read(uri.clone(), "Primary")).await;
+    assert_eq!(fresh.status, StatusCode::OK);
+
+    let count = send(&app, Method::GET, "/orders/count", Some(BARISTA_KEY), None).await;
+    assert_eq!(count.body["data"]["count"], 0);
+    let count = send_request(&app, read("/orders/count".to_owned(), "primary")).await;
+    assert_eq!(count.body["data"]["count"], 1);
+
+    let invalid = send_request(&app, read(uri, "nearest")).await;
+    assert_eq!(invalid.status, StatusCode::BAD_REQUEST);
+    assert_eq!(invalid.body["message"], "X-Read-From must be primary or replica");
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use axum::http::Method;
+use rust_orders::{purge_old_orders, RetentionSettings};
+use serde_json::json;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use common::{app, create_order, flat_white, send, BARISTA_KEY};
+
+#[sqlx::test]
+async fn retention_archives_only_old_finished_orders(pool: PgPool) {
+    let app = app(pool.clone());
+    let mut ids = Vec::new();
+    for (name, status) in [("Ada", "completed"), ("Grace", "cancelled"), ("Linus", "pending"), ("Hedy", "completed")] {
+        let order = create_order(&app, flat_white(name)).await;
+        let id = order["id"].as_i64().unwrap() as i32;
+        sqlx::query("UPDATE orders SET status = $2 WHERE id = $1")
+            .bind(id)
+            .bind(status)
+            .execute(&pool)
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+    // Hedy's order finished recently, so it stays with the pending one
+    sqlx::query("UPDATE orders SET updated_at = now() - interval '31 days' WHERE id = ANY($1)")
+        .bind(&ids[..3])
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let settings = RetentionSettings { after_days: 30, archive: true };
+    let moved = purge_old_orders(&pool, &settings, &CancellationToken::new()).await.unwrap();
+    assert_eq!(moved, 2);
+
+    let archived: Vec<(i32, serde_json::Value)> = sqlx::query_as("SELECT id, data FROM orders_archive ORDER BY id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(archived.iter().map(|(id, _)| *id).collect::<Vec<_>>(), ids[..2]);
+    assert_eq!(archived[0].1["name"], "Ada");
+    assert_eq!(archived[0].1["items"][0]["coffee_name"], "flat white");
+
+    for (id, kept) in ids.iter().zip([false, false, true, true]) {
+        let response = send(&app, Method::GET, &format!("/orders/{id}"), Some(BARISTA_KEY), None).await;
+        assert_eq!(response.status.is_success(), kept, "order {id}");
+    }
+    // the audit trail outlives the order
+    let response = send(&app, Method::GET, &format!("/orders/{}/events", ids[0]), Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["data"][0]["action"], json!("created"), "{}", response.body);
+
+    let settings = RetentionSettings { after_days: 30, archive: false };
+    sqlx::query("UPDATE orders SET updated_at = now() - interval '31 days' WHERE id = $1")
+        .bind(ids[3])
+        .execute(&pool)
+        .await
+        .unwrap();
+    let moved = purge_old_orders(&pool, &settings, &CancellationToken::new()).await.unwrap();
+    assert_eq!(moved, 1);
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM orders_archive").fetch_one(&pool).await.unwrap();
+    assert_eq!(count, 2);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::{Method, StatusCode};
+use rust_orders::MemoryOrderRepository;
+use serde_json::json;
+use sqlx::PgPool;
+
+use common::{app, memory_app, send, BARISTA_KEY};
+
+#[sqlx::test]
+async fn unknown_routes_are_a_json_not_found(pool: PgPool) {
+    let app = app(pool);
+
+    for (method, uri) in [(Method::GET, "/order/5"), (Method::POST, "/nope"), (Method::DELETE, "/orders/5/garbage")] {
+        let response = send(&app, method, uri, Some(BARISTA_KEY), None).await;
+        assert_eq!(response.status, StatusCode::NOT_FOUND, "{uri}");
+        assert_eq!(response.body["status"], false);
+        assert_eq!(response.body["message"], format!("route not found: {uri}"));
+        assert_eq!(response.body["data"], json!(null));
+    }
+}
+
+#[sqlx::test]
+async fn unsupported_methods_list_the_allowed_ones(pool: PgPool) {
+    let app = app(pool);
+
+    for (method, uri, allow) in [
+        (Method::PUT, "/orders", "GET,HEAD,POST,DELETE"),
+        (Method::POST, "/orders/5", "GET,HEAD,PUT,PATCH,DELETE"),
+    ] {
+        let response = send(&app, method.clone(), uri, Some(BARISTA_KEY), None).await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED, "{method} {uri}");
+        assert_eq!(response.headers["allow"], allow);
+        assert_eq!(response.body["status"], false);
+        assert_eq!(response.body["message"], format!("method {method} not allowed on {uri}"));
+        assert_eq!(response.body["data"], json!(null));
+        assert!(response.body["request_id"].is_string());
+    }
+}
+
+#[tokio::test]
+async fn version_answers_without_a_database() {
+    // the pool behind memory_app never connects
+    let app = memory_app(Arc::new(MemoryOrderRepository::new()));
+
+    let response = send(&app, Method::GET, "/version", None, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let build = &response.body["data"];
+    assert_eq!(build["version"], env!("CARGO_PKG_VERSION"));
+    assert!(!build["commit"].as_str().unwrap().is_empty());
+    assert!(chrono::DateTime::parse_from_rfc3339(build["built_at"].as_str().unwrap()).is_ok(), "{build}");
+    assert_eq!(build["environment"], "development");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serves_on_a_unix_socket_and_removes_it_on_shutdown() {
+    use std::os::unix::fs::PermissionsExt;
+    use rust_orders::UnixSocket;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::sync::CancellationToken;
+
+    let path = std::env::temp_dir().join(format!("orders-test-{}.sock", std::process::id()));
+    // what a crashed run leaves behind: a socket file nobody listens on
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+    let socket = UnixSocket::bind(&path, 0o600).await.unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    let stop = CancellationToken::new();
+    let app = memory_app(Arc::new(MemoryOrderRepository::new()));
+    let server = tokio::spawn(socket.serve(app, stop.clone().cancelled_owned()));
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream.write_all(b"GET /version HTTP/1.1\r\nHost: orders\r\nConnection: close\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+
+    // a second bind while this one serves must not steal the path
+    let err = UnixSocket::bind(&path, 0o600).await.err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+    stop.cancel();
+    server.await.unwrap().unwrap();
+    assert!(!path.exists());
+}
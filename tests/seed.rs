@@ -0,0 +1,58 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use rust_orders::seed_sample_data;
+use sqlx::PgPool;
+
+use common::{app, create_order, flat_white, send, ADMIN_KEY, BARISTA_KEY};
+
+#[sqlx::test]
+async fn seeding_replaces_only_sample_orders(pool: PgPool) {
+    let app = app(pool.clone());
+    let own = create_order(&app, flat_white("Ada")).await;
+
+    let first = seed_sample_data(&pool).await.unwrap();
+    assert_eq!(first.orders, 40);
+    assert_eq!(first.replaced, 0);
+    // Ada already ordered, so she is not created again
+    assert_eq!(first.customers, 11);
+
+    let again = seed_sample_data(&pool).await.unwrap();
+    assert_eq!(again.orders, 40);
+    assert_eq!(again.replaced, 40);
+    assert_eq!(again.customers, 0);
+
+    let response = send(&app, Method::GET, "/orders?tag=sample&limit=1", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["meta"]["total"], 40);
+
+    let response = send(&app, Method::GET, &format!("/orders/{}", own["id"]), Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[sqlx::test]
+async fn dev_reset_clears_orders_and_can_reseed(pool: PgPool) {
+    let app = app(pool);
+    create_order(&app, flat_white("Ada")).await;
+
+    let response = send(&app, Method::POST, "/dev/reset", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let response = send(&app, Method::POST, "/dev/reset", Some(ADMIN_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert!(response.body["data"]["tables"].as_array().unwrap().contains(&"orders".into()));
+    assert!(response.body["data"]["seeded"].is_null());
+    let response = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["meta"]["total"], 0);
+
+    // ids restart, so the first new order is 1 again
+    let order = create_order(&app, flat_white("Grace")).await;
+    assert_eq!(order["id"], 1);
+
+    let response = send(&app, Method::POST, "/dev/reset?seed=true", Some(ADMIN_KEY), None).await;
+    assert_eq!(response.status, StatusCode::OK, "{}", response.body);
+    assert_eq!(response.body["data"]["seeded"]["orders"], 40);
+    assert_eq!(response.body["data"]["seeded"]["replaced"], 0);
+    let response = send(&app, Method::GET, "/orders", Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["meta"]["total"], 40);
+}
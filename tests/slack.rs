@@ -0,0 +1,82 @@
+//! Slack notifications need SLACK_WEBHOOK_URL set before the config is
+//! read, so they have a binary to themselves.
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::{Method, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::net::TcpListener;
+
+use common::{app, flat_white, send, BARISTA_KEY};
+
+#[derive(Clone, Default)]
+struct Channel {
+    posted: Arc<Mutex<Vec<String>>>,
+    /// Set once the first post has been refused.
+    refused_one: Arc<AtomicBool>,
+}
+
+async fn receive(State(channel): State<Channel>, Json(body): Json<Value>) -> StatusCode {
+    if !channel.refused_one.swap(true, Ordering::SeqCst) {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    channel.posted.lock().unwrap().push(body["text"].as_str().unwrap().to_owned());
+    StatusCode::OK
+}
+
+async fn wait_for(channel: &Channel, count: usize) -> Vec<String> {
+    for _ in 0..60 {
+        if channel.posted.lock().unwrap().len() >= count {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    channel.posted.lock().unwrap().clone()
+}
+
+#[sqlx::test]
+async fn big_and_tagged_orders_are_posted_to_slack(pool: PgPool) {
+    let channel = Channel::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let routes = Router::new().route("/hook", post(receive)).with_state(channel.clone());
+    tokio::spawn(async move { axum::serve(listener, routes).await.unwrap() });
+    std::env::set_var("SLACK_WEBHOOK_URL", format!("http://{addr}/hook"));
+    std::env::set_var("SLACK_ORDER_MIN_TOTAL", "20.00");
+    std::env::set_var("SLACK_MAX_PER_MINUTE", "2");
+    let app = app(pool);
+
+    // neither big nor tagged, so never posted
+    let quiet = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(flat_white("Grace"))).await;
+    assert_eq!(quiet.status, StatusCode::CREATED);
+
+    // refused once, posted on the retry a second later
+    let mut tagged = flat_white("Ada & co");
+    tagged["tags"] = json!(["Event"]);
+    let tagged = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(tagged)).await;
+    assert_eq!(tagged.status, StatusCode::CREATED, "{}", tagged.body);
+    let posted = wait_for(&channel, 1).await;
+    assert_eq!(posted.len(), 1, "{posted:?}");
+    assert!(posted[0].starts_with(&format!("*Order #{}* for Ada &amp; co (tagged event)", tagged.body["data"]["id"])), "{}", posted[0]);
+    assert!(posted[0].contains("• 1 x medium flat white"), "{}", posted[0]);
+    assert!(posted[0].ends_with(&format!("*Total: {}*", tagged.body["data"]["total"].as_str().unwrap())), "{}", posted[0]);
+
+    let big = json!({ "name": "Linus", "items": [{ "coffee_name": "latte", "size": "large", "quantity": 40 }] });
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(big.clone())).await;
+    assert_eq!(created.status, StatusCode::CREATED, "{}", created.body);
+    let posted = wait_for(&channel, 2).await;
+    assert!(posted[1].contains("(total of at least 20.00)"), "{}", posted[1]);
+
+    // the third match in a minute is over SLACK_MAX_PER_MINUTE
+    let created = send(&app, Method::POST, "/orders", Some(BARISTA_KEY), Some(big)).await;
+    assert_eq!(created.status, StatusCode::CREATED);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(channel.posted.lock().unwrap().len(), 2);
+}
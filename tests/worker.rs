@@ -0,0 +1,45 @@
+mod common;
+
+use std::time::Duration;
+
+use axum::http::Method;
+use rust_orders::{sweep_orders, WorkerSettings};
+use sqlx::PgPool;
+
+use common::{app, create_order, flat_white, send, state, BARISTA_KEY};
+
+#[sqlx::test]
+async fn sweeps_flag_stale_orders_then_accept_pending_ones(pool: PgPool) {
+    let app = app(pool.clone());
+    let state = state(pool.clone());
+    let old = create_order(&app, flat_white("Ada")).await;
+    let fresh = create_order(&app, flat_white("Grace")).await;
+    sqlx::query("UPDATE orders SET created_at = now() - interval '1 hour' WHERE id = $1")
+        .bind(old["id"].as_i64().unwrap() as i32)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let flag_only = WorkerSettings { accept_after: None, stale_after: Some(Duration::from_secs(1800)) };
+    let summary = sweep_orders(&state, &flag_only).await.unwrap();
+    assert_eq!((summary.flagged, summary.accepted), (1, 0));
+    // flagged orders are not flagged again
+    let summary = sweep_orders(&state, &flag_only).await.unwrap();
+    assert_eq!(summary.flagged, 0);
+
+    let accept_all = WorkerSettings { accept_after: Some(Duration::ZERO), stale_after: None };
+    let summary = sweep_orders(&state, &accept_all).await.unwrap();
+    assert_eq!(summary.accepted, 2);
+
+    let response = send(&app, Method::GET, &format!("/orders/{}", old["id"]), Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["data"]["status"], "preparing");
+    assert_eq!(response.body["data"]["tags"], serde_json::json!(["stale"]));
+    let response = send(&app, Method::GET, &format!("/orders/{}", fresh["id"]), Some(BARISTA_KEY), None).await;
+    assert_eq!(response.body["data"]["tags"], serde_json::json!([]));
+
+    let response = send(&app, Method::GET, &format!("/orders/{}/events", old["id"]), Some(BARISTA_KEY), None).await;
+    let events = response.body["data"].as_array().unwrap();
+    assert_eq!(events[0]["action"], "status_changed", "{}", response.body);
+    assert_eq!(events[0]["actor"], "order-worker");
+    assert_eq!(events[0]["changes"]["status"]["to"], "preparing");
+}